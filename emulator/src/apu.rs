@@ -0,0 +1,631 @@
+use std::collections::VecDeque;
+
+use crate::utils::*;
+
+pub mod noise;
+pub mod pulse;
+pub mod wave;
+
+pub use noise::NoiseChannel;
+pub use pulse::PulseChannel;
+pub use wave::WaveChannel;
+
+/*
+ * Audio processing unit, it owns the sound registers (0xFF10 - 0xFF26) and the wave RAM
+ * (0xFF30 - 0xFF3F):
+ *
+ *    FF10 - FF14    channel 1, pulse with frequency sweep
+ *    FF16 - FF19    channel 2, pulse
+ *    FF1A - FF1E    channel 3, wave
+ *    FF20 - FF23    channel 4, noise
+ *    FF24           NR50, master volume, bits 4 - 6 left, bits 0 - 2 right
+ *    FF25           NR51, panning, bits 4 - 7 send channels 1 - 4 left, bits 0 - 3 right
+ *    FF26           NR52, bit 7 powers the APU, bits 0 - 3 read whether each channel is on
+ *
+ * The length counters, the sweep and the envelopes are clocked by the frame sequencer,
+ * which moves one step every time bit 4 of DIV (bit 5 in double speed) goes from 1 to 0,
+ * 512 times per second:
+ *
+ *    step      0    1    2    3    4    5    6    7
+ *    length    x         x         x         x
+ *    sweep               x                   x
+ *    envelope                                     x
+ *
+ * Each channel feeds a DAC turning its 0 - 15 output into an analog level, the levels are
+ * panned, scaled by the master volume and go through a high pass filter, like the capacitor
+ * on the real output, before being resampled to the rate the frontend asks for.
+ *
+ * see https://gbdev.io/pandocs/Audio.html
+ */
+
+pub const NR10_ADDR: Word = 0xFF10;
+pub const NR14_ADDR: Word = 0xFF14;
+pub const NR21_ADDR: Word = 0xFF16;
+pub const NR24_ADDR: Word = 0xFF19;
+pub const NR30_ADDR: Word = 0xFF1A;
+pub const NR34_ADDR: Word = 0xFF1E;
+pub const NR41_ADDR: Word = 0xFF20;
+pub const NR44_ADDR: Word = 0xFF23;
+pub const NR50_ADDR: Word = 0xFF24;
+pub const NR51_ADDR: Word = 0xFF25;
+pub const NR52_ADDR: Word = 0xFF26;
+pub const WAVE_RAM_START: Word = 0xFF30;
+pub const WAVE_RAM_END: Word = 0xFF3F;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+// the APU runs from the 4194304 Hz clock, even in double speed
+const CLOCK_RATE: u32 = 4194304;
+
+// at most one second of audio is kept when nobody pulls the samples
+const MAX_BUFFERED_SECONDS: usize = 1;
+
+// how much of the charge the output capacitor keeps after each clock cycle
+const CAPACITOR_CHARGE: f64 = 0.999958;
+
+const NR52_POWER: u8 = 7;
+
+pub struct Apu {
+    powered: bool,
+    channel1: PulseChannel,
+    channel2: PulseChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+    nr50: Byte,
+    nr51: Byte,
+    // the next step of the frame sequencer
+    frame_step: u8,
+    div_bit: bool,
+    sample_rate: u32,
+    // goes up by the sample rate every clock cycle, a sample is due each CLOCK_RATE
+    sample_clock: u32,
+    left_sum: f32,
+    right_sum: f32,
+    sum_ticks: u32,
+    capacitor: [f32; 2],
+    filter_factor: f32,
+    // interleaved left and right samples
+    samples: VecDeque<i16>,
+}
+
+impl Apu {
+    // register values left behind by the DMG boot ROM, channel 1 is still on after the beep
+    pub fn new() -> Apu {
+        let mut apu = Apu {
+            powered: true,
+            channel1: PulseChannel::new(true),
+            channel2: PulseChannel::new(false),
+            channel3: WaveChannel::new(),
+            channel4: NoiseChannel::new(),
+            nr50: 0,
+            nr51: 0,
+            frame_step: 0,
+            div_bit: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_clock: 0,
+            left_sum: 0.0,
+            right_sum: 0.0,
+            sum_ticks: 0,
+            capacitor: [0.0; 2],
+            filter_factor: filter_factor(DEFAULT_SAMPLE_RATE),
+            samples: VecDeque::new(),
+        };
+        for (addr, data) in [
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (0xFF14, 0x3F),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (0xFF19, 0x3F),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0x3F),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0x3F),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+        ] {
+            apu.write_byte(addr, data);
+        }
+        apu.channel1.enabled = true;
+        apu
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // drops what was buffered at the old rate
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, CLOCK_RATE);
+        self.filter_factor = filter_factor(self.sample_rate);
+        self.sample_clock = 0;
+        self.samples.clear();
+    }
+
+    // the number of i16 values ready, two per stereo sample
+    pub fn samples_available(&self) -> usize {
+        self.samples.len()
+    }
+
+    // fills the buffer with interleaved left and right samples, returns how many were written
+    pub fn take_samples(&mut self, buffer: &mut [i16]) -> usize {
+        // only whole stereo samples are handed out
+        let count = std::cmp::min(buffer.len() & !1, self.samples.len());
+        for (slot, sample) in buffer.iter_mut().zip(self.samples.drain(..count)) {
+            *slot = sample;
+        }
+        count
+    }
+
+    // advances the APU by the given amount of clock cycles, div_bit is the DIV bit that
+    // drives the frame sequencer
+    pub fn do_cycle(&mut self, ticks: u32, div_bit: bool) {
+        if self.powered {
+            if self.div_bit && !div_bit {
+                self.step_frame_sequencer();
+            }
+            self.channel1.do_cycle(ticks);
+            self.channel2.do_cycle(ticks);
+            self.channel3.do_cycle(ticks);
+            self.channel4.do_cycle(ticks);
+        }
+        self.div_bit = div_bit;
+        self.mix(ticks);
+    }
+
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        match addr {
+            NR10_ADDR..=NR14_ADDR => self.channel1.read_byte(addr - NR10_ADDR),
+            NR21_ADDR..=NR24_ADDR => self.channel2.read_byte(addr - NR21_ADDR + 1),
+            NR30_ADDR..=NR34_ADDR => self.channel3.read_byte(addr - NR30_ADDR),
+            NR41_ADDR..=NR44_ADDR => self.channel4.read_byte(addr - NR41_ADDR + 1),
+            NR50_ADDR => self.nr50,
+            NR51_ADDR => self.nr51,
+            NR52_ADDR => {
+                0x70 | ((self.powered as Byte) << NR52_POWER)
+                    | (self.channel1.enabled() as Byte)
+                    | ((self.channel2.enabled() as Byte) << 1)
+                    | ((self.channel3.enabled() as Byte) << 2)
+                    | ((self.channel4.enabled() as Byte) << 3)
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self.channel3.read_wave_ram(addr - WAVE_RAM_START),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        match addr {
+            NR52_ADDR => self.write_power(bit_set(&data, NR52_POWER)),
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.channel3.write_wave_ram(addr - WAVE_RAM_START, data)
+            }
+            // everything else is read only while the APU is off
+            _ if !self.powered => {}
+            NR10_ADDR..=NR14_ADDR => {
+                let extra_clock = self.extra_length_clock();
+                self.channel1
+                    .write_byte(addr - NR10_ADDR, data, extra_clock)
+            }
+            NR21_ADDR..=NR24_ADDR => {
+                let extra_clock = self.extra_length_clock();
+                self.channel2
+                    .write_byte(addr - NR21_ADDR + 1, data, extra_clock)
+            }
+            NR30_ADDR..=NR34_ADDR => {
+                let extra_clock = self.extra_length_clock();
+                self.channel3
+                    .write_byte(addr - NR30_ADDR, data, extra_clock)
+            }
+            NR41_ADDR..=NR44_ADDR => {
+                let extra_clock = self.extra_length_clock();
+                self.channel4
+                    .write_byte(addr - NR41_ADDR + 1, data, extra_clock)
+            }
+            NR50_ADDR => self.nr50 = data,
+            NR51_ADDR => self.nr51 = data,
+            _ => {}
+        }
+    }
+
+    // turning the APU off clears every register but the wave RAM, turning it on restarts
+    // the frame sequencer
+    fn write_power(&mut self, on: bool) {
+        if self.powered && !on {
+            self.nr50 = 0;
+            self.nr51 = 0;
+            self.channel1 = PulseChannel::new(true);
+            self.channel2 = PulseChannel::new(false);
+            self.channel3.power_off();
+            self.channel4 = NoiseChannel::new();
+        } else if !self.powered && on {
+            self.frame_step = 0;
+        }
+        self.powered = on;
+    }
+
+    // enabling a length counter while the next step of the frame sequencer does not clock
+    // it clocks it once more right away
+    fn extra_length_clock(&self) -> bool {
+        self.frame_step % 2 == 1
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // averages the output over every sample period, it keeps the high tones from aliasing
+    fn mix(&mut self, ticks: u32) {
+        let outputs = [
+            self.channel1.dac_output(),
+            self.channel2.dac_output(),
+            self.channel3.dac_output(),
+            self.channel4.dac_output(),
+        ];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel, output) in outputs.iter().enumerate() {
+            if bit_set(&self.nr51, channel as u8 + 4) {
+                left += output;
+            }
+            if bit_set(&self.nr51, channel as u8) {
+                right += output;
+            }
+        }
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+        self.left_sum += left / 4.0 * left_volume / 8.0 * ticks as f32;
+        self.right_sum += right / 4.0 * right_volume / 8.0 * ticks as f32;
+        self.sum_ticks += ticks;
+
+        self.sample_clock += self.sample_rate * ticks;
+        if self.sample_clock < CLOCK_RATE {
+            return;
+        }
+        let left = self.left_sum / self.sum_ticks as f32;
+        let right = self.right_sum / self.sum_ticks as f32;
+        self.left_sum = 0.0;
+        self.right_sum = 0.0;
+        self.sum_ticks = 0;
+        // at rates close to the clock one call can be worth more than one sample
+        while self.sample_clock >= CLOCK_RATE {
+            self.sample_clock -= CLOCK_RATE;
+            let left = self.high_pass(0, left);
+            let right = self.high_pass(1, right);
+            self.push_sample(left, right);
+        }
+    }
+
+    fn high_pass(&mut self, side: usize, input: f32) -> f32 {
+        let output = input - self.capacitor[side];
+        self.capacitor[side] = input - output * self.filter_factor;
+        output
+    }
+
+    fn push_sample(&mut self, left: f32, right: f32) {
+        let capacity = self.sample_rate as usize * 2 * MAX_BUFFERED_SECONDS;
+        while self.samples.len() + 2 > capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(to_i16(left));
+        self.samples.push_back(to_i16(right));
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+/*
+ * Length counter shared by all channels, once enabled it counts down at 256 Hz and turns the
+ * channel off when it reaches 0. Writing the length register loads max - value.
+ */
+struct LengthCounter {
+    max: Word,
+    counter: Word,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn new(max: Word) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    fn load(&mut self, value: Word) {
+        self.counter = self.max - value;
+    }
+
+    // returns true when the channel has to be turned off
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    // handles the length enable and trigger bits of NRx4, returns true when the channel has
+    // to be turned off. a trigger reloads an expired counter with the maximum length
+    fn write_control(&mut self, enabled: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        let mut expired = false;
+        if extra_clock && !was_enabled && enabled && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0 && !trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if extra_clock && enabled {
+                self.counter -= 1;
+            }
+        }
+        expired
+    }
+}
+
+/*
+ * Volume envelope of the pulse and noise channels (NRx2):
+ *
+ *    bit 4 - 7    initial volume
+ *    bit 3        1 counts up, 0 counts down
+ *    bit 0 - 2    pace, the volume moves once every pace ticks of 64 Hz, 0 stops it
+ *
+ * The DAC of the channel is off when the upper 5 bits are all 0.
+ */
+struct Envelope {
+    register: Byte,
+    volume: Byte,
+    timer: Byte,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn pace(&self) -> Byte {
+        self.register & 0x07
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.pace();
+    }
+
+    fn clock(&mut self) {
+        if self.pace() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return;
+        }
+        self.timer = self.pace();
+        if bit_set(&self.register, 3) {
+            self.volume = std::cmp::min(self.volume + 1, 15);
+        } else {
+            self.volume = self.volume.saturating_sub(1);
+        }
+    }
+}
+
+// the DAC turns the 0 - 15 digital output into a level between -1.0 and 1.0
+fn dac(sample: Byte) -> f32 {
+    1.0 - sample as f32 / 7.5
+}
+
+fn filter_factor(sample_rate: u32) -> f32 {
+    CAPACITOR_CHARGE.powf(CLOCK_RATE as f64 / sample_rate as f64) as f32
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one step of the frame sequencer, DIV bit 4 going from 1 to 0
+    fn step(apu: &mut Apu) {
+        apu.do_cycle(4, true);
+        apu.do_cycle(4, false);
+    }
+
+    #[test]
+    fn length_counter_expires() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        assert!(!length.clock());
+        length.write_control(true, false, false);
+        assert!(!length.clock());
+        assert!(length.clock());
+        // an expired counter stays at 0
+        assert!(!length.clock());
+
+        // a trigger reloads it with the maximum
+        length.write_control(true, true, false);
+        assert_eq!(length.counter, 64);
+    }
+
+    #[test]
+    fn length_counter_extra_clock() {
+        // enabled while the next step does not clock the length, it is clocked right away
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        assert!(!length.write_control(true, false, true));
+        assert_eq!(length.counter, 1);
+        // already enabled, nothing happens
+        assert!(!length.write_control(true, false, true));
+        assert_eq!(length.counter, 1);
+
+        // reaching 0 that way turns the channel off, unless it is triggered at the same time
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(length.write_control(true, false, true));
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(!length.write_control(true, true, true));
+        assert_eq!(length.counter, 63);
+    }
+
+    #[test]
+    fn envelope_pace() {
+        let mut envelope = Envelope::new();
+        // volume 15, down, once every 2 ticks
+        envelope.register = 0xF2;
+        envelope.trigger();
+        let mut volumes = Vec::new();
+        for _ in 0..6 {
+            envelope.clock();
+            volumes.push(envelope.volume);
+        }
+        assert_eq!(volumes, [15, 14, 14, 13, 13, 12]);
+
+        // up stops at 15
+        envelope.register = 0xE9;
+        envelope.trigger();
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume, 15);
+
+        // a pace of 0 stops the envelope
+        envelope.register = 0x80;
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume, 8);
+    }
+
+    #[test]
+    fn length_expiry_turns_channels_off() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF1);
+        // channel 2 with a length of 2
+        apu.write_byte(0xFF17, 0xF0);
+        apu.write_byte(0xFF16, 0x3E);
+        apu.write_byte(NR24_ADDR, 0xC0);
+        // channel 3 with a length of 256, running without the length enabled
+        apu.write_byte(NR30_ADDR, 0x80);
+        apu.write_byte(NR34_ADDR, 0x80);
+        // channel 4 has its DAC off, the trigger does not turn it on
+        apu.write_byte(NR44_ADDR, 0x80);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF7);
+
+        step(&mut apu);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF7);
+        step(&mut apu);
+        step(&mut apu);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF5);
+
+        // the DAC going off turns the channel off too
+        apu.write_byte(NR30_ADDR, 0x00);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF1);
+    }
+
+    #[test]
+    fn power_off_keeps_the_wave_ram() {
+        let mut apu = Apu::new();
+        apu.write_byte(WAVE_RAM_START, 0x12);
+        apu.write_byte(NR52_ADDR, 0x00);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0x70);
+        assert_eq!(apu.read_byte(NR50_ADDR), 0x00);
+        assert_eq!(apu.read_byte(NR51_ADDR), 0x00);
+        assert_eq!(apu.read_byte(NR10_ADDR), 0x80);
+        assert_eq!(apu.read_byte(0xFF11), 0x3F);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+        assert_eq!(apu.read_byte(WAVE_RAM_START), 0x12);
+
+        // only NR52 and the wave RAM can be written while it is off
+        apu.write_byte(NR50_ADDR, 0x77);
+        apu.write_byte(0xFF12, 0xF0);
+        apu.write_byte(WAVE_RAM_START + 1, 0x34);
+        assert_eq!(apu.read_byte(NR50_ADDR), 0x00);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+        assert_eq!(apu.read_byte(WAVE_RAM_START + 1), 0x34);
+
+        apu.write_byte(NR52_ADDR, 0x80);
+        assert_eq!(apu.read_byte(NR52_ADDR), 0xF0);
+        apu.write_byte(NR50_ADDR, 0x77);
+        assert_eq!(apu.read_byte(NR50_ADDR), 0x77);
+    }
+
+    #[test]
+    fn samples_come_in_stereo_pairs() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.sample_rate(), DEFAULT_SAMPLE_RATE);
+        // one sample every 128 clock cycles
+        apu.set_sample_rate(32768);
+        assert_eq!(apu.sample_rate(), 32768);
+        for _ in 0..100 * 32 {
+            apu.do_cycle(4, false);
+        }
+        assert_eq!(apu.samples_available(), 200);
+
+        let mut buffer = [0; 7];
+        assert_eq!(apu.take_samples(&mut buffer), 6);
+        assert_eq!(apu.samples_available(), 194);
+        let mut buffer = [0; 1];
+        assert_eq!(apu.take_samples(&mut buffer), 0);
+        let mut buffer = [0; 400];
+        assert_eq!(apu.take_samples(&mut buffer), 194);
+        assert_eq!(apu.samples_available(), 0);
+
+        // changing the rate drops what is buffered
+        apu.do_cycle(128, false);
+        assert_eq!(apu.samples_available(), 2);
+        apu.set_sample_rate(16384);
+        assert_eq!(apu.samples_available(), 0);
+    }
+
+    #[test]
+    fn rates_close_to_the_clock() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(CLOCK_RATE);
+        apu.do_cycle(4, false);
+        assert_eq!(apu.samples_available(), 8);
+        apu.set_sample_rate(CLOCK_RATE / 2 + 1);
+        for _ in 0..1000 {
+            apu.do_cycle(4, false);
+        }
+        assert_eq!(apu.samples_available(), 2 * 2000);
+        // past the clock it is clamped
+        apu.set_sample_rate(u32::MAX);
+        assert_eq!(apu.sample_rate(), CLOCK_RATE);
+    }
+}
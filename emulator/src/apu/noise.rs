@@ -0,0 +1,193 @@
+use crate::apu::{dac, Envelope, LengthCounter};
+use crate::utils::*;
+
+/*
+ * Noise channel 4, outputs the lowest bit of a 15 bit linear feedback shift register (LFSR).
+ *
+ *    NR41    length (write only)
+ *    NR42    volume envelope
+ *    NR43    bit 4 - 7 clock shift, bit 3 7 bit LFSR, bit 0 - 2 clock divider
+ *    NR44    bit 7 trigger, bit 6 length enable
+ *
+ * The LFSR shifts every divider << shift clock cycles, a divider of 0 counts as 8 and the
+ * others as 16 times their value. Each shift XORs the two lowest bits into bit 14, and into
+ * bit 6 as well in 7 bit mode, which gives a shorter, more metallic sequence.
+ *
+ * see https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise
+ */
+
+const DIVIDERS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+const SHORT_MODE: u8 = 3;
+const TRIGGER: u8 = 7;
+const LENGTH_ENABLE: u8 = 6;
+
+pub struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    nr43: Byte,
+    timer: u32,
+    lfsr: Word,
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            nr43: 0,
+            timer: DIVIDERS[0],
+            lfsr: 0x7FFF,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // register is the offset from NR40, which does not exist
+    pub fn read_byte(&self, register: Word) -> Byte {
+        match register {
+            2 => self.envelope.register,
+            3 => self.nr43,
+            4 => 0xBF | ((self.length.enabled as Byte) << LENGTH_ENABLE),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: Word, data: Byte, extra_length_clock: bool) {
+        match register {
+            1 => self.length.load((data & 0x3F) as Word),
+            2 => {
+                self.envelope.register = data;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.nr43 = data,
+            4 => {
+                let trigger = bit_set(&data, TRIGGER);
+                if self.length.write_control(
+                    bit_set(&data, LENGTH_ENABLE),
+                    trigger,
+                    extra_length_clock,
+                ) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period_cycles();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.timer -= 1;
+            if self.timer == 0 {
+                self.timer = self.period_cycles();
+                self.shift();
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // 0 when the channel is off or its DAC is
+    pub fn dac_output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+        let sample = if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        };
+        dac(sample)
+    }
+
+    fn shift(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if bit_set(&self.nr43, SHORT_MODE) {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    fn period_cycles(&self) -> u32 {
+        DIVIDERS[(self.nr43 & 0x07) as usize] << (self.nr43 >> 4)
+    }
+}
+
+impl Default for NoiseChannel {
+    fn default() -> NoiseChannel {
+        NoiseChannel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // shifts until the bits in mask are back to where they started
+    fn sequence_length(channel: &mut NoiseChannel, mask: Word) -> usize {
+        let start = channel.lfsr & mask;
+        let mut shifts = 0;
+        loop {
+            channel.shift();
+            shifts += 1;
+            if channel.lfsr & mask == start {
+                return shifts;
+            }
+        }
+    }
+
+    #[test]
+    fn lfsr_15_bit() {
+        let mut channel = NoiseChannel::new();
+        channel.shift();
+        assert_eq!(channel.lfsr, 0x3FFF);
+        let mut channel = NoiseChannel::new();
+        assert_eq!(sequence_length(&mut channel, 0x7FFF), 32767);
+    }
+
+    #[test]
+    fn lfsr_7_bit() {
+        let mut channel = NoiseChannel::new();
+        channel.write_byte(3, 0x08, false);
+        channel.shift();
+        assert_eq!(channel.lfsr, 0x3FBF);
+        assert_eq!(sequence_length(&mut channel, 0x7F), 127);
+    }
+
+    #[test]
+    fn shift_clock() {
+        let mut channel = NoiseChannel::new();
+        channel.write_byte(2, 0xF0, false);
+        // divider 16 shifted twice, a shift every 64 clock cycles
+        channel.write_byte(3, 0x21, false);
+        channel.write_byte(4, 0x80, false);
+        assert!(channel.enabled());
+        channel.do_cycle(63);
+        assert_eq!(channel.lfsr, 0x7FFF);
+        channel.do_cycle(1);
+        assert_eq!(channel.lfsr, 0x3FFF);
+        // the trigger resets the LFSR
+        channel.write_byte(4, 0x80, false);
+        assert_eq!(channel.lfsr, 0x7FFF);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::apu::{dac, Envelope, LengthCounter};
+use crate::utils::*;
+
+/*
+ * Pulse channels 1 and 2, a square wave with a selectable duty cycle. Only channel 1 has
+ * the frequency sweep (NR10), channel 2 starts at NR21.
+ *
+ *    NRx0    bit 4 - 6 sweep pace, bit 3 sweep direction (1 down), bit 0 - 2 sweep step
+ *    NRx1    bit 6 - 7 duty cycle, bit 0 - 5 length (write only)
+ *    NRx2    volume envelope
+ *    NRx3    lower 8 bits of the period (write only)
+ *    NRx4    bit 7 trigger, bit 6 length enable, bit 0 - 2 upper 3 bits of the period
+ *
+ * The duty step moves every (2048 - period) * 4 clock cycles, 8 steps make one wave.
+ *
+ * see https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-1--pulse-with-period-sweep
+ */
+
+const DUTY_PATTERNS: [[Byte; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const MAX_PERIOD: Word = 0x7FF;
+const SWEEP_DOWN: u8 = 3;
+const TRIGGER: u8 = 7;
+const LENGTH_ENABLE: u8 = 6;
+
+pub struct PulseChannel {
+    pub(super) enabled: bool,
+    has_sweep: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    duty: Byte,
+    duty_step: usize,
+    period: Word,
+    timer: u32,
+    sweep_register: Byte,
+    sweep_enabled: bool,
+    sweep_timer: Byte,
+    shadow_period: Word,
+    // a sweep calculation in the down direction happened since the last trigger, going back
+    // to up after that turns the channel off
+    sweep_went_down: bool,
+}
+
+impl PulseChannel {
+    pub fn new(has_sweep: bool) -> PulseChannel {
+        PulseChannel {
+            enabled: false,
+            has_sweep,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: 2048 * 4,
+            sweep_register: 0,
+            sweep_enabled: false,
+            sweep_timer: 0,
+            shadow_period: 0,
+            sweep_went_down: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // register is the offset from NRx0, write only bits read back as 1
+    pub fn read_byte(&self, register: Word) -> Byte {
+        match register {
+            0 if self.has_sweep => 0x80 | self.sweep_register,
+            1 => 0x3F | (self.duty << 6),
+            2 => self.envelope.register,
+            4 => 0xBF | ((self.length.enabled as Byte) << LENGTH_ENABLE),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: Word, data: Byte, extra_length_clock: bool) {
+        match register {
+            0 if self.has_sweep => {
+                self.sweep_register = data & 0x7F;
+                if !bit_set(&data, SWEEP_DOWN) && self.sweep_went_down {
+                    self.enabled = false;
+                }
+            }
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as Word);
+            }
+            2 => {
+                self.envelope.register = data;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.period = (self.period & 0x700) | data as Word,
+            4 => {
+                self.period = (self.period & 0xFF) | (((data & 0x07) as Word) << 8);
+                let trigger = bit_set(&data, TRIGGER);
+                if self.length.write_control(
+                    bit_set(&data, LENGTH_ENABLE),
+                    trigger,
+                    extra_length_clock,
+                ) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period_cycles();
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.shadow_period = self.period;
+            self.sweep_timer = self.sweep_timer_period();
+            self.sweep_enabled = self.sweep_pace() != 0 || self.sweep_step() != 0;
+            self.sweep_went_down = false;
+            // the overflow check runs right away when there is a step
+            if self.sweep_step() != 0 {
+                self.next_sweep_period();
+            }
+        }
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.timer -= 1;
+            if self.timer == 0 {
+                self.timer = self.period_cycles();
+                self.duty_step = (self.duty_step + 1) % 8;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // the sweep timer treats a pace of 0 as 8 but only changes the period with a real pace
+    pub fn clock_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer != 0 {
+            return;
+        }
+        self.sweep_timer = self.sweep_timer_period();
+        if !self.sweep_enabled || self.sweep_pace() == 0 {
+            return;
+        }
+        let period = self.next_sweep_period();
+        if period <= MAX_PERIOD && self.sweep_step() != 0 {
+            self.shadow_period = period;
+            self.period = period;
+            // the new period is checked for an overflow once more
+            self.next_sweep_period();
+        }
+    }
+
+    // 0 when the channel is off or its DAC is
+    pub fn dac_output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+        let sample = if self.enabled {
+            DUTY_PATTERNS[self.duty as usize][self.duty_step] * self.envelope.volume
+        } else {
+            0
+        };
+        dac(sample)
+    }
+
+    fn period_cycles(&self) -> u32 {
+        (2048 - self.period as u32) * 4
+    }
+
+    fn sweep_pace(&self) -> Byte {
+        (self.sweep_register >> 4) & 0x07
+    }
+
+    fn sweep_timer_period(&self) -> Byte {
+        match self.sweep_pace() {
+            0 => 8,
+            pace => pace,
+        }
+    }
+
+    fn sweep_step(&self) -> Byte {
+        self.sweep_register & 0x07
+    }
+
+    // a period past 0x7FF turns the channel off
+    fn next_sweep_period(&mut self) -> Word {
+        let delta = self.shadow_period >> self.sweep_step();
+        let period = if bit_set(&self.sweep_register, SWEEP_DOWN) {
+            self.sweep_went_down = true;
+            self.shadow_period - delta
+        } else {
+            self.shadow_period + delta
+        };
+        if period > MAX_PERIOD {
+            self.enabled = false;
+        }
+        period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // channel 1 with its DAC on, triggered with the period and NR10
+    fn triggered(period: Word, nr10: Byte) -> PulseChannel {
+        let mut channel = PulseChannel::new(true);
+        channel.write_byte(0, nr10, false);
+        channel.write_byte(2, 0xF0, false);
+        channel.write_byte(3, period as Byte, false);
+        channel.write_byte(4, 0x80 | (period >> 8) as Byte, false);
+        channel
+    }
+
+    #[test]
+    fn sweep_overflow_turns_channel_1_off() {
+        // pace 1, up by period >> 1: 0x500 -> 0x780 -> 0xB40
+        let mut channel = triggered(0x500, 0x11);
+        assert!(channel.enabled());
+        channel.clock_sweep();
+        assert_eq!(channel.period, 0x780);
+        // the second check after the update already sees the overflow
+        assert!(!channel.enabled());
+
+        // the check at the trigger catches it before any sweep clock
+        let channel = triggered(0x600, 0x11);
+        assert!(!channel.enabled());
+        // without a step the period stays but the sweep clock still checks period * 2
+        let mut channel = triggered(0x3FF, 0x10);
+        channel.clock_sweep();
+        assert!(channel.enabled());
+        assert_eq!(channel.period, 0x3FF);
+        let mut channel = triggered(0x400, 0x10);
+        assert!(channel.enabled());
+        channel.clock_sweep();
+        assert!(!channel.enabled());
+    }
+
+    #[test]
+    fn sweep_down_then_up_turns_channel_1_off() {
+        let mut channel = triggered(0x400, 0x19);
+        channel.clock_sweep();
+        assert_eq!(channel.period, 0x200);
+        assert!(channel.enabled());
+        channel.write_byte(0, 0x11, false);
+        assert!(!channel.enabled());
+    }
+
+    #[test]
+    fn channel_2_has_no_sweep() {
+        let mut channel = PulseChannel::new(false);
+        channel.write_byte(0, 0x11, false);
+        assert_eq!(channel.read_byte(0), 0xFF);
+        channel.write_byte(2, 0xF0, false);
+        channel.write_byte(3, 0x00, false);
+        channel.write_byte(4, 0x86, false);
+        channel.clock_sweep();
+        assert!(channel.enabled());
+        assert_eq!(channel.period, 0x600);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::apu::{dac, LengthCounter};
+use crate::utils::*;
+
+/*
+ * Wave channel 3, plays the 32 4 bit samples of the wave RAM (0xFF30 - 0xFF3F), upper nibble
+ * first.
+ *
+ *    NR30    bit 7 DAC on
+ *    NR31    length (write only), 256 steps
+ *    NR32    bit 5 - 6 output level: mute, 100%, 50%, 25%
+ *    NR33    lower 8 bits of the period (write only)
+ *    NR34    bit 7 trigger, bit 6 length enable, bit 0 - 2 upper 3 bits of the period
+ *
+ * The sample index moves every (2048 - period) * 2 clock cycles. While the channel plays, the
+ * wave RAM only reaches the byte being played, as on the CGB.
+ *
+ * see https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-3--wave-output
+ */
+
+const WAVE_RAM_SIZE: usize = 16;
+const WAVE_SAMPLES: usize = WAVE_RAM_SIZE * 2;
+
+const DAC_ENABLE: u8 = 7;
+const TRIGGER: u8 = 7;
+const LENGTH_ENABLE: u8 = 6;
+
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    output_level: Byte,
+    period: Word,
+    timer: u32,
+    position: usize,
+    sample: Byte,
+    wave_ram: [Byte; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            output_level: 0,
+            period: 0,
+            timer: 2048 * 2,
+            position: 0,
+            sample: 0,
+            wave_ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // clears the registers, the wave RAM survives the APU being turned off
+    pub fn power_off(&mut self) {
+        let wave_ram = self.wave_ram;
+        *self = WaveChannel::new();
+        self.wave_ram = wave_ram;
+    }
+
+    // register is the offset from NR30, write only bits read back as 1
+    pub fn read_byte(&self, register: Word) -> Byte {
+        match register {
+            0 => 0x7F | ((self.dac_enabled as Byte) << DAC_ENABLE),
+            2 => 0x9F | (self.output_level << 5),
+            4 => 0xBF | ((self.length.enabled as Byte) << LENGTH_ENABLE),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, register: Word, data: Byte, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = bit_set(&data, DAC_ENABLE);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(data as Word),
+            2 => self.output_level = (data >> 5) & 0x03,
+            3 => self.period = (self.period & 0x700) | data as Word,
+            4 => {
+                self.period = (self.period & 0xFF) | (((data & 0x07) as Word) << 8);
+                let trigger = bit_set(&data, TRIGGER);
+                if self.length.write_control(
+                    bit_set(&data, LENGTH_ENABLE),
+                    trigger,
+                    extra_length_clock,
+                ) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period_cycles();
+                    self.position = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read_wave_ram(&self, offset: Word) -> Byte {
+        match self.enabled {
+            true => self.wave_ram[self.position / 2],
+            false => self.wave_ram[offset as usize],
+        }
+    }
+
+    pub fn write_wave_ram(&mut self, offset: Word, data: Byte) {
+        match self.enabled {
+            true => self.wave_ram[self.position / 2] = data,
+            false => self.wave_ram[offset as usize] = data,
+        }
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.timer -= 1;
+            if self.timer == 0 {
+                self.timer = self.period_cycles();
+                self.position = (self.position + 1) % WAVE_SAMPLES;
+                let byte = self.wave_ram[self.position / 2];
+                self.sample = match self.position % 2 {
+                    0 => byte >> 4,
+                    _ => byte & 0x0F,
+                };
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    // 0 when the channel is off or its DAC is
+    pub fn dac_output(&self) -> f32 {
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        let sample = match (self.enabled, self.output_level) {
+            (false, _) | (true, 0) => 0,
+            (true, level) => self.sample >> (level - 1),
+        };
+        dac(sample)
+    }
+
+    fn period_cycles(&self) -> u32 {
+        (2048 - self.period as u32) * 2
+    }
+}
+
+impl Default for WaveChannel {
+    fn default() -> WaveChannel {
+        WaveChannel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_the_wave_ram() {
+        let mut channel = WaveChannel::new();
+        channel.write_wave_ram(0, 0x9C);
+        channel.write_wave_ram(1, 0x3F);
+        channel.write_byte(0, 0x80, false);
+        channel.write_byte(2, 0x20, false);
+        // the shortest period, the sample index moves every 2 clock cycles
+        channel.write_byte(3, 0xFF, false);
+        channel.write_byte(4, 0x87, false);
+        assert!(channel.enabled());
+
+        // the trigger starts at sample 0 but the first one played is sample 1
+        channel.do_cycle(2);
+        assert_eq!(channel.dac_output(), dac(0x0C));
+        channel.do_cycle(2);
+        assert_eq!(channel.dac_output(), dac(0x03));
+        // 50% and mute
+        channel.write_byte(2, 0x40, false);
+        assert_eq!(channel.dac_output(), dac(0x01));
+        channel.write_byte(2, 0x00, false);
+        assert_eq!(channel.dac_output(), dac(0x00));
+
+        // while playing only the byte being played is reachable
+        assert_eq!(channel.read_wave_ram(0x0F), 0x3F);
+    }
+
+    #[test]
+    fn power_off_keeps_the_wave_ram() {
+        let mut channel = WaveChannel::new();
+        channel.write_wave_ram(5, 0x42);
+        channel.write_byte(0, 0x80, false);
+        channel.write_byte(2, 0x60, false);
+        channel.power_off();
+        assert_eq!(channel.read_byte(0), 0x7F);
+        assert_eq!(channel.read_byte(2), 0x9F);
+        assert_eq!(channel.read_wave_ram(5), 0x42);
+    }
+}
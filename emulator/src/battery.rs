@@ -0,0 +1,78 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::*;
+use crate::mmu::*;
+use crate::utils::*;
+
+// flush every 5 seconds of emulated time by default
+const DEFAULT_SAVE_INTERVAL: u32 = 300 * CYCLES_PER_FRAME;
+
+/*
+ * Keeps the battery backed external RAM of a cartridge in sync with a .sav file. The file is
+ * a raw dump of the RAM, sized to what the header declares, which is the same format other
+ * emulators and flash carts use. Writes only hit the disk when the RAM actually changed.
+ */
+pub struct Battery {
+    path: PathBuf,
+    // clock cycles between two flushes, 0 only flushes on demand
+    interval: u32,
+    elapsed: u32,
+}
+
+impl Battery {
+    pub fn new<P: AsRef<Path>>(path: P) -> Battery {
+        Battery {
+            path: path.as_ref().to_path_buf(),
+            interval: DEFAULT_SAVE_INTERVAL,
+            elapsed: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_interval(&mut self, cycles: u32) {
+        self.interval = cycles;
+    }
+
+    // a missing file is not an error, the game simply starts without a save
+    pub fn load(&self, mmu: &mut Mmu) -> Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => mmu.load_save_data(&data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    pub fn save(&mut self, mmu: &mut Mmu) -> Result<()> {
+        fs::write(&self.path, mmu.save_data())?;
+        mmu.clear_ext_ram_dirty();
+        self.elapsed = 0;
+        Ok(())
+    }
+
+    // called with the clock cycles of every instruction, flushes once the interval has passed
+    // and the RAM was written to
+    pub fn do_cycle(&mut self, mmu: &mut Mmu, cycles: u32) -> Result<()> {
+        if self.interval == 0 {
+            return Ok(());
+        }
+        self.elapsed = self.elapsed.saturating_add(cycles);
+        if self.elapsed < self.interval {
+            return Ok(());
+        }
+        self.elapsed = 0;
+        self.flush(mmu)
+    }
+
+    // a clock keeps changing even when the RAM does not, so it is always written
+    pub fn flush(&mut self, mmu: &mut Mmu) -> Result<()> {
+        match mmu.is_ext_ram_dirty() || mmu.has_rtc() {
+            true => self.save(mmu),
+            false => Ok(()),
+        }
+    }
+}
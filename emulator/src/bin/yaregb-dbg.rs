@@ -0,0 +1,90 @@
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::TcpListener;
+use std::process::ExitCode;
+
+use yaregb::debugger::Debugger;
+use yaregb::gdb;
+use yaregb::GameBoy;
+
+// reads debugger commands from stdin until quit or the end of the input, see the debugger
+// module for the list. the prompt is only shown to a terminal so scripts get clean output.
+// with --gdb it waits for a GDB remote protocol client on the port instead, see the gdb
+// module, port 0 picks a free one
+
+const PROMPT: &str = "(yaregb) ";
+
+const USAGE: &str = "usage: yaregb-dbg ROM < commands
+       yaregb-dbg --gdb PORT ROM";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (rom, port) = match args.as_slice() {
+        [rom] => (rom, None),
+        [flag, port, rom] if flag == "--gdb" => match port.parse::<u16>() {
+            Ok(port) => (rom, Some(port)),
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut gameboy = match GameBoy::from_path(rom) {
+        Ok(gameboy) => gameboy,
+        Err(err) => {
+            eprintln!("could not load {}: {}", rom, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(warning) = gameboy.rom().checksum_warning() {
+        eprintln!("warning: {}", warning);
+    }
+    gameboy.set_save_error_callback(|err| {
+        eprintln!("warning: could not write the save file: {}", err)
+    });
+
+    let debugger = Debugger::new(gameboy);
+    let result = match port {
+        Some(port) => serve_gdb(debugger, port),
+        None => repl(debugger),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn repl(mut debugger: Debugger) -> io::Result<()> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut stdout = io::stdout().lock();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            write!(stdout, "{}", PROMPT)?;
+            stdout.flush()?;
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        if !debugger.execute(&line?, &mut stdout)? {
+            return Ok(());
+        }
+    }
+}
+
+fn serve_gdb(mut debugger: Debugger, port: u16) -> io::Result<()> {
+    // only local clients, there is no authentication of any kind
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    // the port tells scripts where to connect when it was picked for us
+    println!("listening on {}", listener.local_addr()?);
+    io::stdout().flush()?;
+    gdb::serve(&mut debugger, &listener)
+}
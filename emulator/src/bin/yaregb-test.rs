@@ -0,0 +1,126 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use yaregb::testrom::*;
+
+// runs test ROMs headless and prints one line per ROM, the exit code is 1 when any of them
+// did not pass
+
+const USAGE: &str = "usage: yaregb-test [OPTIONS] [ROM or DIR]...
+
+  --cycles N              clock cycles each ROM gets before it times out
+  --suites DIR            runs the blargg suites tracked by the CI, found under DIR
+  --mooneye               runs the ROMs given afterwards as mooneye tests
+  --mooneye-suites DIR    runs the mooneye acceptance/ and emulator-only/ tests under DIR
+  --json FILE             writes a JSON summary of the results to FILE";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Runner {
+    Blargg,
+    Mooneye,
+}
+
+fn main() -> ExitCode {
+    let mut cycle_budget = DEFAULT_CYCLE_BUDGET;
+    let mut runner = Runner::Blargg;
+    let mut json_path = None;
+    // the JSON summary shows paths relative to the last suite directory given
+    let mut root = PathBuf::new();
+    let mut roms = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(cycles) => cycle_budget = cycles,
+                None => return usage(),
+            },
+            "--suites" => match args.next() {
+                Some(dir) => {
+                    for suite in BLARGG_SUITES {
+                        let suite_roms = suite_roms(&dir, suite);
+                        roms.extend(suite_roms.into_iter().map(|rom| (rom, Runner::Blargg)));
+                    }
+                    root = PathBuf::from(dir);
+                }
+                None => return usage(),
+            },
+            "--mooneye" => runner = Runner::Mooneye,
+            "--mooneye-suites" => match args.next() {
+                Some(dir) => {
+                    for suite in MOONEYE_SUITES {
+                        let suite_roms = suite_roms(&dir, suite);
+                        roms.extend(suite_roms.into_iter().map(|rom| (rom, Runner::Mooneye)));
+                    }
+                    root = PathBuf::from(dir);
+                }
+                None => return usage(),
+            },
+            "--json" => match args.next() {
+                Some(path) => json_path = Some(path),
+                None => return usage(),
+            },
+            "-h" | "--help" => return usage(),
+            _ => {
+                let mut found = Vec::new();
+                collect_roms(&PathBuf::from(arg), &mut found);
+                found.sort();
+                roms.extend(found.into_iter().map(|rom| (rom, runner)));
+            }
+        }
+    }
+    if roms.is_empty() {
+        return usage();
+    }
+
+    let mut reports = Vec::new();
+    for (rom, runner) in &roms {
+        let report = match runner {
+            Runner::Blargg => run_blargg(rom, cycle_budget),
+            Runner::Mooneye => run_mooneye(rom, cycle_budget),
+        };
+        match report {
+            Ok(report) => {
+                println!("{:<8} {}", report.outcome.to_string(), rom.display());
+                if !report.passed() {
+                    for line in report.output.lines().filter(|line| !line.is_empty()) {
+                        println!("         | {}", line);
+                    }
+                }
+                reports.push(report);
+            }
+            Err(err) => {
+                let report = TestReport::error(rom, &err);
+                println!(
+                    "{:<8} {}: {}",
+                    report.outcome.to_string(),
+                    rom.display(),
+                    err
+                );
+                reports.push(report);
+            }
+        }
+    }
+
+    let passed = reports.iter().filter(|report| report.passed()).count();
+    println!("{} of {} passed", passed, roms.len());
+
+    if let Some(path) = json_path {
+        if let Err(err) = fs::write(&path, json_summary(&reports, &root)) {
+            eprintln!("could not write {}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match passed == reports.len() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
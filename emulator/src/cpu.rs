@@ -1,296 +1,1302 @@
-use crate::register::cpu_flags::{C, N, H, Z};
-use crate::register::registers;
-use crate::mmu::mmu;    
+use crate::interrupt::Interrupt;
+use crate::mmu::Mmu;
+use crate::register::CpuFlags::{C, H, N, Z};
+use crate::register::Registers;
+use crate::trace::Tracer;
+use crate::utils::*;
 
-pub struct cpu<'a>{
-    reg: registers,
-    pub mmu: mmu<'a>,
+// machine cycles (M-cycles) of every base opcode, conditional jumps, calls and returns are
+// listed with their not-taken cost, the taken cost is added by the instruction itself.
+// the illegal opcodes (0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD)
+// lock up the cpu, they are listed as 1 so the lock up still moves the clock forward.
+// see https://gbdev.io/gb-opcodes/optables/
+#[rustfmt::skip]
+const OPCODE_CYCLES: [u32; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 2
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 3
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 4
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 5
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 6
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 7
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 8
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 9
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // A
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // B
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 1, 3, 6, 2, 4, // C
+    2, 3, 3, 1, 3, 4, 2, 4, 2, 4, 3, 1, 3, 1, 2, 4, // D
+    3, 3, 2, 1, 1, 4, 2, 4, 4, 1, 4, 1, 1, 1, 2, 4, // E
+    3, 3, 2, 1, 1, 4, 2, 4, 3, 2, 4, 1, 1, 1, 2, 4, // F
+];
+
+// machine cycles of every 0xCB prefixed opcode, the prefix fetch included.
+// every operation on (HL) is a read-modify-write except BIT, which only reads
+#[rustfmt::skip]
+const CB_OPCODE_CYCLES: [u32; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 0
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 1
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 2
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 3
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2, // 4
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2, // 5
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2, // 6
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2, // 7
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 8
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // 9
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // A
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // B
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // C
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // D
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // E
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // F
+];
+
+// extra machine cycles spent when the condition of a branch holds
+const JR_TAKEN_CYCLES: u32 = 1;
+const JP_TAKEN_CYCLES: u32 = 1;
+const CALL_TAKEN_CYCLES: u32 = 3;
+const RET_TAKEN_CYCLES: u32 = 3;
+
+// pushing PC and jumping to the handler takes two wait states, two writes and the jump
+const INTERRUPT_DISPATCH_CYCLES: u32 = 5;
+
+/*
+ * Most opcodes encode their operands in fixed bit positions, see https://gbdev.io/pandocs/CPU_Instruction_Set.html
+ *
+ *    r8   (bits 0-2 or 3-5)    B, C, D, E, H, L, (HL), A
+ *    r16  (bits 4-5)           BC, DE, HL, SP
+ *    r16stk (bits 4-5)         BC, DE, HL, AF
+ *    cond (bits 3-4)           NZ, Z, NC, C
+ *    alu  (bits 3-5)           ADD, ADC, SUB, SBC, AND, XOR, OR, CP
+ */
+const R8_HL: Byte = 6;
+
+const LD_B_B: Byte = 0x40;
+
+pub struct Cpu {
+    reg: Registers,
+    pub mmu: Mmu,
     halted: bool,
+    locked: bool,
     ime: bool,
-    setdi: u32,
+    // EI only takes effect after the next instruction, this counts down to it
     setei: u32,
-    mmu: cpu_mmu,
+    // set when HALT is skipped with IME=0 and an interrupt pending, the next opcode fetch
+    // then fails to increment PC, see https://gbdev.io/pandocs/halt.html#halt-bug
+    halt_bug: bool,
+    // machine cycles already spent by the instruction being executed
+    ticks: u32,
+    // set when LD B,B runs
+    breakpoint: bool,
+    // the same, but cleared as soon as the instruction is done: take_breakpoint may never be
+    // called and the ring of traces is only written out once per LD B,B
+    dump_trace: bool,
+    tracer: Option<Tracer>,
 }
 
-impl<'a> cpu<'a>{
-    pub fn new(romname: &str, serial_callback: Option<SerialCallback<'a>>, skip_checksum: bool) -> StrResult<cpu<'a>>{
-        let cpu_mmu = mmu::new(romname, serial_callback, skip_checksum)?;
-        Ok(cpu {
-            reg: registers::new(),
+impl Cpu {
+    pub fn new(mmu: Mmu) -> Cpu {
+        Cpu {
+            reg: if mmu.is_cgb() {
+                Registers::new_cgb()
+            } else {
+                Registers::new()
+            },
+            mmu,
             halted: false,
-            ime: true,
-            setdi: 0,
+            locked: false,
+            ime: false,
             setei: 0,
-            mmu: cpu_mmu,
-        })
+            halt_bug: false,
+            ticks: 0,
+            breakpoint: false,
+            dump_trace: false,
+            tracer: None,
+        }
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.reg
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.reg
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
+    // true after executing one of the illegal opcodes, only a reset gets the cpu back
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // true once after every LD B,B
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::replace(&mut self.breakpoint, false)
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.ime
+    }
+
+    // returns the tracer that was set before
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    // runs one instruction and returns how many clock cycles (T-cycles) it took.
+    // memory accesses move the rest of the hardware along as they happen, whatever is left
+    // of the instruction afterwards are internal cycles that happen after the last access
     pub fn do_cycle(&mut self) -> u32 {
-        let ticks = self.cycle() * 4;
-        return self.mmu.do_cycle(ticks)
+        self.ticks = 0;
+        let cycles = self.cycle();
+        while self.ticks < cycles {
+            self.tick();
+        }
+        // the VRAM DMA and the speed switch keep the cpu waiting after the instruction
+        for _ in 0..self.mmu.take_stall_cycles() {
+            self.tick();
+        }
+        self.ticks * 4
+    }
+
+    // one machine cycle worth of time for everything but the cpu
+    fn tick(&mut self) {
+        self.ticks += 1;
+        self.mmu.do_cycle(4);
     }
 
-    fn cycle(&mut self) -> u32{
-        self.updatetime();
-        match self.handleinterrupt(){
-            0 => {},
+    // returns the amount of machine cycles (M-cycles) spent
+    fn cycle(&mut self) -> u32 {
+        if self.locked {
+            return 1;
+        }
+
+        self.updateime();
+        match self.handleinterrupt() {
+            0 => {}
             n => return n,
         };
 
         if self.halted {
-            1
-        }else{
-            self.call()
+            return 1;
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&self.reg, &self.mmu);
         }
+        let cycles = self.call();
+        // what led to a software breakpoint or a crash is what a ring of traces is kept for
+        if std::mem::take(&mut self.dump_trace) || self.locked {
+            if let Some(tracer) = &mut self.tracer {
+                tracer.dump();
+            }
+        }
+        cycles
     }
 
-    fn fetch_byte(&mut self) -> u8{
-        let b = self.mmu.b(self.rg.pc);
-        self.
-        rg.pc +=1
+    fn updateime(&mut self) {
+        if self.setei > 0 {
+            self.setei -= 1;
+            if self.setei == 0 {
+                self.ime = true;
+            }
+        }
     }
 
-    fn fetch_word(&mut self) -> u16{
+    // any pending interrupt wakes the cpu up from HALT, even with IME=0, but it is only
+    // serviced when IME=1. returns the machine cycles spent dispatching, 0 if none was
+    fn handleinterrupt(&mut self) -> u32 {
+        if self.mmu.pending_interrupts() == 0 {
+            return 0;
+        }
+        self.halted = false;
+        if !self.ime {
+            return 0;
+        }
+        self.ime = false;
+
+        // with EI right before a bugged HALT the return address is the HALT itself
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.reg.pc = self.reg.pc.wrapping_sub(1);
+        }
+
+        self.tick();
+        self.tick();
+
+        // the interrupt to service is only picked after the high byte of PC is pushed, so a
+        // push that lands on IE (SP=0x0000) can cancel the dispatch and jump to 0x0000 instead
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write_memory(self.reg.sp, (self.reg.pc >> 8) as Byte);
+        let interrupt = Interrupt::from_pending(self.mmu.pending_interrupts());
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write_memory(self.reg.sp, (self.reg.pc & 0xFF) as Byte);
+
+        self.reg.pc = match interrupt {
+            Some(interrupt) => {
+                self.mmu.acknowledge_interrupt(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        INTERRUPT_DISPATCH_CYCLES
+    }
+
+    fn fetch_byte(&mut self) -> Byte {
+        let b = self.read_memory(self.reg.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.reg.pc = self.reg.pc.wrapping_add(1);
+        }
+        b
+    }
+
+    // the gameboy is little endian, the low byte comes first
+    fn fetch_word(&mut self) -> Word {
+        let lo = self.fetch_byte() as Word;
+        let hi = self.fetch_byte() as Word;
+        (hi << 8) | lo
+    }
+
+    // every memory access takes one machine cycle, the access happens at the end of it
+    fn read_memory(&mut self, addr: Word) -> Byte {
+        self.tick();
+        self.mmu.read_byte(addr)
+    }
+
+    fn write_memory(&mut self, addr: Word, data: Byte) {
+        self.tick();
+        self.mmu.write_byte(addr, data)
+    }
 
+    fn get_r8(&mut self, index: Byte) -> Byte {
+        match index {
+            0 => self.reg.b,
+            1 => self.reg.c,
+            2 => self.reg.d,
+            3 => self.reg.e,
+            4 => self.reg.h,
+            5 => self.reg.l,
+            R8_HL => self.read_memory(self.reg.hl()),
+            _ => self.reg.a,
+        }
     }
 
-    fn read_memory(){
+    fn set_r8(&mut self, index: Byte, value: Byte) {
+        match index {
+            0 => self.reg.b = value,
+            1 => self.reg.c = value,
+            2 => self.reg.d = value,
+            3 => self.reg.e = value,
+            4 => self.reg.h = value,
+            5 => self.reg.l = value,
+            R8_HL => self.write_memory(self.reg.hl(), value),
+            _ => self.reg.a = value,
+        }
+    }
 
+    fn get_r16(&self, index: Byte) -> Word {
+        match index {
+            0 => self.reg.bc(),
+            1 => self.reg.de(),
+            2 => self.reg.hl(),
+            _ => self.reg.sp,
+        }
     }
 
-    fn write_memory(){
+    fn set_r16(&mut self, index: Byte, value: Word) {
+        match index {
+            0 => self.reg.setbc(value),
+            1 => self.reg.setde(value),
+            2 => self.reg.sethl(value),
+            _ => self.reg.sp = value,
+        }
+    }
 
+    fn condition(&self, index: Byte) -> bool {
+        match index {
+            0 => !self.reg.getflag(Z),
+            1 => self.reg.getflag(Z),
+            2 => !self.reg.getflag(C),
+            _ => self.reg.getflag(C),
+        }
     }
 
     // read about ref and & (https://doc.rust-lang.org/std/keyword.ref.html)
-    fn load_cmd(&mut self, opcode: &OpCode) -> u8{
-        match opcode{
-            //these implementations are probably quite wrong... I have to check them later
+    fn load_cmd(&mut self, opcode: Byte) {
+        match opcode {
             //8 bit loads
             //1. LD nn,n
-            0x06 => reg.b = self.fetch_byte(),
-            0x0E => reg.c = self.fetch_byte(),
-            0x16 => reg.d = self.fetch_byte(),
-            0x1E => reg.e = self.fetch_byte(),
-            0x26 => reg.h = self.fetch_byte(),
-            0x2E => reg.l = self.fetch_byte(),
-            //2. LD r1,r2
-            0x7F => reg.a = reg.a,
-            0x78 => reg.a = reg.b,
-            0x79 => reg.a = reg.c,
-            0x7A => reg.a = reg.d,
-            0x7B => reg.a = reg.e,
-            0x7C => reg.a = reg.h,
-            0x7D => reg.a = reg.l,
-            0x7E => reg.a = reg.hl,
-            0x40 => reg.b = reg.b,
-            0x41 => reg.b = reg.c,
-            0x42 => reg.b = reg.d,
-            0x43 => reg.b = reg.e,
-            0x44 => reg.b = reg.h,
-            0x45 => reg.b = reg.l,
-            0x46 => reg.b = reg.hl,
-            0x48 => reg.c = reg.b,
-            0x49 => reg.c = reg.c,
-            0x4A => reg.c = reg.d,
-            0x4B => reg.c = reg.e,
-            0x4C => reg.c = reg.h,
-            0x4D => reg.c = reg.l,
-            0x4E => reg.c = reg.hl,
-            0x50 => reg.d = reg.b,
-            0x51 => reg.d = reg.c,
-            0x52 => reg.d = reg.d,
-            0x53 => reg.d = reg.e,
-            0x54 => reg.d = reg.h,
-            0x55 => reg.d = reg.l,
-            0x56 => reg.d = reg.hl,
-            0x58 => reg.e = reg.b,
-            0x59 => reg.e = reg.c,
-            0x5A => reg.e = reg.d,
-            0x5B => reg.e = reg.e,
-            0x5C => reg.e = reg.h,
-            0x5D => reg.e = reg.l,
-            0x5E => reg.e = reg.hl,
-            0x60 => reg.h = reg.b,
-            0x61 => reg.h = reg.c,
-            0x62 => reg.h = reg.d,
-            0x63 => reg.h = reg.e,
-            0x64 => reg.h = reg.h,
-            0x65 => reg.h = reg.l,
-            0x66 => reg.h = reg.hl,
-            0x68 => reg.l = reg.b,
-            0x69 => reg.l = reg.c,
-            0x6A => reg.l = reg.d,
-            0x6B => reg.l = reg.e,
-            0x6C => reg.l = reg.h,
-            0x6D => reg.l = reg.l,
-            0x6E => reg.l = reg.hl,
-            0x70 => reg.hl = reg.b,
-            0x71 => reg.hl = reg.c,
-            0x72 => reg.hl = reg.d,
-            0x73 => reg.hl = reg.e,
-            0x74 => reg.hl = reg.h,
-            0x75 => reg.hl = reg.l,
-            0x36 => reg.hl = self.fetch_byte(),
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+                let value = self.fetch_byte();
+                self.set_r8((opcode >> 3) & 0x7, value);
+            }
+            //2. LD r1,r2. LD B,B does nothing, test ROMs and debuggers use it as a breakpoint
+            0x40..=0x7F => {
+                if opcode == LD_B_B {
+                    self.breakpoint = true;
+                    self.dump_trace = true;
+                }
+                let value = self.get_r8(opcode & 0x7);
+                self.set_r8((opcode >> 3) & 0x7, value);
+            }
             //3. LD A,n
-            0x0A => reg.a = reg.bc,
-            0x1A => reg.a = reg.de,
-            0x7E => reg.a = reg.hl,
-            0xFA => reg.a = self.fetch_word(),
-            0x3E => reg.a = self.fetch_byte(),
+            0x0A => self.reg.a = self.read_memory(self.reg.bc()),
+            0x1A => self.reg.a = self.read_memory(self.reg.de()),
+            0xFA => {
+                let addr = self.fetch_word();
+                self.reg.a = self.read_memory(addr);
+            }
             //4. LD n,A
-            0x4F => reg.a = reg.c,
-            0x5F => reg.a = reg.e,
-            0x6F => reg.a = reg.l,
-            0x02 => reg.a = reg.bc,
-            0x12 => reg.a = reg.de,
-            0x77 => reg.a = reg.hl,
-            0xEA => reg.a = self.fetch_word(),
+            0x02 => self.write_memory(self.reg.bc(), self.reg.a),
+            0x12 => self.write_memory(self.reg.de(), self.reg.a),
+            0xEA => {
+                let addr = self.fetch_word();
+                self.write_memory(addr, self.reg.a);
+            }
             //5. LD A,(C)
-            0xF2 => reg.a = self.read_memory(0xFF00 + reg.c),
+            0xF2 => self.reg.a = self.read_memory(0xFF00 | self.reg.c as Word),
             //6. LD (C),A
-            0xE2 => self.write_memory(0xFF00 + reg.c, reg.a)
+            0xE2 => self.write_memory(0xFF00 | self.reg.c as Word, self.reg.a),
             //7. LD A,(HLD)
             //8. LD A,(HL-)
             //9. LDD A,(HL)
             0x3A => {
-                reg.a = self.read_memory(reg.hl),
-                reg.hl = self.hl.wrapping_sub(1)
-            },
+                let addr = self.reg.hld();
+                self.reg.a = self.read_memory(addr);
+            }
             //10. LD (HLD),A
             //11. LD (HL-),A
             //12. LDD (HL),A
             0x32 => {
-                reg.hl = self.read_memory(reg.a),
-                reg.hl = self.hl.wrapping_sub(1)
-
-            },
+                let addr = self.reg.hld();
+                self.write_memory(addr, self.reg.a);
+            }
             //13. LD A,(HLI)
             //14. LD A,(HL+)
-            //15. LD A,(HL-)
+            //15. LDI A,(HL)
             0x2A => {
-                reg.a = self.read_memory(reg.hl),
-                reg.hl = self.hl.wrapping_add(1)
-            },
+                let addr = self.reg.hli();
+                self.reg.a = self.read_memory(addr);
+            }
             //16. LD (HLI),A
             //17. LD (HL+),A
             //18. LDI (HL),A
             0x22 => {
-                reg.hl = self.read_memory(reg.a),
-                reg.hl = self.hl.wrapping_add(1)
-            },
+                let addr = self.reg.hli();
+                self.write_memory(addr, self.reg.a);
+            }
             //19. LDH (n),A
-            0xE0 => self.write_memory(0xFF00 + self.fetch_byte()) = reg.a
+            0xE0 => {
+                let addr = 0xFF00 | self.fetch_byte() as Word;
+                self.write_memory(addr, self.reg.a);
+            }
             //20. LDH A,(n)
-            0xF0 => reg.a = self.read_memory(0xFF00 + self.fetch_byte())
+            0xF0 => {
+                let addr = 0xFF00 | self.fetch_byte() as Word;
+                self.reg.a = self.read_memory(addr);
+            }
             //16 bit Loads
             //1. LD n,nn
-            0x01 => reg.setbc = self.fetch_word(),
-            0x11 => reg.setde = self.fetch_word(),
-            0x21 => reg.sethl = self.fetch_word(),
-            0x31 => reg.sp = self.fetch_word(),
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let value = self.fetch_word();
+                self.set_r16(opcode >> 4, value);
+            }
             //2. LD SP,HL
-            0xF9 => reg.sp = reg.hl
+            0xF9 => self.reg.sp = self.reg.hl(),
             //These are way complex than I initially thought...
             //see Line 1097: https://github.com/kbernst30/rusty-boy/blob/main/src/cpu.rs
             //3. LD HL,SP+n
-            //4. LDHLD SP,n
-            0xF8 => reg.hl = reg.sp + self.fetch_byte();
+            //4. LDHL SP,n
+            0xF8 => {
+                let value = self.add_sp_cmd();
+                self.reg.sethl(value);
+            }
             //5. LD (nn),SP
-            0x08 => self.write_memory(self.fetch_word()) = reg.sp
+            0x08 => {
+                let addr = self.fetch_word();
+                self.write_memory(addr, (self.reg.sp & 0xFF) as Byte);
+                self.write_memory(addr.wrapping_add(1), (self.reg.sp >> 8) as Byte);
+            }
             //6. PUSH nn
-            0xF5 => self.push_cmd(reg.af),
-            0xC5 => self.push_cmd(reg.bc),
-            0xD5 => self.push_cmd(reg.de),
-            0xE5 => self.push_cmd(reg.hl),
+            0xF5 => self.push_cmd(self.reg.af()),
+            0xC5 => self.push_cmd(self.reg.bc()),
+            0xD5 => self.push_cmd(self.reg.de()),
+            0xE5 => self.push_cmd(self.reg.hl()),
             //7. POP nn
-            0xF1 => self.pop_cmd(reg.af),
-            0xC1 => self.pop_cmd(reg.bc),
-            0xD1 => self.pop_cmd(reg.de),
-            0xE1 => self.pop_cmd(reg.hl),
-            //8 bit ALU
-
-            
-
+            0xF1 => {
+                let value = self.pop_cmd();
+                self.reg.setaf(value);
+            }
+            0xC1 | 0xD1 | 0xE1 => {
+                let value = self.pop_cmd();
+                self.set_r16((opcode >> 4) & 0x3, value);
+            }
+            _ => unreachable!("opcode {:02X} is not a load", opcode),
         }
+    }
 
+    // SP is decremented on an internal cycle before the two writes
+    fn push_cmd(&mut self, value: Word) {
+        self.tick();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write_memory(self.reg.sp, (value >> 8) as Byte);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write_memory(self.reg.sp, (value & 0xFF) as Byte);
     }
 
+    fn pop_cmd(&mut self) -> Word {
+        let lo = self.read_memory(self.reg.sp) as Word;
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        let hi = self.read_memory(self.reg.sp) as Word;
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        (hi << 8) | lo
+    }
 
-    fn push_cmd(){
+    // SP plus a signed immediate, the flags come from the unsigned addition of the low byte
+    fn add_sp_cmd(&mut self) -> Word {
+        let b = self.fetch_byte() as i8 as i16 as Word;
+        let sp = self.reg.sp;
+        self.reg.flag(Z, false);
+        self.reg.flag(N, false);
+        self.reg.flag(H, (sp & 0x000F) + (b & 0x000F) > 0x000F);
+        self.reg.flag(C, (sp & 0x00FF) + (b & 0x00FF) > 0x00FF);
+        sp.wrapping_add(b)
+    }
 
+    // the operation is selected by bits 3-5 of the opcode, the same order is used by the
+    // register (0x80-0xBF) and the immediate (0xC6, 0xCE, ... 0xFE) forms
+    fn alu_cmd(&mut self, operation: Byte, value: Byte) {
+        match operation {
+            0 => self.add_cmd(value, false),
+            1 => self.adc_cmd(value),
+            2 => self.sub_cmd(value, false),
+            3 => self.sbc_cmd(value),
+            4 => self.and_cmd(value),
+            5 => self.xor_cmd(value),
+            6 => self.or_cmd(value),
+            _ => self.cp_cmd(value),
+        }
     }
 
-    fn pop_cmd(){
+    fn add_cmd(&mut self, b: Byte, usec: bool) {
+        let c = if usec && self.reg.getflag(C) { 1 } else { 0 };
+        let a = self.reg.a;
+        let r = a.wrapping_add(b).wrapping_add(c);
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(H, (a & 0xF) + (b & 0xF) + c > 0xF);
+        self.reg.flag(N, false);
+        self.reg
+            .flag(C, (a as u16) + (b as u16) + (c as u16) > 0xFF);
+        self.reg.a = r;
+    }
 
+    fn adc_cmd(&mut self, b: Byte) {
+        self.add_cmd(b, true);
     }
 
-    fn add_cmd(&mut self, b:u8, usec:bool){
-        let c;
-        if usec && self.reg.getflag(C){
-            let c = 1;
-        }else{
-            let c = 0
-        }
+    fn sub_cmd(&mut self, b: Byte, usec: bool) {
+        let c = if usec && self.reg.getflag(C) { 1 } else { 0 };
         let a = self.reg.a;
-        let r = a.wrapping_add(b).wrapping_add(c);
-        self.reg.flag(Z, r == 0)
-        self.reg.flag(H, (a & 0xF) + (b & 0xF) + c > 0xF)
-        self.reg.flag(N, false)
-        self.reg.flag(C, (a as u16) + (b as u16) + (c as u16) > 0xFF)
+        let r = a.wrapping_sub(b).wrapping_sub(c);
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(H, (a & 0xF) < (b & 0xF) + c);
+        self.reg.flag(N, true);
+        self.reg.flag(C, (a as u16) < (b as u16) + (c as u16));
         self.reg.a = r;
     }
 
-    fn adc_cmd(){
+    fn sbc_cmd(&mut self, b: Byte) {
+        self.sub_cmd(b, true);
+    }
 
+    fn and_cmd(&mut self, b: Byte) {
+        let r = self.reg.a & b;
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(H, true);
+        self.reg.flag(C, false);
+        self.reg.flag(N, false);
+        self.reg.a = r;
     }
 
-    fn sub_cmd(){
+    fn or_cmd(&mut self, b: Byte) {
+        let r = self.reg.a | b;
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(C, false);
+        self.reg.flag(H, false);
+        self.reg.flag(N, false);
+        self.reg.a = r;
+    }
 
+    fn xor_cmd(&mut self, b: Byte) {
+        let r = self.reg.a ^ b;
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(C, false);
+        self.reg.flag(H, false);
+        self.reg.flag(N, false);
+        self.reg.a = r;
     }
 
-    fn sbc_cmd(){
+    // a subtraction that throws the result away and keeps the flags
+    fn cp_cmd(&mut self, b: Byte) {
+        let r = self.reg.a;
+        self.sub_cmd(b, false);
+        self.reg.a = r;
+    }
 
+    // the carry flag is left untouched by INC and DEC
+    fn inc_cmd(&mut self, a: Byte) -> Byte {
+        let r = a.wrapping_add(1);
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(H, (a & 0x0F) + 1 > 0x0F);
+        self.reg.flag(N, false);
+        r
     }
 
-    fn and_cmd(){
+    fn dec_cmd(&mut self, a: Byte) -> Byte {
+        let r = a.wrapping_sub(1);
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(H, (a & 0x0F) == 0);
+        self.reg.flag(N, true);
+        r
+    }
+
+    // ADD HL,rr leaves Z alone, H and C come from bits 11 and 15
+    fn add_hl_cmd(&mut self, b: Word) {
+        let a = self.reg.hl();
+        let r = a.wrapping_add(b);
+        self.reg.flag(H, (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF);
+        self.reg.flag(N, false);
+        self.reg.flag(C, a > 0xFFFF - b);
+        self.reg.sethl(r);
+    }
+
+    // adjusts A back into binary coded decimal after an addition or subtraction
+    // see https://ehaskins.com/2018-01-30%20Z80%20DAA/
+    fn daa_cmd(&mut self) {
+        let mut a = self.reg.a;
+        let mut adjust = if self.reg.getflag(C) { 0x60 } else { 0x00 };
+        if self.reg.getflag(H) {
+            adjust |= 0x06;
+        }
+        if !self.reg.getflag(N) {
+            if a & 0x0F > 0x09 {
+                adjust |= 0x06;
+            }
+            if a > 0x99 {
+                adjust |= 0x60;
+            }
+            a = a.wrapping_add(adjust);
+        } else {
+            a = a.wrapping_sub(adjust);
+        }
+
+        self.reg.flag(C, adjust >= 0x60);
+        self.reg.flag(H, false);
+        self.reg.flag(Z, a == 0);
+        self.reg.a = a;
+    }
 
+    fn swap_cmd(&mut self, a: Byte) -> Byte {
+        self.reg.flag(Z, a == 0);
+        self.reg.flag(C, false);
+        self.reg.flag(H, false);
+        self.reg.flag(N, false);
+        a.rotate_left(4)
     }
 
-    fn or_cmd(){
+    // every rotate and shift sets the flags the same way, the carry gets the bit shifted out
+    fn shift_flags(&mut self, r: Byte, carry: bool) -> Byte {
+        self.reg.flag(Z, r == 0);
+        self.reg.flag(C, carry);
+        self.reg.flag(H, false);
+        self.reg.flag(N, false);
+        r
+    }
 
+    fn rlc_cmd(&mut self, a: Byte) -> Byte {
+        self.shift_flags(a.rotate_left(1), a & 0x80 != 0)
     }
 
-    fn xor_cmd(){
+    fn rl_cmd(&mut self, a: Byte) -> Byte {
+        let c = self.reg.getflag(C) as Byte;
+        self.shift_flags((a << 1) | c, a & 0x80 != 0)
+    }
 
+    fn rrc_cmd(&mut self, a: Byte) -> Byte {
+        self.shift_flags(a.rotate_right(1), a & 0x01 != 0)
     }
 
-    fn cp_cmd(){
+    fn rr_cmd(&mut self, a: Byte) -> Byte {
+        let c = self.reg.getflag(C) as Byte;
+        self.shift_flags((a >> 1) | (c << 7), a & 0x01 != 0)
+    }
 
+    fn sla_cmd(&mut self, a: Byte) -> Byte {
+        self.shift_flags(a << 1, a & 0x80 != 0)
     }
 
-    fn inc_cmd(){
+    // arithmetic shift, bit 7 keeps its value
+    fn sra_cmd(&mut self, a: Byte) -> Byte {
+        self.shift_flags((a >> 1) | (a & 0x80), a & 0x01 != 0)
+    }
 
+    fn srl_cmd(&mut self, a: Byte) -> Byte {
+        self.shift_flags(a >> 1, a & 0x01 != 0)
     }
 
-    fn dec_cmd(){
+    fn bit_cmd(&mut self, bit: Byte, a: Byte) {
+        self.reg.flag(Z, !bit_set(&a, bit));
+        self.reg.flag(H, true);
+        self.reg.flag(N, false);
+    }
 
+    // JR e, the offset is signed and relative to the address after the instruction
+    fn jr_cmd(&mut self, condition: bool) -> u32 {
+        let offset = self.fetch_byte() as i8;
+        if condition {
+            self.reg.pc = self.reg.pc.wrapping_add(offset as Word);
+            JR_TAKEN_CYCLES
+        } else {
+            0
+        }
     }
 
-    fn swap_cmd(){
+    fn jump_cmd(&mut self, condition: bool) -> u32 {
+        let addr = self.fetch_word();
+        if condition {
+            self.reg.pc = addr;
+            JP_TAKEN_CYCLES
+        } else {
+            0
+        }
+    }
 
+    fn call_cmd(&mut self, condition: bool) -> u32 {
+        let addr = self.fetch_word();
+        if condition {
+            self.push_cmd(self.reg.pc);
+            self.reg.pc = addr;
+            CALL_TAKEN_CYCLES
+        } else {
+            0
+        }
     }
 
-    fn jump_cmd(){
+    fn ret_cmd(&mut self, condition: bool) -> u32 {
+        if condition {
+            self.reg.pc = self.pop_cmd();
+            RET_TAKEN_CYCLES
+        } else {
+            0
+        }
+    }
 
+    fn rst_cmd(&mut self, vector: Word) {
+        self.push_cmd(self.reg.pc);
+        self.reg.pc = vector;
     }
 
+    // decodes and executes a single instruction, returns the machine cycles it took
     fn call(&mut self) -> u32 {
-        let opcode = self.fetchbyte();
-        match opcode{
-            0x76 =>{
-                self.halted = true; 1   
+        let opcode = self.fetch_byte();
+        if opcode == 0xCB {
+            let cb_opcode = self.fetch_byte();
+            self.cb_call(cb_opcode);
+            return CB_OPCODE_CYCLES[cb_opcode as usize];
+        }
+
+        let extra_cycles = match opcode {
+            //NOP
+            0x00 => 0,
+            //STOP 0, the second byte is skipped. on the CGB it switches the speed when KEY1 asks for it
+            0x10 => {
+                self.fetch_byte();
+                self.mmu.switch_speed();
+                0
+            }
+            //HALT, skipped when an interrupt is already pending but can't be serviced
+            0x76 => {
+                if !self.ime && self.mmu.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+                0
+            }
+            //all the loads, 8 and 16 bit
+            0x01
+            | 0x02
+            | 0x06
+            | 0x08
+            | 0x0A
+            | 0x0E
+            | 0x11
+            | 0x12
+            | 0x16
+            | 0x1A
+            | 0x1E
+            | 0x21
+            | 0x22
+            | 0x26
+            | 0x2A
+            | 0x2E
+            | 0x31
+            | 0x32
+            | 0x36
+            | 0x3A
+            | 0x3E
+            | 0x40..=0x7F
+            | 0xC1
+            | 0xC5
+            | 0xD1
+            | 0xD5
+            | 0xE0
+            | 0xE1
+            | 0xE2
+            | 0xE5
+            | 0xEA
+            | 0xF0
+            | 0xF1
+            | 0xF2
+            | 0xF5
+            | 0xF8
+            | 0xF9
+            | 0xFA => {
+                self.load_cmd(opcode);
+                0
+            }
+            //8 bit ALU
+            //1-8. ADD, ADC, SUB, SBC, AND, XOR, OR, CP A,r
+            0x80..=0xBF => {
+                let value = self.get_r8(opcode & 0x7);
+                self.alu_cmd((opcode >> 3) & 0x7, value);
+                0
+            }
+            //1-8. ADD, ADC, SUB, SBC, AND, XOR, OR, CP A,n
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                let value = self.fetch_byte();
+                self.alu_cmd((opcode >> 3) & 0x7, value);
+                0
+            }
+            //9. INC n
+            0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+                let index = (opcode >> 3) & 0x7;
+                let value = self.get_r8(index);
+                let value = self.inc_cmd(value);
+                self.set_r8(index, value);
+                0
+            }
+            //10. DEC n
+            0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+                let index = (opcode >> 3) & 0x7;
+                let value = self.get_r8(index);
+                let value = self.dec_cmd(value);
+                self.set_r8(index, value);
+                0
+            }
+            //16 bit ALU
+            //1. ADD HL,n
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                self.add_hl_cmd(self.get_r16(opcode >> 4));
+                0
+            }
+            //2. ADD SP,n
+            0xE8 => {
+                self.reg.sp = self.add_sp_cmd();
+                0
+            }
+            //3. INC nn
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                let index = opcode >> 4;
+                self.set_r16(index, self.get_r16(index).wrapping_add(1));
+                0
+            }
+            //4. DEC nn
+            0x0B | 0x1B | 0x2B | 0x3B => {
+                let index = opcode >> 4;
+                self.set_r16(index, self.get_r16(index).wrapping_sub(1));
+                0
             }
+            //Miscellaneous
+            //DAA
+            0x27 => {
+                self.daa_cmd();
+                0
+            }
+            //CPL
+            0x2F => {
+                self.reg.a = !self.reg.a;
+                self.reg.flag(H, true);
+                self.reg.flag(N, true);
+                0
+            }
+            //CCF
+            0x3F => {
+                let c = self.reg.getflag(C);
+                self.reg.flag(C, !c);
+                self.reg.flag(H, false);
+                self.reg.flag(N, false);
+                0
+            }
+            //SCF
+            0x37 => {
+                self.reg.flag(C, true);
+                self.reg.flag(H, false);
+                self.reg.flag(N, false);
+                0
+            }
+            //DI, takes effect immediately and cancels a pending EI
+            0xF3 => {
+                self.ime = false;
+                self.setei = 0;
+                0
+            }
+            //EI, takes effect after the next instruction
+            0xFB => {
+                if !self.ime && self.setei == 0 {
+                    self.setei = 2;
+                }
+                0
+            }
+            //Rotates, unlike their 0xCB counterparts these always reset Z
+            //RLCA
+            0x07 => {
+                self.reg.a = self.rlc_cmd(self.reg.a);
+                self.reg.flag(Z, false);
+                0
+            }
+            //RLA
+            0x17 => {
+                self.reg.a = self.rl_cmd(self.reg.a);
+                self.reg.flag(Z, false);
+                0
+            }
+            //RRCA
+            0x0F => {
+                self.reg.a = self.rrc_cmd(self.reg.a);
+                self.reg.flag(Z, false);
+                0
+            }
+            //RRA
+            0x1F => {
+                self.reg.a = self.rr_cmd(self.reg.a);
+                self.reg.flag(Z, false);
+                0
+            }
+            //Jumps
+            //1. JP nn
+            0xC3 => {
+                self.jump_cmd(true);
+                0
+            }
+            //2. JP cc,nn
+            0xC2 | 0xCA | 0xD2 | 0xDA => self.jump_cmd(self.condition((opcode >> 3) & 0x3)),
+            //3. JP (HL)
+            0xE9 => {
+                self.reg.pc = self.reg.hl();
+                0
+            }
+            //4. JR n
+            0x18 => {
+                self.jr_cmd(true);
+                0
+            }
+            //5. JR cc,n
+            0x20 | 0x28 | 0x30 | 0x38 => self.jr_cmd(self.condition((opcode >> 3) & 0x3)),
+            //Calls
+            //1. CALL nn
+            0xCD => {
+                self.call_cmd(true);
+                0
+            }
+            //2. CALL cc,nn
+            0xC4 | 0xCC | 0xD4 | 0xDC => self.call_cmd(self.condition((opcode >> 3) & 0x3)),
+            //Restarts
+            //1. RST n
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                self.rst_cmd((opcode & 0x38) as Word);
+                0
+            }
+            //Returns
+            //1. RET
+            0xC9 => {
+                self.ret_cmd(true);
+                0
+            }
+            //2. RET cc
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => {
+                // evaluating the condition takes an internal cycle before popping
+                self.tick();
+                self.ret_cmd(self.condition((opcode >> 3) & 0x3))
+            }
+            //3. RETI, unlike EI there is no delay
+            0xD9 => {
+                self.ret_cmd(true);
+                self.ime = true;
+                self.setei = 0;
+                0
+            }
+            //illegal opcodes hang the cpu
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                self.locked = true;
+                0
+            }
+            0xCB => unreachable!(),
+        };
+
+        OPCODE_CYCLES[opcode as usize] + extra_cycles
+    }
+
+    // the 0xCB prefixed opcodes are fully regular: bits 0-2 select the register,
+    // bits 3-5 the operation (or the bit number) and bits 6-7 the group
+    fn cb_call(&mut self, opcode: Byte) {
+        let index = opcode & 0x7;
+        let bit = (opcode >> 3) & 0x7;
+        let value = self.get_r8(index);
+
+        match opcode >> 6 {
+            //Rotates & Shifts
+            0 => {
+                let r = match bit {
+                    0 => self.rlc_cmd(value),
+                    1 => self.rrc_cmd(value),
+                    2 => self.rl_cmd(value),
+                    3 => self.rr_cmd(value),
+                    4 => self.sla_cmd(value),
+                    5 => self.sra_cmd(value),
+                    6 => self.swap_cmd(value),
+                    _ => self.srl_cmd(value),
+                };
+                self.set_r8(index, r);
+            }
+            //BIT b,r
+            1 => self.bit_cmd(bit, value),
+            //RES b,r
+            2 => self.set_r8(index, value & !(1 << bit)),
+            //SET b,r
+            _ => self.set_r8(index, value | (1 << bit)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::test_rom;
+
+    // runs code from WRAM, the cartridge is not involved
+    fn cpu(code: &[Byte]) -> Cpu {
+        let mut cpu = Cpu::new(Mmu::new(test_rom(0x00, 0x00, 0x00)).unwrap());
+        for (i, &byte) in code.iter().enumerate() {
+            cpu.mmu.write_byte(0xC000 + i as Word, byte);
         }
+        cpu.reg.pc = 0xC000;
+        cpu
+    }
+
+    // runs count instructions and returns the clock cycles of the last one
+    fn run(cpu: &mut Cpu, count: usize) -> u32 {
+        (0..count).map(|_| cpu.do_cycle()).last().unwrap_or(0)
+    }
+
+    // Z N H C
+    fn flags(cpu: &Cpu) -> [bool; 4] {
+        [Z, N, H, C].map(|flag| cpu.reg.getflag(flag))
+    }
+
+    #[test]
+    fn daa_after_addition() {
+        // LD A,15 / ADD A,27 / DAA
+        let mut cpu = cpu(&[0x3E, 0x15, 0xC6, 0x27, 0x27]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.reg.a, 0x3C);
+        assert_eq!(run(&mut cpu, 1), 4);
+        assert_eq!(cpu.reg.a, 0x42);
+        assert_eq!(flags(&cpu), [false, false, false, false]);
+
+        // LD A,99 / ADD A,01 / DAA wraps to 00 with the carry
+        let mut cpu = self::cpu(&[0x3E, 0x99, 0xC6, 0x01, 0x27]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.reg.a, 0x00);
+        assert_eq!(flags(&cpu), [true, false, false, true]);
+    }
+
+    #[test]
+    fn daa_after_subtraction() {
+        // LD A,42 / SUB 15 / DAA
+        let mut cpu = cpu(&[0x3E, 0x42, 0xD6, 0x15, 0x27]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.reg.a, 0x2D);
+        assert_eq!(flags(&cpu), [false, true, true, false]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.a, 0x27);
+        assert_eq!(flags(&cpu), [false, true, false, false]);
+    }
+
+    #[test]
+    fn add_sp_e() {
+        // LD SP,FFF8 / ADD SP,08 / ADD SP,-1
+        let mut cpu = cpu(&[0x31, 0xF8, 0xFF, 0xE8, 0x08, 0xE8, 0xFF]);
+        assert_eq!(run(&mut cpu, 1), 12);
+        assert_eq!(run(&mut cpu, 1), 16);
+        assert_eq!(cpu.reg.sp, 0x0000);
+        assert_eq!(flags(&cpu), [false, false, true, true]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.sp, 0xFFFF);
+        assert_eq!(flags(&cpu), [false, false, false, false]);
+    }
+
+    #[test]
+    fn ld_hl_sp_e() {
+        // LD SP,00FF / LD HL,SP+1 / LD HL,SP-2
+        let mut cpu = cpu(&[0x31, 0xFF, 0x00, 0xF8, 0x01, 0xF8, 0xFE]);
+        run(&mut cpu, 1);
+        assert_eq!(run(&mut cpu, 1), 12);
+        assert_eq!(cpu.reg.hl(), 0x0100);
+        assert_eq!(cpu.reg.sp, 0x00FF);
+        assert_eq!(flags(&cpu), [false, false, true, true]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.hl(), 0x00FD);
+        assert_eq!(flags(&cpu), [false, false, true, true]);
+    }
+
+    #[test]
+    fn adc_half_carry() {
+        // LD A,0F / SCF / ADC A,00: the carry alone brings the low nibble over
+        let mut cpu = cpu(&[0x3E, 0x0F, 0x37, 0xCE, 0x00]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.reg.a, 0x10);
+        assert_eq!(flags(&cpu), [false, false, true, false]);
+
+        // LD A,FF / SCF / ADC A,00
+        let mut cpu = self::cpu(&[0x3E, 0xFF, 0x37, 0xCE, 0x00]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.reg.a, 0x00);
+        assert_eq!(flags(&cpu), [true, false, true, true]);
+    }
+
+    #[test]
+    fn sbc_half_carry() {
+        // LD A,10 / SCF / SBC A,00: the borrow alone comes from bit 4
+        let mut cpu = cpu(&[0x3E, 0x10, 0x37, 0xDE, 0x00]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.reg.a, 0x0F);
+        assert_eq!(flags(&cpu), [false, true, true, false]);
+
+        // LD A,00 / SCF / SBC A,00
+        let mut cpu = self::cpu(&[0x3E, 0x00, 0x37, 0xDE, 0x00]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.reg.a, 0xFF);
+        assert_eq!(flags(&cpu), [false, true, true, true]);
+    }
+
+    // runs the branch at C002 after XOR A (Z set) or OR 1 (Z clear), returns its clock
+    // cycles and where it went
+    fn branch(taken: bool, code: &[Byte]) -> (u32, Word) {
+        // XOR A is a single byte, a NOP pads it
+        let setup: [Byte; 2] = if taken { [0xF6, 0x01] } else { [0xAF, 0x00] };
+        let mut program = setup.to_vec();
+        program.extend_from_slice(code);
+        let mut cpu = cpu(&program);
+        cpu.reg.sp = 0xD000;
+        // RET pops C100
+        cpu.mmu.write_byte(0xD000, 0x00);
+        cpu.mmu.write_byte(0xD001, 0xC1);
+        run(&mut cpu, if taken { 1 } else { 2 });
+        let cycles = run(&mut cpu, 1);
+        (cycles, cpu.reg.pc)
+    }
+
+    #[test]
+    fn conditional_branch_cycles() {
+        // JR NZ,+5
+        assert_eq!(branch(false, &[0x20, 0x05]), (8, 0xC004));
+        assert_eq!(branch(true, &[0x20, 0x05]), (12, 0xC009));
+        // JP NZ,C123
+        assert_eq!(branch(false, &[0xC2, 0x23, 0xC1]), (12, 0xC005));
+        assert_eq!(branch(true, &[0xC2, 0x23, 0xC1]), (16, 0xC123));
+        // CALL NZ,C123
+        assert_eq!(branch(false, &[0xC4, 0x23, 0xC1]), (12, 0xC005));
+        assert_eq!(branch(true, &[0xC4, 0x23, 0xC1]), (24, 0xC123));
+        // RET NZ
+        assert_eq!(branch(false, &[0xC0]), (8, 0xC003));
+        assert_eq!(branch(true, &[0xC0]), (20, 0xC100));
+    }
+
+    #[test]
+    fn call_pushes_the_return_address() {
+        // LD SP,D000 / CALL C010, RET at C010
+        let mut cpu = cpu(&[0x31, 0x00, 0xD0, 0xCD, 0x10, 0xC0]);
+        cpu.mmu.write_byte(0xC010, 0xC9);
+        run(&mut cpu, 1);
+        assert_eq!(run(&mut cpu, 1), 24);
+        assert_eq!(cpu.reg.sp, 0xCFFE);
+        assert_eq!(cpu.mmu.read_byte(0xCFFE), 0x06);
+        assert_eq!(cpu.mmu.read_byte(0xCFFF), 0xC0);
+        assert_eq!(run(&mut cpu, 1), 16);
+        assert_eq!((cpu.reg.pc, cpu.reg.sp), (0xC006, 0xD000));
+    }
+
+    #[test]
+    fn cb_prefixed() {
+        // LD HL,C100 / SWAP A / BIT 0,(HL) / SET 0,(HL) / BIT 0,(HL) / RL C
+        let mut cpu = cpu(&[
+            0x21, 0x00, 0xC1, 0xCB, 0x37, 0xCB, 0x46, 0xCB, 0xC6, 0xCB, 0x46, 0xCB, 0x11,
+        ]);
+        cpu.reg.a = 0x12;
+        run(&mut cpu, 1);
+        assert_eq!(run(&mut cpu, 1), 8);
+        assert_eq!(cpu.reg.a, 0x21);
+        assert_eq!(flags(&cpu), [false, false, false, false]);
+        assert_eq!(run(&mut cpu, 1), 12);
+        assert_eq!(flags(&cpu), [true, false, true, false]);
+        assert_eq!(run(&mut cpu, 1), 16);
+        assert_eq!(cpu.mmu.read_byte(0xC100), 0x01);
+        run(&mut cpu, 1);
+        assert_eq!(flags(&cpu), [false, false, true, false]);
+        // C is 13 and the carry clear
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.c, 0x26);
+        assert_eq!(flags(&cpu), [false, false, false, false]);
+    }
+
+    #[test]
+    fn add_hl_keeps_z() {
+        // LD HL,0FFF / LD BC,0001 / ADD HL,BC, Z stays set from the boot state
+        let mut cpu = cpu(&[0x21, 0xFF, 0x0F, 0x01, 0x01, 0x00, 0x09]);
+        run(&mut cpu, 2);
+        assert_eq!(run(&mut cpu, 1), 8);
+        assert_eq!(cpu.reg.hl(), 0x1000);
+        assert_eq!(flags(&cpu), [true, false, true, false]);
+    }
+
+    // the code with SP at D000 and IE / IF set, IME is left clear
+    fn with_interrupts(code: &[Byte], enabled: Byte, requested: Byte) -> Cpu {
+        let mut cpu = cpu(code);
+        cpu.reg.sp = 0xD000;
+        cpu.mmu.write_byte(INTERRUPT_ENABLE_ADDR, enabled);
+        cpu.mmu.write_byte(INTERRUPT_FLAG_ADDR, requested);
+        cpu
     }
-}
\ No newline at end of file
+
+    fn return_address(cpu: &Cpu) -> Word {
+        cpu.mmu.read_word(cpu.reg.sp)
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI / NOP / NOP with the timer interrupt pending
+        let mut cpu = with_interrupts(&[0xFB, 0x00, 0x00], 0x04, 0x04);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.reg.pc, 0xC002);
+        assert_eq!(run(&mut cpu, 1), 20);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(return_address(&cpu), 0xC002);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn di_cancels_a_pending_ei() {
+        // EI / DI / NOP / NOP
+        let mut cpu = with_interrupts(&[0xFB, 0xF3, 0x00, 0x00], 0x04, 0x04);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.reg.pc, 0xC004);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_right_away() {
+        // RETI back to C001, a NOP
+        let mut cpu = with_interrupts(&[0xD9, 0x00], 0x04, 0x04);
+        cpu.reg.sp = 0xCFFE;
+        cpu.mmu.write_word(0xCFFE, 0xC001);
+        assert_eq!(run(&mut cpu, 1), 16);
+        assert_eq!(cpu.reg.pc, 0xC001);
+        assert!(cpu.ime);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(return_address(&cpu), 0xC001);
+    }
+
+    #[test]
+    fn dispatch_priority_and_cycles() {
+        // timer and joypad requested, the timer goes first
+        let mut cpu = with_interrupts(&[0x00], 0x1F, 0x14);
+        cpu.ime = true;
+        assert_eq!(run(&mut cpu, 1), 20);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(cpu.reg.sp, 0xCFFE);
+        assert_eq!(return_address(&cpu), 0xC000);
+        // only its bit of IF is cleared
+        assert_eq!(cpu.mmu.read_byte(INTERRUPT_FLAG_ADDR), 0xF0);
+
+        // only the enabled ones count
+        let mut cpu = with_interrupts(&[0x00], 0x10, 0x14);
+        cpu.ime = true;
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.pc, 0x0060);
+        assert_eq!(cpu.mmu.read_byte(INTERRUPT_FLAG_ADDR), 0xE4);
+    }
+
+    #[test]
+    fn halt_wakes_up_with_ime_clear() {
+        // HALT / NOP
+        let mut cpu = with_interrupts(&[0x76, 0x00], 0x04, 0x00);
+        run(&mut cpu, 1);
+        assert!(cpu.halted);
+        assert_eq!(run(&mut cpu, 1), 4);
+        assert!(cpu.halted);
+        assert_eq!(cpu.reg.pc, 0xC001);
+
+        // the interrupt wakes the cpu up but is not serviced
+        cpu.mmu.write_byte(INTERRUPT_FLAG_ADDR, 0x04);
+        run(&mut cpu, 1);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0xC002);
+        assert_eq!(cpu.mmu.read_byte(INTERRUPT_FLAG_ADDR), 0xE4);
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_byte_twice() {
+        // HALT / INC A / NOP with IME clear and an interrupt already pending
+        let mut cpu = with_interrupts(&[0x76, 0x3C, 0x00], 0x04, 0x04);
+        cpu.reg.a = 0x00;
+        run(&mut cpu, 1);
+        assert!(!cpu.halted);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x01, 0xC001));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x02, 0xC002));
+    }
+
+    #[test]
+    fn ei_then_halt() {
+        // EI / HALT / NOP, the interrupt comes while halted and returns after the HALT
+        let mut cpu = with_interrupts(&[0xFB, 0x76, 0x00], 0x04, 0x00);
+        run(&mut cpu, 3);
+        assert!(cpu.halted);
+        cpu.mmu.write_byte(INTERRUPT_FLAG_ADDR, 0x04);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(return_address(&cpu), 0xC002);
+
+        // already pending: the HALT is skipped and the handler returns to the HALT itself
+        let mut cpu = with_interrupts(&[0xFB, 0x76, 0x00], 0x04, 0x04);
+        run(&mut cpu, 2);
+        assert!(!cpu.halted);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(return_address(&cpu), 0xC001);
+    }
+}
@@ -0,0 +1,863 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use crate::disasm::{disassemble, disassemble_rom, disassemble_rom_bank, listing_lines};
+use crate::gameboy::GameBoy;
+use crate::register::CpuFlags;
+use crate::trace::Tracer;
+use crate::utils::*;
+use crate::watchpoint::*;
+
+/*
+ * A command-line debugger, one command per line so a script can be piped in as well:
+ *
+ *    break LOC                  b      stop when PC gets to LOC: ADDR, BANK:ADDR or a LABEL
+ *    delete [N]                 d      deletes breakpoint N, or all of them
+ *    breakpoints                bl     lists the breakpoints
+ *    watch KIND RANGE [BYTE]    wa     stops after an instruction that reads (KIND read),
+ *                                      writes (write) or does either (access) in RANGE:
+ *                                      LOC or LOC-ADDR, BYTE limits a write to that value
+ *    unwatch [N]                       deletes watchpoint N, or all of them
+ *    watchpoints                wl     lists the watchpoints
+ *    step [N]                   s      runs N instructions, 1 by default
+ *    next                       n      like step, but runs a CALL or RST until it returns
+ *    finish                     fin    runs until the current function returns
+ *    backtrace                  bt     the return addresses on the stack, innermost first
+ *    continue [N]               c      runs until a breakpoint, or for at most N instructions
+ *    registers                  r      prints the registers and the Z/N/H/C flags
+ *    examine ADDR [LEN]         x      hexdump of LEN bytes (64 by default) from ADDR
+ *    write ADDR BYTE...         w      writes the bytes from ADDR on, through the mmu
+ *    write REG VALUE            w      sets a register: A-L, AF, BC, DE, HL, SP or PC
+ *    disassemble [LOC] [N]      dis    N instructions (8 by default) from PC or LOC, a
+ *                                      BANK:ADDR or LABEL is read from the ROM
+ *    disassemble-bank BANK FILE        writes the listing of a whole ROM bank to FILE
+ *    trace FILE [OPTION...]            traces the instructions to FILE as gameboy-doctor
+ *                                      logs them, the options are pc START-END, bank BANK,
+ *                                      ring N to only keep the last N instructions and
+ *                                      doctor to make LY read 90 until the trace stops
+ *    trace dump                        writes out what the ring holds
+ *    trace off                         stops tracing, after writing out the ring
+ *    help                       h
+ *    quit                       q
+ *
+ * Addresses, banks and values are hexadecimal, with or without a $ or 0x prefix. Counts
+ * are decimal. A label of the symbol file works anywhere an address does, examine and write
+ * go through the current mapping whatever the bank of the label. Register names are looked
+ * at before labels and labels before hexadecimal numbers: "write a 12" sets A and a label
+ * called Fade is not FADE.
+ * Lines starting with # are comments.
+ *
+ * Breakpoints are checked after every instruction, the one a run starts on does not stop it.
+ *
+ * Watchpoints see every access of the cpu, the I/O registers and the writes to the mbc at
+ * 0000 - 7FFF included. The run stops once the instruction that made the access is done and
+ * tells where it was. A bank in RANGE is the bank mapped there at the time of the access,
+ * 02:A000 is the cartridge RAM bank 2. The debugger's own reads, examine and disassemble,
+ * never stop a run.
+ *
+ * A ring of traces is written out on its own when a run stops on a breakpoint or a
+ * watchpoint, and by the cpu on LD B,B or when it locks up.
+ *
+ * The call stack is followed as the instructions run: a CALL, RST or interrupt that pushes
+ * its return address opens a frame and the frame closes once SP is back above it, whether
+ * that is through a RET or a POP that drops the return address.
+ */
+
+const HELP: &str = "break ADDR | BANK:ADDR | LABEL, delete [N], breakpoints,
+watch read|write|access LOC[-ADDR] [BYTE], unwatch [N], watchpoints, step [N], next, finish,
+backtrace, continue [N], registers, examine ADDR [LEN], write ADDR BYTE..., write REG VALUE,
+disassemble [ADDR | BANK:ADDR | LABEL] [N], disassemble-bank BANK FILE,
+trace FILE [pc START-END] [bank BANK] [ring N] [doctor], trace dump, trace off, quit";
+
+const DEFAULT_EXAMINE_LEN: usize = 64;
+const EXAMINE_ROW_LEN: usize = 16;
+const DEFAULT_DISASSEMBLE_COUNT: u64 = 8;
+
+const INTERRUPT_VECTORS: [Word; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    // any bank when None
+    pub bank: Option<usize>,
+    pub addr: Word,
+}
+
+// why a run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // the command got where it was going
+    Done,
+    Breakpoint(usize),
+    // the instruction count ran out
+    Limit,
+    // the cpu ran an illegal opcode
+    Locked,
+    // pc is where the instruction that made the access starts
+    Watchpoint {
+        hit: WatchHit,
+        bank: usize,
+        pc: Word,
+    },
+}
+
+// a function that has not returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub return_bank: usize,
+    pub return_addr: Word,
+    // where the return address is on the stack
+    pub sp: Word,
+}
+
+pub struct Debugger {
+    gameboy: GameBoy,
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    // outermost first
+    call_stack: Vec<Frame>,
+}
+
+impl Debugger {
+    pub fn new(gameboy: GameBoy) -> Debugger {
+        Debugger {
+            gameboy,
+            breakpoints: Vec::new(),
+            next_id: 0,
+            call_stack: Vec::new(),
+        }
+    }
+
+    pub fn gameboy(&self) -> &GameBoy {
+        &self.gameboy
+    }
+
+    pub fn gameboy_mut(&mut self) -> &mut GameBoy {
+        &mut self.gameboy
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // the functions that have been called and did not return yet, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    // returns the id of the new breakpoint
+    pub fn add_breakpoint(&mut self, bank: Option<usize>, addr: Word) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, bank, addr });
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != len
+    }
+
+    // runs a single command and writes what it has to say to out, returns false on quit.
+    // mistakes in the command are reported to out as well
+    pub fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = args.split_first() else {
+            return Ok(true);
+        };
+        let result = match command {
+            _ if command.starts_with('#') => Ok(String::new()),
+            "break" | "b" => self.command_break(args),
+            "delete" | "d" => self.command_delete(args),
+            "breakpoints" | "bl" => Ok(self.command_breakpoints()),
+            "watch" | "wa" => self.command_watch(args),
+            "unwatch" => self.command_unwatch(args),
+            "watchpoints" | "wl" => Ok(self.command_watchpoints()),
+            "step" | "s" => optional_count(args).map(|count| {
+                let stop = self.step(count.unwrap_or(1));
+                self.describe(stop)
+            }),
+            "next" | "n" => {
+                let stop = self.step_over();
+                Ok(self.describe(stop))
+            }
+            "finish" | "fin" => {
+                let stop = self.finish();
+                Ok(self.describe(stop))
+            }
+            "backtrace" | "bt" => Ok(self.command_backtrace()),
+            "continue" | "c" => optional_count(args).map(|limit| {
+                let stop = self.resume(limit);
+                self.describe(stop)
+            }),
+            "registers" | "r" => Ok(self.command_registers()),
+            "examine" | "x" => self.command_examine(args),
+            "write" | "w" => self.command_write(args).map(|_| String::new()),
+            "disassemble" | "dis" => self.command_disassemble(args),
+            "disassemble-bank" => self.command_disassemble_bank(args),
+            "trace" => self.command_trace(args),
+            "help" | "h" => Ok(format!("{}\n", HELP)),
+            "quit" | "q" => return Ok(false),
+            _ => Err(format!("unknown command {}, try help", command)),
+        };
+        match result {
+            Ok(output) => out.write_all(output.as_bytes())?,
+            Err(err) => writeln!(out, "error: {}", err)?,
+        }
+        Ok(true)
+    }
+
+    // runs count instructions, unless a breakpoint comes first
+    pub fn step(&mut self, count: u64) -> Stop {
+        // run only looks at the limit after an instruction
+        if count == 0 {
+            return Stop::Done;
+        }
+        match self.run(Some(count), |_, _, _| false) {
+            Stop::Limit => Stop::Done,
+            stop => stop,
+        }
+    }
+
+    // runs until a breakpoint, or for at most limit instructions
+    pub fn resume(&mut self, limit: Option<u64>) -> Stop {
+        self.run(limit, |_, _, _| false)
+    }
+
+    // steps over CALL and RST: they run until the instruction right after them, with the
+    // stack back where it was so a recursive call to the same function does not stop it
+    pub fn step_over(&mut self) -> Stop {
+        let reg = *self.gameboy.registers();
+        let len = match self.gameboy.mmu().peek_byte(reg.pc) {
+            // CALL, CALL cc
+            0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => 3,
+            // RST
+            opcode if opcode & 0xC7 == 0xC7 => 1,
+            _ => return self.step(1),
+        };
+        let return_addr = reg.pc.wrapping_add(len);
+        self.run(None, |gameboy, _, _| {
+            let now = gameboy.registers();
+            now.pc == return_addr && now.sp >= reg.sp
+        })
+    }
+
+    // runs until a RET out of the current function, the ones of the functions it calls
+    // leave the stack below where it is now
+    pub fn finish(&mut self) -> Stop {
+        let frame = self.gameboy.registers().sp;
+        self.run(None, |gameboy, opcode, sp| {
+            // a conditional RET that is not taken leaves SP alone
+            let returns = matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8);
+            returns && sp >= frame && gameboy.registers().sp == sp.wrapping_add(2)
+        })
+    }
+
+    // steps until done returns true, a breakpoint is hit or limit instructions ran. done gets
+    // the opcode that was executed and SP before it
+    fn run<F: FnMut(&GameBoy, Byte, Word) -> bool>(
+        &mut self,
+        limit: Option<u64>,
+        mut done: F,
+    ) -> Stop {
+        let mut count = 0;
+        loop {
+            if self.gameboy.cpu().is_locked() {
+                return Stop::Locked;
+            }
+            let reg = *self.gameboy.registers();
+            let bank = self.gameboy.mmu().bank_at(reg.pc);
+            let opcode = self.gameboy.mmu().peek_byte(reg.pc);
+            // only the accesses of the instruction count, not the reads of the debugger
+            self.gameboy.mmu().watchpoints().take_hit();
+            self.gameboy.step_instruction();
+            let hit = self.gameboy.mmu().watchpoints().take_hit();
+            self.follow_call_stack(opcode, reg.pc, reg.sp);
+            count += 1;
+
+            if let Some(hit) = hit {
+                self.dump_trace();
+                return Stop::Watchpoint {
+                    hit,
+                    bank,
+                    pc: reg.pc,
+                };
+            }
+            if done(&self.gameboy, opcode, reg.sp) {
+                return Stop::Done;
+            }
+            if let Some(breakpoint) = self.breakpoint_hit() {
+                self.dump_trace();
+                return Stop::Breakpoint(breakpoint.id);
+            }
+            if limit.is_some_and(|limit| count >= limit) {
+                return Stop::Limit;
+            }
+        }
+    }
+
+    // pc and sp are the ones from before the instruction that just ran
+    fn follow_call_stack(&mut self, opcode: Byte, pc: Word, sp: Word) {
+        let now = *self.gameboy.registers();
+        self.call_stack.retain(|frame| frame.sp >= now.sp);
+        if now.sp != sp.wrapping_sub(2) {
+            return;
+        }
+        let mmu = self.gameboy.mmu();
+        // peeked, reading through the cpu would trigger the read watchpoints on the stack
+        let pushed =
+            mmu.peek_byte(now.sp) as Word | (mmu.peek_byte(now.sp.wrapping_add(1)) as Word) << 8;
+        let calls = match opcode {
+            0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => pushed == pc.wrapping_add(3),
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => pushed == pc.wrapping_add(1),
+            _ => false,
+        };
+        // an interrupt is dispatched instead of running the instruction at pc
+        let interrupted = pushed == pc && INTERRUPT_VECTORS.contains(&now.pc);
+        if calls || interrupted {
+            self.call_stack.push(Frame {
+                return_bank: mmu.bank_at(pushed),
+                return_addr: pushed,
+                sp: now.sp,
+            });
+        }
+    }
+
+    fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        let pc = self.gameboy.registers().pc;
+        let bank = self.gameboy.mmu().bank_at(pc);
+        self.breakpoints.iter().copied().find(|breakpoint| {
+            breakpoint.addr == pc && breakpoint.bank.is_none_or(|expected| expected == bank)
+        })
+    }
+
+    // BANK:ADDR of PC
+    fn location(&self) -> String {
+        let pc = self.gameboy.registers().pc;
+        self.describe_location(self.gameboy.mmu().bank_at(pc), pc)
+    }
+
+    // BANK:ADDR, followed by the label it is at or after if there is one
+    fn describe_location(&self, bank: usize, addr: Word) -> String {
+        match self.gameboy.symbols().describe(bank, addr) {
+            Some(label) => format!("{:02X}:{:04X} ({})", bank, addr, label),
+            None => format!("{:02X}:{:04X}", bank, addr),
+        }
+    }
+
+    fn breakpoint_location(&self, bank: Option<usize>, addr: Word) -> String {
+        match bank {
+            Some(bank) => self.describe_location(bank, addr),
+            None => format!("{:04X}", addr),
+        }
+    }
+
+    // LABEL, ADDR or BANK:ADDR
+    fn parse_location(&self, text: &str) -> Result<(Option<usize>, Word), String> {
+        if let Some((bank, addr)) = self.gameboy.symbols().lookup(text) {
+            return Ok((Some(bank), addr));
+        }
+        let (bank, addr) = match text.split_once(':') {
+            Some((bank, addr)) => (Some(parse_hex(bank, 0x1FF)? as usize), addr),
+            None => (None, text),
+        };
+        match parse_hex(addr, 0xFFFF) {
+            Ok(addr) => Ok((bank, addr as Word)),
+            Err(_) if bank.is_none() => Err(format!("{} is neither a label nor an address", text)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // where the run stopped and the instruction that comes next, after the access for a
+    // watchpoint
+    fn describe(&self, stop: Stop) -> String {
+        let reason = match stop {
+            Stop::Done | Stop::Limit => String::from("stopped"),
+            Stop::Breakpoint(id) => format!("breakpoint {}", id),
+            Stop::Locked => String::from("cpu locked up"),
+            Stop::Watchpoint { hit, bank, pc } => {
+                let (access, direction) = match hit.access {
+                    Access::Read => ("read", "from"),
+                    Access::Write => ("write", "to"),
+                };
+                format!(
+                    "watchpoint {}: {} of ${:02X} {} {} by {}\nstopped",
+                    hit.id,
+                    access,
+                    hit.value,
+                    direction,
+                    self.describe_location(hit.bank, hit.addr),
+                    self.describe_location(bank, pc)
+                )
+            }
+        };
+        let pc = self.gameboy.registers().pc;
+        let instruction = disassemble(self.gameboy.mmu(), self.gameboy.symbols(), pc);
+        let output = format!("{} at {}  {}\n", reason, self.location(), instruction.text);
+        // describing is no access of the program either
+        self.gameboy.mmu().watchpoints().take_hit();
+        output
+    }
+
+    fn command_break(&mut self, args: &[&str]) -> Result<String, String> {
+        let [location] = args else {
+            return Err(String::from("usage: break ADDR | BANK:ADDR"));
+        };
+        let (bank, addr) = self.parse_location(location)?;
+        let id = self.add_breakpoint(bank, addr);
+        Ok(format!(
+            "breakpoint {} at {}\n",
+            id,
+            self.breakpoint_location(bank, addr)
+        ))
+    }
+
+    fn command_delete(&mut self, args: &[&str]) -> Result<String, String> {
+        match optional_count(args)? {
+            Some(id) if self.remove_breakpoint(id as usize) => {
+                Ok(format!("deleted breakpoint {}\n", id))
+            }
+            Some(id) => Err(format!("no breakpoint {}", id)),
+            None => {
+                self.breakpoints.clear();
+                Ok(String::from("deleted all breakpoints\n"))
+            }
+        }
+    }
+
+    fn command_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return String::from("no breakpoints\n");
+        }
+        let mut output = String::new();
+        for breakpoint in &self.breakpoints {
+            output.push_str(&format!(
+                "{:<4} {}\n",
+                breakpoint.id,
+                self.breakpoint_location(breakpoint.bank, breakpoint.addr)
+            ));
+        }
+        output
+    }
+
+    // watch KIND LOC[-ADDR] [BYTE]
+    fn command_watch(&mut self, args: &[&str]) -> Result<String, String> {
+        let usage = "usage: watch read|write|access LOC[-ADDR] [BYTE]";
+        let (kind, range, value) = match args {
+            [kind, range] => (kind, range, None),
+            [kind, range, value] => (kind, range, Some(parse_hex(value, 0xFF)? as Byte)),
+            _ => return Err(String::from(usage)),
+        };
+        let kind = match (*kind, value) {
+            ("read", None) => WatchKind::Read,
+            ("access", None) => WatchKind::Access,
+            ("write", None) => WatchKind::Write,
+            ("write", Some(value)) => WatchKind::Value(value),
+            ("read" | "access", Some(_)) => {
+                return Err(String::from("only a write watchpoint takes a value"))
+            }
+            _ => return Err(String::from(usage)),
+        };
+        // a label may well have a - in it, the whole range is tried as a location first
+        let (bank, start, end) = match self.parse_location(range) {
+            Ok((bank, addr)) => (bank, addr, addr),
+            Err(err) => {
+                let Some((start, end)) = range.rsplit_once('-') else {
+                    return Err(err);
+                };
+                let (bank, start) = self.parse_location(start)?;
+                (bank, start, parse_hex(end, 0xFFFF)? as Word)
+            }
+        };
+        if end < start {
+            return Err(format!("{} ends before it starts", range));
+        }
+        let id = self
+            .gameboy
+            .mmu_mut()
+            .watchpoints_mut()
+            .add(start, end, bank, kind);
+        let watchpoint = Watchpoint {
+            id,
+            start,
+            end,
+            bank,
+            kind,
+        };
+        Ok(format!(
+            "watchpoint {}: {}\n",
+            id,
+            self.describe_watchpoint(&watchpoint)
+        ))
+    }
+
+    fn command_unwatch(&mut self, args: &[&str]) -> Result<String, String> {
+        let watchpoints = self.gameboy.mmu_mut().watchpoints_mut();
+        match optional_count(args)? {
+            Some(id) if watchpoints.remove(id as usize) => {
+                Ok(format!("deleted watchpoint {}\n", id))
+            }
+            Some(id) => Err(format!("no watchpoint {}", id)),
+            None => {
+                watchpoints.clear();
+                Ok(String::from("deleted all watchpoints\n"))
+            }
+        }
+    }
+
+    fn command_watchpoints(&self) -> String {
+        let watchpoints = self.gameboy.mmu().watchpoints().list();
+        if watchpoints.is_empty() {
+            return String::from("no watchpoints\n");
+        }
+        let mut output = String::new();
+        for watchpoint in watchpoints {
+            output.push_str(&format!(
+                "{:<4} {}\n",
+                watchpoint.id,
+                self.describe_watchpoint(watchpoint)
+            ));
+        }
+        output
+    }
+
+    // write of $FF to 01:A000-A0FF (sSaveData)
+    fn describe_watchpoint(&self, watchpoint: &Watchpoint) -> String {
+        let mut range = match watchpoint.bank {
+            Some(bank) => format!("{:02X}:{:04X}", bank, watchpoint.start),
+            None => format!("{:04X}", watchpoint.start),
+        };
+        if watchpoint.end != watchpoint.start {
+            range.push_str(&format!("-{:04X}", watchpoint.end));
+        }
+        let label = watchpoint
+            .bank
+            .and_then(|bank| self.gameboy.symbols().describe(bank, watchpoint.start));
+        if let Some(label) = label {
+            range.push_str(&format!(" ({})", label));
+        }
+        match watchpoint.kind {
+            WatchKind::Read => format!("read of {}", range),
+            WatchKind::Write => format!("write to {}", range),
+            WatchKind::Access => format!("access to {}", range),
+            WatchKind::Value(value) => format!("write of ${:02X} to {}", value, range),
+        }
+    }
+
+    fn command_registers(&self) -> String {
+        let reg = self.gameboy.registers();
+        let cpu = self.gameboy.cpu();
+        format!(
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X}\n\
+             Z={} N={} H={} C={} IME={} HALT={} at {}\n",
+            reg.af(),
+            reg.bc(),
+            reg.de(),
+            reg.hl(),
+            reg.sp,
+            reg.pc,
+            reg.getflag(CpuFlags::Z) as u8,
+            reg.getflag(CpuFlags::N) as u8,
+            reg.getflag(CpuFlags::H) as u8,
+            reg.getflag(CpuFlags::C) as u8,
+            cpu.interrupts_enabled() as u8,
+            cpu.is_halted() as u8,
+            self.location()
+        )
+    }
+
+    // 16 bytes per row, with the printable ones on the right
+    fn command_examine(&self, args: &[&str]) -> Result<String, String> {
+        let (addr, len) = match args {
+            [addr] => (self.parse_location(addr)?.1, DEFAULT_EXAMINE_LEN),
+            [addr, len] => (
+                self.parse_location(addr)?.1,
+                parse_count(len)?.min(0x10000) as usize,
+            ),
+            _ => return Err(String::from("usage: examine ADDR [LEN]")),
+        };
+
+        let mmu = self.gameboy.mmu();
+        let bytes: Vec<Byte> = (0..len)
+            .map(|i| mmu.peek_byte(addr.wrapping_add(i as Word)))
+            .collect();
+        let mut output = String::new();
+        for (row, chunk) in bytes.chunks(EXAMINE_ROW_LEN).enumerate() {
+            let start = addr.wrapping_add((row * EXAMINE_ROW_LEN) as Word);
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&byte| match byte.is_ascii_graphic() || byte == b' ' {
+                    true => byte as char,
+                    false => '.',
+                })
+                .collect();
+            output.push_str(&format!(
+                "{:04X}  {:<width$}  {}\n",
+                start,
+                hex.join(" "),
+                text,
+                width = EXAMINE_ROW_LEN * 3 - 1
+            ));
+        }
+        Ok(output)
+    }
+
+    // #0 is where PC is, then one line per return address
+    fn command_backtrace(&self) -> String {
+        let mut output = format!("#0  {}\n", self.location());
+        for (i, frame) in self.call_stack.iter().rev().enumerate() {
+            output.push_str(&format!(
+                "#{:<2} {}\n",
+                i + 1,
+                self.describe_location(frame.return_bank, frame.return_addr)
+            ));
+        }
+        output
+    }
+
+    fn command_disassemble(&self, args: &[&str]) -> Result<String, String> {
+        let (location, count) = match args {
+            [] => (None, DEFAULT_DISASSEMBLE_COUNT),
+            [location] => (
+                Some(self.parse_location(location)?),
+                DEFAULT_DISASSEMBLE_COUNT,
+            ),
+            [location, count] => (Some(self.parse_location(location)?), parse_count(count)?),
+            _ => return Err(String::from("usage: disassemble [ADDR | BANK:ADDR] [N]")),
+        };
+        let (bank, mut addr) = location.unwrap_or((None, self.gameboy.registers().pc));
+        if let Some(bank) = bank {
+            check_rom_location(bank, addr)?;
+        }
+
+        let symbols = self.gameboy.symbols();
+        let mut output = String::new();
+        for _ in 0..count {
+            let instruction = match bank {
+                Some(bank) => disassemble_rom(self.gameboy.rom(), symbols, bank, addr),
+                None => disassemble(self.gameboy.mmu(), symbols, addr),
+            };
+            output.push_str(&listing_lines(symbols, &instruction));
+            let next = instruction.next_addr();
+            // an explicit bank ends where the bank does
+            if bank.is_some() && (next as usize).is_multiple_of(ROM_BANK_SIZE) {
+                break;
+            }
+            addr = next;
+        }
+        Ok(output)
+    }
+
+    fn command_disassemble_bank(&self, args: &[&str]) -> Result<String, String> {
+        let [bank, path] = args else {
+            return Err(String::from("usage: disassemble-bank BANK FILE"));
+        };
+        let bank = parse_hex(bank, 0x1FF)? as usize;
+        let Some(listing) = disassemble_rom_bank(self.gameboy.rom(), self.gameboy.symbols(), bank)
+        else {
+            return Err(format!("the ROM has no bank {:02X}", bank));
+        };
+        fs::write(path, listing).map_err(|err| format!("could not write {}: {}", path, err))?;
+        Ok(format!("bank {:02X} written to {}\n", bank, path))
+    }
+
+    fn command_trace(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            ["off"] => {
+                self.gameboy.set_doctor_mode(false);
+                if let Some(mut tracer) = self.gameboy.set_tracer(None) {
+                    tracer.dump();
+                    check_trace(&tracer)?;
+                    tracer
+                        .flush()
+                        .map_err(|err| format!("could not write the trace: {}", err))?;
+                }
+                Ok(String::from("tracing stopped\n"))
+            }
+            ["dump"] => match self.gameboy.tracer_mut() {
+                Some(tracer) if tracer.ring_size().is_some() => {
+                    tracer.dump();
+                    check_trace(tracer)?;
+                    Ok(String::new())
+                }
+                Some(_) => Err(String::from(
+                    "the trace has no ring, it is written as it goes",
+                )),
+                None => Err(String::from("not tracing")),
+            },
+            [path, options @ ..] => {
+                let (tracer, doctor) = self.parse_tracer(path, options)?;
+                self.gameboy.set_doctor_mode(doctor);
+                // the previous trace gets the end of its ring before it is closed
+                if let Some(mut previous) = self.gameboy.set_tracer(Some(tracer)) {
+                    previous.dump();
+                }
+                Ok(format!("tracing to {}\n", path))
+            }
+            [] => Err(String::from(
+                "usage: trace FILE [pc START-END] [bank BANK] [ring N] [doctor] | trace dump | trace off",
+            )),
+        }
+    }
+
+    // the tracer and whether LY has to read 90
+    fn parse_tracer(&self, path: &str, options: &[&str]) -> Result<(Tracer, bool), String> {
+        let mut options = options.iter();
+        let mut range = None;
+        let mut bank = None;
+        let mut ring = None;
+        let mut doctor = false;
+        while let Some(&option) = options.next() {
+            if option == "doctor" {
+                doctor = true;
+                continue;
+            }
+            let Some(&value) = options.next() else {
+                return Err(format!("{} needs a value", option));
+            };
+            match option {
+                "pc" => {
+                    range = Some(match value.split_once('-') {
+                        Some((start, end)) => (
+                            self.parse_location(start)?.1,
+                            parse_hex(end, 0xFFFF)? as Word,
+                        ),
+                        None => {
+                            let (_, addr) = self.parse_location(value)?;
+                            (addr, addr)
+                        }
+                    });
+                }
+                "bank" => bank = Some(parse_hex(value, 0x1FF)? as usize),
+                "ring" => ring = Some(parse_count(value)? as usize),
+                _ => return Err(format!("unknown trace option {}", option)),
+            }
+        }
+        let file =
+            File::create(path).map_err(|err| format!("could not create {}: {}", path, err))?;
+        let mut tracer = Tracer::new(BufWriter::new(file));
+        if let Some((start, end)) = range {
+            tracer.set_pc_range(start, end);
+        }
+        tracer.set_bank(bank);
+        tracer.set_ring_size(ring);
+        Ok((tracer, doctor))
+    }
+
+    fn dump_trace(&mut self) {
+        if let Some(tracer) = self.gameboy.tracer_mut() {
+            tracer.dump();
+        }
+    }
+
+    fn command_write(&mut self, args: &[&str]) -> Result<(), String> {
+        let Some((&target, values)) = args.split_first() else {
+            return Err(String::from("usage: write ADDR BYTE... | write REG VALUE"));
+        };
+        let name = target.to_ascii_lowercase();
+        let max = match name.as_str() {
+            "a" | "b" | "c" | "d" | "e" | "f" | "h" | "l" => 0xFF,
+            "af" | "bc" | "de" | "hl" | "sp" | "pc" => 0xFFFF,
+            _ => return self.write_memory(target, values),
+        };
+        match values {
+            [value] => {
+                let value = parse_hex(value, max)? as Word;
+                self.write_register(&name, value);
+                Ok(())
+            }
+            _ => Err(String::from("a register takes a single value")),
+        }
+    }
+
+    fn write_memory(&mut self, addr: &str, values: &[&str]) -> Result<(), String> {
+        if values.is_empty() {
+            return Err(String::from("nothing to write"));
+        }
+        let (_, addr) = self.parse_location(addr)?;
+        let bytes = values
+            .iter()
+            .map(|value| parse_hex(value, 0xFF).map(|byte| byte as Byte))
+            .collect::<Result<Vec<Byte>, String>>()?;
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.gameboy
+                .mmu_mut()
+                .write_byte(addr.wrapping_add(i as Word), byte);
+        }
+        Ok(())
+    }
+
+    fn write_register(&mut self, name: &str, value: Word) {
+        let reg = self.gameboy.registers_mut();
+        match name {
+            "a" => reg.a = value as Byte,
+            "b" => reg.b = value as Byte,
+            "c" => reg.c = value as Byte,
+            "d" => reg.d = value as Byte,
+            "e" => reg.e = value as Byte,
+            // F only exists as the low byte of AF
+            "f" => reg.setaf(((reg.a as Word) << 8) | value),
+            "h" => reg.h = value as Byte,
+            "l" => reg.l = value as Byte,
+            "af" => reg.setaf(value),
+            "bc" => reg.setbc(value),
+            "de" => reg.setde(value),
+            "hl" => reg.sethl(value),
+            "sp" => reg.sp = value,
+            _ => reg.pc = value,
+        }
+    }
+}
+
+// the tracer keeps going when a write fails, the error comes out on trace dump or off
+fn check_trace(tracer: &Tracer) -> Result<(), String> {
+    match tracer.last_error() {
+        Some(err) => Err(format!("could not write the trace: {}", err)),
+        None => Ok(()),
+    }
+}
+
+// bank 0 is at 0000 - 3FFF and all the others at 4000 - 7FFF
+fn check_rom_location(bank: usize, addr: Word) -> Result<(), String> {
+    match (bank, addr) {
+        (0, 0x0000..=0x3FFF) => Ok(()),
+        (1.., 0x4000..=0x7FFF) => Ok(()),
+        _ => Err(format!(
+            "{:02X}:{:04X} is not in ROM, bank 0 is at 0000-3FFF and the others at 4000-7FFF",
+            bank, addr
+        )),
+    }
+}
+
+// hexadecimal, with an optional $ or 0x prefix, up to max
+fn parse_hex(text: &str, max: u32) -> Result<u32, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    match u32::from_str_radix(digits, 16) {
+        Ok(value) if value <= max => Ok(value),
+        Ok(_) => Err(format!("{} is larger than {:X}", text, max)),
+        Err(_) => Err(format!("{} is not a hexadecimal number", text)),
+    }
+}
+
+// decimal, or hexadecimal with a $ or 0x prefix
+fn parse_count(text: &str) -> Result<u64, String> {
+    if text.starts_with('$') || text.starts_with("0x") || text.starts_with("0X") {
+        return parse_hex(text, u32::MAX).map(|value| value as u64);
+    }
+    text.parse()
+        .map_err(|_| format!("{} is not a number", text))
+}
+
+fn optional_count(args: &[&str]) -> Result<Option<u64>, String> {
+    match args {
+        [] => Ok(None),
+        [count] => parse_count(count).map(Some),
+        _ => Err(String::from("too many arguments")),
+    }
+}
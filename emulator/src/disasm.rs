@@ -0,0 +1,322 @@
+use std::fmt;
+
+use crate::mmu::Mmu;
+use crate::rom::Rom;
+use crate::symbols::SymbolTable;
+use crate::utils::*;
+
+/*
+ * SM83 disassembler, RGBDS syntax: lowercase mnemonics, $ hexadecimal and [] for memory
+ * operands, see https://rgbds.gbdev.io/docs/gbz80.7
+ *
+ *    ld a, [hl+]        ldh [$FF40], a        ld hl, sp+$05        jr nz, $0157
+ *
+ * Jumps, calls and JR show the address they go to rather than their operand. The illegal
+ * opcodes come out as data, db $D3, and so does an instruction cut short by the end of a
+ * ROM bank.
+ *
+ * Bytes are either read through the mmu, so they are whatever the current mapping shows,
+ * or straight from the ROM for an explicit bank:addr.
+ *
+ * With a symbol table, jump targets and the addresses loads go through show as labels,
+ * call PlayerUpdate or ld [wPlayerX], a. A listing also puts every label on a line of its
+ * own, right before the instruction it is on.
+ */
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STK: [&str; 4] = ["bc", "de", "hl", "af"];
+const COND: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROTATE: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+// the longest instruction, CALL nn and friends
+pub const MAX_INSTRUCTION_LEN: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub bank: usize,
+    pub addr: Word,
+    pub bytes: Vec<Byte>,
+    // the mnemonic and its operands
+    pub text: String,
+    // where a JP, JR, CALL or RST goes, None for JP HL and everything else
+    pub target: Option<Word>,
+    // the address read or written by the loads with an immediate address, LD (nn),A or LDH
+    pub operand: Option<Word>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    // the address right after the instruction
+    pub fn next_addr(&self) -> Word {
+        self.addr.wrapping_add(self.len() as Word)
+    }
+
+    // swaps the target and operand addresses in the text for labels. bank_of tells which bank
+    // an address is in, None when there is no way to know
+    pub fn resolve_labels<F: Fn(Word) -> Option<usize>>(
+        &mut self,
+        symbols: &SymbolTable,
+        bank_of: F,
+    ) {
+        for addr in [self.target, self.operand].into_iter().flatten() {
+            let label = bank_of(addr).and_then(|bank| symbols.describe(bank, addr));
+            if let Some(label) = label {
+                self.text = self.text.replace(&format!("${:04X}", addr), &label);
+            }
+        }
+    }
+}
+
+// one listing line, 01:4000  CD 00 02  call $0200
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        write!(
+            f,
+            "{:02X}:{:04X}  {:<width$}  {}",
+            self.bank,
+            self.addr,
+            bytes.join(" "),
+            self.text,
+            width = MAX_INSTRUCTION_LEN * 3 - 1
+        )
+    }
+}
+
+// the instruction at addr in the banks mapped right now
+pub fn disassemble(mmu: &Mmu, symbols: &SymbolTable, addr: Word) -> Instruction {
+    let mut instruction = decode(mmu.bank_at(addr), addr, MAX_INSTRUCTION_LEN, |addr| {
+        mmu.peek_byte(addr)
+    });
+    instruction.resolve_labels(symbols, |addr| Some(mmu.bank_at(addr)));
+    instruction
+}
+
+// the instruction at bank:addr of the ROM, whatever bank is mapped in. addr is in 0000 - 3FFF
+// for bank 0 and in 4000 - 7FFF for the others, the instruction does not go past the bank
+pub fn disassemble_rom(rom: &Rom, symbols: &SymbolTable, bank: usize, addr: Word) -> Instruction {
+    let offset = (addr as usize) % ROM_BANK_SIZE;
+    let available = (ROM_BANK_SIZE - offset).min(MAX_INSTRUCTION_LEN);
+    let mut instruction = decode(bank, addr, available, |addr| {
+        rom.get_byte(bank * ROM_BANK_SIZE + (addr as usize) % ROM_BANK_SIZE)
+    });
+    instruction.resolve_labels(symbols, |target| match target {
+        0x0000..=0x3FFF => Some(0),
+        // bank 0 can jump to any of the others
+        0x4000..=0x7FFF => (bank > 0).then_some(bank),
+        // WRAMX, the DMG only has bank 1
+        0xD000..=0xDFFF => Some(1),
+        _ => Some(0),
+    });
+    instruction
+}
+
+// the instruction listing line, after the line of the label on it if there is one
+pub fn listing_lines(symbols: &SymbolTable, instruction: &Instruction) -> String {
+    match symbols.label_at(instruction.bank, instruction.addr) {
+        Some(label) => format!("{}:\n{}\n", label, instruction),
+        None => format!("{}\n", instruction),
+    }
+}
+
+// a whole ROM bank, one listing line per instruction. None when the ROM is not that large
+pub fn disassemble_rom_bank(rom: &Rom, symbols: &SymbolTable, bank: usize) -> Option<String> {
+    if bank >= rom.length().div_ceil(ROM_BANK_SIZE) {
+        return None;
+    }
+    let start = match bank {
+        0 => 0x0000,
+        _ => 0x4000,
+    };
+    let end = start + ROM_BANK_SIZE;
+
+    let mut listing = String::new();
+    let mut addr = start;
+    while addr < end {
+        let instruction = disassemble_rom(rom, symbols, bank, addr as Word);
+        addr += instruction.len();
+        listing.push_str(&listing_lines(symbols, &instruction));
+    }
+    Some(listing)
+}
+
+// decodes the instruction at addr, reading at most available bytes with read
+pub fn decode<F: Fn(Word) -> Byte>(
+    bank: usize,
+    addr: Word,
+    available: usize,
+    read: F,
+) -> Instruction {
+    let opcode = read(addr);
+    let len = instruction_len(opcode).min(available.max(1));
+    let bytes: Vec<Byte> = (0..len)
+        .map(|i| read(addr.wrapping_add(i as Word)))
+        .collect();
+    if len < instruction_len(opcode) || is_illegal(opcode) {
+        return Instruction {
+            bank,
+            addr,
+            text: format!("db ${:02X}", opcode),
+            bytes: vec![opcode],
+            target: None,
+            operand: None,
+        };
+    }
+
+    let n = bytes.get(1).copied().unwrap_or(0);
+    let nn = (n as Word) | ((bytes.get(2).copied().unwrap_or(0) as Word) << 8);
+    let next = addr.wrapping_add(len as Word);
+    let (text, target) = mnemonic(opcode, n, nn, next);
+    let operand = match opcode {
+        0x08 | 0xEA | 0xFA => Some(nn),
+        0xE0 | 0xF0 => Some(0xFF00 | n as Word),
+        _ => None,
+    };
+    Instruction {
+        bank,
+        addr,
+        bytes,
+        text,
+        target,
+        operand,
+    }
+}
+
+fn is_illegal(opcode: Byte) -> bool {
+    matches!(
+        opcode,
+        0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD
+    )
+}
+
+// the opcode and its immediate operand
+fn instruction_len(opcode: Byte) -> usize {
+    match opcode {
+        // LD r16,nn / LD (nn),SP / JP / CALL / LD (nn),A / LD A,(nn)
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 => 3,
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA => 3,
+        0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+        0xEA | 0xFA => 3,
+        // LD r8,n / JR / STOP / ALU A,n / LDH / ADD SP,e / LD HL,SP+e / the 0xCB prefix
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0x10 => 2,
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        0xE0 | 0xF0 | 0xE8 | 0xF8 | 0xCB => 2,
+        _ => 1,
+    }
+}
+
+// e as a signed offset, sp+$05 or sp-$03
+fn signed(e: Byte) -> String {
+    match e as i8 {
+        e if e < 0 => format!("-${:02X}", e.unsigned_abs()),
+        e => format!("+${:02X}", e),
+    }
+}
+
+fn mnemonic(opcode: Byte, n: Byte, nn: Word, next: Word) -> (String, Option<Word>) {
+    let dst = R8[((opcode >> 3) & 0x7) as usize];
+    let src = R8[(opcode & 0x7) as usize];
+    let r16 = R16[((opcode >> 4) & 0x3) as usize];
+    let cond = COND[((opcode >> 3) & 0x3) as usize];
+    let jr_target = next.wrapping_add(n as i8 as Word);
+
+    let text = match opcode {
+        0x00 => String::from("nop"),
+        0x10 => String::from("stop"),
+        0x76 => String::from("halt"),
+        0xF3 => String::from("di"),
+        0xFB => String::from("ei"),
+        0x07 => String::from("rlca"),
+        0x0F => String::from("rrca"),
+        0x17 => String::from("rla"),
+        0x1F => String::from("rra"),
+        0x27 => String::from("daa"),
+        0x2F => String::from("cpl"),
+        0x37 => String::from("scf"),
+        0x3F => String::from("ccf"),
+        0xCB => return (cb_mnemonic(n), None),
+
+        0x01 | 0x11 | 0x21 | 0x31 => format!("ld {}, ${:04X}", r16, nn),
+        0x02 | 0x12 => format!("ld [{}], a", r16),
+        0x0A | 0x1A => format!("ld a, [{}]", r16),
+        0x22 => String::from("ld [hl+], a"),
+        0x2A => String::from("ld a, [hl+]"),
+        0x32 => String::from("ld [hl-], a"),
+        0x3A => String::from("ld a, [hl-]"),
+        0x08 => format!("ld [${:04X}], sp", nn),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+            format!("ld {}, ${:02X}", dst, n)
+        }
+        0x40..=0x7F => format!("ld {}, {}", dst, src),
+        0xE0 => format!("ldh [$FF{:02X}], a", n),
+        0xF0 => format!("ldh a, [$FF{:02X}]", n),
+        0xE2 => String::from("ldh [c], a"),
+        0xF2 => String::from("ldh a, [c]"),
+        0xEA => format!("ld [${:04X}], a", nn),
+        0xFA => format!("ld a, [${:04X}]", nn),
+        0xF8 => format!("ld hl, sp{}", signed(n)),
+        0xF9 => String::from("ld sp, hl"),
+
+        0x03 | 0x13 | 0x23 | 0x33 => format!("inc {}", r16),
+        0x0B | 0x1B | 0x2B | 0x3B => format!("dec {}", r16),
+        0x09 | 0x19 | 0x29 | 0x39 => format!("add hl, {}", r16),
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => format!("inc {}", dst),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => format!("dec {}", dst),
+        0x80..=0xBF => format!("{} a, {}", ALU[((opcode >> 3) & 0x7) as usize], src),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+            format!("{} a, ${:02X}", ALU[((opcode >> 3) & 0x7) as usize], n)
+        }
+        0xE8 => format!("add sp, {}", signed(n).trim_start_matches('+')),
+
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => format!("pop {}", R16_STK[((opcode >> 4) & 0x3) as usize]),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => {
+            format!("push {}", R16_STK[((opcode >> 4) & 0x3) as usize])
+        }
+
+        0x18 => return (format!("jr ${:04X}", jr_target), Some(jr_target)),
+        0x20 | 0x28 | 0x30 | 0x38 => {
+            return (format!("jr {}, ${:04X}", cond, jr_target), Some(jr_target))
+        }
+        0xC3 => return (format!("jp ${:04X}", nn), Some(nn)),
+        0xC2 | 0xCA | 0xD2 | 0xDA => return (format!("jp {}, ${:04X}", cond, nn), Some(nn)),
+        0xE9 => String::from("jp hl"),
+        0xCD => return (format!("call ${:04X}", nn), Some(nn)),
+        0xC4 | 0xCC | 0xD4 | 0xDC => return (format!("call {}, ${:04X}", cond, nn), Some(nn)),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            let vector = (opcode & 0x38) as Word;
+            return (format!("rst ${:02X}", vector), Some(vector));
+        }
+        0xC9 => String::from("ret"),
+        0xD9 => String::from("reti"),
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => format!("ret {}", cond),
+
+        _ => unreachable!("opcode {:02X} is illegal", opcode),
+    };
+    (text, None)
+}
+
+// bits 0-2 select the register, bits 3-5 the operation or the bit and bits 6-7 the group
+fn cb_mnemonic(opcode: Byte) -> String {
+    let r8 = R8[(opcode & 0x7) as usize];
+    let bit = (opcode >> 3) & 0x7;
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATE[bit as usize], r8),
+        1 => format!("bit {}, {}", bit, r8),
+        2 => format!("res {}, {}", bit, r8),
+        _ => format!("set {}, {}", bit, r8),
+    }
+}
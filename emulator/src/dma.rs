@@ -0,0 +1,124 @@
+use crate::utils::*;
+
+/*
+ * OAM DMA. Writing a page number to 0xFF46 copies 0xXX00 - 0xXX9F into OAM, one byte per
+ * machine cycle for 160 machine cycles, after one cycle of setup. While it runs the DMA owns
+ * the buses, the cpu only gets through to the I/O registers and HRAM, which is why games copy
+ * a small wait loop to HRAM and start the transfer from there.
+ *
+ * Writing 0xFF46 again while a transfer is running restarts it, the old transfer keeps going
+ * (and keeps the buses blocked) during the setup cycle of the new one.
+ *
+ * see https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+ */
+
+pub const OAM_DMA_LENGTH: Word = 0xA0;
+
+// machine cycles between the write to 0xFF46 and the first byte copied
+const OAM_DMA_SETUP_CYCLES: u8 = 1;
+
+pub struct OamDma {
+    register: Byte,
+    source: Word,
+    index: Word,
+    active: bool,
+    // a transfer requested by a write, with the setup cycles it still has to wait
+    pending: Option<(Word, u8)>,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            source: 0,
+            index: 0,
+            active: false,
+            pending: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn read_byte(&self) -> Byte {
+        self.register
+    }
+
+    pub fn write_byte(&mut self, data: Byte) {
+        self.register = data;
+        self.pending = Some(((data as Word) << 8, OAM_DMA_SETUP_CYCLES));
+    }
+
+    // advances the transfer by one machine cycle, returns the source address and the OAM
+    // offset of the byte that has to be copied during it
+    pub fn do_cycle(&mut self) -> Option<(Word, Word)> {
+        match self.pending {
+            Some((source, 0)) => {
+                self.pending = None;
+                self.source = source;
+                self.index = 0;
+                self.active = true;
+            }
+            Some((source, delay)) => self.pending = Some((source, delay - 1)),
+            None => {}
+        }
+
+        if !self.active {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        if self.index == OAM_DMA_LENGTH {
+            self.active = false;
+        }
+        Some((self.source + index, index))
+    }
+}
+
+impl Default for OamDma {
+    fn default() -> OamDma {
+        OamDma::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the cycles and returns what each of them copied
+    fn run(dma: &mut OamDma, cycles: usize) -> Vec<Option<(Word, Word)>> {
+        (0..cycles).map(|_| dma.do_cycle()).collect()
+    }
+
+    #[test]
+    fn copies_160_bytes_after_the_setup() {
+        let mut dma = OamDma::new();
+        dma.write_byte(0xC1);
+        assert_eq!(dma.read_byte(), 0xC1);
+        assert_eq!(run(&mut dma, 1), [None]);
+        assert!(!dma.is_active());
+
+        let copied = run(&mut dma, 160);
+        assert!(copied
+            .iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == Some((0xC100 + i as Word, i as Word))));
+        assert!(!dma.is_active());
+        assert_eq!(run(&mut dma, 1), [None]);
+    }
+
+    #[test]
+    fn restart_keeps_the_old_transfer_during_the_setup() {
+        let mut dma = OamDma::new();
+        dma.write_byte(0xC1);
+        run(&mut dma, 11);
+        dma.write_byte(0xD0);
+        assert_eq!(run(&mut dma, 1), [Some((0xC10A, 0x0A))]);
+        assert!(dma.is_active());
+        assert_eq!(run(&mut dma, 1), [Some((0xD000, 0x00))]);
+        // the new transfer copies all 160 bytes
+        assert_eq!(run(&mut dma, 159).last(), Some(&Some((0xD09F, 0x9F))));
+        assert!(!dma.is_active());
+    }
+}
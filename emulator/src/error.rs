@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+
+use crate::utils::*;
+
+// everything that can go wrong while loading a cartridge or its save file
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // the file is too short to hold a complete cartridge header
+    RomTooSmall(usize),
+    UnsupportedCartridgeType(Byte),
+    HeaderChecksumMismatch { expected: Byte, computed: Byte },
+    GlobalChecksumMismatch { expected: Word, computed: Word },
+    // the file is shorter than the ROM size declared in the header
+    RomSizeMismatch { expected: usize, actual: usize },
+    SaveSizeMismatch { expected: usize, actual: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::RomTooSmall(len) => write!(
+                f,
+                "ROM is {} bytes long, too small to hold a cartridge header",
+                len
+            ),
+            Error::UnsupportedCartridgeType(value) => {
+                write!(f, "unsupported cartridge type {:02X}", value)
+            }
+            Error::HeaderChecksumMismatch { expected, computed } => write!(
+                f,
+                "header checksum mismatch: header says {:02X}, computed {:02X}",
+                expected, computed
+            ),
+            Error::GlobalChecksumMismatch { expected, computed } => write!(
+                f,
+                "global checksum mismatch: header says {:04X}, computed {:04X}",
+                expected, computed
+            ),
+            Error::RomSizeMismatch { expected, actual } => write!(
+                f,
+                "header declares a {} bytes ROM but the file is {} bytes long",
+                expected, actual
+            ),
+            Error::SaveSizeMismatch { expected, actual } => write!(
+                f,
+                "save file is {} bytes long, the cartridge RAM is {} bytes",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
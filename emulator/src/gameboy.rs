@@ -0,0 +1,290 @@
+use std::path::Path;
+
+use crate::battery::Battery;
+use crate::cpu::Cpu;
+use crate::error::{Error, Result};
+use crate::joypad::Joypad;
+use crate::mbc::Mbc;
+use crate::mmu::Mmu;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::register::Registers;
+use crate::rom::Rom;
+use crate::serial::LinkCable;
+use crate::symbols::SymbolTable;
+use crate::trace::Tracer;
+use crate::utils::*;
+
+type SaveErrorCallback = Box<dyn FnMut(&Error)>;
+
+/// The whole console: the `Cpu` owns the `Mmu`, which in turn owns the `Rom`, the `Joypad`
+/// and the cartridge `Mbc`. This is the entry point for anything built on top of the crate.
+pub struct GameBoy {
+    cpu: Cpu,
+    // only set for battery backed cartridges
+    battery: Option<Battery>,
+    rumble_callback: Option<Box<dyn FnMut(bool)>>,
+    rumble: bool,
+    save_error_callback: Option<SaveErrorCallback>,
+    symbols: SymbolTable,
+}
+
+impl GameBoy {
+    /// Builds a console from a ROM file already loaded in memory.
+    pub fn from_rom_bytes(data: Vec<Byte>) -> Result<GameBoy> {
+        GameBoy::new(Rom::from_bytes(data)?)
+    }
+
+    /// Builds a console from a ROM file on disk. Battery backed cartridges load `<rom>.sav`
+    /// when it exists and keep it up to date while running. The labels of `<rom>.sym` are
+    /// loaded as well when there is one, see [`GameBoy::symbols`].
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<GameBoy> {
+        let mut gameboy = GameBoy::new(Rom::from_path(&path)?)?;
+        if gameboy.rom().header().cartridge_type.has_battery() {
+            // tetris.gb saves to tetris.sav, right next to the ROM
+            gameboy.set_save_path(path.as_ref().with_extension("sav"))?;
+        }
+        let symbols = path.as_ref().with_extension("sym");
+        if symbols.is_file() {
+            gameboy.set_symbols(SymbolTable::from_path(symbols)?);
+        }
+        Ok(gameboy)
+    }
+
+    /// Fails when the cartridge uses a mapper the emulator does not support.
+    pub fn new(rom: Rom) -> Result<GameBoy> {
+        Ok(GameBoy {
+            cpu: Cpu::new(Mmu::new(rom)?),
+            battery: None,
+            rumble_callback: None,
+            rumble: false,
+            save_error_callback: None,
+            symbols: SymbolTable::new(),
+        })
+    }
+
+    /// Persists the cartridge RAM to `path`, loading it first if the file already exists.
+    pub fn set_save_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let battery = Battery::new(path);
+        battery.load(&mut self.cpu.mmu)?;
+        self.battery = Some(battery);
+        Ok(())
+    }
+
+    /// How often, in clock cycles of emulated time, a dirty cartridge RAM is written back to
+    /// the save file. 0 only saves on `save` and when the console is dropped.
+    pub fn set_save_interval(&mut self, cycles: u32) {
+        if let Some(battery) = &mut self.battery {
+            battery.set_interval(cycles);
+        }
+    }
+
+    /// Writes the cartridge RAM to the save file if it changed since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        match &mut self.battery {
+            Some(battery) => battery.flush(&mut self.cpu.mmu),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a single instruction and returns the amount of clock cycles it took.
+    pub fn step_instruction(&mut self) -> u32 {
+        let cycles = self.cpu.do_cycle();
+        if let Some(battery) = &mut self.battery {
+            if let Err(err) = battery.do_cycle(&mut self.cpu.mmu, cycles) {
+                self.report_save_error(&err);
+            }
+        }
+        self.update_rumble();
+        cycles
+    }
+
+    /// Registers a function called with `true` when the cartridge rumble motor turns on and
+    /// with `false` when it turns off.
+    pub fn set_rumble_callback<F: FnMut(bool) + 'static>(&mut self, callback: F) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    /// Registers a function called with the errors of the saves made while running and when
+    /// the console is dropped. Without one these errors are lost, [`GameBoy::save`] returns
+    /// its own.
+    pub fn set_save_error_callback<F: FnMut(&Error) + 'static>(&mut self, callback: F) {
+        self.save_error_callback = Some(Box::new(callback));
+    }
+
+    fn report_save_error(&mut self, err: &Error) {
+        if let Some(callback) = &mut self.save_error_callback {
+            callback(err);
+        }
+    }
+
+    fn update_rumble(&mut self) {
+        let rumble = self.mbc().map(|mbc| mbc.rumble()).unwrap_or(false);
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            if let Some(callback) = &mut self.rumble_callback {
+                callback(rumble);
+            }
+        }
+    }
+
+    /// Runs instructions until the ppu finishes the current frame (enters VBlank) and returns
+    /// the amount of clock cycles executed. With the LCD off it stops after one frame worth
+    /// of clock cycles instead.
+    pub fn run_frame(&mut self) -> u32 {
+        let mut cycles = 0;
+        loop {
+            cycles += self.step_instruction();
+            if self.cpu.mmu.ppu_mut().take_frame_ready() {
+                break;
+            }
+            if !self.cpu.mmu.ppu().lcd_enabled() && cycles >= CYCLES_PER_FRAME {
+                break;
+            }
+        }
+        cycles
+    }
+
+    /// The last frame drawn, one shade per pixel from 0 (white) to 3 (black), row by row.
+    pub fn framebuffer(&self) -> &[Byte; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        self.cpu.mmu.ppu().framebuffer()
+    }
+
+    /// The last frame drawn as RGB555 colors (red in the low bits), row by row. DMG games
+    /// get their four shades mapped to greys.
+    pub fn rgb_framebuffer(&self) -> &[Word; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        self.cpu.mmu.ppu().rgb_framebuffer()
+    }
+
+    /// Whether the cartridge runs in Game Boy Color mode, picked from its header.
+    pub fn is_cgb(&self) -> bool {
+        self.cpu.mmu.is_cgb()
+    }
+
+    /// Plugs a link cable into the serial port and returns the one plugged in before, by
+    /// default nothing is connected.
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) -> Box<dyn LinkCable> {
+        self.cpu.mmu.set_link_cable(cable)
+    }
+
+    /// Sets the rate, in Hz, of the samples handed out by [`GameBoy::take_audio_samples`].
+    /// Samples buffered at the previous rate are dropped.
+    pub fn set_audio_sample_rate(&mut self, rate: u32) {
+        self.cpu.mmu.apu_mut().set_sample_rate(rate);
+    }
+
+    /// Moves the audio produced so far into `buffer`, as interleaved left and right `i16`
+    /// samples, and returns how many values were written. About a second of audio is kept
+    /// when nobody pulls it, older samples are dropped.
+    pub fn take_audio_samples(&mut self, buffer: &mut [i16]) -> usize {
+        self.cpu.mmu.apu_mut().take_samples(buffer)
+    }
+
+    /// The labels of the ROM, as loaded from an RGBDS or no$gmb `.sym` file. Empty unless
+    /// [`GameBoy::from_path`] found one or [`GameBoy::set_symbols`] was called.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    /// Traces every instruction run from now on in the gameboy-doctor format, see
+    /// [`Tracer`]. `None` stops tracing. Returns the tracer that was set before.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        self.cpu.set_tracer(tracer)
+    }
+
+    /// The tracer given to [`GameBoy::set_tracer`], to change its filters or dump its ring.
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.cpu.tracer_mut()
+    }
+
+    /// Makes LY (0xFF44) always read 0x90, as gameboy-doctor's reference logs expect, so the
+    /// traces of ROMs that wait for VBlank line up with them. Off by default, the PPU itself
+    /// is not affected.
+    pub fn set_doctor_mode(&mut self, enabled: bool) {
+        self.cpu.mmu.set_doctor_mode(enabled);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        self.cpu.registers_mut()
+    }
+
+    pub fn mmu(&self) -> &Mmu {
+        &self.cpu.mmu
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.cpu.mmu
+    }
+
+    pub fn rom(&self) -> &Rom {
+        self.cpu.mmu.rom()
+    }
+
+    pub fn mbc(&self) -> Option<&dyn Mbc> {
+        self.cpu.mmu.mbc()
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        self.cpu.mmu.joypad()
+    }
+
+    /// Tilts an MBC7 cartridge, in g along the X (right) and Y (down) axes.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        if let Some(mbc) = self.cpu.mmu.mbc_mut() {
+            mbc.set_tilt(x, y);
+        }
+    }
+
+    /// Feeds the Pocket Camera sensor with a 128 x 112 grayscale image, 0 is black and 255
+    /// white. It is picked up by the next capture the game starts.
+    pub fn set_camera_image(&mut self, image: &[Byte]) {
+        if let Some(mbc) = self.cpu.mmu.mbc_mut() {
+            mbc.set_camera_image(image);
+        }
+    }
+
+    /// Whether the infrared LED of a HuC1 or HuC3 cartridge is on.
+    pub fn ir_led(&self) -> bool {
+        self.mbc().map(|mbc| mbc.ir_led()).unwrap_or(false)
+    }
+
+    /// Tells a HuC1 or HuC3 cartridge whether its infrared receiver sees light.
+    pub fn set_ir_signal(&mut self, signal: bool) {
+        if let Some(mbc) = self.cpu.mmu.mbc_mut() {
+            mbc.set_ir_signal(signal);
+        }
+    }
+
+    /// Presses one of the `joypad::*_BUTTON` buttons.
+    pub fn press_button(&mut self, button: usize) {
+        self.cpu.mmu.set_button_state(button);
+    }
+
+    /// Releases one of the `joypad::*_BUTTON` buttons.
+    pub fn release_button(&mut self, button: usize) {
+        self.cpu.mmu.reset_button_state(button);
+    }
+}
+
+impl Drop for GameBoy {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            self.report_save_error(&err);
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger::{Debugger, Stop};
+use crate::utils::*;
+use crate::watchpoint::*;
+
+/*
+ * A stub for the GDB remote serial protocol, so gdb, lldb or any other RSP client can drive
+ * the emulator over TCP. Packets look like $data#checksum and get a + back unless the client
+ * asked for QStartNoAckMode. What is supported:
+ *
+ *    ?                         why the target stopped
+ *    g / G                     all the registers, see TARGET_XML for the layout
+ *    p N / P N=VALUE           a single register
+ *    m ADDR,LEN                reads memory through the mmu
+ *    M ADDR,LEN:HEX            writes memory through the mmu
+ *    X ADDR,LEN:BINARY         the same with the bytes sent as they are
+ *    c [ADDR] / s [ADDR]       continue and single step, from ADDR if there is one
+ *    Z0 / Z1 ADDR,KIND         software and hardware breakpoints, both stop when PC gets
+ *                              to ADDR whatever the ROM bank
+ *    Z2 / Z3 / Z4 ADDR,LEN     write, read and access watchpoints
+ *    z0 ... z4                 remove them again
+ *    qSupported, qXfer:features:read:target.xml, qAttached, qC, qfThreadInfo, H, T, D, k
+ *
+ * Anything else gets the empty reply that tells the client the packet is not supported.
+ * There is no architecture gdb knows for the SM83, so the registers come from the target
+ * description: A, F, B, C, D, E, H and L are 8 bits wide, SP and PC 16 bits, in this order.
+ * Register values are sent little-endian, as hex.
+ *
+ * A continue runs CONTINUE_CHUNK instructions at a time and looks for the 0x03 the client
+ * sends on Ctrl-C in between.
+ */
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.yaregb.sm83.core">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="f" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="b" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="c" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="d" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="e" bitsize="8" regnum="5" type="uint8"/>
+    <reg name="h" bitsize="8" regnum="6" type="uint8"/>
+    <reg name="l" bitsize="8" regnum="7" type="uint8"/>
+    <reg name="sp" bitsize="16" regnum="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// the largest packet the client may send, data included, as hex
+const PACKET_SIZE: usize = 0x4000;
+// a reply of m holds two hex digits per byte
+const MAX_READ_LEN: usize = (PACKET_SIZE - 4) / 2;
+const CONTINUE_CHUNK: u64 = 10_000;
+const INTERRUPT: u8 = 0x03;
+
+// signal numbers of the stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// A F B C D E H L SP PC
+const REGISTER_COUNT: usize = 10;
+
+// waits for a single client and serves it until it detaches, kills the target or goes away
+pub fn serve(debugger: &mut Debugger, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(debugger, stream).run()
+}
+
+struct GdbStub<'a> {
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    no_ack: bool,
+    // the reply to ?
+    last_stop: Vec<u8>,
+}
+
+impl<'a> GdbStub<'a> {
+    fn new(debugger: &'a mut Debugger, stream: TcpStream) -> GdbStub<'a> {
+        GdbStub {
+            debugger,
+            stream,
+            no_ack: false,
+            last_stop: stop_signal(SIGTRAP),
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Some(reply) => self.send_packet(&reply)?,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    // the data of the next packet with a valid checksum, None once the client is gone
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // acks, and interrupts that came too late to stop anything
+            match self.read_byte()? {
+                Some(b'$') => (),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            let mut sum: u8 = 0;
+            // past PACKET_SIZE the rest of the packet is only read to get to the next one
+            let mut too_long = false;
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        sum = sum.wrapping_add(byte);
+                        match data.len() < PACKET_SIZE {
+                            true => data.push(byte),
+                            false => too_long = true,
+                        }
+                    }
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+            let checksum = parse_hex(&[high, low]).map(|value| value as u8);
+            if !self.no_ack && checksum != Some(sum) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            if !self.no_ack {
+                self.stream.write_all(b"+")?;
+            }
+            if too_long {
+                self.send_packet(&error())?;
+                continue;
+            }
+            return Ok(Some(unescape(&data)));
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // sends the packet again until the client acks it
+    fn send_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut packet = vec![b'$'];
+        for &byte in data {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => packet.extend_from_slice(&[b'}', byte ^ 0x20]),
+                _ => packet.push(byte),
+            }
+        }
+        let sum = packet[1..]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        packet.extend_from_slice(format!("#{:02x}", sum).as_bytes());
+        loop {
+            self.stream.write_all(&packet)?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // the reply to a packet, None ends the session
+    fn handle(&mut self, packet: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let Some((&command, args)) = packet.split_first() else {
+            return Ok(Some(Vec::new()));
+        };
+        let reply = match command {
+            b'?' => self.last_stop.clone(),
+            b'g' => self.read_registers(),
+            b'G' => ok_or_error(self.write_registers(args)),
+            b'p' => self.read_register(args).unwrap_or_else(error),
+            b'P' => ok_or_error(self.write_register(args)),
+            b'm' => self.read_memory(args).unwrap_or_else(error),
+            b'M' => ok_or_error(self.write_memory(args, true)),
+            b'X' => ok_or_error(self.write_memory(args, false)),
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(pc) => self.debugger.gameboy_mut().registers_mut().pc = pc as Word,
+                        None => return Ok(Some(error())),
+                    }
+                }
+                let stop = match command {
+                    b's' => Some(self.debugger.step(1)),
+                    _ => self.resume()?,
+                };
+                self.last_stop = match stop {
+                    Some(stop) => self.stop_reply(stop),
+                    None => stop_signal(SIGINT),
+                };
+                self.last_stop.clone()
+            }
+            b'Z' => ok_or_error(self.insert_point(args)),
+            b'z' => ok_or_error(self.remove_point(args)),
+            b'q' | b'Q' => self.query(packet),
+            b'H' | b'T' => b"OK".to_vec(),
+            b'D' => {
+                self.send_packet(b"OK")?;
+                return Ok(None);
+            }
+            b'k' => return Ok(None),
+            _ => Vec::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, packet: &[u8]) -> Vec<u8> {
+        let packet = String::from_utf8_lossy(packet);
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            )
+            .into_bytes();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match range.split_once(',').and_then(|(offset, len)| {
+                Some((parse_hex(offset.as_bytes())?, parse_hex(len.as_bytes())?))
+            }) {
+                Some((offset, len)) => xfer(TARGET_XML.as_bytes(), offset as usize, len as usize),
+                None => error(),
+            };
+        }
+        match packet.as_ref() {
+            "QStartNoAckMode" => {
+                // the OK still gets acked
+                self.no_ack = true;
+                b"OK".to_vec()
+            }
+            "qAttached" => b"1".to_vec(),
+            "qC" => b"QC1".to_vec(),
+            "qfThreadInfo" => b"m1".to_vec(),
+            "qsThreadInfo" => b"l".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    // None when the client interrupted the run
+    fn resume(&mut self) -> io::Result<Option<Stop>> {
+        loop {
+            match self.debugger.resume(Some(CONTINUE_CHUNK)) {
+                Stop::Limit if self.interrupted()? => return Ok(None),
+                Stop::Limit => (),
+                stop => return Ok(Some(stop)),
+            }
+        }
+    }
+
+    // a Ctrl-C, or the client going away, ends a continue
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn stop_reply(&self, stop: Stop) -> Vec<u8> {
+        match stop {
+            Stop::Locked => stop_signal(SIGILL),
+            Stop::Watchpoint { hit, .. } => {
+                let kind = self
+                    .debugger
+                    .gameboy()
+                    .mmu()
+                    .watchpoints()
+                    .list()
+                    .iter()
+                    .find(|watchpoint| watchpoint.id == hit.id)
+                    .map(|watchpoint| watchpoint.kind);
+                let reason = match kind {
+                    Some(WatchKind::Read) => "rwatch",
+                    Some(WatchKind::Access) => "awatch",
+                    _ => "watch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, reason, hit.addr).into_bytes()
+            }
+            Stop::Done | Stop::Breakpoint(_) | Stop::Limit => stop_signal(SIGTRAP),
+        }
+    }
+
+    fn registers(&self) -> [Word; REGISTER_COUNT] {
+        let reg = self.debugger.gameboy().registers();
+        let [a, f] = reg.af().to_be_bytes();
+        [
+            a as Word,
+            f as Word,
+            reg.b as Word,
+            reg.c as Word,
+            reg.d as Word,
+            reg.e as Word,
+            reg.h as Word,
+            reg.l as Word,
+            reg.sp,
+            reg.pc,
+        ]
+    }
+
+    fn set_register(&mut self, n: usize, value: Word) {
+        let reg = self.debugger.gameboy_mut().registers_mut();
+        match n {
+            0 => reg.setaf((value << 8) | (reg.af() & 0xFF)),
+            1 => reg.setaf((reg.af() & 0xFF00) | (value & 0xFF)),
+            2 => reg.b = value as Byte,
+            3 => reg.c = value as Byte,
+            4 => reg.d = value as Byte,
+            5 => reg.e = value as Byte,
+            6 => reg.h = value as Byte,
+            7 => reg.l = value as Byte,
+            8 => reg.sp = value,
+            _ => reg.pc = value,
+        }
+    }
+
+    fn read_registers(&self) -> Vec<u8> {
+        let registers = self.registers();
+        (0..REGISTER_COUNT)
+            .flat_map(|n| register_hex(n, registers[n]).into_bytes())
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &[u8]) -> Option<()> {
+        let mut rest = args;
+        let mut values = [0; REGISTER_COUNT];
+        for (n, value) in values.iter_mut().enumerate() {
+            let len = register_size(n) * 2;
+            if rest.len() < len {
+                return None;
+            }
+            let (hex, tail) = rest.split_at(len);
+            *value = parse_le_hex(hex)?;
+            rest = tail;
+        }
+        for (n, value) in values.into_iter().enumerate() {
+            self.set_register(n, value);
+        }
+        Some(())
+    }
+
+    fn read_register(&self, args: &[u8]) -> Option<Vec<u8>> {
+        let n = parse_hex(args)? as usize;
+        let value = *self.registers().get(n)?;
+        Some(register_hex(n, value).into_bytes())
+    }
+
+    // P N=VALUE
+    fn write_register(&mut self, args: &[u8]) -> Option<()> {
+        let (n, value) = split_at_byte(args, b'=')?;
+        let n = parse_hex(n)? as usize;
+        if n >= REGISTER_COUNT || value.len() != register_size(n) * 2 {
+            return None;
+        }
+        let value = parse_le_hex(value)?;
+        self.set_register(n, value);
+        Some(())
+    }
+
+    // m ADDR,LEN
+    fn read_memory(&self, args: &[u8]) -> Option<Vec<u8>> {
+        let (addr, len) = split_at_byte(args, b',')?;
+        let addr = parse_hex(addr)?;
+        let len = (parse_hex(len)? as usize).min(MAX_READ_LEN);
+        if addr > 0xFFFF {
+            return None;
+        }
+        let mmu = self.debugger.gameboy().mmu();
+        // the bus ends at FFFF
+        let len = len.min(0x10000 - addr as usize);
+        Some(
+            (0..len)
+                .flat_map(|i| {
+                    format!("{:02x}", mmu.peek_byte(addr as Word + i as Word)).into_bytes()
+                })
+                .collect(),
+        )
+    }
+
+    // M ADDR,LEN:HEX or X ADDR,LEN:BINARY
+    fn write_memory(&mut self, args: &[u8], hex: bool) -> Option<()> {
+        let (location, data) = split_at_byte(args, b':')?;
+        let (addr, len) = split_at_byte(location, b',')?;
+        let addr = parse_hex(addr)?;
+        let len = parse_hex(len)? as usize;
+        let bytes = match hex {
+            true => data
+                .chunks(2)
+                .map(|pair| parse_hex(pair).map(|byte| byte as Byte))
+                .collect::<Option<Vec<Byte>>>()?,
+            false => data.to_vec(),
+        };
+        if bytes.len() != len || addr as usize + len > 0x10000 {
+            return None;
+        }
+        let mmu = self.debugger.gameboy_mut().mmu_mut();
+        for (i, byte) in bytes.into_iter().enumerate() {
+            mmu.write_byte(addr as Word + i as Word, byte);
+        }
+        Some(())
+    }
+
+    // TYPE,ADDR,KIND
+    fn insert_point(&mut self, args: &[u8]) -> Option<()> {
+        let (kind, addr, len) = parse_point(args)?;
+        match kind {
+            b'0' | b'1' => {
+                self.debugger.add_breakpoint(None, addr);
+            }
+            _ => {
+                let end = point_end(addr, len)?;
+                self.debugger.gameboy_mut().mmu_mut().watchpoints_mut().add(
+                    addr,
+                    end,
+                    None,
+                    watch_kind(kind)?,
+                );
+            }
+        }
+        Some(())
+    }
+
+    fn remove_point(&mut self, args: &[u8]) -> Option<()> {
+        let (kind, addr, len) = parse_point(args)?;
+        match kind {
+            b'0' | b'1' => {
+                let id = self
+                    .debugger
+                    .breakpoints()
+                    .iter()
+                    .find(|breakpoint| breakpoint.bank.is_none() && breakpoint.addr == addr)?
+                    .id;
+                self.debugger.remove_breakpoint(id);
+            }
+            _ => {
+                let end = point_end(addr, len)?;
+                let kind = watch_kind(kind)?;
+                let watchpoints = self.debugger.gameboy_mut().mmu_mut().watchpoints_mut();
+                let id = watchpoints
+                    .list()
+                    .iter()
+                    .find(|watchpoint| {
+                        (
+                            watchpoint.start,
+                            watchpoint.end,
+                            watchpoint.bank,
+                            watchpoint.kind,
+                        ) == (addr, end, None, kind)
+                    })?
+                    .id;
+                watchpoints.remove(id);
+            }
+        }
+        Some(())
+    }
+}
+
+fn stop_signal(signal: u8) -> Vec<u8> {
+    format!("S{:02x}", signal).into_bytes()
+}
+
+fn error() -> Vec<u8> {
+    b"E01".to_vec()
+}
+
+fn ok_or_error(result: Option<()>) -> Vec<u8> {
+    match result {
+        Some(()) => b"OK".to_vec(),
+        None => error(),
+    }
+}
+
+// in bytes
+fn register_size(n: usize) -> usize {
+    match n {
+        0..=7 => 1,
+        _ => 2,
+    }
+}
+
+fn register_hex(n: usize, value: Word) -> String {
+    match register_size(n) {
+        1 => format!("{:02x}", value),
+        _ => format!("{:02x}{:02x}", value & 0xFF, value >> 8),
+    }
+}
+
+// the value of a register as sent, low byte first
+fn parse_le_hex(hex: &[u8]) -> Option<Word> {
+    hex.chunks(2).rev().try_fold(0, |value: Word, pair| {
+        Some((value << 8) | parse_hex(pair)? as Word)
+    })
+}
+
+fn parse_hex(hex: &[u8]) -> Option<u32> {
+    if hex.is_empty() || hex.len() > 8 {
+        return None;
+    }
+    u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
+
+fn split_at_byte(data: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = data.iter().position(|&byte| byte == separator)?;
+    Some((&data[..at], &data[at + 1..]))
+}
+
+// TYPE,ADDR,KIND of Z and z, KIND is the length for the watchpoints
+// the length stays 32 bit, a watchpoint over the whole address space is 0x10000 long
+fn parse_point(args: &[u8]) -> Option<(u8, Word, u32)> {
+    let (&kind, rest) = args.split_first()?;
+    let rest = rest.strip_prefix(b",")?;
+    let (addr, len) = split_at_byte(rest, b',')?;
+    // a breakpoint may come with conditions after a ;
+    let len = len.split(|&byte| byte == b';').next()?;
+    let addr = parse_hex(addr)?;
+    let len = parse_hex(len)?;
+    if addr > 0xFFFF || len > 0x10000 || !(b'0'..=b'4').contains(&kind) {
+        return None;
+    }
+    Some((kind, addr as Word, len))
+}
+
+// the last address covered by a watchpoint of len bytes, at least one
+fn point_end(addr: Word, len: u32) -> Option<Word> {
+    Word::try_from(addr as u32 + len.max(1) - 1).ok()
+}
+
+fn watch_kind(kind: u8) -> Option<WatchKind> {
+    match kind {
+        b'2' => Some(WatchKind::Write),
+        b'3' => Some(WatchKind::Read),
+        b'4' => Some(WatchKind::Access),
+        _ => None,
+    }
+}
+
+// }X stands for X ^ 0x20
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for &byte in data {
+        match (escaped, byte) {
+            (true, _) => {
+                bytes.push(byte ^ 0x20);
+                escaped = false;
+            }
+            (false, b'}') => escaped = true,
+            (false, _) => bytes.push(byte),
+        }
+    }
+    bytes
+}
+
+// a part of an qXfer object, l when it is the last one
+fn xfer(object: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let start = offset.min(object.len());
+    let end = offset.saturating_add(len).min(object.len());
+    let mut reply = vec![if end == object.len() { b'l' } else { b'm' }];
+    reply.extend_from_slice(&object[start..end]);
+    reply
+}
@@ -0,0 +1,118 @@
+use crate::utils::*;
+
+/*
+ * CGB VRAM DMA (HDMA). 0xFF51/0xFF52 hold the source and 0xFF53/0xFF54 the destination in
+ * VRAM, the lower 4 bits of both are ignored. Writing 0xFF55 starts a transfer of
+ * (bits 0 - 6 + 1) blocks of 16 bytes:
+ *
+ *    bit 7 = 0    general purpose DMA, everything is copied at once
+ *    bit 7 = 1    HBlank DMA, one block at the start of every HBlank
+ *
+ * The cpu is stopped while a block is being copied, 8 machine cycles per block at normal
+ * speed and 16 at double speed (the DMA runs at the same speed in both modes). Writing
+ * bit 7 = 0 while an HBlank DMA is running stops it.
+ *
+ * Reading 0xFF55 returns the blocks left minus one, with bit 7 cleared while an HBlank DMA
+ * is running, 0xFF once a transfer is done.
+ *
+ * see https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+ */
+
+pub const HDMA_BLOCK_SIZE: Word = 0x10;
+
+// machine cycles the cpu waits for each block at normal speed
+pub const HDMA_BLOCK_CYCLES: u32 = 8;
+
+const HDMA_HBLANK_MODE: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdmaMode {
+    GeneralPurpose,
+    HBlank,
+}
+
+pub struct Hdma {
+    source: Word,
+    destination: Word,
+    // blocks left minus one, as 0xFF55 reads it
+    remaining: Byte,
+    mode: Option<HdmaMode>,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            destination: 0,
+            remaining: 0x7F,
+            mode: None,
+        }
+    }
+
+    pub fn mode(&self) -> Option<HdmaMode> {
+        self.mode
+    }
+
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        match addr {
+            VRAM_DMA_TRANSFER_ADDR => match self.mode {
+                Some(_) => self.remaining,
+                None => 0x80 | self.remaining,
+            },
+            // the address registers are write only
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        match addr {
+            VRAM_DMA_SOURCE_HIGH_ADDR => {
+                self.source = (self.source & 0x00F0) | ((data as Word) << 8)
+            }
+            VRAM_DMA_SOURCE_LOW_ADDR => {
+                self.source = (self.source & 0xFF00) | (data & 0xF0) as Word
+            }
+            VRAM_DMA_DESTINATION_HIGH_ADDR => {
+                self.destination = (self.destination & 0x00F0) | (((data & 0x1F) as Word) << 8)
+            }
+            VRAM_DMA_DESTINATION_LOW_ADDR => {
+                self.destination = (self.destination & 0x1F00) | (data & 0xF0) as Word
+            }
+            VRAM_DMA_TRANSFER_ADDR => {
+                if self.mode == Some(HdmaMode::HBlank) && !bit_set(&data, HDMA_HBLANK_MODE) {
+                    self.mode = None;
+                    return;
+                }
+                self.remaining = data & 0x7F;
+                self.mode = if bit_set(&data, HDMA_HBLANK_MODE) {
+                    Some(HdmaMode::HBlank)
+                } else {
+                    Some(HdmaMode::GeneralPurpose)
+                };
+            }
+            _ => {}
+        }
+    }
+
+    // the source and the VRAM destination of the next block, moves both to the block after
+    pub fn next_block(&mut self) -> Option<(Word, Word)> {
+        self.mode?;
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.destination = (self.destination + HDMA_BLOCK_SIZE) & 0x1FF0;
+        // the destination wrapping past 0x9FFF ends the transfer as well
+        if self.remaining == 0 || self.destination == 0 {
+            self.remaining = 0x7F;
+            self.mode = None;
+        } else {
+            self.remaining -= 1;
+        }
+        Some(block)
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Hdma {
+        Hdma::new()
+    }
+}
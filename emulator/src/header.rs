@@ -0,0 +1,568 @@
+use crate::error::*;
+use crate::utils::*;
+
+/*
+ * Cartridge header, every cartridge carries it between 0x0100 and 0x014F:
+ *    0100 - 0103	    Entry point, usually NOP; JP 0150
+ *    0104 - 0133	    Nintendo logo
+ *    0134 - 0143	    Title, upper case ASCII padded with 0x00
+ *    013F - 0142	    Manufacturer code (newer cartridges only, overlaps the title)
+ *    0143 - 0143	    CGB flag (newer cartridges only, overlaps the title)
+ *    0144 - 0145	    New licensee code, two ASCII characters
+ *    0146 - 0146	    SGB flag
+ *    0147 - 0147	    Cartridge type, which mapper and extra hardware it has
+ *    0148 - 0148	    ROM size
+ *    0149 - 0149	    RAM size
+ *    014A - 014A	    Destination code
+ *    014B - 014B	    Old licensee code, 0x33 means the new licensee code is used instead
+ *    014C - 014C	    Mask ROM version number
+ *    014D - 014D	    Header checksum over 0134 - 014C, the boot ROM locks up if it is wrong
+ *    014E - 014F	    Global checksum over the whole ROM (big endian), never verified by hardware
+ *
+ * see https://gbdev.io/pandocs/The_Cartridge_Header.html
+ */
+
+const TITLE_ADDR: usize = 0x0134;
+const MANUFACTURER_CODE_ADDR: usize = 0x013F;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const NEW_LICENSEE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
+const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+const ROM_SIZE_ADDR: usize = 0x0148;
+const RAM_SIZE_ADDR: usize = 0x0149;
+const DESTINATION_ADDR: usize = 0x014A;
+const OLD_LICENSEE_ADDR: usize = 0x014B;
+const VERSION_ADDR: usize = 0x014C;
+const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x014E;
+
+// old licensee value that points at the two character new licensee code
+const USE_NEW_LICENSEE: Byte = 0x33;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbFlag {
+    // DMG only cartridge, the byte is still part of the title
+    None,
+    // works on DMG, uses CGB features when available (0x80)
+    Supported,
+    // CGB only (0xC0)
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(Byte),
+}
+
+// what to do when one of the header checksums does not match the ROM contents. the boot ROM
+// locks up on a bad header checksum but never looks at the global one, so Reject only turns
+// the former away and warns about the latter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumPolicy {
+    Ignore,
+    Warn,
+    #[default]
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub old_licensee_code: Byte,
+    pub new_licensee_code: Option<String>,
+    pub sgb_flag: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub version: Byte,
+    pub header_checksum: Byte,
+    pub global_checksum: Word,
+    computed_header_checksum: Byte,
+    computed_global_checksum: Word,
+}
+
+impl CartridgeType {
+    pub fn from_byte(value: Byte) -> CartridgeType {
+        match value {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::Mbc1,
+            0x02 => CartridgeType::Mbc1Ram,
+            0x03 => CartridgeType::Mbc1RamBattery,
+            0x05 => CartridgeType::Mbc2,
+            0x06 => CartridgeType::Mbc2Battery,
+            0x08 => CartridgeType::RomRam,
+            0x09 => CartridgeType::RomRamBattery,
+            0x0B => CartridgeType::Mmm01,
+            0x0C => CartridgeType::Mmm01Ram,
+            0x0D => CartridgeType::Mmm01RamBattery,
+            0x0F => CartridgeType::Mbc3TimerBattery,
+            0x10 => CartridgeType::Mbc3TimerRamBattery,
+            0x11 => CartridgeType::Mbc3,
+            0x12 => CartridgeType::Mbc3Ram,
+            0x13 => CartridgeType::Mbc3RamBattery,
+            0x19 => CartridgeType::Mbc5,
+            0x1A => CartridgeType::Mbc5Ram,
+            0x1B => CartridgeType::Mbc5RamBattery,
+            0x1C => CartridgeType::Mbc5Rumble,
+            0x1D => CartridgeType::Mbc5RumbleRam,
+            0x1E => CartridgeType::Mbc5RumbleRamBattery,
+            0x20 => CartridgeType::Mbc6,
+            0x22 => CartridgeType::Mbc7SensorRumbleRamBattery,
+            0xFC => CartridgeType::PocketCamera,
+            0xFD => CartridgeType::BandaiTama5,
+            0xFE => CartridgeType::HuC3,
+            0xFF => CartridgeType::HuC1RamBattery,
+            _ => CartridgeType::Unknown(value),
+        }
+    }
+
+    pub fn to_byte(self) -> Byte {
+        match self {
+            CartridgeType::RomOnly => 0x00,
+            CartridgeType::Mbc1 => 0x01,
+            CartridgeType::Mbc1Ram => 0x02,
+            CartridgeType::Mbc1RamBattery => 0x03,
+            CartridgeType::Mbc2 => 0x05,
+            CartridgeType::Mbc2Battery => 0x06,
+            CartridgeType::RomRam => 0x08,
+            CartridgeType::RomRamBattery => 0x09,
+            CartridgeType::Mmm01 => 0x0B,
+            CartridgeType::Mmm01Ram => 0x0C,
+            CartridgeType::Mmm01RamBattery => 0x0D,
+            CartridgeType::Mbc3TimerBattery => 0x0F,
+            CartridgeType::Mbc3TimerRamBattery => 0x10,
+            CartridgeType::Mbc3 => 0x11,
+            CartridgeType::Mbc3Ram => 0x12,
+            CartridgeType::Mbc3RamBattery => 0x13,
+            CartridgeType::Mbc5 => 0x19,
+            CartridgeType::Mbc5Ram => 0x1A,
+            CartridgeType::Mbc5RamBattery => 0x1B,
+            CartridgeType::Mbc5Rumble => 0x1C,
+            CartridgeType::Mbc5RumbleRam => 0x1D,
+            CartridgeType::Mbc5RumbleRamBattery => 0x1E,
+            CartridgeType::Mbc6 => 0x20,
+            CartridgeType::Mbc7SensorRumbleRamBattery => 0x22,
+            CartridgeType::PocketCamera => 0xFC,
+            CartridgeType::BandaiTama5 => 0xFD,
+            CartridgeType::HuC3 => 0xFE,
+            CartridgeType::HuC1RamBattery => 0xFF,
+            CartridgeType::Unknown(value) => value,
+        }
+    }
+
+    pub fn has_ram(self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc1Ram
+                | CartridgeType::Mbc1RamBattery
+                // MBC2 always has its 512 x 4 bit RAM built in
+                | CartridgeType::Mbc2
+                | CartridgeType::Mbc2Battery
+                | CartridgeType::RomRam
+                | CartridgeType::RomRamBattery
+                | CartridgeType::Mmm01Ram
+                | CartridgeType::Mmm01RamBattery
+                | CartridgeType::Mbc3TimerRamBattery
+                | CartridgeType::Mbc3Ram
+                | CartridgeType::Mbc3RamBattery
+                | CartridgeType::Mbc5Ram
+                | CartridgeType::Mbc5RamBattery
+                | CartridgeType::Mbc5RumbleRam
+                | CartridgeType::Mbc5RumbleRamBattery
+                | CartridgeType::Mbc6
+                | CartridgeType::Mbc7SensorRumbleRamBattery
+                | CartridgeType::PocketCamera
+                | CartridgeType::HuC3
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+
+    pub fn has_battery(self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc1RamBattery
+                | CartridgeType::Mbc2Battery
+                | CartridgeType::RomRamBattery
+                | CartridgeType::Mmm01RamBattery
+                | CartridgeType::Mbc3TimerBattery
+                | CartridgeType::Mbc3TimerRamBattery
+                | CartridgeType::Mbc3RamBattery
+                | CartridgeType::Mbc5RamBattery
+                | CartridgeType::Mbc5RumbleRamBattery
+                | CartridgeType::Mbc6
+                | CartridgeType::Mbc7SensorRumbleRamBattery
+                | CartridgeType::PocketCamera
+                | CartridgeType::BandaiTama5
+                | CartridgeType::HuC3
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+
+    // the clocks kept in the .sav file, which are the MBC3 ones only: the HuC3 clock is lost
+    // when the emulator closes and the TAMA5 one is not emulated
+    pub fn has_rtc(self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc3TimerBattery | CartridgeType::Mbc3TimerRamBattery
+        )
+    }
+
+    pub fn has_rumble(self) -> bool {
+        matches!(
+            self,
+            CartridgeType::Mbc5Rumble
+                | CartridgeType::Mbc5RumbleRam
+                | CartridgeType::Mbc5RumbleRamBattery
+                | CartridgeType::Mbc7SensorRumbleRamBattery
+        )
+    }
+}
+
+impl CartridgeHeader {
+    pub fn parse(data: &[Byte]) -> CartridgeHeader {
+        // anything past the end of a truncated file reads as an open bus
+        let byte = |addr: usize| data.get(addr).copied().unwrap_or(0xFF);
+
+        let cgb_flag = match byte(CGB_FLAG_ADDR) {
+            0x80 => CgbFlag::Supported,
+            0xC0 => CgbFlag::Only,
+            _ => CgbFlag::None,
+        };
+
+        // the manufacturer code only exists on CGB era cartridges, older titles use those bytes
+        let manufacturer_code = match cgb_flag {
+            CgbFlag::None => None,
+            _ => {
+                let code: Vec<Byte> = (0..4).map(|i| byte(MANUFACTURER_CODE_ADDR + i)).collect();
+                match code
+                    .iter()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                {
+                    true => Some(String::from_utf8_lossy(&code).into_owned()),
+                    false => None,
+                }
+            }
+        };
+
+        let title_end = match (cgb_flag, &manufacturer_code) {
+            (CgbFlag::None, _) => CGB_FLAG_ADDR + 1,
+            (_, Some(_)) => MANUFACTURER_CODE_ADDR,
+            (_, None) => CGB_FLAG_ADDR,
+        };
+        let title: String = (TITLE_ADDR..title_end)
+            .map(byte)
+            .take_while(|c| *c != 0x00)
+            .filter(|c| c.is_ascii_graphic() || *c == b' ')
+            .map(|c| c as char)
+            .collect();
+
+        let old_licensee_code = byte(OLD_LICENSEE_ADDR);
+        let new_licensee_code = match old_licensee_code {
+            USE_NEW_LICENSEE => {
+                let code = [byte(NEW_LICENSEE_ADDR), byte(NEW_LICENSEE_ADDR + 1)];
+                Some(String::from_utf8_lossy(&code).into_owned())
+            }
+            _ => None,
+        };
+
+        // SGB functions are only enabled when the old licensee code also says so
+        let sgb_flag = byte(SGB_FLAG_ADDR) == 0x03 && old_licensee_code == USE_NEW_LICENSEE;
+
+        let destination = match byte(DESTINATION_ADDR) {
+            0x00 => Destination::Japan,
+            _ => Destination::Overseas,
+        };
+
+        let computed_header_checksum = (TITLE_ADDR..HEADER_CHECKSUM_ADDR)
+            .fold(0 as Byte, |sum, addr| {
+                sum.wrapping_sub(byte(addr)).wrapping_sub(1)
+            });
+
+        // every byte of the ROM except the two checksum bytes themselves
+        let computed_global_checksum = data
+            .iter()
+            .enumerate()
+            .filter(|(addr, _)| *addr != GLOBAL_CHECKSUM_ADDR && *addr != GLOBAL_CHECKSUM_ADDR + 1)
+            .fold(0 as Word, |sum, (_, value)| {
+                sum.wrapping_add(*value as Word)
+            });
+
+        CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            old_licensee_code,
+            new_licensee_code,
+            sgb_flag,
+            cartridge_type: CartridgeType::from_byte(byte(CARTRIDGE_TYPE_ADDR)),
+            rom_size: rom_banks(byte(ROM_SIZE_ADDR)) * ROM_BANK_SIZE,
+            ram_size: ram_size(byte(RAM_SIZE_ADDR)),
+            destination,
+            version: byte(VERSION_ADDR),
+            header_checksum: byte(HEADER_CHECKSUM_ADDR),
+            global_checksum: ((byte(GLOBAL_CHECKSUM_ADDR) as Word) << 8)
+                | byte(GLOBAL_CHECKSUM_ADDR + 1) as Word,
+            computed_header_checksum,
+            computed_global_checksum,
+        }
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_size / ROM_BANK_SIZE
+    }
+
+    pub fn ram_banks(&self) -> usize {
+        self.ram_size.div_ceil(RAM_BANK_SIZE)
+    }
+
+    // size of the battery backed RAM as stored in a .sav file, MBC2 declares no RAM in the
+    // header but always carries 512 half bytes, saved one per byte. The mappers with memory
+    // other than RAM save that instead (MBC7, TAMA5) or after it (MBC6)
+    pub fn save_size(&self) -> usize {
+        match self.cartridge_type {
+            CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => MBC2_RAM_SIZE,
+            CartridgeType::Mbc6 => MBC6_RAM_SIZE + MBC6_FLASH_SIZE,
+            CartridgeType::Mbc7SensorRumbleRamBattery => MBC7_EEPROM_SIZE,
+            CartridgeType::BandaiTama5 => TAMA5_RAM_SIZE,
+            _ => self.ram_size,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb_flag != CgbFlag::None
+    }
+
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    // describes the first checksum mismatch, if any
+    pub fn verify_checksums(&self) -> Result<()> {
+        if !self.header_checksum_valid() {
+            return Err(Error::HeaderChecksumMismatch {
+                expected: self.header_checksum,
+                computed: self.computed_header_checksum,
+            });
+        }
+        if !self.global_checksum_valid() {
+            return Err(Error::GlobalChecksumMismatch {
+                expected: self.global_checksum,
+                computed: self.computed_global_checksum,
+            });
+        }
+        Ok(())
+    }
+
+    // fails on the mismatches the policy rejects, returns the one it only warns about
+    pub fn check(&self, policy: ChecksumPolicy) -> Result<Option<Error>> {
+        match (policy, self.verify_checksums()) {
+            (ChecksumPolicy::Reject, Err(err @ Error::HeaderChecksumMismatch { .. })) => Err(err),
+            (ChecksumPolicy::Reject | ChecksumPolicy::Warn, Err(err)) => Ok(Some(err)),
+            _ => Ok(None),
+        }
+    }
+}
+
+// 0x00 - 0x08 are 32 KiB shifted left by the value, 0x52 - 0x54 only show up in unofficial docs
+fn rom_banks(value: Byte) -> usize {
+    match value {
+        0x00..=0x08 => 2 << value,
+        0x52 => 72,
+        0x53 => 80,
+        0x54 => 96,
+        _ => 2,
+    }
+}
+
+fn ram_size(value: Byte) -> usize {
+    match value {
+        0x01 => 0x800,
+        0x02 => RAM_BANK_SIZE,
+        0x03 => 4 * RAM_BANK_SIZE,
+        0x04 => 16 * RAM_BANK_SIZE,
+        0x05 => 8 * RAM_BANK_SIZE,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::set_header_checksum;
+
+    // an empty 32 KiB ROM with the given bytes from 0x0134 on
+    fn header(fields: &[Byte]) -> Vec<Byte> {
+        let mut data = vec![0; 0x8000];
+        data[TITLE_ADDR..TITLE_ADDR + fields.len()].copy_from_slice(fields);
+        data
+    }
+
+    fn with_byte(addr: usize, value: Byte) -> CartridgeHeader {
+        let mut data = header(b"TEST");
+        data[addr] = value;
+        CartridgeHeader::parse(&data)
+    }
+
+    #[test]
+    fn title_and_manufacturer_code() {
+        // 16 characters on a DMG cartridge
+        let parsed = CartridgeHeader::parse(&header(b"SIXTEEN CHARS OK"));
+        assert_eq!(parsed.title, "SIXTEEN CHARS OK");
+        assert_eq!(parsed.manufacturer_code, None);
+
+        let parsed = CartridgeHeader::parse(&header(b"ZELDA\0\0\0\0\0\0AZLE\x80"));
+        assert_eq!(parsed.title, "ZELDA");
+        assert_eq!(parsed.manufacturer_code.as_deref(), Some("AZLE"));
+
+        let parsed = CartridgeHeader::parse(&header(b"POKEMON SILVAAX\x80"));
+        assert_eq!(parsed.title, "POKEMON SIL");
+        assert_eq!(parsed.manufacturer_code.as_deref(), Some("VAAX"));
+
+        // without a valid code the title goes up to the CGB flag
+        let parsed = CartridgeHeader::parse(&header(b"POKEMON_SILv\0\0\0\xC0"));
+        assert_eq!(parsed.title, "POKEMON_SILv");
+        assert_eq!(parsed.manufacturer_code, None);
+    }
+
+    #[test]
+    fn cgb_and_sgb_flags() {
+        assert_eq!(with_byte(CGB_FLAG_ADDR, 0x00).cgb_flag, CgbFlag::None);
+        assert_eq!(with_byte(CGB_FLAG_ADDR, 0x80).cgb_flag, CgbFlag::Supported);
+        assert_eq!(with_byte(CGB_FLAG_ADDR, 0xC0).cgb_flag, CgbFlag::Only);
+        assert!(with_byte(CGB_FLAG_ADDR, 0xC0).is_cgb());
+        assert!(!with_byte(CGB_FLAG_ADDR, 0x42).is_cgb());
+
+        let mut data = header(b"TEST");
+        data[SGB_FLAG_ADDR] = 0x03;
+        assert!(!CartridgeHeader::parse(&data).sgb_flag);
+        data[OLD_LICENSEE_ADDR] = USE_NEW_LICENSEE;
+        assert!(CartridgeHeader::parse(&data).sgb_flag);
+    }
+
+    #[test]
+    fn licensee_codes() {
+        let mut data = header(b"TEST");
+        data[NEW_LICENSEE_ADDR..NEW_LICENSEE_ADDR + 2].copy_from_slice(b"01");
+        data[OLD_LICENSEE_ADDR] = 0x01;
+        let parsed = CartridgeHeader::parse(&data);
+        assert_eq!(parsed.old_licensee_code, 0x01);
+        assert_eq!(parsed.new_licensee_code, None);
+
+        data[OLD_LICENSEE_ADDR] = USE_NEW_LICENSEE;
+        let parsed = CartridgeHeader::parse(&data);
+        assert_eq!(parsed.new_licensee_code.as_deref(), Some("01"));
+    }
+
+    #[test]
+    fn rom_and_ram_sizes() {
+        assert_eq!(with_byte(ROM_SIZE_ADDR, 0x00).rom_size, 0x8000);
+        assert_eq!(with_byte(ROM_SIZE_ADDR, 0x05).rom_banks(), 64);
+        assert_eq!(with_byte(ROM_SIZE_ADDR, 0x08).rom_size, 0x800000);
+        assert_eq!(with_byte(ROM_SIZE_ADDR, 0x52).rom_banks(), 72);
+        assert_eq!(with_byte(ROM_SIZE_ADDR, 0x54).rom_banks(), 96);
+
+        let sizes = [0, 0x800, 0x2000, 0x8000, 0x20000, 0x10000];
+        for (value, size) in sizes.into_iter().enumerate() {
+            assert_eq!(with_byte(RAM_SIZE_ADDR, value as Byte).ram_size, size);
+        }
+        assert_eq!(with_byte(RAM_SIZE_ADDR, 0x01).ram_banks(), 1);
+        assert_eq!(with_byte(RAM_SIZE_ADDR, 0x04).ram_banks(), 16);
+    }
+
+    #[test]
+    fn save_sizes() {
+        let save_size = |cartridge_type: Byte| with_byte(CARTRIDGE_TYPE_ADDR, cartridge_type);
+        assert_eq!(save_size(0x06).save_size(), MBC2_RAM_SIZE);
+        assert_eq!(save_size(0x20).save_size(), MBC6_RAM_SIZE + MBC6_FLASH_SIZE);
+        assert_eq!(save_size(0x22).save_size(), MBC7_EEPROM_SIZE);
+        assert_eq!(save_size(0xFD).save_size(), TAMA5_RAM_SIZE);
+
+        let mut data = header(b"TEST");
+        data[CARTRIDGE_TYPE_ADDR] = 0x03;
+        data[RAM_SIZE_ADDR] = 0x03;
+        assert_eq!(CartridgeHeader::parse(&data).save_size(), 0x8000);
+    }
+
+    #[test]
+    fn checksum_fields() {
+        let mut data = header(b"TEST");
+        data[DESTINATION_ADDR] = 0x01;
+        data[VERSION_ADDR] = 0x02;
+        set_header_checksum(&mut data);
+        let sum = data
+            .iter()
+            .fold(0 as Word, |sum, &b| sum.wrapping_add(b as Word));
+        data[GLOBAL_CHECKSUM_ADDR..GLOBAL_CHECKSUM_ADDR + 2].copy_from_slice(&sum.to_be_bytes());
+
+        let parsed = CartridgeHeader::parse(&data);
+        assert_eq!(parsed.destination, Destination::Overseas);
+        assert_eq!(parsed.version, 0x02);
+        assert_eq!(parsed.header_checksum, data[HEADER_CHECKSUM_ADDR]);
+        assert_eq!(parsed.global_checksum, sum);
+        assert!(parsed.header_checksum_valid());
+        assert!(parsed.global_checksum_valid());
+        assert!(parsed.verify_checksums().is_ok());
+
+        // the version is covered by the header checksum
+        data[VERSION_ADDR] = 0x03;
+        let parsed = CartridgeHeader::parse(&data);
+        assert!(matches!(
+            parsed.verify_checksums(),
+            Err(Error::HeaderChecksumMismatch { .. })
+        ));
+        assert!(parsed.check(ChecksumPolicy::Reject).is_err());
+        assert!(parsed.check(ChecksumPolicy::Warn).unwrap().is_some());
+        assert!(parsed.check(ChecksumPolicy::Ignore).unwrap().is_none());
+
+        // past the header only the global one changes
+        data[VERSION_ADDR] = 0x02;
+        data[0x4000] = 0x01;
+        let parsed = CartridgeHeader::parse(&data);
+        assert!(parsed.header_checksum_valid());
+        assert!(matches!(
+            parsed.check(ChecksumPolicy::Reject),
+            Ok(Some(Error::GlobalChecksumMismatch { .. }))
+        ));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::utils::*;
+
+// there are five interrupt sources, each one owns a bit on IF (0xFF0F) and IE (0xFFFF).
+// when more than one is pending the lowest bit wins, which is also the order below.
+// see https://gbdev.io/pandocs/Interrupts.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0b00001,
+    LcdStat = 0b00010,
+    Timer = 0b00100,
+    Serial = 0b01000,
+    Joypad = 0b10000,
+}
+
+// highest priority first
+pub const INTERRUPTS: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+// only the five lower bits of IF and IE are wired to something
+pub const INTERRUPT_MASK: Byte = 0x1F;
+
+impl Interrupt {
+    pub fn mask(self) -> Byte {
+        self as Byte
+    }
+
+    // the handler addresses are 0x40, 0x48, 0x50, 0x58 and 0x60
+    pub fn vector(self) -> Word {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+
+    // the pending interrupt with the highest priority, if any
+    pub fn from_pending(pending: Byte) -> Option<Interrupt> {
+        INTERRUPTS.iter().copied().find(|i| pending & i.mask() != 0)
+    }
+}
@@ -1,42 +1,67 @@
-pub struct Joypad{
-    state: [u8; 8]
+// the joypad is read through 0xFF00 (P1/JOYP), the game selects either the action or the
+// direction buttons with bits 4-5 and reads them back in the lower nibble.
+// a pressed button reads as 0, see https://gbdev.io/pandocs/Joypad_Input.html
+
+pub const RIGHT_BUTTON: usize = 0;
+pub const LEFT_BUTTON: usize = 1;
+pub const UP_BUTTON: usize = 2;
+pub const DOWN_BUTTON: usize = 3;
+pub const A_BUTTON: usize = 4;
+pub const B_BUTTON: usize = 5;
+pub const SELECT_BUTTON: usize = 6;
+pub const START_BUTTON: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoypadMode {
+    Direction,
+    Action,
 }
 
-impl Joypad{
+pub struct Joypad {
+    state: [u8; 8],
+}
 
-    pub fn new() -> Joypad{
-        state: [1;8]
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad { state: [1; 8] }
     }
 
-    pub fn get_button_press(&self, button: usize){
-        self.state[button]    
+    pub fn get_button_press(&self, button: usize) -> u8 {
+        self.state[button]
     }
 
-    pub fn set_button_press(&mut self, button: usize){
+    pub fn set_button_press(&mut self, button: usize) {
         self.state[button] = 0;
     }
 
-    pub fn reset_button_state(&mut self, button: usize){
+    pub fn reset_button_state(&mut self, button: usize) {
         self.state[button] = 1;
     }
-    
-    pub fn get_button_mode(&mut self, mode:JoypadMode){
-        JoypadMode::DIRECTION => {
-            let up = self.state[UP_BUTTON];
-            let down = self.state[DOWN_BUTTON];
-            let left = self.state[LEFT_BUTTON];
-            let right = self.state[RIGH_BUTTON];
-
-            (down << 3) | (up << 2) | (left << 1) | right
-        }
-        JoypadMode::ACTION => {
-            let start = self.state[START_BUTTON];
-            let select = self.state[SELECT_BUTTON];
-            let a = self.state[A_BUTTON];
-            let b = self.state[B_BUTTON];
 
-            (start << 3) | (select << 2) | (b << 1) | a
+    pub fn get_buttons_for_mode(&self, mode: JoypadMode) -> u8 {
+        match mode {
+            JoypadMode::Direction => {
+                let up = self.state[UP_BUTTON];
+                let down = self.state[DOWN_BUTTON];
+                let left = self.state[LEFT_BUTTON];
+                let right = self.state[RIGHT_BUTTON];
+
+                (down << 3) | (up << 2) | (left << 1) | right
+            }
+            JoypadMode::Action => {
+                let start = self.state[START_BUTTON];
+                let select = self.state[SELECT_BUTTON];
+                let a = self.state[A_BUTTON];
+                let b = self.state[B_BUTTON];
+
+                (start << 3) | (select << 2) | (b << 1) | a
+            }
         }
+    }
+}
 
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,37 @@
+//! YAREGB is **Y**et **A**nother **R**ust **E**mulator (for) **G**ame **B**oy.
+//!
+//! The crate is organised the same way the hardware is: the `cpu` fetches and
+//! executes instructions, every access goes through the `mmu` (the control bus),
+//! and the `mmu` forwards cartridge accesses to the `mbc` of the loaded `rom`.
+//!
+//! Most users only need the [`GameBoy`] facade, which owns all of the above.
+
+pub mod apu;
+pub mod battery;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod dma;
+pub mod error;
+pub mod gameboy;
+pub mod gdb;
+pub mod hdma;
+pub mod header;
+pub mod interrupt;
+pub mod joypad;
+pub mod mbc;
+pub mod mmu;
+pub mod ppu;
+pub mod register;
+pub mod rom;
+pub mod rtc;
+pub mod serial;
+pub mod symbols;
+pub mod testrom;
+pub mod timer;
+pub mod trace;
+pub mod utils;
+pub mod watchpoint;
+
+pub use error::{Error, Result};
+pub use gameboy::GameBoy;
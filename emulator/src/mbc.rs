@@ -1,397 +1,828 @@
 //maybe I should implement an object to extend to avoid code repetition, look at MbcType impls
 
 use std::cmp;
-use std::fmt;
 
+use crate::error::*;
+use crate::header::*;
 use crate::rom::*;
-
+use crate::rtc::*;
+use crate::utils::*;
+
+pub mod camera;
+pub mod huc1;
+pub mod huc3;
+pub mod mbc6;
+pub mod mbc7;
+pub mod mmm01;
+pub mod tama5;
+
+pub use camera::PocketCamera;
+pub use huc1::HuC1;
+pub use huc3::HuC3;
+pub use mbc6::Mbc6;
+pub use mbc7::Mbc7;
+pub use mmm01::Mmm01;
+pub use tama5::Tama5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MbcType {
-    MBC1,
-    MBC2,
-    MBC3,
-    MBC5,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    HuC1,
+    HuC3,
+    Mmm01,
+    Tama5,
+    PocketCamera,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankingMode {
+    Rom,
+    Ram,
 }
 
-pub trait Mbc{
+// every read_rom_bank0 address is relative to 0x0000, every read_rom address is relative to
+// the switchable bank (0x4000 - 0x7FFF) and every read_ram/write_ram address is relative to the
+// external RAM window (0xA000 - 0xBFFF). write_ram returns whether the byte was stored in the
+// memory get_ext_ram hands over to the .sav file, the writes to disabled RAM or to registers
+// do not make a save necessary
+pub trait Mbc {
     fn get_mbc_type(&self) -> MbcType;
+    fn read_rom_bank0(&self, addr: Word) -> Byte;
     fn read_rom(&self, addr: Word) -> Byte;
     fn read_ram(&self, addr: Word) -> Byte;
-    fn write_ram(&mut self, addr: Word, data: Byte);
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool;
     fn handle_bank(&mut self, addr: Word, data: Byte);
     fn get_ext_ram(&self) -> &[Byte];
-    fn load_ext_ram(&mut self, buffer = Vec<Byte>);
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>);
+
+    // advances anything on the cartridge that keeps time, by the given amount of clock cycles
+    fn do_cycle(&mut self, _ticks: u32) {}
+
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    // true while the rumble motor is spinning
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    // MBC7 accelerometer input, in g along the X (right) and Y (down) axes
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // Pocket Camera sensor input, one brightness per pixel, 128 x 112
+    fn set_camera_image(&mut self, _image: &[Byte]) {}
+
+    // HuC1 and HuC3 infrared port
+    fn ir_led(&self) -> bool {
+        false
+    }
+
+    fn set_ir_signal(&mut self, _signal: bool) {}
+
+    // the bank mapped at 0x4000 - 0x7FFF, mappers that switch smaller windows report 1
+    fn current_rom_bank(&self) -> usize {
+        1
+    }
+
+    // the RAM bank mapped at 0xA000 - 0xBFFF, 0 for the mappers that don't switch it
+    fn current_ram_bank(&self) -> usize {
+        0
+    }
 }
 
-pub struct Mbc1{
+pub struct Mbc1 {
     memory: Vec<Byte>,
+    // BANK1, the lower 5 bits of the ROM bank, never 0
     rom_bank: usize,
+    // BANK2, 2 bits used as the upper ROM bank bits or as the RAM bank
     ram_bank: usize,
-    ext_ram: [Byte; MAX_RAM_BANKS * RAM_BANK_SIZE],
+    ext_ram: Vec<Byte>,
     enable_ram: bool,
-    ram_banks: u8,
+    number_of_rom_banks: usize,
     banking_mode: BankingMode,
+    // MBC1M multicarts only wire 4 bits of BANK1, so BANK2 starts at bit 4
+    multicart: bool,
 }
 
-pub struct Mbc2{
+pub struct Mbc2 {
     memory: Vec<Byte>,
     rom_bank: usize,
-    ext_ram: [Byte; 0x200],
+    // 512 half bytes, only the lower nibble of each entry is used
+    ext_ram: [Byte; MBC2_RAM_SIZE],
     enable_ram: bool,
+    number_of_rom_banks: usize,
 }
 
-pub struct Mbc3{
+pub struct Mbc3 {
     memory: Vec<Byte>,
     rom_bank: usize,
     ram_bank_or_rtc: usize,
     ext_ram: [Byte; MAX_RAM_BANKS * RAM_BANK_SIZE],
     enable_ram_rtc: bool,
-    number_of_rom_banks: u8,
+    number_of_rom_banks: usize,
 
     //Real Time Clock if needed
-    rtc_seconds: Byte,
-    rtc_minutes: Byte,
-    rtc_hours: Byte,
-    rtc_dl: Byte,
-    rtc_dh
+    rtc: Option<Rtc>,
 }
 
-pub struct Mbc5{
+pub struct Mbc5 {
     memory: Vec<Byte>,
     rom_bank: usize,
     ram_bank: usize,
-    ext_ram: [Byte; 16 * RAM_BANK_SIZE],
+    ext_ram: Vec<Byte>,
     enable_ram: bool,
-    ram_banks: u8,
+    number_of_rom_banks: usize,
+    // rumble cartridges wire bit 3 of the RAM bank register to the motor instead
+    has_rumble: bool,
+    rumble: bool,
 }
 
-pub fn get_mbc(rom: &Rom) -> Option<Box<dyn Mbc>>{
-    let rom_mode = rom.get_cartridge_type();
-    match rom_mode {
-        0x01 | 0x02 | 0x03 => Some(Box::new(Mbc1::new(rom))),
-        0x05 | 0x06 => Some(Box::new(Mbc2::new(rom))),
-        0x0F | 0x10 | 0x11 | 0x12 | 0x13 => Some(Box::new(Mbc3::new(rom))),
-        0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Some(Box::new(Mbc5::new(rom))),
-        _ => None
+// ROM only cartridges have no mapper at all, Ok(None) means the mmu reads the ROM directly
+pub fn get_mbc(rom: &Rom) -> Result<Option<Box<dyn Mbc>>> {
+    let mbc: Box<dyn Mbc> = match rom.get_cartridge_type() {
+        CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+            return Ok(None)
+        }
+        CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
+            Box::new(Mbc1::new(rom))
+        }
+        CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => Box::new(Mbc2::new(rom)),
+        CartridgeType::Mbc3TimerBattery
+        | CartridgeType::Mbc3TimerRamBattery
+        | CartridgeType::Mbc3
+        | CartridgeType::Mbc3Ram
+        | CartridgeType::Mbc3RamBattery => Box::new(Mbc3::new(rom)),
+        CartridgeType::Mbc5
+        | CartridgeType::Mbc5Ram
+        | CartridgeType::Mbc5RamBattery
+        | CartridgeType::Mbc5Rumble
+        | CartridgeType::Mbc5RumbleRam
+        | CartridgeType::Mbc5RumbleRamBattery => Box::new(Mbc5::new(rom)),
+        CartridgeType::Mbc6 => Box::new(Mbc6::new(rom)),
+        CartridgeType::Mbc7SensorRumbleRamBattery => Box::new(Mbc7::new(rom)),
+        CartridgeType::HuC1RamBattery => Box::new(HuC1::new(rom)),
+        CartridgeType::HuC3 => Box::new(HuC3::new(rom)),
+        CartridgeType::Mmm01 | CartridgeType::Mmm01Ram | CartridgeType::Mmm01RamBattery => {
+            Box::new(Mmm01::new(rom))
+        }
+        CartridgeType::BandaiTama5 => Box::new(Tama5::new(rom)),
+        CartridgeType::PocketCamera => Box::new(PocketCamera::new(rom)),
+        cartridge_type => return Err(Error::UnsupportedCartridgeType(cartridge_type.to_byte())),
+    };
+    Ok(Some(mbc))
+}
+
+fn copy_rom(rom: &Rom) -> Vec<Byte> {
+    let mut memory = Vec::with_capacity(rom.length());
+    for i in 0..rom.length() {
+        memory.push(rom.get_byte(i));
     }
+    memory
 }
 
-impl Mbc1{
+// a save buffer may be shorter or longer than the cartridge RAM, copy what fits
+fn copy_ram(ext_ram: &mut [Byte], buffer: &[Byte]) {
+    let len = cmp::min(ext_ram.len(), buffer.len());
+    ext_ram[..len].copy_from_slice(&buffer[..len]);
+}
 
-    pub fn new(rom: &Rom) -> Mbc1{
-        let mut memory = Vec::new();
-        for i in 0..rom.length(){
-            memory.push(rom.get_byte)(i));
-        }
+// banks past the end of the file read as an open bus instead of panicking
+fn read_bank(memory: &[Byte], rom_bank: usize, addr: Word) -> Byte {
+    let destination_addr = (addr as usize) + (rom_bank * ROM_BANK_SIZE);
+    memory.get(destination_addr).copied().unwrap_or(0xFF)
+}
 
+/*
+ * MBC1 has two bank registers: BANK1 (5 bits, 0x2000 - 0x3FFF) and BANK2 (2 bits, 0x4000 -
+ * 0x5FFF). The 0x4000 - 0x7FFF region always uses BANK2:BANK1. In mode 1 (BankingMode::Ram)
+ * BANK2 also drives the upper bits of 0x0000 - 0x3FFF and selects the RAM bank, in mode 0 both
+ * of those see 0 instead. Writing 0 to BANK1 selects 1, which is why banks 0x20, 0x40 and 0x60
+ * can only be reached through 0x0000 - 0x3FFF in mode 1.
+ *
+ * see https://gbdev.io/pandocs/MBC1.html
+ */
+impl Mbc1 {
+    pub fn new(rom: &Rom) -> Mbc1 {
+        let memory = copy_rom(rom);
+        let multicart = is_multicart(&memory);
         Mbc1 {
-            memory: memory,
+            memory,
             rom_bank: 1,
             ram_bank: 0,
-            ext_ram: [0; MAX_RAM_BANKS * RAM_BANK_SIZE],
+            ext_ram: vec![0; rom.header().ram_size],
             enable_ram: false,
-            number_of_rom_banks: rom.get_number_banks() as u8,
-            banking_mode: BankingMode::ROM,
+            number_of_rom_banks: rom.get_number_banks(),
+            banking_mode: BankingMode::Rom,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> usize {
+        match self.multicart {
+            true => 4,
+            false => 5,
         }
     }
+
+    // the bank lines past the size of the ROM chip are not connected, so the bank wraps. the
+    // unofficial 72, 80 and 96 bank sizes are no power of two and can't be masked
+    fn mask_rom_bank(&self, bank: usize) -> usize {
+        bank % self.number_of_rom_banks
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.current_ram_bank() * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
+}
+
+// MBC1M multicarts are 1 MiB cartridges holding several games of 256 KiB each, every one of
+// them with its own header, so the Nintendo logo shows up again at the start of bank 0x10
+fn is_multicart(memory: &[Byte]) -> bool {
+    const LOGO_START: usize = 0x0104;
+    const LOGO_END: usize = 0x0134;
+    const SECOND_GAME: usize = 0x10 * ROM_BANK_SIZE;
+
+    memory.len() == 64 * ROM_BANK_SIZE
+        && memory[LOGO_START..LOGO_END] == memory[SECOND_GAME + LOGO_START..SECOND_GAME + LOGO_END]
 }
 
-impl Mbc for Mbc1{
-    fn get_mbc_type(&self) -> MbcType{
-        MbcType::MBC1
+impl Mbc for Mbc1 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc1
     }
 
-    fn read_rom(&self, addr: Word) -> Byte{
-        let destination_addr = (addr as usize) + (self.rom_bank * 0x4000);
-        self.memory[destination_addr]
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        let rom_bank = match self.banking_mode {
+            BankingMode::Ram => self.ram_bank << self.bank2_shift(),
+            BankingMode::Rom => 0,
+        };
+        read_bank(&self.memory, self.mask_rom_bank(rom_bank), addr)
     }
 
-    fn read_ram(&self, addr: Word){
-        self.ext_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE) as usize]
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
     }
 
-    fn write_ram(&mut self, addr: Word, data: Byte){
-        self.ext_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE)] = data;
+    fn current_ram_bank(&self) -> usize {
+        match self.banking_mode {
+            BankingMode::Ram => self.ram_bank,
+            BankingMode::Rom => 0,
+        }
     }
 
-    fn handle_bank(&mut self, addr: Word, data: Byte){
-        match addr{
-            0x0000..=0x1FFF => if (data & 0x0F) == 0xA (self.enable_ram = true) else (self.enable_ram = false)},
+    fn current_rom_bank(&self) -> usize {
+        let bank1 = match self.multicart {
+            true => self.rom_bank & 0x0F,
+            false => self.rom_bank,
+        };
+        self.mask_rom_bank((self.ram_bank << self.bank2_shift()) | bank1)
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return 0xFF;
+        }
+        self.ext_ram[self.ram_addr(addr)]
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return false;
+        }
+        let ram_addr = self.ram_addr(addr);
+        self.ext_ram[ram_addr] = data;
+        true
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram = (data & 0x0F) == 0xA,
             0x2000..=0x3FFF => {
-                let new_rom_bank + data & 0x1F;
-                self.rom_bank = (self.rom_bank & 0b11100000) | (new_rom_bank as usize);
-                
+                // the zero check looks at all 5 bits, even on multicarts
+                self.rom_bank = (data & 0x1F) as usize;
                 if self.rom_bank == 0 {
-                    self.rom_bank += 1;
-                }
-
-                if self.rom_bank > self.number_of_rom_banks as usize{
-                    println!("TO DO ADD MANY BANKS")
-                }
-            },
-            0x4000..=0x5FFF => {
-                match self.banking_mode{
-                    BankingMode::RAM => self.ram_bank = (data & 0x03) as usize,
-                    BankingMode::ROM => {
-                        let new_rom_bank = data & 0xE0;
-
-                        self.rom_bank = (new_rom_bank | ((self.rom_bank as u8) & 0b00011111)) as usize;
-                        if self.rom_bank == 0{
-                            self.rom_bank += 1;
-                        }
-
-                        if self.rom_bank > self.number_of_rom_banks as usize{
-                            println!("TO DO ADD MANY BANKS")
-                        }
-                    }
+                    self.rom_bank = 1;
                 }
-            },
+            }
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
             0x6000..=0x7FFF => {
-                self.banking_mode = match bit_set(&data, 0){
-                    true => BankingMode::RAM,
-                    false => BankingMode::ROM,
+                self.banking_mode = match bit_set(&data, 0) {
+                    true => BankingMode::Ram,
+                    false => BankingMode::Rom,
                 };
-            },
-            _ => println("INVALID ADDR {}", addr)
+            }
+            _ => {}
         };
     }
 
-    fn get_ext_ram(&self) -> &[Byte]{
+    fn get_ext_ram(&self) -> &[Byte] {
         &self.ext_ram
     }
 
-    fn load_ext_ram(mut &self, buffer: Vec<Byte>){
-        let ram_len = self.ext_ram.len();
-        for i in 0..cmp::min(ram_len, buffer.len()){
-            self.ext_ram[i] = buffer[i];
-        }
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
     }
-
 }
 
-impl Mbc2{
-    pub fn new(rom: &Rom) -> Mbc2{
-        let mut memory = Vec::new();
-        for i in 0..rom.length(){
-            memory.push(rom.get_byte(i));
-        }
-
-        Mbc2{
-            memory: memory,
+/*
+ * MBC2 has no RAM bank or mode registers. Both of its registers live in 0x0000 - 0x3FFF and bit 8
+ * of the address picks one: clear for the RAM enable, set for the 4 bit ROM bank. The RAM is
+ * inside the chip, 512 x 4 bits, and it repeats all over 0xA000 - 0xBFFF. Only the lower nibble
+ * is connected to the data bus, the upper one reads back as 1s.
+ *
+ * see https://gbdev.io/pandocs/MBC2.html
+ */
+impl Mbc2 {
+    pub fn new(rom: &Rom) -> Mbc2 {
+        Mbc2 {
+            memory: copy_rom(rom),
             rom_bank: 1,
-            ext_ram: [0; 0x200],
+            ext_ram: [0; MBC2_RAM_SIZE],
             enable_ram: false,
+            number_of_rom_banks: rom.get_number_banks(),
         }
     }
 }
 
-impl Mbc for Mbc2{
-    fn get_mbc_type(&self) -> MbcType{
-        MbcType::MBC2
+impl Mbc for Mbc2 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc2
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram {
+            return 0xFF;
+        }
+        let dest_addr = (addr as usize) % MBC2_RAM_SIZE;
+        0xF0 | self.ext_ram[dest_addr]
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
     }
 
-    fn read_ram(&self, addr: Word) -> Byte{
-        let dest_addr = (addr as usize) % 0x200;
-        self.ext_ram[dest_addr]
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
     }
 
-    fn read_rom(&self, addr: Word) -> Byte{
-        let dest_addr = (addr as usize) + (self.rom_bank * 0x4000);
-        self.memory[dest_addr]
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
     }
 
-    fn write_ram(&mut self, addr: Word, data: Byte){
-        if self.enable_ram{
-            let dest_addr = (addr as usize) % 0x200;
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if self.enable_ram {
+            let dest_addr = (addr as usize) % MBC2_RAM_SIZE;
             self.ext_ram[dest_addr] = data & 0xF;
         }
+        self.enable_ram
     }
 
-    fn handle_bank(&mut self, addr: Word, data: Byte){
-        if addr >= 0x0000 && addr <= 0x4000 {
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        // only 0x0000 - 0x3FFF is wired to the MBC2 registers
+        if addr < 0x4000 {
             let high_byte = (addr >> 8) as Byte;
-        
 
-        match is_bit_set{
-            true =>{
-                self.rom_bank = (data & 0xF) as usize;
-                if self.rom_bank == 0{
-                    self.rom_bank = 1;
+            match bit_set(&high_byte, 0) {
+                true => {
+                    self.rom_bank = (data & 0xF) as usize;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
                 }
-            },
-            false =>{
-                self.enable_ram == 0xA;
-            }
-        };
-    }else{
-        println!("INVALID ADDR FOR MBC2 - {:04X}", addr);
-    }}
-
-    fn get_ext_ram(&self) -> &[Byte]{
-        self.ext_ram;
-    }
-    
-    fn load_ext_ram(&mut self, buffer: Vec<Byte>){
-        let ram_len = self.ext_ram.length();
-        for i in 0..cmp::min(ram_len, buffer.len()){
-            memory.push(rom.get_byte(i));
+                false => {
+                    self.enable_ram = (data & 0xF) == 0xA;
+                }
+            };
         }
     }
 
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
     }
 
-}
-
-impl Mbc3{
-    pub fn new(rom: &Rom) -> Mbc3{
-        let mut memory = Vec::new();
-        for i in 0..rom.length(){
-            memory.push(rom.get_byte([)i));
+    // saves from other emulators may have the upper nibble set, it is not stored
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+        for value in self.ext_ram.iter_mut() {
+            *value &= 0xF;
         }
     }
+}
 
-    Mbc3{
-        memory: memory,
-        rom_bank: 1,
-        rom_bank_rtc: 0,
-        ext_ram: [0; MAX_RAM_BANKS * RAM_BANK_SIZE],
-        enable_ram_rtc: false,
-        number_of_rom_banks: rom.get_number_banks() as u8,
-        rtc_seconds: 0,
-        rtc_minutes: 0,
-        rtc_hours: 0,
-        rtc_dh: 0,
-        rtc_dl: 0,
+impl Mbc3 {
+    pub fn new(rom: &Rom) -> Mbc3 {
+        Mbc3 {
+            memory: copy_rom(rom),
+            rom_bank: 1,
+            ram_bank_or_rtc: 0,
+            ext_ram: [0; MAX_RAM_BANKS * RAM_BANK_SIZE],
+            enable_ram_rtc: false,
+            number_of_rom_banks: rom.get_number_banks(),
+            rtc: match rom.get_cartridge_type().has_rtc() {
+                true => Some(Rtc::new()),
+                false => None,
+            },
+        }
     }
 }
 
-impl Mbc for Mbc3{
-    fn get_mbc_type(&self){
-        MbcType::MBC3
+impl Mbc for Mbc3 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc3
     }
 
-    fn read_ram(&self, addr: Word) -> Byte{
-        match self.ram_bank_or_rtc{
-            0x00..=0x03 => self.ext_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE) as usize],
-            0x08 => self.rtc_seconds,
-            0x09 => self.rtc_minutes,
-            0x0A => self.rtc_hours,
-            0x0B => self.rtc_dl,
-            0x0C => self.rtc_dh,
-            _ => {
-                println!("INVALID READ MEM ADDR FOR RAM/RTC BANK MB3 [{:02X}]", addr);
-                0
-            }
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram_rtc {
+            return 0xFF;
+        }
+        match self.ram_bank_or_rtc {
+            0x00..=0x03 => self.ext_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE)],
+            register @ 0x08..=0x0C => match &self.rtc {
+                Some(rtc) => rtc.read(register),
+                None => 0xFF,
+            },
+            // nothing answers on unmapped banks
+            _ => 0xFF,
         }
     }
 
-    fn read_rom(&self, addr: Word) -> Byte{
-        let dest_addr = (addr as usize) + (self.rom_bank * 0x4000);
-        self.memory[dest_addr]
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
     }
 
-    fn write_ram(&mut self, addr: Word, data: Byte){
-        if self.enable_ram_rtc{
-            match self.ram_bank_or_rtc{
-            0x00..=0x03 => self.ext_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE)] = data,
-            0x08 => self.rtc_seconds = data,
-            0x09 => self.rtc_minutes = data,
-            0x0A => self.rtc_hours = data,
-            0x0B => self.rtc_dl = data,
-            0x0C => self.rtc_dh = data,
-            _ => {
-                println!("INVALID WRITE MEM ADDR FOR RAM/RTC BANK MB3 [{:02X}]", addr);
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
+    }
+
+    // with an RTC register selected, the number of the register
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank_or_rtc
+    }
+
+    // the clock registers are saved on every flush anyway, see Battery::flush
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if !self.enable_ram_rtc {
+            return false;
+        }
+        match self.ram_bank_or_rtc {
+            0x00..=0x03 => {
+                self.ext_ram[(addr as usize) + (self.ram_bank_or_rtc * RAM_BANK_SIZE)] = data;
+                true
+            }
+            register @ 0x08..=0x0C => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write(register, data);
                 }
+                false
             }
+            _ => false,
         }
     }
 
-    fn handle_bank(&mut self, addr: Word, data: Byte){
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
         match addr {
-            0x0000..=0x1FFF => if (data & 0xF) == 0xA {self.enable_ram_rtc = true} else {self.enable_ram_rtc = false}
+            0x0000..=0x1FFF => self.enable_ram_rtc = (data & 0xF) == 0xA,
             0x2000..=0x3FFF => {
                 self.rom_bank = (data & 0x7F) as usize;
-                if rom_bank == 0 {
+                if self.rom_bank == 0 {
                     self.rom_bank = 1;
                 }
             }
-            0x4000..=0x5FFF => self.ram_bank_or_rtc = data a usize,
-            0x6000..=0x7FFF => println!("NOT IMPLEMENTED"),
-            _=> println!("Invalid addr at {}", addr)
+            0x4000..=0x5FFF => self.ram_bank_or_rtc = data as usize,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(data);
+                }
+            }
         };
     }
 
-    fn get_ext_ram(&self) -> &[Byte]{
-        self.ext_ram;
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
     }
-    
-    fn load_ext_ram(&mut self, buffer: Vec<Byte>){
-        let ram_len = self.ext_ram.length();
-        for i in 0..cmp::min(ram_len, buffer.len()){
-            self.ext_ram[i] = buffer[i];
-        }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
     }
-}
 
-impl Mbc5{
-    pub fn new(rom: &Rom) -> Mbc5{
-        let mut memory = Vec::new();
-        for i in 0..rom.length(){
-            memory.push(rom.get_byte(1));
+    fn do_cycle(&mut self, ticks: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.do_cycle(ticks);
         }
+    }
 
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+/*
+ * MBC5 has a 9 bit ROM bank split in two registers, 0x2000 - 0x2FFF for the lower 8 bits and
+ * 0x3000 - 0x3FFF for bit 8, and unlike the older MBCs bank 0 can be mapped at 0x4000 too. The
+ * 4 bit RAM bank register selects one of 16 banks, on rumble cartridges bit 3 drives the motor
+ * so only 8 banks are left.
+ *
+ * see https://gbdev.io/pandocs/MBC5.html
+ */
+impl Mbc5 {
+    pub fn new(rom: &Rom) -> Mbc5 {
         Mbc5 {
-            memory: memory,
+            memory: copy_rom(rom),
             rom_bank: 1,
             ram_bank: 0,
-            ext_ram: [0; 16 * RAM_BANK_SIZE],
+            ext_ram: vec![0; rom.header().ram_size],
             enable_ram: false,
-            number_of_rom_banks: rom.get_number_banks() as u8,
+            number_of_rom_banks: rom.get_number_banks(),
+            has_rumble: rom.get_cartridge_type().has_rumble(),
+            rumble: false,
         }
     }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.ram_bank * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
 }
 
-impl Mbc for Mbc5{
-    fn get_mbc_type(&self) -> MbcType{
-        MbcType::MBC5
+impl Mbc for Mbc5 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc5
     }
 
-    fn read_rom(&self, addr: Word) -> Byte{
-        let dest_addr = (addr as usize) + (self.rom_bank * 0x4000);
-        self.memory[dest_addr];
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
     }
 
-    fn read_ram(&self, addr: Word) -> Byte{
-        self.ext_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE) as usize]
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
     }
 
-    fn write_ram(&self, addr: Word, data: Byte){
-        self.ext_ram[(addr as usize) + (self.ram_bank * RAM_BANK_SIZE)] = data;
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
     }
 
-    fn handle_bank(&mut self, addr: Word, data: Byte){
-        match addr{
-            0x0000..=0x1FFF => if (data & 0xF) == 0xA {self.enable_ram = true} else{self.enable_ram = false},
-            0x2000..=0x2FFF => {
-                let bit_9 = self.rom_bank >> 8;
-                self.rom_bank = (bit_9 << 8) | (data as usize);
-            },
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return 0xFF;
+        }
+        self.ext_ram[self.ram_addr(addr)]
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return false;
+        }
+        let ram_addr = self.ram_addr(addr);
+        self.ext_ram[ram_addr] = data;
+        true
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram = (data & 0xF) == 0xA,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | (data as usize),
             0x3000..=0x3FFF => {
-                let bit = if data > 0 {1} else {0};
-                self.rom_bank = bit << 8 | self.rom_bank;
+                self.rom_bank = (((data & 0x01) as usize) << 8) | (self.rom_bank & 0xFF)
+            }
+            0x4000..=0x5FFF => match self.has_rumble {
+                true => {
+                    self.ram_bank = (data & 0x07) as usize;
+                    self.rumble = bit_set(&data, 3);
+                }
+                false => self.ram_bank = (data & 0x0F) as usize,
             },
-            0x4000..=0x5FFF => self.ram_bank = data as usize,
-            _ => println("INVALID ADDR AT MBC5 {}", addr)
+            _ => {}
         }
     }
 
-    fn get_ext_ram(&self) -> &[Byte]{
-        self.ext_ram;
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
     }
-    
-    fn load_ext_ram(&mut self, buffer: Vec<Byte>){
-        let ram_len = self.ext_ram.length();
-        for i in 0..cmp::min(ram_len, buffer.len()){
-            self.ext_ram[i] = buffer[i];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mbc3_ram_and_rtc_need_enabling() {
+        // MBC3+TIMER+RAM+BATTERY with 4 RAM banks
+        let mut mbc = Mbc3::new(&test_rom(0x10, 0x00, 0x03));
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.handle_bank(0x4000, 0x02);
+        mbc.write_ram(0x0010, 0x42);
+        mbc.handle_bank(0x4000, 0x08);
+        mbc.write_ram(0x0000, 0x17);
+        mbc.handle_bank(0x6000, 0x00);
+        mbc.handle_bank(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x17);
+
+        mbc.handle_bank(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.handle_bank(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0x0010), 0xFF);
+        // writes are dropped too
+        mbc.write_ram(0x0010, 0x99);
+        mbc.handle_bank(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0x0010), 0x42);
+    }
+
+    #[test]
+    fn mbc3_wraps_past_the_rom_size() {
+        // 128 KiB, the upper bits of the bank number are not connected
+        let mut mbc = Mbc3::new(&test_rom(0x11, 0x02, 0x00));
+        mbc.handle_bank(0x2000, 0x0B);
+        assert_eq!(mbc.current_rom_bank(), 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x03);
+        mbc.handle_bank(0x2000, 0x7F);
+        assert_eq!(mbc.current_rom_bank(), 0x07);
+        assert_eq!(mbc.read_rom(0x3FFF), 0x07);
+    }
+
+    #[test]
+    fn mbc1_banking_modes() {
+        // 1 MiB and 32 KiB of RAM, the byte read tells the bank
+        let mut mbc = Mbc1::new(&test_rom(0x03, 0x05, 0x03));
+        assert_eq!(mbc.read_rom(0x0000), 0x01);
+        mbc.handle_bank(0x2000, 0x00);
+        assert_eq!(mbc.current_rom_bank(), 0x01);
+        mbc.handle_bank(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x21);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x00);
+        assert_eq!(mbc.current_ram_bank(), 0);
+
+        // mode 1 moves BANK2 to 0x0000 - 0x3FFF and the RAM bank
+        mbc.handle_bank(0x6000, 0x01);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x0000), 0x21);
+        assert_eq!(mbc.current_ram_bank(), 1);
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0x42);
+        mbc.handle_bank(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.handle_bank(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+    }
+
+    #[test]
+    fn mbc1_wraps_past_the_rom_size() {
+        // 256 KiB, BANK2 is not connected
+        let mut mbc = Mbc1::new(&test_rom(0x01, 0x03, 0x00));
+        mbc.handle_bank(0x2000, 0x13);
+        mbc.handle_bank(0x4000, 0x02);
+        assert_eq!(mbc.current_rom_bank(), 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x03);
+
+        // 72 banks is no power of two
+        let mut mbc = Mbc1::new(&test_rom(0x01, 0x52, 0x00));
+        mbc.handle_bank(0x2000, 0x08);
+        mbc.handle_bank(0x4000, 0x02);
+        assert_eq!(mbc.current_rom_bank(), 0x00);
+        mbc.handle_bank(0x4000, 0x03);
+        assert_eq!(mbc.current_rom_bank(), 0x20);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        mbc.handle_bank(0x6000, 0x01);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x18);
+    }
+
+    #[test]
+    fn mbc1_multicart() {
+        let mut data = test_rom_data(0x01, 0x05, 0x00);
+        // the same logo at the start of bank 0 and bank 0x10
+        for addr in 0x0104..0x0134 {
+            data[addr] = addr as Byte;
+            data[0x10 * ROM_BANK_SIZE + addr] = addr as Byte;
+        }
+        let rom = Rom::from_bytes(data.clone()).unwrap();
+        let mut mbc = Mbc1::new(&rom);
+        assert!(mbc.multicart);
+        // BANK2 starts at bit 4 and BANK1 only has 4 bits
+        mbc.handle_bank(0x2000, 0x13);
+        mbc.handle_bank(0x4000, 0x01);
+        assert_eq!(mbc.current_rom_bank(), 0x13);
+        mbc.handle_bank(0x6000, 0x01);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x10);
+        // the zero check still sees all 5 bits
+        mbc.handle_bank(0x2000, 0x10);
+        assert_eq!(mbc.current_rom_bank(), 0x10);
+
+        data[0x10 * ROM_BANK_SIZE + 0x0104] ^= 0xFF;
+        let mut mbc = Mbc1::new(&Rom::from_bytes(data).unwrap());
+        assert!(!mbc.multicart);
+        mbc.handle_bank(0x2000, 0x13);
+        mbc.handle_bank(0x4000, 0x01);
+        assert_eq!(mbc.current_rom_bank(), 0x33);
+    }
+
+    #[test]
+    fn mbc2_registers_and_ram() {
+        // MBC2+BATTERY with 256 KiB
+        let mut mbc = Mbc2::new(&test_rom(0x06, 0x03, 0x00));
+        // bank 0 selects bank 1
+        assert_eq!(mbc.read_rom(0x0000), 0x01);
+        mbc.handle_bank(0x2100, 0x00);
+        assert_eq!(mbc.current_rom_bank(), 0x01);
+
+        // address bit 8 picks the register, whatever the rest of the address is
+        mbc.handle_bank(0x0100, 0x05);
+        assert_eq!(mbc.read_rom(0x0000), 0x05);
+        mbc.handle_bank(0x3FFF, 0x0A);
+        assert_eq!(mbc.current_rom_bank(), 0x0A);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.handle_bank(0x0000, 0x0A);
+        assert_eq!(mbc.current_rom_bank(), 0x0A);
+        // only the lower nibble of the bank counts
+        mbc.handle_bank(0x2100, 0xF3);
+        assert_eq!(mbc.current_rom_bank(), 0x03);
+
+        // 512 half bytes, the upper nibble reads as 1s
+        assert!(mbc.write_ram(0x0010, 0xAB));
+        assert_eq!(mbc.read_ram(0x0010), 0xFB);
+        assert_eq!(mbc.get_ext_ram()[0x10], 0x0B);
+        // mirrored all over 0xA000 - 0xBFFF
+        assert_eq!(mbc.read_ram(0x0210), 0xFB);
+        assert_eq!(mbc.read_ram(0x1E10), 0xFB);
+        assert!(mbc.write_ram(0x1FFF, 0x07));
+        assert_eq!(mbc.read_ram(0x01FF), 0xF7);
+
+        mbc.handle_bank(0x3E00, 0x00);
+        assert_eq!(mbc.read_ram(0x0010), 0xFF);
+        assert!(!mbc.write_ram(0x0010, 0x01));
+        mbc.handle_bank(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0x0010), 0xFB);
+    }
+
+    #[test]
+    fn mbc5_banks() {
+        // MBC5+RAM+BATTERY with 8 MiB and 128 KiB of RAM
+        let mut mbc = Mbc5::new(&test_rom(0x1B, 0x08, 0x04));
+        // 9 bit ROM bank, split over two registers
+        mbc.handle_bank(0x2000, 0x05);
+        mbc.handle_bank(0x3000, 0x01);
+        assert_eq!(mbc.current_rom_bank(), 0x105);
+        assert_eq!(mbc.read_rom(0x0000), 0x05);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x00);
+        // only bit 0 of the upper register is wired
+        mbc.handle_bank(0x3000, 0xFE);
+        assert_eq!(mbc.current_rom_bank(), 0x005);
+
+        // unlike the older mappers, bank 0 can be mapped at 0x4000 as well
+        mbc.handle_bank(0x2000, 0x00);
+        assert_eq!(mbc.current_rom_bank(), 0x00);
+        assert_eq!(mbc.read_rom(0x3FFF), 0x00);
+
+        // 16 RAM banks
+        mbc.handle_bank(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.handle_bank(0x4000, bank);
+            assert_eq!(mbc.current_ram_bank(), bank as usize);
+            assert!(mbc.write_ram(0x1FFF, bank + 0x40));
+        }
+        for bank in 0..16 {
+            mbc.handle_bank(0x4000, bank);
+            assert_eq!(mbc.read_ram(0x1FFF), bank + 0x40);
         }
+        assert_eq!(mbc.get_ext_ram()[15 * RAM_BANK_SIZE + 0x1FFF], 0x4F);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn mbc5_rumble_takes_bit_3() {
+        // MBC5+RUMBLE+RAM+BATTERY with 32 KiB of RAM
+        let mut mbc = Mbc5::new(&test_rom(0x1E, 0x01, 0x03));
+        mbc.handle_bank(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.current_ram_bank(), 0x03);
+        mbc.handle_bank(0x4000, 0x03);
+        assert!(!mbc.rumble());
     }
 }
@@ -0,0 +1,237 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * Game Boy Camera (Pocket Camera). The mapper works like an MBC3 without a clock, with 16 RAM
+ * banks and one more trick: setting bit 4 of the RAM bank register maps the image sensor
+ * registers over 0xA000 - 0xBFFF instead of the RAM.
+ *    A000	            bit 0: start a capture, reads 1 while it is running
+ *    A001	            N, VH and gain
+ *    A002 / A003	    exposure time, high / low byte
+ *    A004 / A005	    edge enhancement and output reference
+ *    A006 - A035	    4 x 4 dither matrix, 3 thresholds per pixel
+ *
+ * A capture takes the 128 x 112 grayscale image supplied by the frontend and writes it as 2 bit
+ * tiles to RAM bank 0 at 0xA100, 16 x 14 tiles. The sensor is modelled as an ideal one: every
+ * pixel is compared against the three thresholds of its dither matrix entry, the analog
+ * settings (gain, edge enhancement, exposure) only change how long the capture takes.
+ *
+ * see https://gbdev.io/pandocs/Gameboy_Camera.html
+ */
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+const CAMERA_REGISTERS: usize = 0x36;
+const CAMERA_SELECT_BIT: u8 = 4;
+const DITHER_MATRIX_ADDR: usize = 0x06;
+const IMAGE_ADDR: usize = 0x0100;
+
+pub struct PocketCamera {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ext_ram: Vec<Byte>,
+    enable_ram: bool,
+    number_of_rom_banks: usize,
+    registers_selected: bool,
+    registers: [Byte; CAMERA_REGISTERS],
+    // one brightness per pixel, 0 is black and 255 white
+    image: Vec<Byte>,
+    // clock cycles left until the running capture is done
+    capture_cycles: u32,
+}
+
+impl PocketCamera {
+    pub fn new(rom: &Rom) -> PocketCamera {
+        PocketCamera {
+            memory: copy_rom(rom),
+            rom_bank: 1,
+            ram_bank: 0,
+            ext_ram: vec![0; rom.header().ram_size.max(16 * RAM_BANK_SIZE)],
+            enable_ram: false,
+            number_of_rom_banks: rom.get_number_banks(),
+            registers_selected: false,
+            registers: [0; CAMERA_REGISTERS],
+            image: vec![0xFF; CAMERA_WIDTH * CAMERA_HEIGHT],
+            capture_cycles: 0,
+        }
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.ram_bank * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
+
+    // in clock cycles, the documented formula counts machine cycles
+    fn capture_time(&self) -> u32 {
+        let exposure = ((self.registers[0x02] as u32) << 8) | self.registers[0x03] as u32;
+        let n_bit = match bit_set(&self.registers[0x01], 7) {
+            true => 0,
+            false => 512,
+        };
+        (32446 + n_bit + 16 * exposure) * 4
+    }
+
+    fn capture(&mut self) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let matrix = DITHER_MATRIX_ADDR + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let value = self.image[y * CAMERA_WIDTH + x];
+                let color: Byte = match value {
+                    v if v < thresholds[0] => 3,
+                    v if v < thresholds[1] => 2,
+                    v if v < thresholds[2] => 1,
+                    _ => 0,
+                };
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let addr = IMAGE_ADDR + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                self.ext_ram[addr] &= !(1 << bit);
+                self.ext_ram[addr + 1] &= !(1 << bit);
+                self.ext_ram[addr] |= (color & 0x1) << bit;
+                self.ext_ram[addr + 1] |= ((color >> 1) & 0x1) << bit;
+            }
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::PocketCamera
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    // the RAM can be read even when it is not enabled, of the registers only A000 reads back
+    fn read_ram(&self, addr: Word) -> Byte {
+        match self.registers_selected {
+            true if addr & 0x7F == 0 => (self.capture_cycles > 0) as Byte,
+            true => 0x00,
+            false => self.ext_ram[self.ram_addr(addr)],
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if self.registers_selected {
+            let register = (addr & 0x7F) as usize;
+            if register == 0 {
+                self.registers[0] = data & 0x07;
+                if bit_set(&data, 0) && self.capture_cycles == 0 {
+                    self.capture_cycles = self.capture_time();
+                }
+            } else if register < CAMERA_REGISTERS {
+                self.registers[register] = data;
+            }
+            false
+        } else if self.enable_ram {
+            let ram_addr = self.ram_addr(addr);
+            self.ext_ram[ram_addr] = data;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram = (data & 0x0F) == 0xA,
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x3F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.registers_selected = bit_set(&data, CAMERA_SELECT_BIT);
+                self.ram_bank = (data & 0x0F) as usize;
+            }
+            _ => {}
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+        self.capture_cycles = self.capture_cycles.saturating_sub(ticks);
+        if self.capture_cycles == 0 {
+            self.capture();
+            self.registers[0] &= !0x01;
+        }
+    }
+
+    fn set_camera_image(&mut self, image: &[Byte]) {
+        copy_ram(&mut self.image, image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture() {
+        let mut mbc = PocketCamera::new(&test_rom(0xFC, 0x05, 0x04));
+        let mut image = vec![0xFF; CAMERA_WIDTH * CAMERA_HEIGHT];
+        image[..3].copy_from_slice(&[0x00, 0x50, 0x90]);
+        mbc.set_camera_image(&image);
+
+        mbc.handle_bank(0x4000, 1 << CAMERA_SELECT_BIT);
+        for entry in 0..16 {
+            for (i, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                mbc.write_ram((DITHER_MATRIX_ADDR + entry * 3 + i) as Word, threshold);
+            }
+        }
+        mbc.write_ram(0x0000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x01);
+        // no exposure and N clear
+        mbc.do_cycle((32446 + 512) * 4 - 1);
+        assert_eq!(mbc.read_ram(0x0000), 0x01);
+        mbc.do_cycle(1);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+
+        // colors 3, 2, 1 then 0, as the low and high bit planes of the first row
+        mbc.handle_bank(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(IMAGE_ADDR as Word), 0xA0);
+        assert_eq!(mbc.read_ram(IMAGE_ADDR as Word + 1), 0xC0);
+        assert_eq!(mbc.read_ram(IMAGE_ADDR as Word + 2), 0x00);
+    }
+
+    #[test]
+    fn ram_reads_without_enabling() {
+        let mut mbc = PocketCamera::new(&test_rom(0xFC, 0x05, 0x04));
+        mbc.handle_bank(0x4000, 0x0F);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0x42);
+        mbc.handle_bank(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        assert_eq!(mbc.get_ext_ram()[15 * RAM_BANK_SIZE], 0x42);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * HuC1, Hudson's MBC1 lookalike with an infrared LED and receiver. Writing 0x0E to 0x0000 -
+ * 0x1FFF maps the IR port over 0xA000 - 0xBFFF, any other value maps the RAM back:
+ *    0000 - 1FFF	    RAM / IR select
+ *    2000 - 3FFF	    ROM bank (6 bits)
+ *    4000 - 5FFF	    RAM bank (2 bits)
+ *
+ * In IR mode reads return 0xC1 while light is received and 0xC0 otherwise, bit 0 of a write
+ * turns the LED on.
+ *
+ * see https://gbdev.io/pandocs/HuC1.html
+ */
+
+const IR_MODE: Byte = 0x0E;
+
+pub struct HuC1 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ext_ram: Vec<Byte>,
+    number_of_rom_banks: usize,
+    ir_mode: bool,
+    ir_led: bool,
+    ir_signal: bool,
+}
+
+impl HuC1 {
+    pub fn new(rom: &Rom) -> HuC1 {
+        HuC1 {
+            memory: copy_rom(rom),
+            rom_bank: 1,
+            ram_bank: 0,
+            ext_ram: vec![0; rom.header().ram_size],
+            number_of_rom_banks: rom.get_number_banks(),
+            ir_mode: false,
+            ir_led: false,
+            ir_signal: false,
+        }
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.ram_bank * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
+}
+
+impl Mbc for HuC1 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::HuC1
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if self.ir_mode {
+            return 0xC0 | self.ir_signal as Byte;
+        }
+        match self.ext_ram.is_empty() {
+            true => 0xFF,
+            false => self.ext_ram[self.ram_addr(addr)],
+        }
+    }
+
+    // HuC1 has no RAM enable, the RAM is always writable unless the IR port is mapped
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if self.ir_mode {
+            self.ir_led = bit_set(&data, 0);
+            false
+        } else if !self.ext_ram.is_empty() {
+            let ram_addr = self.ram_addr(addr);
+            self.ext_ram[ram_addr] = data;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = (data & 0x0F) == IR_MODE,
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x3F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            _ => {}
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_signal(&mut self, signal: bool) {
+        self.ir_signal = signal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banks_and_ir_port() {
+        let mut mbc = HuC1::new(&test_rom(0xFF, 0x05, 0x03));
+        mbc.handle_bank(0x2000, 0x25);
+        assert_eq!(mbc.read_rom(0x0000), 0x25);
+        // no MBC1 style zero fix
+        mbc.handle_bank(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // the RAM needs no enabling
+        mbc.handle_bank(0x4000, 0x02);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.get_ext_ram()[2 * RAM_BANK_SIZE], 0x42);
+
+        mbc.handle_bank(0x0000, IR_MODE);
+        assert_eq!(mbc.read_ram(0x0000), 0xC0);
+        mbc.set_ir_signal(true);
+        assert_eq!(mbc.read_ram(0x0000), 0xC1);
+        mbc.write_ram(0x0000, 0x01);
+        assert!(mbc.ir_led());
+        assert_eq!(mbc.get_ext_ram()[2 * RAM_BANK_SIZE], 0x42);
+
+        mbc.handle_bank(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+    }
+}
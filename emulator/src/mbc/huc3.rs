@@ -0,0 +1,296 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * HuC3, Hudson's mapper with a clock, a speaker and the same IR port as the HuC1. The lower
+ * nibble written to 0x0000 - 0x1FFF selects what shows up at 0xA000 - 0xBFFF:
+ *    0x0	            RAM, read only
+ *    0xA	            RAM, read and write
+ *    0xB	            RTC command, written to 0xA000
+ *    0xC	            RTC response, read from 0xA000
+ *    0xD	            RTC semaphore, reads 1 when ready, a write runs the last command
+ *    0xE	            IR port
+ *
+ * The clock is driven by 4 bit commands on a small nibble memory: 0x1 reads and 0x3 writes the
+ * nibble at the access index (incrementing it), 0x4 and 0x5 set the low and high nibble of the
+ * index, 0x6 with 0 copies the time to nibbles 0 - 5 and with 1 copies it back. The time is
+ * 12 bits of minutes of the day followed by 12 bits of days.
+ *
+ * The clock state is not part of the .sav file, only the RAM is, so CartridgeType::has_rtc
+ * leaves the HuC3 out.
+ *
+ * see https://gbdev.io/pandocs/HuC3.html
+ */
+
+const CYCLES_PER_MINUTE: u32 = 60 * 4194304;
+const MINUTES_PER_DAY: Word = 24 * 60;
+const RTC_MEMORY_SIZE: usize = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HuC3Mode {
+    RamReadOnly,
+    Ram,
+    RtcCommand,
+    RtcResponse,
+    RtcSemaphore,
+    Ir,
+}
+
+pub struct HuC3 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ext_ram: Vec<Byte>,
+    number_of_rom_banks: usize,
+    mode: HuC3Mode,
+    command: Byte,
+    response: Byte,
+    access_index: usize,
+    rtc_memory: [Byte; RTC_MEMORY_SIZE],
+    minutes: Word,
+    days: Word,
+    cycles: u32,
+    ir_led: bool,
+    ir_signal: bool,
+}
+
+impl HuC3 {
+    pub fn new(rom: &Rom) -> HuC3 {
+        HuC3 {
+            memory: copy_rom(rom),
+            rom_bank: 1,
+            ram_bank: 0,
+            ext_ram: vec![0; rom.header().ram_size],
+            number_of_rom_banks: rom.get_number_banks(),
+            mode: HuC3Mode::RamReadOnly,
+            command: 0,
+            response: 0,
+            access_index: 0,
+            rtc_memory: [0; RTC_MEMORY_SIZE],
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            ir_led: false,
+            ir_signal: false,
+        }
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.ram_bank * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
+
+    fn run_command(&mut self) {
+        let argument = self.command & 0x0F;
+        match self.command >> 4 {
+            0x1 => {
+                self.response = self.rtc_memory[self.access_index];
+                self.access_index = (self.access_index + 1) % RTC_MEMORY_SIZE;
+            }
+            0x3 => {
+                self.rtc_memory[self.access_index] = argument;
+                self.access_index = (self.access_index + 1) % RTC_MEMORY_SIZE;
+            }
+            0x4 => self.access_index = (self.access_index & 0xF0) | argument as usize,
+            0x5 => self.access_index = (self.access_index & 0x0F) | ((argument as usize) << 4),
+            0x6 => match argument {
+                0x0 => {
+                    for i in 0..3 {
+                        self.rtc_memory[i] = ((self.minutes >> (i * 4)) & 0xF) as Byte;
+                        self.rtc_memory[i + 3] = ((self.days >> (i * 4)) & 0xF) as Byte;
+                    }
+                }
+                0x1 => {
+                    let nibbles = |start: usize| {
+                        (0..3).fold(0, |value, i| {
+                            value | ((self.rtc_memory[start + i] as Word) << (i * 4))
+                        })
+                    };
+                    self.minutes = nibbles(0) % MINUTES_PER_DAY;
+                    self.days = nibbles(3);
+                    self.cycles = 0;
+                }
+                // the clock is always ready
+                0x2 => self.response = 0x1,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::HuC3
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        match self.mode {
+            HuC3Mode::RamReadOnly | HuC3Mode::Ram if !self.ext_ram.is_empty() => {
+                self.ext_ram[self.ram_addr(addr)]
+            }
+            HuC3Mode::RtcResponse => (self.command & 0xF0) | self.response,
+            HuC3Mode::RtcSemaphore => 0x01,
+            HuC3Mode::Ir => 0xC0 | self.ir_signal as Byte,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        match self.mode {
+            HuC3Mode::Ram if !self.ext_ram.is_empty() => {
+                let ram_addr = self.ram_addr(addr);
+                self.ext_ram[ram_addr] = data;
+                return true;
+            }
+            HuC3Mode::RtcCommand => self.command = data,
+            HuC3Mode::RtcSemaphore => self.run_command(),
+            HuC3Mode::Ir => self.ir_led = bit_set(&data, 0),
+            _ => {}
+        }
+        false
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.mode = match data & 0x0F {
+                    0xA => HuC3Mode::Ram,
+                    0xB => HuC3Mode::RtcCommand,
+                    0xC => HuC3Mode::RtcResponse,
+                    0xD => HuC3Mode::RtcSemaphore,
+                    0xE => HuC3Mode::Ir,
+                    _ => HuC3Mode::RamReadOnly,
+                }
+            }
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x7F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            _ => {}
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.cycles += ticks;
+        while self.cycles >= CYCLES_PER_MINUTE {
+            self.cycles -= CYCLES_PER_MINUTE;
+            self.minutes += 1;
+            if self.minutes == MINUTES_PER_DAY {
+                self.minutes = 0;
+                self.days = (self.days + 1) & 0xFFF;
+            }
+        }
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_signal(&mut self, signal: bool) {
+        self.ir_signal = signal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn huc3() -> HuC3 {
+        HuC3::new(&test_rom(0xFE, 0x05, 0x03))
+    }
+
+    // runs an RTC command and returns the response register
+    fn command(mbc: &mut HuC3, command: Byte) -> Byte {
+        mbc.handle_bank(0x0000, 0x0B);
+        mbc.write_ram(0x0000, command);
+        mbc.handle_bank(0x0000, 0x0D);
+        assert_eq!(mbc.read_ram(0x0000), 0x01);
+        mbc.write_ram(0x0000, 0x00);
+        mbc.handle_bank(0x0000, 0x0C);
+        mbc.read_ram(0x0000)
+    }
+
+    fn set_time(mbc: &mut HuC3, minutes: Word, days: Word) {
+        command(mbc, 0x40);
+        command(mbc, 0x50);
+        for value in [minutes, days] {
+            for i in 0..3 {
+                command(mbc, 0x30 | ((value >> (i * 4)) & 0xF) as Byte);
+            }
+        }
+        command(mbc, 0x61);
+    }
+
+    // minutes and days, read back through the nibble memory
+    fn time(mbc: &mut HuC3) -> (Word, Word) {
+        command(mbc, 0x60);
+        command(mbc, 0x40);
+        command(mbc, 0x50);
+        let mut nibble = || (command(mbc, 0x10) & 0x0F) as Word;
+        let minutes = nibble() | (nibble() << 4) | (nibble() << 8);
+        let days = nibble() | (nibble() << 4) | (nibble() << 8);
+        (minutes, days)
+    }
+
+    #[test]
+    fn rtc_commands() {
+        let mut mbc = huc3();
+        set_time(&mut mbc, 0x123, 0x045);
+        assert_eq!(time(&mut mbc), (0x123, 0x045));
+        mbc.do_cycle(CYCLES_PER_MINUTE);
+        mbc.do_cycle(CYCLES_PER_MINUTE);
+        assert_eq!(time(&mut mbc), (0x125, 0x045));
+
+        // the response keeps the upper nibble of the command
+        assert_eq!(command(&mut mbc, 0x62), 0x61);
+    }
+
+    #[test]
+    fn rtc_day_rollover() {
+        let mut mbc = huc3();
+        set_time(&mut mbc, MINUTES_PER_DAY - 1, 0xFFF);
+        mbc.do_cycle(CYCLES_PER_MINUTE);
+        assert_eq!(time(&mut mbc), (0, 0x000));
+    }
+
+    #[test]
+    fn ram_modes_and_ir_port() {
+        let mut mbc = huc3();
+        mbc.handle_bank(0x4000, 0x01);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0x42);
+        mbc.handle_bank(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+
+        mbc.handle_bank(0x0000, 0x0E);
+        mbc.set_ir_signal(true);
+        assert_eq!(mbc.read_ram(0x0000), 0xC1);
+        mbc.write_ram(0x0000, 0x01);
+        assert!(mbc.ir_led());
+    }
+}
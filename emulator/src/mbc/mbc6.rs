@@ -0,0 +1,313 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * MBC6, only used by Net de Get: Minna no Soft 80. Both the ROM and the RAM windows are split in
+ * two halves with a bank register each:
+ *    4000 - 5FFF	    8 KiB ROM or flash bank A
+ *    6000 - 7FFF	    8 KiB ROM or flash bank B
+ *    A000 - AFFF	    4 KiB RAM bank A
+ *    B000 - BFFF	    4 KiB RAM bank B
+ *
+ * Registers:
+ *    0000 - 03FF	    RAM enable (0x0A)
+ *    0400 - 07FF	    RAM bank A
+ *    0800 - 0BFF	    RAM bank B
+ *    0C00 - 0FFF	    Flash enable (bit 0)
+ *    1000 - 1000	    Flash write enable (bit 0)
+ *    2000 - 27FF	    ROM/flash bank A
+ *    2800 - 2FFF	    bank A source, 0x08 selects the flash
+ *    3000 - 37FF	    ROM/flash bank B
+ *    3800 - 3FFF	    bank B source, 0x08 selects the flash
+ *
+ * The cartridge also carries a 1 MiB Macronix flash chip, programmed with the usual JEDEC
+ * command sequences. Only byte program, sector erase (64 KiB) and chip erase are emulated, it is
+ * saved right after the RAM.
+ *
+ * see https://gbdev.io/pandocs/MBC6.html
+ */
+
+const HALF_ROM_BANK_SIZE: usize = 0x2000;
+const HALF_RAM_BANK_SIZE: usize = 0x1000;
+const FLASH_SECTOR_SIZE: usize = 0x10000;
+const FLASH_SELECT: Byte = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Idle,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    EraseSetup,
+    EraseUnlocked1,
+    EraseUnlocked2,
+}
+
+pub struct Mbc6 {
+    memory: Vec<Byte>,
+    // RAM first, then the flash, in the same layout as the .sav file
+    ext_ram: Vec<Byte>,
+    enable_ram: bool,
+    ram_banks: [usize; 2],
+    rom_banks: [usize; 2],
+    flash_selected: [bool; 2],
+    enable_flash: bool,
+    enable_flash_write: bool,
+    flash_state: FlashState,
+}
+
+impl Mbc6 {
+    pub fn new(rom: &Rom) -> Mbc6 {
+        let mut ext_ram = vec![0; MBC6_RAM_SIZE + MBC6_FLASH_SIZE];
+        // an erased flash reads as all 1s
+        ext_ram[MBC6_RAM_SIZE..].fill(0xFF);
+        Mbc6 {
+            memory: copy_rom(rom),
+            ext_ram,
+            enable_ram: false,
+            ram_banks: [0, 0],
+            rom_banks: [0, 0],
+            flash_selected: [false, false],
+            enable_flash: false,
+            enable_flash_write: false,
+            flash_state: FlashState::Idle,
+        }
+    }
+
+    // which half (A or B) and the offset inside it
+    fn rom_half(addr: Word) -> (usize, usize) {
+        let addr = addr as usize;
+        (addr / HALF_ROM_BANK_SIZE, addr % HALF_ROM_BANK_SIZE)
+    }
+
+    fn flash_addr(&self, half: usize, offset: usize) -> usize {
+        (self.rom_banks[half] * HALF_ROM_BANK_SIZE + offset) % MBC6_FLASH_SIZE
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        let addr = addr as usize;
+        let half = addr / HALF_RAM_BANK_SIZE;
+        (self.ram_banks[half] * HALF_RAM_BANK_SIZE + addr % HALF_RAM_BANK_SIZE) % MBC6_RAM_SIZE
+    }
+
+    fn flash_mut(&mut self) -> &mut [Byte] {
+        &mut self.ext_ram[MBC6_RAM_SIZE..]
+    }
+
+    // addr is relative to 0x4000, writes only reach the flash when it is mapped and unlocked
+    fn write_rom(&mut self, addr: Word, data: Byte) {
+        let (half, offset) = Mbc6::rom_half(addr);
+        if self.flash_selected[half] && self.enable_flash && self.enable_flash_write {
+            let flash_addr = self.flash_addr(half, offset);
+            self.write_flash(flash_addr, data);
+        }
+    }
+
+    // JEDEC command sequences, the magic addresses are the chip addresses 0x5555 and 0x2AAA
+    fn write_flash(&mut self, flash_addr: usize, data: Byte) {
+        let command_addr = flash_addr & 0x7FFF;
+        self.flash_state = match (self.flash_state, data) {
+            // the byte to program can be anything, 0xF0 included
+            (FlashState::Program, _) => {
+                // programming can only clear bits, erasing sets them back
+                self.flash_mut()[flash_addr] &= data;
+                FlashState::Idle
+            }
+            (_, 0xF0) => FlashState::Idle,
+            (FlashState::Idle, 0xAA) if command_addr == 0x5555 => FlashState::Unlocked1,
+            (FlashState::Unlocked1, 0x55) if command_addr == 0x2AAA => FlashState::Unlocked2,
+            (FlashState::Unlocked2, 0xA0) if command_addr == 0x5555 => FlashState::Program,
+            (FlashState::Unlocked2, 0x80) if command_addr == 0x5555 => FlashState::EraseSetup,
+            (FlashState::EraseSetup, 0xAA) if command_addr == 0x5555 => FlashState::EraseUnlocked1,
+            (FlashState::EraseUnlocked1, 0x55) if command_addr == 0x2AAA => {
+                FlashState::EraseUnlocked2
+            }
+            (FlashState::EraseUnlocked2, 0x30) => {
+                let sector = flash_addr - flash_addr % FLASH_SECTOR_SIZE;
+                self.flash_mut()[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+                FlashState::Idle
+            }
+            (FlashState::EraseUnlocked2, 0x10) if command_addr == 0x5555 => {
+                self.flash_mut().fill(0xFF);
+                FlashState::Idle
+            }
+            _ => FlashState::Idle,
+        };
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc6
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        let (half, offset) = Mbc6::rom_half(addr);
+        match self.flash_selected[half] {
+            true => self.ext_ram[MBC6_RAM_SIZE + self.flash_addr(half, offset)],
+            false => {
+                let rom_addr = self.rom_banks[half] * HALF_ROM_BANK_SIZE + offset;
+                self.memory.get(rom_addr).copied().unwrap_or(0xFF)
+            }
+        }
+    }
+
+    // the 8 KiB bank at 0x4000, counted in 16 KiB banks
+    fn current_rom_bank(&self) -> usize {
+        self.rom_banks[0] / 2
+    }
+
+    // the 4 KiB bank at 0xA000
+    fn current_ram_bank(&self) -> usize {
+        self.ram_banks[0]
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        match self.enable_ram {
+            true => self.ext_ram[self.ram_addr(addr)],
+            false => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if self.enable_ram {
+            let ram_addr = self.ram_addr(addr);
+            self.ext_ram[ram_addr] = data;
+        }
+        self.enable_ram
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x03FF => self.enable_ram = (data & 0x0F) == 0xA,
+            0x0400..=0x07FF => self.ram_banks[0] = (data & 0x07) as usize,
+            0x0800..=0x0BFF => self.ram_banks[1] = (data & 0x07) as usize,
+            0x0C00..=0x0FFF => self.enable_flash = bit_set(&data, 0),
+            0x1000 => self.enable_flash_write = bit_set(&data, 0),
+            0x2000..=0x27FF => self.rom_banks[0] = (data & 0x7F) as usize,
+            0x2800..=0x2FFF => self.flash_selected[0] = data == FLASH_SELECT,
+            0x3000..=0x37FF => self.rom_banks[1] = (data & 0x7F) as usize,
+            0x3800..=0x3FFF => self.flash_selected[1] = data == FLASH_SELECT,
+            // 0x4000 - 0x7FFF writes go to the flash, see write_rom
+            _ => self.write_rom(addr - 0x4000, data),
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // flash bank 2 of half A puts 0x5555 at 0x5555, flash bank 1 of half B 0x2AAA at 0x6AAA
+    fn flash_mbc6() -> Mbc6 {
+        let mut mbc = Mbc6::new(&test_rom(0x20, 0x02, 0x00));
+        mbc.handle_bank(0x0C00, 0x01);
+        mbc.handle_bank(0x1000, 0x01);
+        mbc.handle_bank(0x2000, 0x02);
+        mbc.handle_bank(0x2800, FLASH_SELECT);
+        mbc.handle_bank(0x3000, 0x01);
+        mbc.handle_bank(0x3800, FLASH_SELECT);
+        mbc
+    }
+
+    fn command(mbc: &mut Mbc6, writes: &[(Word, Byte)]) {
+        for &(addr, data) in writes {
+            mbc.handle_bank(addr, data);
+        }
+    }
+
+    const UNLOCK: [(Word, Byte); 2] = [(0x5555, 0xAA), (0x6AAA, 0x55)];
+
+    fn program(mbc: &mut Mbc6, addr: Word, data: Byte) {
+        command(mbc, &UNLOCK);
+        command(mbc, &[(0x5555, 0xA0), (addr, data)]);
+    }
+
+    fn erase(mbc: &mut Mbc6, addr: Word, data: Byte) {
+        command(mbc, &UNLOCK);
+        command(mbc, &[(0x5555, 0x80)]);
+        command(mbc, &UNLOCK);
+        command(mbc, &[(addr, data)]);
+    }
+
+    #[test]
+    fn rom_and_ram_halves() {
+        let mut mbc = Mbc6::new(&test_rom(0x20, 0x02, 0x00));
+        // 8 KiB banks, the test ROM numbers the 16 KiB ones
+        mbc.handle_bank(0x2000, 0x03);
+        mbc.handle_bank(0x3000, 0x04);
+        assert_eq!(mbc.read_rom(0x0000), 0x01);
+        assert_eq!(mbc.read_rom(0x2000), 0x02);
+        assert_eq!(mbc.current_rom_bank(), 0x01);
+
+        mbc.write_ram(0x0000, 0x11);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.handle_bank(0x0400, 0x01);
+        mbc.handle_bank(0x0800, 0x03);
+        mbc.write_ram(0x0000, 0x11);
+        mbc.write_ram(0x1000, 0x33);
+        assert_eq!(mbc.get_ext_ram()[0x1000], 0x11);
+        assert_eq!(mbc.get_ext_ram()[0x3000], 0x33);
+    }
+
+    #[test]
+    fn flash_program() {
+        let mut mbc = flash_mbc6();
+        assert_eq!(mbc.read_rom(0x0010), 0xFF);
+        program(&mut mbc, 0x4010, 0x3C);
+        assert_eq!(mbc.read_rom(0x0010), 0x3C);
+        assert_eq!(mbc.get_ext_ram()[MBC6_RAM_SIZE + 0x4010], 0x3C);
+        // programming only clears bits
+        program(&mut mbc, 0x4010, 0xF0);
+        assert_eq!(mbc.read_rom(0x0010), 0x30);
+
+        // without the unlock sequence the write is dropped
+        command(&mut mbc, &[(0x5555, 0xA0), (0x4011, 0x00)]);
+        assert_eq!(mbc.read_rom(0x0011), 0xFF);
+        // and so it is with the flash write protected
+        mbc.handle_bank(0x1000, 0x00);
+        program(&mut mbc, 0x4011, 0x00);
+        assert_eq!(mbc.read_rom(0x0011), 0xFF);
+    }
+
+    #[test]
+    fn flash_reset_aborts_a_command() {
+        let mut mbc = flash_mbc6();
+        command(&mut mbc, &UNLOCK);
+        command(&mut mbc, &[(0x5555, 0xF0), (0x5555, 0xA0), (0x4010, 0x00)]);
+        assert_eq!(mbc.read_rom(0x0010), 0xFF);
+    }
+
+    #[test]
+    fn flash_erase() {
+        let mut mbc = flash_mbc6();
+        program(&mut mbc, 0x4010, 0x00);
+        // 0x22000 is in another sector, flash bank 0x11 of half B still decodes 0x6AAA as
+        // the command address 0x2AAA
+        mbc.handle_bank(0x3000, 0x11);
+        program(&mut mbc, 0x6000, 0x00);
+
+        erase(&mut mbc, 0x4123, 0x30);
+        assert_eq!(mbc.read_rom(0x0010), 0xFF);
+        assert_eq!(mbc.read_rom(0x2000), 0x00);
+
+        erase(&mut mbc, 0x5555, 0x10);
+        assert!(mbc.get_ext_ram()[MBC6_RAM_SIZE..]
+            .iter()
+            .all(|&byte| byte == 0xFF));
+    }
+}
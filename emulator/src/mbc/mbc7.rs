@@ -0,0 +1,423 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * MBC7, used by Kirby Tilt 'n' Tumble and Command Master. Besides the usual ROM banking it has a
+ * two axis accelerometer and a 93LC56 serial EEPROM instead of RAM, both reached through
+ * registers in 0xA000 - 0xAFFF once the two RAM enable registers are set:
+ *    0000 - 1FFF	    RAM enable 1 (0x0A)
+ *    2000 - 3FFF	    ROM bank
+ *    4000 - 5FFF	    RAM enable 2 (0x40)
+ *
+ *    A00x	            write 0x55 to erase the latched accelerometer values
+ *    A01x	            write 0xAA to latch the accelerometer
+ *    A02x / A03x	    X, low / high byte
+ *    A04x / A05x	    Y, low / high byte
+ *    A08x	            EEPROM pins, bit 7: CS, bit 6: CLK, bit 1: DI, bit 0: DO
+ *
+ * The accelerometer reads 0x81D0 when flat and moves about 0x70 per g of tilt.
+ *
+ * see https://gbdev.io/pandocs/MBC7.html
+ */
+
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+const ACCELEROMETER_G: f32 = 0x70 as f32;
+const ACCELEROMETER_ERASED: Word = 0x8000;
+
+// the EEPROM is 128 words of 16 bits, a command is a start bit, a 2 bit opcode and 8 address bits
+const EEPROM_WORDS: usize = MBC7_EEPROM_SIZE / 2;
+const EEPROM_COMMAND_BITS: u8 = 10;
+const EEPROM_WORD_BITS: u8 = 16;
+
+const EEPROM_CS_BIT: u8 = 7;
+const EEPROM_CLK_BIT: u8 = 6;
+const EEPROM_DI_BIT: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    // waiting for the start bit
+    Idle,
+    Command {
+        bits: u8,
+        value: Word,
+    },
+    Reading {
+        bits: u8,
+        value: Word,
+    },
+    // None writes every word (WRAL)
+    Writing {
+        addr: Option<usize>,
+        bits: u8,
+        value: Word,
+    },
+}
+
+pub struct Mbc7 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    enable_ram_1: bool,
+    enable_ram_2: bool,
+    // the tilt set by the frontend, in g
+    tilt: (f32, f32),
+    latched_x: Word,
+    latched_y: Word,
+    latch_ready: bool,
+    // stored little endian, the same layout other emulators use for the .sav file
+    eeprom: Vec<Byte>,
+    eeprom_state: EepromState,
+    eeprom_write_enabled: bool,
+    // set by every change to the EEPROM, until write_ram reports it
+    eeprom_written: bool,
+    eeprom_cs: bool,
+    eeprom_clk: bool,
+    eeprom_di: bool,
+    eeprom_do: bool,
+}
+
+impl Mbc7 {
+    pub fn new(rom: &Rom) -> Mbc7 {
+        Mbc7 {
+            memory: copy_rom(rom),
+            rom_bank: 1,
+            enable_ram_1: false,
+            enable_ram_2: false,
+            tilt: (0.0, 0.0),
+            latched_x: ACCELEROMETER_ERASED,
+            latched_y: ACCELEROMETER_ERASED,
+            latch_ready: true,
+            eeprom: vec![0xFF; MBC7_EEPROM_SIZE],
+            eeprom_state: EepromState::Idle,
+            eeprom_write_enabled: false,
+            eeprom_written: false,
+            eeprom_cs: false,
+            eeprom_clk: false,
+            eeprom_di: false,
+            eeprom_do: true,
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.enable_ram_1 && self.enable_ram_2
+    }
+
+    fn read_word(&self, addr: usize) -> Word {
+        let addr = addr % EEPROM_WORDS;
+        (self.eeprom[addr * 2] as Word) | ((self.eeprom[addr * 2 + 1] as Word) << 8)
+    }
+
+    fn write_word(&mut self, addr: usize, value: Word) {
+        let addr = addr % EEPROM_WORDS;
+        self.eeprom[addr * 2] = value as Byte;
+        self.eeprom[addr * 2 + 1] = (value >> 8) as Byte;
+        self.eeprom_written = true;
+    }
+
+    fn latch_accelerometer(&mut self) {
+        let axis =
+            |g: f32| (ACCELEROMETER_CENTER + g * ACCELEROMETER_G).clamp(0.0, 65535.0) as Word;
+        self.latched_x = axis(self.tilt.0);
+        self.latched_y = axis(self.tilt.1);
+        self.latch_ready = false;
+    }
+
+    fn write_eeprom_pins(&mut self, data: Byte) {
+        let cs = bit_set(&data, EEPROM_CS_BIT);
+        let clk = bit_set(&data, EEPROM_CLK_BIT);
+        self.eeprom_di = bit_set(&data, EEPROM_DI_BIT);
+
+        if !cs {
+            self.eeprom_state = EepromState::Idle;
+        } else if clk && !self.eeprom_clk {
+            self.clock_eeprom();
+        }
+        self.eeprom_cs = cs;
+        self.eeprom_clk = clk;
+    }
+
+    // one rising edge of CLK while CS is high
+    fn clock_eeprom(&mut self) {
+        let di = self.eeprom_di as Word;
+        self.eeprom_state = match self.eeprom_state {
+            EepromState::Idle if di == 1 => EepromState::Command { bits: 0, value: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, value } => {
+                let value = (value << 1) | di;
+                match bits + 1 {
+                    EEPROM_COMMAND_BITS => self.run_eeprom_command(value),
+                    bits => EepromState::Command { bits, value },
+                }
+            }
+            EepromState::Reading { bits, value } => {
+                self.eeprom_do = value & 0x8000 != 0;
+                match bits + 1 {
+                    EEPROM_WORD_BITS => EepromState::Idle,
+                    bits => EepromState::Reading {
+                        bits,
+                        value: value << 1,
+                    },
+                }
+            }
+            EepromState::Writing { addr, bits, value } => {
+                let value = (value << 1) | di;
+                match bits + 1 {
+                    EEPROM_WORD_BITS => {
+                        if self.eeprom_write_enabled {
+                            match addr {
+                                Some(addr) => self.write_word(addr, value),
+                                None => (0..EEPROM_WORDS).for_each(|i| self.write_word(i, value)),
+                            }
+                        }
+                        self.eeprom_do = true;
+                        EepromState::Idle
+                    }
+                    bits => EepromState::Writing { addr, bits, value },
+                }
+            }
+        };
+    }
+
+    fn run_eeprom_command(&mut self, command: Word) -> EepromState {
+        let addr = (command & 0x7F) as usize;
+        match command >> 8 {
+            0b10 => {
+                // a dummy 0 comes out before the data
+                self.eeprom_do = false;
+                EepromState::Reading {
+                    bits: 0,
+                    value: self.read_word(addr),
+                }
+            }
+            0b01 => EepromState::Writing {
+                addr: Some(addr),
+                bits: 0,
+                value: 0,
+            },
+            0b11 => {
+                if self.eeprom_write_enabled {
+                    self.write_word(addr, 0xFFFF);
+                }
+                self.eeprom_do = true;
+                EepromState::Idle
+            }
+            _ => match (command >> 6) & 0x3 {
+                0b00 => {
+                    self.eeprom_write_enabled = false;
+                    EepromState::Idle
+                }
+                0b01 => EepromState::Writing {
+                    addr: None,
+                    bits: 0,
+                    value: 0,
+                },
+                0b10 => {
+                    if self.eeprom_write_enabled {
+                        self.eeprom.fill(0xFF);
+                        self.eeprom_written = true;
+                    }
+                    self.eeprom_do = true;
+                    EepromState::Idle
+                }
+                _ => {
+                    self.eeprom_write_enabled = true;
+                    EepromState::Idle
+                }
+            },
+        }
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mbc7
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.rom_bank, addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.registers_enabled() || addr >= 0x1000 {
+            return 0xFF;
+        }
+        match (addr >> 4) & 0xF {
+            0x2 => self.latched_x as Byte,
+            0x3 => (self.latched_x >> 8) as Byte,
+            0x4 => self.latched_y as Byte,
+            0x5 => (self.latched_y >> 8) as Byte,
+            0x6 => 0x00,
+            0x8 => {
+                ((self.eeprom_cs as Byte) << EEPROM_CS_BIT)
+                    | ((self.eeprom_clk as Byte) << EEPROM_CLK_BIT)
+                    | ((self.eeprom_di as Byte) << EEPROM_DI_BIT)
+                    | self.eeprom_do as Byte
+            }
+            _ => 0xFF,
+        }
+    }
+
+    // the EEPROM is what gets saved, a write to the pins can end a command that changes it
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if !self.registers_enabled() || addr >= 0x1000 {
+            return false;
+        }
+        match (addr >> 4) & 0xF {
+            0x0 if data == 0x55 => {
+                self.latched_x = ACCELEROMETER_ERASED;
+                self.latched_y = ACCELEROMETER_ERASED;
+                self.latch_ready = true;
+            }
+            0x1 if data == 0xAA && self.latch_ready => self.latch_accelerometer(),
+            0x8 => self.write_eeprom_pins(data),
+            _ => {}
+        }
+        std::mem::take(&mut self.eeprom_written)
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => self.enable_ram_1 = data == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x7F) as usize,
+            0x4000..=0x5FFF => self.enable_ram_2 = data == 0x40,
+            _ => {}
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.eeprom
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.eeprom, &buffer);
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CS: Byte = 1 << EEPROM_CS_BIT;
+    const CLK: Byte = 1 << EEPROM_CLK_BIT;
+
+    fn mbc7() -> Mbc7 {
+        let mut mbc = Mbc7::new(&test_rom(0x22, 0x01, 0x00));
+        mbc.handle_bank(0x0000, 0x0A);
+        mbc.handle_bank(0x4000, 0x40);
+        mbc
+    }
+
+    // clocks one bit in and returns DO after the rising edge
+    fn clock(mbc: &mut Mbc7, bit: bool) -> bool {
+        let di = (bit as Byte) << EEPROM_DI_BIT;
+        mbc.write_ram(0x0080, CS | di);
+        mbc.write_ram(0x0080, CS | CLK | di);
+        mbc.read_ram(0x0080) & 0x01 == 1
+    }
+
+    // the start bit, the opcode and the address, returns DO after the last bit
+    fn command(mbc: &mut Mbc7, command: Word) -> bool {
+        clock(mbc, true);
+        (0..EEPROM_COMMAND_BITS)
+            .rev()
+            .fold(false, |_, i| clock(mbc, command & (1 << i) != 0))
+    }
+
+    // a command and its data bits if any, CS goes low at the end. returns DO after the last bit
+    fn send(mbc: &mut Mbc7, command: Word, data: Option<Word>) -> bool {
+        let mut out = self::command(mbc, command);
+        if let Some(data) = data {
+            for i in (0..EEPROM_WORD_BITS).rev() {
+                out = clock(mbc, data & (1 << i) != 0);
+            }
+        }
+        mbc.write_ram(0x0080, 0x00);
+        out
+    }
+
+    fn read(mbc: &mut Mbc7, addr: Word) -> Word {
+        // a dummy 0 comes before the data
+        assert!(!command(mbc, READ | addr));
+        let value =
+            (0..EEPROM_WORD_BITS).fold(0, |value, _| (value << 1) | clock(mbc, false) as Word);
+        mbc.write_ram(0x0080, 0x00);
+        value
+    }
+
+    const READ: Word = 0x200;
+    const EWEN: Word = 0x0C0;
+    const EWDS: Word = 0x000;
+    const WRITE: Word = 0x100;
+    const ERASE: Word = 0x300;
+    const WRAL: Word = 0x040;
+    const ERAL: Word = 0x080;
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc7();
+        assert_eq!(read(&mut mbc, 0x05), 0xFFFF);
+        // write protected until EWEN
+        send(&mut mbc, WRITE | 0x05, Some(0xBEEF));
+        assert_eq!(read(&mut mbc, 0x05), 0xFFFF);
+
+        send(&mut mbc, EWEN, None);
+        assert!(send(&mut mbc, WRITE | 0x05, Some(0xBEEF)));
+        assert_eq!(read(&mut mbc, 0x05), 0xBEEF);
+        assert_eq!(mbc.get_ext_ram()[0x0A..0x0C], [0xEF, 0xBE]);
+
+        send(&mut mbc, ERASE | 0x05, None);
+        assert_eq!(read(&mut mbc, 0x05), 0xFFFF);
+
+        send(&mut mbc, EWDS, None);
+        send(&mut mbc, WRITE | 0x05, Some(0x1234));
+        assert_eq!(read(&mut mbc, 0x05), 0xFFFF);
+    }
+
+    #[test]
+    fn eeprom_write_and_erase_all() {
+        let mut mbc = mbc7();
+        send(&mut mbc, EWEN, None);
+        send(&mut mbc, WRAL, Some(0x5AA5));
+        assert_eq!(read(&mut mbc, 0x00), 0x5AA5);
+        assert_eq!(read(&mut mbc, 0x7F), 0x5AA5);
+        send(&mut mbc, ERAL, None);
+        assert!(mbc.get_ext_ram().iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn accelerometer() {
+        let mut mbc = mbc7();
+        let axis = |mbc: &Mbc7, addr: Word| {
+            (mbc.read_ram(addr) as Word) | ((mbc.read_ram(addr + 0x10) as Word) << 8)
+        };
+        assert_eq!(axis(&mbc, 0x0020), ACCELEROMETER_ERASED);
+        mbc.set_tilt(1.0, -0.5);
+        mbc.write_ram(0x0010, 0xAA);
+        assert_eq!(axis(&mbc, 0x0020), 0x81D0 + 0x70);
+        assert_eq!(axis(&mbc, 0x0040), 0x81D0 - 0x38);
+
+        // latching again needs an erase first
+        mbc.set_tilt(0.0, 0.0);
+        mbc.write_ram(0x0010, 0xAA);
+        assert_eq!(axis(&mbc, 0x0020), 0x81D0 + 0x70);
+        mbc.write_ram(0x0000, 0x55);
+        mbc.write_ram(0x0010, 0xAA);
+        assert_eq!(axis(&mbc, 0x0020), 0x81D0);
+
+        // both enables are needed
+        mbc.handle_bank(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0x0020), 0xFF);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, BankingMode, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * MMM01, the multicart mapper behind Momotarou Collection 2 and Taito Variety Pack. It powers
+ * up "unmapped", showing the last 32 KiB of the ROM (the menu) at 0x0000 - 0x7FFF. The menu
+ * writes the outer bank bits of the game it wants to run and then sets bit 6 of 0x0000 - 0x1FFF,
+ * which maps the game and freezes the outer bits, from then on it behaves like an MBC1:
+ *    0000 - 1FFF	    RAM enable (0x0A), bit 6 maps the game
+ *    2000 - 3FFF	    ROM bank, bits 0 - 4 and before mapping bits 5 - 6 (ROM bank mid)
+ *    4000 - 5FFF	    RAM bank, bits 0 - 1 and before mapping bits 2 - 3 (RAM bank high),
+ *                      bits 4 - 5 (ROM bank high), bit 6 (protect the mode register)
+ *    6000 - 7FFF	    mode (bit 0), before mapping bits 2 - 5 mask ROM bank bits 1 - 4
+ *
+ * Since the menu lives at the end of the ROM, so does the header that describes the mapper,
+ * Rom::from_bytes takes care of finding it.
+ *
+ * see https://github.com/Gekkio/gb-hardware/blob/main/docs/mmm01.md
+ */
+
+pub struct Mmm01 {
+    memory: Vec<Byte>,
+    ext_ram: Vec<Byte>,
+    number_of_rom_banks: usize,
+    mapped: bool,
+    enable_ram: bool,
+    rom_bank_low: usize,
+    rom_bank_mid: usize,
+    rom_bank_high: usize,
+    // ROM bank bits that the game can no longer change once mapped
+    rom_bank_mask: usize,
+    ram_bank_low: usize,
+    ram_bank_high: usize,
+    banking_mode: BankingMode,
+    protect_mode: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: &Rom) -> Mmm01 {
+        Mmm01 {
+            memory: copy_rom(rom),
+            ext_ram: vec![0; rom.header().ram_size],
+            number_of_rom_banks: rom.get_number_banks(),
+            mapped: false,
+            enable_ram: false,
+            rom_bank_low: 1,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            banking_mode: BankingMode::Rom,
+            protect_mode: false,
+        }
+    }
+
+    fn outer_bank(&self) -> usize {
+        (self.rom_bank_high << 7) | (self.rom_bank_mid << 5)
+    }
+
+    fn mask_rom_bank(&self, bank: usize) -> usize {
+        bank % self.number_of_rom_banks
+    }
+
+    fn ram_addr(&self, addr: Word) -> usize {
+        ((addr as usize) + (self.current_ram_bank() * RAM_BANK_SIZE)) % self.ext_ram.len()
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Mmm01
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        let rom_bank = match self.mapped {
+            true => self.outer_bank() | (self.rom_bank_low & self.rom_bank_mask),
+            false => self.number_of_rom_banks - 2,
+        };
+        read_bank(&self.memory, self.mask_rom_bank(rom_bank), addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_ram_bank(&self) -> usize {
+        match self.banking_mode {
+            BankingMode::Ram => (self.ram_bank_high << 2) | self.ram_bank_low,
+            BankingMode::Rom => self.ram_bank_high << 2,
+        }
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        let rom_bank = match self.mapped {
+            true => self.outer_bank() | self.rom_bank_low,
+            false => self.number_of_rom_banks - 1,
+        };
+        self.mask_rom_bank(rom_bank)
+    }
+
+    fn read_ram(&self, addr: Word) -> Byte {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return 0xFF;
+        }
+        self.ext_ram[self.ram_addr(addr)]
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        if !self.enable_ram || self.ext_ram.is_empty() {
+            return false;
+        }
+        let ram_addr = self.ram_addr(addr);
+        self.ext_ram[ram_addr] = data;
+        true
+    }
+
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.enable_ram = (data & 0x0F) == 0xA;
+                if !self.mapped {
+                    self.mapped = bit_set(&data, 6);
+                }
+            }
+            0x2000..=0x3FFF => {
+                let mut bank = (data & 0x1F) as usize;
+                if self.mapped {
+                    bank = (self.rom_bank_low & self.rom_bank_mask) | (bank & !self.rom_bank_mask);
+                } else {
+                    self.rom_bank_mid = ((data >> 5) & 0x03) as usize;
+                }
+                // as on MBC1, 0 selects 1 unless the masked bits keep the bank away from 0
+                if bank == 0 {
+                    bank = 1;
+                }
+                self.rom_bank_low = bank;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = (data & 0x03) as usize;
+                if !self.mapped {
+                    self.ram_bank_high = ((data >> 2) & 0x03) as usize;
+                    self.rom_bank_high = ((data >> 4) & 0x03) as usize;
+                    self.protect_mode = bit_set(&data, 6);
+                }
+            }
+            _ => {
+                if !self.protect_mode {
+                    self.banking_mode = match bit_set(&data, 0) {
+                        true => BankingMode::Ram,
+                        false => BankingMode::Rom,
+                    };
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (((data >> 2) & 0x0F) as usize) << 1;
+                }
+            }
+        }
+    }
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::CartridgeType;
+
+    // 1 MiB with 32 KiB of RAM, the header describing the MMM01 is the one of the menu in the
+    // last 32 KiB
+    fn mmm01() -> Mmm01 {
+        let mut data = test_rom_data(0x00, 0x05, 0x00);
+        let menu = data.len() - 2 * ROM_BANK_SIZE;
+        data[menu + 0x147] = 0x0D;
+        data[menu + 0x148] = 0x05;
+        data[menu + 0x149] = 0x03;
+        set_header_checksum(&mut data[menu..]);
+        let rom = Rom::from_bytes(data).unwrap();
+        assert_eq!(rom.get_cartridge_type(), CartridgeType::Mmm01RamBattery);
+        Mmm01::new(&rom)
+    }
+
+    #[test]
+    fn menu_then_game() {
+        let mut mbc = mmm01();
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x3E);
+        assert_eq!(mbc.read_rom(0x0000), 0x3F);
+
+        // the game at bank 0x20: ROM bank mid 1, then map it
+        mbc.handle_bank(0x2000, 0x20);
+        mbc.handle_bank(0x0000, 0x40);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x0000), 0x21);
+        mbc.handle_bank(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x0000), 0x25);
+        // the outer bits are frozen now
+        mbc.handle_bank(0x2000, 0x43);
+        assert_eq!(mbc.read_rom(0x0000), 0x23);
+        mbc.handle_bank(0x0000, 0x00);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x20);
+    }
+
+    #[test]
+    fn rom_bank_mask() {
+        let mut mbc = mmm01();
+        // bits 1 - 2 of the ROM bank stay as the menu left them
+        mbc.handle_bank(0x2000, 0x26);
+        mbc.handle_bank(0x6000, 0x0C);
+        mbc.handle_bank(0x0000, 0x40);
+        assert_eq!(mbc.read_rom_bank0(0x0000), 0x26);
+        mbc.handle_bank(0x2000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x27);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mmm01();
+        // RAM bank high is set before mapping, with 32 KiB bank 4 wraps around to bank 0
+        mbc.handle_bank(0x4000, 0x04);
+        mbc.handle_bank(0x0000, 0x4A);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.get_ext_ram()[0], 0x42);
+        // bank 5 in mode 1, the high bits are frozen once mapped
+        mbc.handle_bank(0x6000, 0x01);
+        mbc.handle_bank(0x4000, 0x01);
+        mbc.write_ram(0x0000, 0x43);
+        assert_eq!(mbc.get_ext_ram()[RAM_BANK_SIZE], 0x43);
+    }
+}
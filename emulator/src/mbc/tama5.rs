@@ -0,0 +1,170 @@
+use crate::mbc::{copy_ram, copy_rom, read_bank, Mbc, MbcType};
+use crate::rom::*;
+use crate::utils::*;
+
+/*
+ * Bandai TAMA5, only used by Game de Hakken!! Tamagotchi 3. It has no registers in 0x0000 -
+ * 0x7FFF, everything goes through two ports: 0xA001 selects a 4 bit register and 0xA000 reads
+ * or writes its lower nibble.
+ *    0x0	            ROM bank, bits 0 - 3
+ *    0x1	            ROM bank, bit 4
+ *    0x4 / 0x5	        data to write, low / high nibble
+ *    0x6	            bit 0: address bit 4, bits 1 - 3: command (0 write, 1 read)
+ *    0x7	            address bits 0 - 3, writing it runs the command
+ *    0xA	            reads 0x1 once the chip is ready
+ *    0xC / 0xD	        data read back, low / high nibble
+ *
+ * The 32 bytes of memory behind it are battery backed. The TAMA6 clock commands are not
+ * emulated.
+ *
+ * see https://gbdev.io/pandocs/TAMA5.html
+ */
+
+const COMMAND_WRITE: Byte = 0x0;
+const COMMAND_READ: Byte = 0x1;
+
+pub struct Tama5 {
+    memory: Vec<Byte>,
+    rom_bank: usize,
+    number_of_rom_banks: usize,
+    ext_ram: Vec<Byte>,
+    register: Byte,
+    data_in: Byte,
+    data_out: Byte,
+    // register 0x6, command and the upper address bit
+    command: Byte,
+}
+
+impl Tama5 {
+    pub fn new(rom: &Rom) -> Tama5 {
+        Tama5 {
+            memory: copy_rom(rom),
+            rom_bank: 0,
+            number_of_rom_banks: rom.get_number_banks(),
+            ext_ram: vec![0; TAMA5_RAM_SIZE],
+            register: 0,
+            data_in: 0,
+            data_out: 0,
+            command: 0,
+        }
+    }
+
+    // true when a write command stored a byte in the memory
+    fn write_register(&mut self, data: Byte) -> bool {
+        let data = data & 0x0F;
+        match self.register {
+            0x0 => self.rom_bank = (self.rom_bank & 0x10) | data as usize,
+            0x1 => self.rom_bank = (self.rom_bank & 0x0F) | (((data & 0x01) as usize) << 4),
+            0x4 => self.data_in = (self.data_in & 0xF0) | data,
+            0x5 => self.data_in = (self.data_in & 0x0F) | (data << 4),
+            0x6 => self.command = data,
+            0x7 => {
+                let addr = (((self.command & 0x01) << 4) | data) as usize;
+                match self.command >> 1 {
+                    COMMAND_WRITE => {
+                        self.ext_ram[addr] = self.data_in;
+                        return true;
+                    }
+                    COMMAND_READ => self.data_out = self.ext_ram[addr],
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+impl Mbc for Tama5 {
+    fn get_mbc_type(&self) -> MbcType {
+        MbcType::Tama5
+    }
+
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, 0, addr)
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        read_bank(&self.memory, self.current_rom_bank(), addr)
+    }
+
+    fn current_rom_bank(&self) -> usize {
+        self.rom_bank % self.number_of_rom_banks
+    }
+
+    // only 0xA000 answers, with the selected register in the lower nibble
+    fn read_ram(&self, addr: Word) -> Byte {
+        if addr != 0 {
+            return 0xFF;
+        }
+        match self.register {
+            0xA => 0xF1,
+            0xC => 0xF0 | (self.data_out & 0x0F),
+            0xD => 0xF0 | (self.data_out >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: Word, data: Byte) -> bool {
+        match addr {
+            0x0000 => return self.write_register(data),
+            0x0001 => self.register = data & 0x0F,
+            _ => {}
+        }
+        false
+    }
+
+    fn handle_bank(&mut self, _addr: Word, _data: Byte) {}
+
+    fn get_ext_ram(&self) -> &[Byte] {
+        &self.ext_ram
+    }
+
+    fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        copy_ram(&mut self.ext_ram, &buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(mbc: &mut Tama5, register: Byte, data: Byte) {
+        mbc.write_ram(0x0001, register);
+        mbc.write_ram(0x0000, data);
+    }
+
+    fn read_register(mbc: &mut Tama5, register: Byte) -> Byte {
+        mbc.write_ram(0x0001, register);
+        mbc.read_ram(0x0000)
+    }
+
+    #[test]
+    fn memory_through_the_registers() {
+        let mut mbc = Tama5::new(&test_rom(0xFD, 0x04, 0x00));
+        assert_eq!(read_register(&mut mbc, 0xA), 0xF1);
+
+        // write 5A to 0x13
+        write_register(&mut mbc, 0x4, 0xA);
+        write_register(&mut mbc, 0x5, 0x5);
+        write_register(&mut mbc, 0x6, (COMMAND_WRITE << 1) | 0x1);
+        write_register(&mut mbc, 0x7, 0x3);
+        assert_eq!(mbc.get_ext_ram()[0x13], 0x5A);
+
+        write_register(&mut mbc, 0x6, (COMMAND_READ << 1) | 0x1);
+        write_register(&mut mbc, 0x7, 0x3);
+        assert_eq!(read_register(&mut mbc, 0xC), 0xFA);
+        assert_eq!(read_register(&mut mbc, 0xD), 0xF5);
+        // only 0xA000 answers
+        assert_eq!(mbc.read_ram(0x0002), 0xFF);
+    }
+
+    #[test]
+    fn rom_bank() {
+        let mut mbc = Tama5::new(&test_rom(0xFD, 0x04, 0x00));
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        write_register(&mut mbc, 0x0, 0x3);
+        write_register(&mut mbc, 0x1, 0x1);
+        assert_eq!(mbc.read_rom(0x0000), 0x13);
+    }
+}
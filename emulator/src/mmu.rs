@@ -1,283 +1,830 @@
-use std::cmp;
-
+use crate::apu::*;
+use crate::dma::*;
+use crate::error::*;
+use crate::hdma::*;
+use crate::interrupt::*;
 use crate::joypad::*;
 use crate::mbc::*;
+use crate::ppu::*;
 use crate::rom::*;
+use crate::rtc::*;
+use crate::serial::*;
+use crate::timer::*;
 use crate::utils::*;
+use crate::watchpoint::*;
 
 // this is the implementation of the memory management unit
 // mmu is the interface between cpu, ppu, spu and the memory. this is the control bus
 
-
-/**
-    * Memory Management Unit for the Gameboy. Memory has a 16 bit address bus and is broken down as follows:
-    *    0000 - 3FFF	    16 KiB ROM bank 00	            From cartridge, usually a fixed bank
-    *    4000 - 7FFF	    16 KiB ROM Bank 01~NN	        From cartridge, switchable bank via mapper (if any)
-    *    8000 - 9FFF	    8 KiB Video RAM (VRAM)	        In CGB mode, switchable bank 0/1
-    *    A000 - BFFF	    8 KiB External RAM	            From cartridge, switchable bank if any
-    *    C000 - CFFF	    4 KiB Work RAM (WRAM)
-    *    D000 - DFFF	    4 KiB Work RAM (WRAM)	        In CGB mode, switchable bank 1~7
-    *    E000 - FDFF	    Mirror of C000~DDFF (ECHO RAM)	Nintendo says use of this area is prohibited.
-    *    FE00 - FE9F	    Sprite attribute table (OAM)
-    *    FEA0 - FEFF	    Not Usable	                    Nintendo says use of this area is prohibited
-    *    FF00 - FF7F	    I/O Registers
-    *    FF80 - FFFE	    High RAM (HRAM)
-    *    FFFF - FFFF	    Interrupt Enable register (IE)
-    **/
-
-
-
-//wram is the working ram
-const wram_size: usize = 0x8000;
-const zram_size: usize = 0x7f;
-
-pub struct mmu {
-    memory: [Byte; memory_size],
-    oam: bool,
-    vram: bool,
-    color_pallette: bool,
+/*
+ * Memory Management Unit for the Gameboy. Memory has a 16 bit address bus and is broken down as follows:
+ *    0000 - 3FFF	    16 KiB ROM bank 00	            From cartridge, usually a fixed bank
+ *    4000 - 7FFF	    16 KiB ROM Bank 01~NN	        From cartridge, switchable bank via mapper (if any)
+ *    8000 - 9FFF	    8 KiB Video RAM (VRAM)	        In CGB mode, switchable bank 0/1
+ *    A000 - BFFF	    8 KiB External RAM	            From cartridge, switchable bank if any
+ *    C000 - CFFF	    4 KiB Work RAM (WRAM)
+ *    D000 - DFFF	    4 KiB Work RAM (WRAM)	        In CGB mode, switchable bank 1~7
+ *    E000 - FDFF	    Mirror of C000~DDFF (ECHO RAM)	Nintendo says use of this area is prohibited.
+ *    FE00 - FE9F	    Sprite attribute table (OAM)
+ *    FEA0 - FEFF	    Not Usable	                    Nintendo says use of this area is prohibited
+ *    FF00 - FF7F	    I/O Registers
+ *    FF80 - FFFE	    High RAM (HRAM)
+ *    FFFF - FFFF	    Interrupt Enable register (IE)
+ *
+ * CGB mode is picked from the CGB flag of the header, it adds the second VRAM bank, WRAM banks
+ * 2~7 (SVBK, 0xFF70), the double speed mode (KEY1, 0xFF4D) and the VRAM DMA (0xFF51 - 0xFF55).
+ * In DMG mode these registers read 0xFF and ignore writes.
+ */
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
+// KEY1 (0xFF4D) bits
+const KEY1_SWITCH_ARMED: u8 = 0;
+const KEY1_DOUBLE_SPEED: u8 = 7;
+
+// the counter bit of the timer that drives the frame sequencer of the APU, DIV bit 4
+const FRAME_SEQUENCER_BIT: u8 = 12;
+
+// machine cycles the cpu stays stopped while the speed changes
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+pub struct Mmu {
+    memory: Box<[Byte; MEMORY_SIZE]>,
+    cgb: bool,
+    // banks 1~7 of the D000 - DFFF area, the DMG only has bank 1
+    wram: Box<[Byte; WRAM_BANK_SIZE * (WRAM_BANKS - 1)]>,
+    wram_bank: usize,
+    double_speed: bool,
+    speed_switch_armed: bool,
     rom: Rom,
     joypad: Joypad,
-    mbc: Option<Box<dyn Mbc>>
+    mbc: Option<Box<dyn Mbc>>,
+    intf: Byte,
+    inte: Byte,
+    timer: Timer,
+    ppu: Ppu,
+    apu: Apu,
+    serial: Serial,
+    dma: OamDma,
+    hdma: Hdma,
+    // machine cycles the cpu has to wait for the VRAM DMA
+    stall_cycles: u32,
+    // set when the mapper stores a byte in the external RAM, cleared once it has been saved
+    ext_ram_dirty: bool,
+    watchpoints: Watchpoints,
+    // LY reads 0x90 whatever the PPU is at, for traces compared with gameboy-doctor
+    doctor_mode: bool,
 }
 
-impl Mmu{
-
-    pub fn init(rom: Rom, joypad: Joypad) -> Mmu
-    {
-        Mmu{
-            memory: [0; memory_size],
-            oam: true,
-            vram: true,
-            color_pallette: true,
-            rom: rom,
-            joypad: joypad,
-            mbc: None     
-        }   
-    }
-
-    pub fn get_ext_ram(&self) -> &[Byte]{
-        match &self.mbc{
-            Some(mbc) => mbc.get_ext_ram();
-            None => format!("MBC Type: None"),
+impl Mmu {
+    pub fn new(rom: Rom) -> Result<Mmu> {
+        let mbc = get_mbc(&rom)?;
+        let mut mmu = Mmu::init(rom, Joypad::new());
+        mmu.mbc = mbc;
+        mmu.cgb = mmu.rom.header().is_cgb();
+        mmu.reset();
+        Ok(mmu)
+    }
+
+    pub fn init(rom: Rom, joypad: Joypad) -> Mmu {
+        Mmu {
+            memory: boxed_array(0),
+            cgb: false,
+            wram: boxed_array(0),
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            rom,
+            joypad,
+            mbc: None,
+            intf: 0,
+            inte: 0,
+            timer: Timer::new(),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            serial: Serial::new(false),
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            ext_ram_dirty: false,
+            watchpoints: Watchpoints::new(),
+            doctor_mode: false,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn mbc(&self) -> Option<&dyn Mbc> {
+        self.mbc.as_deref()
+    }
+
+    pub fn mbc_mut(&mut self) -> Option<&mut (dyn Mbc + 'static)> {
+        self.mbc.as_deref_mut()
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    pub fn get_ext_ram(&self) -> &[Byte] {
+        match &self.mbc {
+            Some(mbc) => mbc.get_ext_ram(),
+            None => &self.memory[0xA000..0xC000],
         }
     }
 
-    pub fn load_ext_ram(&self) -> &[Byte]{
-        match &mut self.mbc{
+    pub fn load_ext_ram(&mut self, buffer: Vec<Byte>) {
+        match &mut self.mbc {
             Some(mbc) => mbc.load_ext_ram(buffer),
             None => {
-                let ram_length = 0xC000 - 0xA000;
-                for i in 0..cmp:min(ram_length, buffer.len()){
-                    self.memory[0xA000 + i] + buffer[i];
-                }
+                let ram_length = std::cmp::min(0xC000 - 0xA000, buffer.len());
+                self.memory[0xA000..0xA000 + ram_length].copy_from_slice(&buffer[..ram_length]);
             }
         }
+    }
 
+    // the battery backed part of the external RAM, exactly as big as the header says so the
+    // file can be shared with other emulators and flash carts, followed by the clock if the
+    // cartridge has one
+    pub fn save_data(&self) -> Vec<Byte> {
+        let save_size = self.rom.header().save_size();
+        let ext_ram = self.get_ext_ram();
+        let mut data = ext_ram[..std::cmp::min(save_size, ext_ram.len())].to_vec();
+        data.resize(save_size, 0);
+        if let Some(rtc) = self.mbc.as_ref().and_then(|mbc| mbc.rtc()) {
+            data.extend_from_slice(&rtc.to_footer());
+        }
+        data
     }
 
-    pub fn bus_read(&self, address:u16) -> u8{
-        let address;
-        if address < 0x8000 {
-            self.read_rom(address)
-        }else if address >= 0x8000 && address < 0xC000  {
-            self.read_ram(address)
-        }else {
-            self.memory[address as usize]
+    // the clock footer is optional, a save without it leaves the clock as it is
+    pub fn load_save_data(&mut self, data: &[Byte]) -> Result<()> {
+        let save_size = self.rom.header().save_size();
+        if data.len() != save_size && !(self.has_rtc() && data.len() == save_size + RTC_FOOTER_SIZE)
+        {
+            return Err(Error::SaveSizeMismatch {
+                expected: save_size,
+                actual: data.len(),
+            });
+        }
+        self.load_ext_ram(data[..save_size].to_vec());
+        if let Some(rtc) = self.mbc.as_mut().and_then(|mbc| mbc.rtc_mut()) {
+            rtc.load_footer(&data[save_size..]);
         }
+        self.ext_ram_dirty = false;
+        Ok(())
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.mbc.as_ref().and_then(|mbc| mbc.rtc()).is_some()
+    }
+
+    pub fn is_ext_ram_dirty(&self) -> bool {
+        self.ext_ram_dirty
     }
-    pub fn reset(&mut self){
-        self.memory[0xFF05] = 0x00;
-        self.memory[0xFF06] = 0x00;
-        self.memory[0xFF07] = 0x00;
-        self.memory[0xFF10] = 0x00;
-        self.memory[0xFF11] = 0x00;
-        self.memory[0xFF12] = 0x00;
-        self.memory[0xFF14] = 0x00;
-        self.memory[0xFF16] = 0x00;
-        self.memory[0xFF17] = 0x00;
-        self.memory[0xFF19] = 0x00;
-        self.memory[0xFF1A] = 0x00;
-        self.memory[0xFF1B] = 0x00;
-        self.memory[0xFF1E] = 0x00;
-        self.memory[0xFF20] = 0x00;
-        self.memory[0xFF21] = 0x00;
-        self.memory[0xFF22] = 0x00;
-        self.memory[0xFF23] = 0x00;
-        self.memory[0xFF24] = 0x00;
-        self.memory[0xFF25] = 0x00;
-        self.memory[0xFF26] = 0x00;
-        self.memory[0xFF40] = 0x00;
-        self.memory[0xFF42] = 0x00;
-        self.memory[0xFF43] = 0x00;
-        self.memory[0xFF45] = 0x00;
-        self.memory[0xFF47] = 0x00;
-        self.memory[0xFF48] = 0x00;
-        self.memory[0xFF49] = 0x00;
-        self.memory[0xFF4A] = 0x00;
-        self.memory[0xFF4B] = 0x00;
-        self.memory[0xFFFF] = 0x00;
-    
-        self.memory[joypad_register_address as usize] = 0xFF;
+
+    pub fn clear_ext_ram_dirty(&mut self) {
+        self.ext_ram_dirty = false;
+    }
+
+    pub fn reset(&mut self) {
+        self.timer = Timer::new();
+        self.apu = Apu::new();
+        // the cable stays plugged in
+        let cable = self.serial.set_cable(Box::new(Disconnected));
+        self.serial = Serial::new(self.cgb);
+        self.serial.set_cable(cable);
+        self.ppu = if self.cgb { Ppu::new_cgb() } else { Ppu::new() };
+        self.dma = OamDma::new();
+        self.hdma = Hdma::new();
+        self.stall_cycles = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.inte = 0x00;
+        // the boot ROM leaves a VBlank pending when it hands over
+        self.intf = 0x01;
+
+        self.memory[JOYPAD_REGISTER_ADDR as usize] = 0xFF;
         self.load_rom();
-    
-    }
-    
-    // may seem strange, but some carts do have access to write to ROM (like lithium battery)
-    pub fn bus_write(&self, address:u16 ) -> u8{
-        let address;
-        if address < 0x8000 {
-            self.write_rom(address)
-        }else if address >= 0x8000 && address < 0xC000  {
-            self.read_ram(address)
-        }else {
-            self.memory[address as usize]
+    }
+
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // returns the cable that was plugged in before
+    pub fn set_link_cable(&mut self, cable: Box<dyn LinkCable>) -> Box<dyn LinkCable> {
+        self.serial.set_cable(cable)
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    // the bank mapped at addr right now, 0 for the regions that don't switch
+    pub fn bank_at(&self, addr: Word) -> usize {
+        match addr {
+            0x4000..=0x7FFF => self.mbc.as_ref().map_or(1, |mbc| mbc.current_rom_bank()),
+            0xA000..=0xBFFF => self.mbc.as_ref().map_or(0, |mbc| mbc.current_ram_bank()),
+            0x8000..=0x9FFF => self.ppu.vram_bank(),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => self.wram_bank,
+            _ => 0,
         }
     }
 
-    pub fn write_byte(&mut self, addr: Word, data: Byte){
-        let is_writing_restricted_oam = addr >= 0xFE00 && addr <= 0xFE9F && !self.oam;
-        let is writing_restricted_vram = addr >= 0x8000 && addr <= 0x9FFF && !self.vram;
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
 
-        if !is_writing_restricted_oam && !is_writing_restricted_vram{
-            match addr {
-                0x0000..=0x7FFF => self.handle_bank(addr, data),
-                0x8000..=0x9FFF => self.handle_vram_write(addr, data),
-                0xA000..=0xBFFF => self.write_ram(addr, data),
-                0xD000..=0xDFFF //to be implemented, wram
-                0xE000..=0xFDFF =>{
-                    self.memory[addr - 0x2000 as usize] = data;
-                    self.memory[addr as usize] = data; 
-                }
-                0xFEA0..=0xFEFF => (),
-                JOYPAD_REGISTER_ADDR => self.handle_joypad,
-                DIVIDER_REGISTER_ADDR | CURRENT_SCANLINE_ADDR => self.memory[addr as usize] = 0,
-                0xFF46..= self.dma_transfer(data), //implement dma_transfer
-                0xFF4F..= self.vram_bank_switch(data),//implement vram_bank_switch
-                TIMER_CONTROL_ADDR => self.timer_control(data),
-                VRAM_DMA_TRANSFER_ADDR => {
-                    self.memory[addr as usize] = data;
-                }
-                WRAM_BANK_SELECT_ADDR => self.wram_bank_switch, //implement wram_bank_swtich
-                _ => self.memory[addr as usize] = data;
-            };
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    pub fn set_doctor_mode(&mut self, enabled: bool) {
+        self.doctor_mode = enabled;
+    }
+
+    pub fn doctor_mode(&self) -> bool {
+        self.doctor_mode
+    }
+
+    // STOP with KEY1 bit 0 set changes the speed, the divider is reset and the cpu stays
+    // stopped for a while. returns false when no switch was armed
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb || !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.timer.write_byte(DIVIDER_REGISTER_ADDR, 0);
+        self.stall_cycles += SPEED_SWITCH_CYCLES;
+        true
+    }
+
+    // machine cycles the cpu has to wait before running the next instruction
+    pub fn take_stall_cycles(&mut self) -> u32 {
+        std::mem::replace(&mut self.stall_cycles, 0)
+    }
+
+    // advances everything that is not the cpu by the given amount of clock cycles. in double
+    // speed the timer and the OAM DMA follow the cpu while the ppu and the cartridge keep
+    // their speed, so they only see half of the cycles
+    pub fn do_cycle(&mut self, ticks: u32) -> u32 {
+        // the APU keeps the normal speed but its frame sequencer follows DIV, which runs
+        // twice as fast in double speed so it watches the next bit up
+        let frame_sequencer_bit = FRAME_SEQUENCER_BIT + self.double_speed as u8;
+        let apu_ticks = if self.double_speed { 2 } else { 4 };
+        for _ in 0..ticks / 4 {
+            if self.timer.do_cycle() {
+                self.request_interrupt(Interrupt::Timer);
+            }
+            if self.serial.do_cycle() {
+                self.request_interrupt(Interrupt::Serial);
+            }
+            let div_bit = (self.timer.counter() >> frame_sequencer_bit) & 1 == 1;
+            self.apu.do_cycle(apu_ticks, div_bit);
+            if let Some((source, offset)) = self.dma.do_cycle() {
+                // sources past 0xDFFF read the echo of WRAM
+                let source = match source {
+                    0xE000..=0xFFFF => source - 0x2000,
+                    _ => source,
+                };
+                let data = self.read_bus(source);
+                self.ppu.write_oam(0xFE00 + offset, data);
+            }
+        }
+        let dots = if self.double_speed { ticks / 2 } else { ticks };
+        self.intf |= self.ppu.do_cycle(dots);
+        if self.ppu.take_hblank_started() && self.hdma.mode() == Some(HdmaMode::HBlank) {
+            self.hdma_block();
+        }
+        if let Some(mbc) = &mut self.mbc {
+            mbc.do_cycle(dots);
         }
+        ticks
     }
 
-    pub fn read_byte(&self, addr: Word) -> Byte{
-        //*    FE00 - FE9F	    Sprite attribute table (OAM)
-        let is_reading_restricted_oam = addr >= 0xFE00 && addr <= 0xFE9F && !self.oam;
-        let is_reading_restricted_vram = addr >= 0x8000 && addr <= 0x9FFF && !self.vram;
-
-        if is_reading_restricted_oam || is_reading_restricted_vram{
-            0xFF
-            /*
-            *    4000 - 7FFF	    16 KiB ROM Bank 01~NN	        From cartridge, switchable bank via mapper (if any)
-            *    8000 - 9FFF	    8 KiB Video RAM (VRAM)	        In CGB mode, switchable bank 0/1
-            */
-        } else if addr >= 0x4000 && addr < 0x8000{
-            self.read_rom(addr);
-        } else if addr >= 0xA000 && addr < 0xC000{
-            self.read_ram(addr);
-        } else{
-            self.memory[addr as usize];
+    // copies one block of the VRAM DMA and stops the cpu for the time it takes
+    fn hdma_block(&mut self) -> bool {
+        let Some((source, destination)) = self.hdma.next_block() else {
+            return false;
+        };
+        for i in 0..HDMA_BLOCK_SIZE {
+            let data = self.read_bus(source.wrapping_add(i));
+            self.ppu.write_vram(destination + i, data);
+        }
+        self.stall_cycles += match self.double_speed {
+            true => HDMA_BLOCK_CYCLES * 2,
+            false => HDMA_BLOCK_CYCLES,
+        };
+        true
+    }
+
+    fn write_hdma(&mut self, addr: Word, data: Byte) {
+        self.hdma.write_byte(addr, data);
+        if self.hdma.mode() == Some(HdmaMode::GeneralPurpose) {
+            while self.hdma_block() {}
         }
     }
 
-    fn load_rom(&mut self){
+    fn read_key1(&self) -> Byte {
+        0x7E | ((self.double_speed as Byte) << KEY1_DOUBLE_SPEED)
+            | ((self.speed_switch_armed as Byte) << KEY1_SWITCH_ARMED)
+    }
+
+    // bank 0 is always at C000 - CFFF, SVBK picks the one at D000 - DFFF
+    fn read_wram(&self, addr: Word) -> Byte {
+        match addr {
+            0xC000..=0xCFFF => self.memory[addr as usize],
+            _ => self.wram[(self.wram_bank - 1) * WRAM_BANK_SIZE + (addr - 0xD000) as usize],
+        }
+    }
+
+    fn write_wram(&mut self, addr: Word, data: Byte) {
+        match addr {
+            0xC000..=0xCFFF => self.memory[addr as usize] = data,
+            _ => self.wram[(self.wram_bank - 1) * WRAM_BANK_SIZE + (addr - 0xD000) as usize] = data,
+        }
+    }
+
+    pub fn dma(&self) -> &OamDma {
+        &self.dma
+    }
+
+    // VRAM can't be accessed while the ppu is drawing and OAM neither during the OAM scan,
+    // during OAM DMA the cpu only reaches the I/O registers and HRAM
+    fn is_restricted(&self, addr: Word) -> bool {
+        if self.dma.is_active() && addr < 0xFF00 {
+            return true;
+        }
+        let is_restricted_oam = (0xFE00..=0xFE9F).contains(&addr) && !self.ppu.oam_accessible();
+        let is_restricted_vram = (0x8000..=0x9FFF).contains(&addr) && !self.ppu.vram_accessible();
+        is_restricted_oam || is_restricted_vram
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        // before the bank can change, the write is made to the bank mapped now
+        self.watchpoints
+            .check(addr, Access::Write, data, |addr| self.bank_at(addr));
+        if self.is_restricted(addr) {
+            return;
+        }
+
+        match addr {
+            0x0000..=0x7FFF => self.handle_bank(addr, data),
+            0x8000..=0x9FFF => self.handle_vram_write(addr, data),
+            0xA000..=0xBFFF => self.write_ram(addr, data),
+            0xC000..=0xDFFF => self.write_wram(addr, data),
+            0xE000..=0xFDFF => self.write_wram(addr - 0x2000, data),
+            0xFE00..=0xFE9F => self.ppu.write_oam(addr, data),
+            0xFEA0..=0xFEFF => (),
+            JOYPAD_REGISTER_ADDR => self.handle_joypad(addr, data),
+            SERIAL_DATA_ADDR | SERIAL_CONTROL_ADDR => self.serial.write_byte(addr, data),
+            NR10_ADDR..=WAVE_RAM_END => self.apu.write_byte(addr, data),
+            DIVIDER_REGISTER_ADDR..=TIMER_CONTROL_ADDR => self.timer.write_byte(addr, data),
+            INTERRUPT_FLAG_ADDR => self.intf = data & INTERRUPT_MASK,
+            DMA_TRANSFER_ADDR => self.dma.write_byte(data),
+            LCD_CONTROL_ADDR..=LY_COMPARE_ADDR | BG_PALETTE_ADDR..=WINDOW_X_ADDR => {
+                self.ppu.write_byte(addr, data)
+            }
+            VRAM_BANK_SELECT_ADDR | BG_PALETTE_INDEX_ADDR..=OBJ_PALETTE_DATA_ADDR => {
+                self.ppu.write_byte(addr, data)
+            }
+            SPEED_SWITCH_ADDR if self.cgb => {
+                self.speed_switch_armed = bit_set(&data, KEY1_SWITCH_ARMED)
+            }
+            VRAM_DMA_SOURCE_HIGH_ADDR..=VRAM_DMA_TRANSFER_ADDR if self.cgb => {
+                self.write_hdma(addr, data)
+            }
+            // bank 0 selects bank 1 as well
+            WRAM_BANK_SELECT_ADDR if self.cgb => self.wram_bank = ((data & 0x07) as usize).max(1),
+            SPEED_SWITCH_ADDR
+            | VRAM_DMA_SOURCE_HIGH_ADDR..=VRAM_DMA_TRANSFER_ADDR
+            | WRAM_BANK_SELECT_ADDR => {}
+            INTERRUPT_ENABLE_ADDR => self.inte = data,
+            _ => self.memory[addr as usize] = data,
+        };
+    }
+
+    /*
+     *    0000 - 3FFF	    16 KiB ROM bank 00	            From cartridge, usually a fixed bank
+     *    4000 - 7FFF	    16 KiB ROM Bank 01~NN	        From cartridge, switchable bank via mapper (if any)
+     *    8000 - 9FFF	    8 KiB Video RAM (VRAM)	        In CGB mode, switchable bank 0/1
+     */
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        let data = match self.is_restricted(addr) {
+            true => 0xFF,
+            false => self.read_bus(addr),
+        };
+        self.watchpoints
+            .check(addr, Access::Read, data, |addr| self.bank_at(addr));
+        data
+    }
+
+    // for the debugger and the disassembler: the memory as it is, without triggering the
+    // watchpoints and without the 0xFF the cpu reads during OAM DMA or from VRAM and OAM
+    // while the PPU uses them
+    pub fn peek_byte(&self, addr: Word) -> Byte {
+        self.read_bus(addr)
+    }
+
+    // the read itself, OAM DMA reads through here
+    fn read_bus(&self, addr: Word) -> Byte {
+        match addr {
+            0x0000..=0x3FFF => self.read_rom_bank0(addr),
+            0x4000..=0x7FFF => self.read_rom(addr),
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xA000..=0xBFFF => self.read_ram(addr),
+            0xC000..=0xDFFF => self.read_wram(addr),
+            0xE000..=0xFDFF => self.read_wram(addr - 0x2000),
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
+            JOYPAD_REGISTER_ADDR => self.read_joypad(),
+            SERIAL_DATA_ADDR | SERIAL_CONTROL_ADDR => self.serial.read_byte(addr),
+            NR10_ADDR..=WAVE_RAM_END => self.apu.read_byte(addr),
+            DIVIDER_REGISTER_ADDR..=TIMER_CONTROL_ADDR => self.timer.read_byte(addr),
+            // the three unused bits of IF always read back as 1
+            INTERRUPT_FLAG_ADDR => 0xE0 | self.intf,
+            DMA_TRANSFER_ADDR => self.dma.read_byte(),
+            CURRENT_SCANLINE_ADDR if self.doctor_mode => 0x90,
+            LCD_CONTROL_ADDR..=LY_COMPARE_ADDR | BG_PALETTE_ADDR..=WINDOW_X_ADDR => {
+                self.ppu.read_byte(addr)
+            }
+            VRAM_BANK_SELECT_ADDR | BG_PALETTE_INDEX_ADDR..=OBJ_PALETTE_DATA_ADDR => {
+                self.ppu.read_byte(addr)
+            }
+            SPEED_SWITCH_ADDR if self.cgb => self.read_key1(),
+            VRAM_DMA_SOURCE_HIGH_ADDR..=VRAM_DMA_TRANSFER_ADDR if self.cgb => {
+                self.hdma.read_byte(addr)
+            }
+            WRAM_BANK_SELECT_ADDR if self.cgb => 0xF8 | self.wram_bank as Byte,
+            SPEED_SWITCH_ADDR
+            | VRAM_DMA_SOURCE_HIGH_ADDR..=VRAM_DMA_TRANSFER_ADDR
+            | WRAM_BANK_SELECT_ADDR => 0xFF,
+            INTERRUPT_ENABLE_ADDR => self.inte,
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    pub fn read_word(&self, addr: Word) -> Word {
+        (self.read_byte(addr) as Word) | ((self.read_byte(addr.wrapping_add(1)) as Word) << 8)
+    }
+
+    pub fn write_word(&mut self, addr: Word, data: Word) {
+        self.write_byte(addr, (data & 0xFF) as Byte);
+        self.write_byte(addr.wrapping_add(1), (data >> 8) as Byte);
+    }
+
+    fn load_rom(&mut self) {
         let end_address = 0x8000;
-        for i in 0..cmp::min(end_address, self.rom.lenght()){
+        for i in 0..std::cmp::min(end_address, self.rom.length()) {
             self.memory[i] = self.rom.get_byte(i);
         }
-        self.mbc = get_mbc(&self.rom)
     }
 
-    fn read_rom(&self, addr: Word) -> Byte{
-        match &self.mbc{
-            Some(mbc) => mbc.read_rom(addr - 0xA000);
+    fn read_rom_bank0(&self, addr: Word) -> Byte {
+        match &self.mbc {
+            Some(mbc) => mbc.read_rom_bank0(addr),
+            None => self.memory[addr as usize],
+        }
+    }
+
+    fn read_rom(&self, addr: Word) -> Byte {
+        match &self.mbc {
+            Some(mbc) => mbc.read_rom(addr - 0x4000),
             None => self.rom.get_byte(addr as usize),
         }
     }
 
-    fn read_ram(&self, addr: Word) -> Byte{
-        match &self.mbc{
-            Some(mbc) => mbc.read_ram(addr - AxA000, data),
-            None(mbc) => self.memory[addr as usize],
+    fn read_ram(&self, addr: Word) -> Byte {
+        match &self.mbc {
+            Some(mbc) => mbc.read_ram(addr - 0xA000),
+            None => self.memory[addr as usize],
         }
     }
 
-    fn write_ram(&mut self, addr: Word, data: Byte){
-        match &mut self.mbc{
-            Some(mbc) => mbc.write_ram(addr - AxA000, data),
-            None(mbc) => self.memory[addr as usize] = data,
+    fn write_ram(&mut self, addr: Word, data: Byte) {
+        match &mut self.mbc {
+            Some(mbc) => {
+                if mbc.write_ram(addr - 0xA000, data) {
+                    self.ext_ram_dirty = true;
+                }
+            }
+            None => self.memory[addr as usize] = data,
         }
-    } 
+    }
 
-    fn handle_bank(&mut self, addr: Word, data: Byte){
-        match &mut self.mbc{
-            Some(mbc) => mbc.handle_bank(addr, data);
-            None => {},
+    fn handle_bank(&mut self, addr: Word, data: Byte) {
+        if let Some(mbc) = &mut self.mbc {
+            mbc.handle_bank(addr, data);
         }
     }
 
-    fn handle_vram_write(&mut self, addr: Word, data: Byte){
+    fn handle_vram_write(&mut self, addr: Word, data: Byte) {
+        self.ppu.write_vram(addr, data);
+    }
 
+    // only bits 4 and 5 of P1 are writable, they select which half of the buttons is read back
+    fn handle_joypad(&mut self, addr: Word, data: Byte) {
+        self.memory[addr as usize] = 0xC0 | (data & 0x30) | 0x0F;
     }
 
-    //I don't understand this, why 0x3?
-    fn handle_joypad(&mut self, addr: Word, data: Byte){
-        let mode_bits = (data >> 4) & 0x3;
-        let mode = match mode_bits {
-            1 => Some(Joypad::ACTION), 
-            2 => Some(Joypad::DIRECTION),
-            _ => None
+    // bit 4 low selects the directions, bit 5 low selects the actions, hence the 0x3 mask
+    // over both select bits. with both selected the pressed buttons of both halves show up
+    fn read_joypad(&self) -> Byte {
+        let select = self.memory[JOYPAD_REGISTER_ADDR as usize] & 0x30;
+        let mode_bits = select >> 4;
+        let low_4bits = match mode_bits {
+            0 => {
+                self.joypad.get_buttons_for_mode(JoypadMode::Direction)
+                    & self.joypad.get_buttons_for_mode(JoypadMode::Action)
+            }
+            1 => self.joypad.get_buttons_for_mode(JoypadMode::Action),
+            2 => self.joypad.get_buttons_for_mode(JoypadMode::Direction),
+            _ => 0x0F,
         };
+        0xC0 | select | low_4bits
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.intf |= interrupt.mask();
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.intf &= !interrupt.mask();
+    }
+
+    // interrupts that are both requested (IF) and enabled (IE)
+    pub fn pending_interrupts(&self) -> Byte {
+        self.intf & self.inte & INTERRUPT_MASK
+    }
 
-        if let Some(joypad_mode) = mode{
-            let low_4bits = self.joypad.get_buttons_for_mode(joypad_mode);
-            self.memory[addr as usize] = data (data & 0xF0) | low_4bits;
-        }else{
-            self.memory[addr as usize] = data (data & 0xF0) | 0xF0;
+    // a button going from released to pressed raises the joypad interrupt
+    pub fn set_button_state(&mut self, button: usize) {
+        if self.joypad.get_button_press(button) == 1 {
+            self.request_interrupt(Interrupt::Joypad);
         }
+        self.joypad.set_button_press(button);
     }
 
-    pub fn update_timer_freq(&mut self, val:bool){
-        self.timer_freq_changed = val;
+    pub fn reset_button_state(&mut self, button: usize) {
+        self.joypad.reset_button_state(button);
     }
+}
 
-    pub fn timer_freq_is_changed(&mut self) -> bool{
-        self.timer_freq_changed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dmg_mmu() -> Mmu {
+        Mmu::new(test_rom(0x00, 0x00, 0x00)).unwrap()
     }
 
-    pub fn update_scanline(&mut self){
-        self.memory[CURRENT_SCANLINE_ADDR as usize] = self.memory[CURRENT_SCANLINE_ADDR as usize].wrapping_add(1);
+    fn cgb_mmu() -> Mmu {
+        let mut data = test_rom_data(0x00, 0x00, 0x00);
+        data[0x143] = 0x80;
+        set_header_checksum(&mut data);
+        Mmu::new(Rom::from_bytes(data).unwrap()).unwrap()
     }
 
-    pub fn reset_scanline(&mut self){
-        self.memory[CURRENT_SCANLINE_ADDR as usize] = 0;
+    // a VRAM DMA from 0xC000 to 0x8000, after filling 0xC000 - 0xC0FF
+    fn start_hdma(mmu: &mut Mmu, control: Byte) {
+        for i in 0..0x100 {
+            mmu.write_byte(0xC000 + i, i as Byte ^ 0xA5);
+        }
+        mmu.write_byte(VRAM_DMA_SOURCE_HIGH_ADDR, 0xC0);
+        mmu.write_byte(VRAM_DMA_SOURCE_LOW_ADDR, 0x00);
+        mmu.write_byte(VRAM_DMA_DESTINATION_HIGH_ADDR, 0x80);
+        mmu.write_byte(VRAM_DMA_DESTINATION_LOW_ADDR, 0x00);
+        mmu.write_byte(VRAM_DMA_TRANSFER_ADDR, control);
     }
 
-    pub fn increment_timer_register(&mut self){
-        self.memory[TIMER_ADDR as usize] = self.memory[TIMER_ADDR as usize].wrapping_add(1);
+    // how many bytes from the start of VRAM hold what start_hdma copies
+    fn copied(mmu: &Mmu) -> usize {
+        (0..0x100)
+            .take_while(|&i| mmu.peek_byte(0x8000 + i) == i as Byte ^ 0xA5)
+            .count()
     }
 
-    pub fn increment_division_register(&mut self){
-        self.memory[DIVIDER_REGISTER_ADDR as usize] = self.memory[DIVIDER_REGISTER_ADDR as usize].wrapping_add(1);
+    fn run_to_hblank(mmu: &mut Mmu) {
+        while mmu.ppu().mode() == PpuMode::HBlank {
+            run(mmu, 1);
+        }
+        while mmu.ppu().mode() != PpuMode::HBlank {
+            run(mmu, 1);
+        }
     }
 
-    pub fn set_button_state(&mut self, button:usize){
-        self.joypad.set_button_state(button);
+    // machine cycles of the cpu at normal speed
+    fn run(mmu: &mut Mmu, cycles: u32) {
+        for _ in 0..cycles {
+            mmu.do_cycle(4);
+        }
     }
 
-    pub fn reset_button_state(&mut self, button:usize){
-        self.joypad.reset_button_state(button);
+    fn oam(mmu: &Mmu) -> Vec<Byte> {
+        (0xFE00..0xFEA0).map(|addr| mmu.peek_byte(addr)).collect()
     }
 
-    fn timer_control(&mut self, data:Byte){
-        self.update_timer_freq(true);
-        self.memory[TIMER_CONTROL_ADDR as usize] = data;
+    #[test]
+    fn oam_dma_blocks_the_cpu_outside_hram() {
+        let mut mmu = dmg_mmu();
+        for i in 0..0xA0 {
+            mmu.write_byte(0xC000 + i, i as Byte ^ 0x5A);
+        }
+        mmu.write_byte(0xFF80, 0x42);
+        mmu.write_byte(DMA_TRANSFER_ADDR, 0xC0);
+        // the setup cycle leaves the buses to the cpu
+        run(&mut mmu, 1);
+        assert_eq!(mmu.read_byte(0xC000), 0x5A);
+
+        run(&mut mmu, 1);
+        assert!(mmu.dma().is_active());
+        assert_eq!(mmu.read_byte(0xC000), 0xFF);
+        assert_eq!(mmu.read_byte(0x0000), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF80), 0x42);
+        assert_eq!(mmu.read_byte(DMA_TRANSFER_ADDR), 0xC0);
+        mmu.write_byte(0xC100, 0x17);
+        mmu.write_byte(0xFF81, 0x17);
+        assert_eq!(mmu.read_byte(0xFF81), 0x17);
+
+        run(&mut mmu, 158);
+        assert!(mmu.dma().is_active());
+        run(&mut mmu, 1);
+        assert!(!mmu.dma().is_active());
+        assert_eq!(mmu.read_byte(0xC000), 0x5A);
+        assert_eq!(mmu.read_byte(0xC100), 0x00);
+        let expected: Vec<Byte> = (0..0xA0).map(|i| i as Byte ^ 0x5A).collect();
+        assert_eq!(oam(&mmu), expected);
     }
 
-}
+    #[test]
+    fn oam_dma_from_echo_ram_reads_wram() {
+        let mut mmu = dmg_mmu();
+        for i in 0..0xA0 {
+            mmu.write_byte(0xDE00 + i, i as Byte);
+        }
+        // 0xFE00 - 0xFE9F is OAM itself on the cpu side, the DMA sees 0xDE00 there too
+        mmu.write_byte(DMA_TRANSFER_ADDR, 0xFE);
+        run(&mut mmu, 161);
+        let expected: Vec<Byte> = (0..0xA0).map(|i| i as Byte).collect();
+        assert_eq!(oam(&mmu), expected);
+    }
+
+    #[test]
+    fn cgb_wram_banks() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read_byte(WRAM_BANK_SELECT_ADDR), 0xF9);
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(WRAM_BANK_SELECT_ADDR, 0x07);
+        assert_eq!(mmu.read_byte(WRAM_BANK_SELECT_ADDR), 0xFF);
+        assert_eq!(mmu.read_byte(0xD000), 0x00);
+        mmu.write_byte(0xD000, 0x77);
+        assert_eq!(mmu.read_byte(0xF000), 0x77);
+        assert_eq!(mmu.bank_at(0xD000), 7);
+        // bank 0 maps bank 1, C000 - CFFF never changes
+        mmu.write_byte(0xC000, 0x42);
+        mmu.write_byte(WRAM_BANK_SELECT_ADDR, 0x00);
+        assert_eq!(mmu.read_byte(WRAM_BANK_SELECT_ADDR), 0xF9);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+        assert_eq!(mmu.read_byte(0xC000), 0x42);
+
+        // a DMG has bank 1 only and no register
+        let mut mmu = dmg_mmu();
+        mmu.write_byte(0xD000, 0x11);
+        mmu.write_byte(WRAM_BANK_SELECT_ADDR, 0x02);
+        assert_eq!(mmu.read_byte(WRAM_BANK_SELECT_ADDR), 0xFF);
+        assert_eq!(mmu.read_byte(0xD000), 0x11);
+    }
 
+    #[test]
+    fn cgb_vram_banks() {
+        let mut mmu = cgb_mmu();
+        mmu.write_byte(LCD_CONTROL_ADDR, 0x00);
+        mmu.write_byte(0x9800, 0x01);
+        mmu.write_byte(VRAM_BANK_SELECT_ADDR, 0x01);
+        assert_eq!(mmu.bank_at(0x9800), 1);
+        assert_eq!(mmu.read_byte(0x9800), 0x00);
+        mmu.write_byte(0x9800, 0x08);
+        mmu.write_byte(VRAM_BANK_SELECT_ADDR, 0x00);
+        assert_eq!(mmu.read_byte(0x9800), 0x01);
+    }
 
+    #[test]
+    fn speed_switch() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read_byte(SPEED_SWITCH_ADDR), 0x7E);
+        assert!(!mmu.switch_speed());
+
+        mmu.write_byte(SPEED_SWITCH_ADDR, 0x01);
+        assert_eq!(mmu.read_byte(SPEED_SWITCH_ADDR), 0x7F);
+        run(&mut mmu, 1000);
+        assert_ne!(mmu.read_byte(DIVIDER_REGISTER_ADDR), 0x00);
+        assert!(mmu.switch_speed());
+        assert!(mmu.is_double_speed());
+        assert_eq!(mmu.read_byte(SPEED_SWITCH_ADDR), 0xFE);
+        assert_eq!(mmu.read_byte(DIVIDER_REGISTER_ADDR), 0x00);
+        assert_eq!(mmu.take_stall_cycles(), SPEED_SWITCH_CYCLES);
+        assert_eq!(mmu.take_stall_cycles(), 0);
+        // every switch has to be armed again
+        assert!(!mmu.switch_speed());
+
+        mmu.write_byte(SPEED_SWITCH_ADDR, 0x01);
+        assert!(mmu.switch_speed());
+        assert_eq!(mmu.read_byte(SPEED_SWITCH_ADDR), 0x7E);
+
+        let mut mmu = dmg_mmu();
+        mmu.write_byte(SPEED_SWITCH_ADDR, 0x01);
+        assert_eq!(mmu.read_byte(SPEED_SWITCH_ADDR), 0xFF);
+        assert!(!mmu.switch_speed());
+    }
+
+    #[test]
+    fn general_purpose_hdma() {
+        let mut mmu = cgb_mmu();
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0xFF);
+        // 3 blocks, copied at once with the cpu stopped for 8 machine cycles each
+        start_hdma(&mut mmu, 0x02);
+        assert_eq!(copied(&mmu), 0x30);
+        assert_eq!(mmu.take_stall_cycles(), 3 * HDMA_BLOCK_CYCLES);
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0xFF);
+
+        // the DMA keeps its speed, the cpu waits twice as many cycles at double speed
+        let mut mmu = cgb_mmu();
+        mmu.write_byte(SPEED_SWITCH_ADDR, 0x01);
+        mmu.switch_speed();
+        mmu.take_stall_cycles();
+        start_hdma(&mut mmu, 0x00);
+        assert_eq!(copied(&mmu), 0x10);
+        assert_eq!(mmu.take_stall_cycles(), 2 * HDMA_BLOCK_CYCLES);
+
+        // a DMG has no VRAM DMA
+        let mut mmu = dmg_mmu();
+        mmu.write_byte(LCD_CONTROL_ADDR, 0x00);
+        start_hdma(&mut mmu, 0x00);
+        assert_eq!(copied(&mmu), 0);
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0xFF);
+    }
+
+    #[test]
+    fn hblank_hdma() {
+        let mut mmu = cgb_mmu();
+        start_hdma(&mut mmu, 0x81);
+        // bit 7 reads 0 while the transfer runs
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0x01);
+        assert_eq!(copied(&mmu), 0);
+        assert_eq!(mmu.take_stall_cycles(), 0);
+
+        run_to_hblank(&mut mmu);
+        assert_eq!(copied(&mmu), 0x10);
+        assert_eq!(mmu.take_stall_cycles(), HDMA_BLOCK_CYCLES);
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0x00);
+        // nothing more until the next HBlank
+        run(&mut mmu, 20);
+        assert_eq!(copied(&mmu), 0x10);
+
+        run_to_hblank(&mut mmu);
+        assert_eq!(copied(&mmu), 0x20);
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0xFF);
+        run_to_hblank(&mut mmu);
+        assert_eq!(copied(&mmu), 0x20);
+    }
+
+    #[test]
+    fn hblank_hdma_cancel() {
+        let mut mmu = cgb_mmu();
+        start_hdma(&mut mmu, 0x83);
+        run_to_hblank(&mut mmu);
+        assert_eq!(copied(&mmu), 0x10);
+        // bit 7 = 0 stops it instead of starting a general purpose DMA
+        mmu.write_byte(VRAM_DMA_TRANSFER_ADDR, 0x00);
+        assert_eq!(mmu.read_byte(VRAM_DMA_TRANSFER_ADDR), 0x82);
+        assert_eq!(copied(&mmu), 0x10);
+        run_to_hblank(&mut mmu);
+        assert_eq!(copied(&mmu), 0x10);
+        assert_eq!(mmu.take_stall_cycles(), HDMA_BLOCK_CYCLES);
+    }
+}
@@ -0,0 +1,860 @@
+use crate::interrupt::Interrupt;
+use crate::utils::*;
+
+// the picture processing unit, it owns the video RAM, the sprite attribute table (OAM) and
+// the LCD registers (0xFF40 - 0xFF4B), see https://gbdev.io/pandocs/Rendering.html
+//
+// in CGB mode it also owns the second VRAM bank (VBK, 0xFF4F) and the color palette RAM
+// (0xFF68 - 0xFF6B), see https://gbdev.io/pandocs/Palettes.html
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const VRAM_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xA0;
+// 8 palettes of 4 colors, 2 bytes per color
+const PALETTE_RAM_SIZE: usize = 0x40;
+
+/*
+ * Every scanline takes 456 dots (clock cycles) and goes through the modes below, after the 144
+ * visible lines there are 10 lines of VBlank. The length of mode 3 actually depends on the
+ * scroll, the window and the sprites on the line, here it is always the shortest one.
+ *
+ *    mode 2    OAM scan     80 dots     OAM is not accessible
+ *    mode 3    Drawing      172 dots    OAM and VRAM are not accessible
+ *    mode 0    HBlank       204 dots
+ *    mode 1    VBlank       4560 dots   10 full lines
+ */
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const VBLANK_LINE: Byte = 144;
+const LINES_PER_FRAME: Byte = 154;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+const SPRITE_COUNT: usize = 40;
+
+// LCDC (0xFF40) bits
+const LCDC_BG_ENABLE: u8 = 0;
+const LCDC_OBJ_ENABLE: u8 = 1;
+const LCDC_OBJ_SIZE: u8 = 2;
+const LCDC_BG_TILE_MAP: u8 = 3;
+const LCDC_TILE_DATA: u8 = 4;
+const LCDC_WINDOW_ENABLE: u8 = 5;
+const LCDC_WINDOW_TILE_MAP: u8 = 6;
+const LCDC_LCD_ENABLE: u8 = 7;
+
+// STAT (0xFF41) bits, 3 to 6 select which conditions raise the LCD STAT interrupt
+const STAT_COINCIDENCE: u8 = 2;
+const STAT_HBLANK_INTERRUPT: u8 = 3;
+const STAT_VBLANK_INTERRUPT: u8 = 4;
+const STAT_OAM_INTERRUPT: u8 = 5;
+const STAT_LYC_INTERRUPT: u8 = 6;
+
+// sprite attribute flags (byte 3 of each OAM entry)
+const SPRITE_BG_PRIORITY: u8 = 7;
+const SPRITE_Y_FLIP: u8 = 6;
+const SPRITE_X_FLIP: u8 = 5;
+const SPRITE_PALETTE: u8 = 4;
+const SPRITE_CGB_BANK: u8 = 3;
+
+/*
+ * In CGB mode every entry of the tile maps has an attribute byte at the same address in
+ * VRAM bank 1:
+ *
+ *    bit 0 - 2    background palette
+ *    bit 3        tile data bank
+ *    bit 5        horizontal flip
+ *    bit 6        vertical flip
+ *    bit 7        background over sprites
+ */
+const BG_ATTR_BANK: u8 = 3;
+const BG_ATTR_X_FLIP: u8 = 5;
+const BG_ATTR_Y_FLIP: u8 = 6;
+const BG_ATTR_PRIORITY: u8 = 7;
+
+// bit 7 of BCPS/OCPS moves the index to the next byte after every write to the data register
+const PALETTE_AUTO_INCREMENT: u8 = 7;
+
+// RGB555 colors used for the four DMG shades, from white to black
+const DMG_COLORS: [Word; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+pub struct Ppu {
+    cgb: bool,
+    // bank 1 follows bank 0, it only exists in CGB mode
+    vram: Box<[Byte; VRAM_SIZE * 2]>,
+    vram_bank: usize,
+    oam: [Byte; OAM_SIZE],
+    lcdc: Byte,
+    stat: Byte,
+    scy: Byte,
+    scx: Byte,
+    ly: Byte,
+    lyc: Byte,
+    bgp: Byte,
+    obp0: Byte,
+    obp1: Byte,
+    wy: Byte,
+    wx: Byte,
+    bcps: Byte,
+    ocps: Byte,
+    bg_palettes: [Byte; PALETTE_RAM_SIZE],
+    obj_palettes: [Byte; PALETTE_RAM_SIZE],
+    mode: PpuMode,
+    dot: u32,
+    // the window keeps its own line counter, it only moves on lines where the window was drawn
+    window_line: Byte,
+    // the STAT interrupt is raised on the rising edge of all its sources ORed together
+    stat_line: bool,
+    // the lines are drawn into the back buffers, which are swapped with the front ones at
+    // VBlank so that framebuffer never shows half a frame
+    framebuffer: Box<[Byte; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    rgb_framebuffer: Box<[Word; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    back_buffer: Box<[Byte; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    rgb_back_buffer: Box<[Word; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    frame_ready: bool,
+    // set when a visible line enters HBlank, HBlank DMA copies a block every time
+    hblank_started: bool,
+}
+
+impl Ppu {
+    // register values left behind by the DMG boot ROM
+    pub fn new() -> Ppu {
+        Ppu {
+            cgb: false,
+            vram: boxed_array(0),
+            vram_bank: 0,
+            oam: [0; OAM_SIZE],
+            lcdc: 0x91,
+            stat: 0x00,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0x00,
+            wx: 0x00,
+            bcps: 0x00,
+            ocps: 0x00,
+            bg_palettes: [0xFF; PALETTE_RAM_SIZE],
+            obj_palettes: [0xFF; PALETTE_RAM_SIZE],
+            mode: PpuMode::OamScan,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            framebuffer: boxed_array(0),
+            rgb_framebuffer: boxed_array(DMG_COLORS[0]),
+            back_buffer: boxed_array(0),
+            rgb_back_buffer: boxed_array(DMG_COLORS[0]),
+            frame_ready: false,
+            hblank_started: false,
+        }
+    }
+
+    // the CGB boot ROM leaves the palettes white and the registers as on the DMG
+    pub fn new_cgb() -> Ppu {
+        Ppu {
+            cgb: true,
+            ..Ppu::new()
+        }
+    }
+
+    // the last finished frame, one shade per pixel from 0 (white) to 3 (black). in CGB mode
+    // the colors come from the palette RAM, this only holds the color numbers
+    pub fn framebuffer(&self) -> &[Byte; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.framebuffer
+    }
+
+    // the last finished frame as RGB555 colors, red in bits 0 - 4, green in bits 5 - 9 and
+    // blue in bits 10 - 14, the format of the CGB palette RAM
+    pub fn rgb_framebuffer(&self) -> &[Word; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.rgb_framebuffer
+    }
+
+    pub fn take_hblank_started(&mut self) -> bool {
+        std::mem::replace(&mut self.hblank_started, false)
+    }
+
+    // true once per frame, when the ppu enters VBlank
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        bit_set(&self.lcdc, LCDC_LCD_ENABLE)
+    }
+
+    pub fn vram_accessible(&self) -> bool {
+        self.mode != PpuMode::Drawing
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        self.mode != PpuMode::Drawing && self.mode != PpuMode::OamScan
+    }
+
+    // advances the ppu by the given amount of dots, returns the interrupts to request
+    pub fn do_cycle(&mut self, ticks: u32) -> Byte {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+
+        let mut interrupts = 0;
+        for _ in 0..ticks {
+            self.dot += 1;
+            if self.dot == DOTS_PER_LINE {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES_PER_FRAME;
+                if self.ly == VBLANK_LINE {
+                    self.mode = PpuMode::VBlank;
+                    std::mem::swap(&mut self.framebuffer, &mut self.back_buffer);
+                    std::mem::swap(&mut self.rgb_framebuffer, &mut self.rgb_back_buffer);
+                    self.frame_ready = true;
+                    interrupts |= Interrupt::VBlank.mask();
+                } else if self.ly < VBLANK_LINE {
+                    if self.ly == 0 {
+                        self.window_line = 0;
+                    }
+                    self.mode = PpuMode::OamScan;
+                }
+            } else if self.ly < VBLANK_LINE {
+                if self.dot == OAM_SCAN_DOTS {
+                    self.mode = PpuMode::Drawing;
+                    self.render_scanline();
+                } else if self.dot == OAM_SCAN_DOTS + DRAWING_DOTS {
+                    self.mode = PpuMode::HBlank;
+                    self.hblank_started = true;
+                }
+            }
+
+            if self.update_stat_line() {
+                interrupts |= Interrupt::LcdStat.mask();
+            }
+        }
+        interrupts
+    }
+
+    pub fn vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    pub fn read_vram(&self, addr: Word) -> Byte {
+        self.vram[self.vram_bank * VRAM_SIZE + (addr - 0x8000) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: Word, data: Byte) {
+        self.vram[self.vram_bank * VRAM_SIZE + (addr - 0x8000) as usize] = data;
+    }
+
+    pub fn read_oam(&self, addr: Word) -> Byte {
+        self.oam[(addr - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: Word, data: Byte) {
+        self.oam[(addr - 0xFE00) as usize] = data;
+    }
+
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        match addr {
+            LCD_CONTROL_ADDR => self.lcdc,
+            LCD_STATUS_ADDR => {
+                let coincidence = (self.ly == self.lyc) as Byte;
+                let mode = if self.lcd_enabled() {
+                    self.mode as Byte
+                } else {
+                    0
+                };
+                0x80 | self.stat | (coincidence << STAT_COINCIDENCE) | mode
+            }
+            SCROLL_Y_ADDR => self.scy,
+            SCROLL_X_ADDR => self.scx,
+            CURRENT_SCANLINE_ADDR => self.ly,
+            LY_COMPARE_ADDR => self.lyc,
+            BG_PALETTE_ADDR => self.bgp,
+            OBJ_PALETTE_0_ADDR => self.obp0,
+            OBJ_PALETTE_1_ADDR => self.obp1,
+            WINDOW_Y_ADDR => self.wy,
+            WINDOW_X_ADDR => self.wx,
+            VRAM_BANK_SELECT_ADDR if self.cgb => 0xFE | self.vram_bank as Byte,
+            BG_PALETTE_INDEX_ADDR if self.cgb => 0x40 | self.bcps,
+            OBJ_PALETTE_INDEX_ADDR if self.cgb => 0x40 | self.ocps,
+            // the palette RAM is not accessible while the ppu reads it
+            BG_PALETTE_DATA_ADDR | OBJ_PALETTE_DATA_ADDR
+                if self.cgb && self.mode == PpuMode::Drawing =>
+            {
+                0xFF
+            }
+            BG_PALETTE_DATA_ADDR if self.cgb => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            OBJ_PALETTE_DATA_ADDR if self.cgb => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        match addr {
+            LCD_CONTROL_ADDR => self.write_lcdc(data),
+            LCD_STATUS_ADDR => self.stat = data & 0x78,
+            SCROLL_Y_ADDR => self.scy = data,
+            SCROLL_X_ADDR => self.scx = data,
+            // LY is read only
+            CURRENT_SCANLINE_ADDR => {}
+            LY_COMPARE_ADDR => self.lyc = data,
+            BG_PALETTE_ADDR => self.bgp = data,
+            OBJ_PALETTE_0_ADDR => self.obp0 = data,
+            OBJ_PALETTE_1_ADDR => self.obp1 = data,
+            WINDOW_Y_ADDR => self.wy = data,
+            WINDOW_X_ADDR => self.wx = data,
+            VRAM_BANK_SELECT_ADDR if self.cgb => self.vram_bank = (data & 0x01) as usize,
+            BG_PALETTE_INDEX_ADDR if self.cgb => self.bcps = data & 0xBF,
+            OBJ_PALETTE_INDEX_ADDR if self.cgb => self.ocps = data & 0xBF,
+            BG_PALETTE_DATA_ADDR if self.cgb => {
+                let drawing = self.mode == PpuMode::Drawing;
+                write_palette(&mut self.bg_palettes, &mut self.bcps, data, drawing)
+            }
+            OBJ_PALETTE_DATA_ADDR if self.cgb => {
+                let drawing = self.mode == PpuMode::Drawing;
+                write_palette(&mut self.obj_palettes, &mut self.ocps, data, drawing)
+            }
+            _ => {}
+        }
+    }
+
+    // turning the LCD off resets LY and leaves the ppu idle in mode 0, turning it back on
+    // starts a new frame from line 0
+    fn write_lcdc(&mut self, data: Byte) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = data;
+        if was_enabled && !self.lcd_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.window_line = 0;
+            self.mode = PpuMode::HBlank;
+            self.stat_line = false;
+            self.framebuffer.fill(0);
+            self.rgb_framebuffer.fill(DMG_COLORS[0]);
+            self.back_buffer.fill(0);
+            self.rgb_back_buffer.fill(DMG_COLORS[0]);
+        } else if !was_enabled && self.lcd_enabled() {
+            self.mode = PpuMode::OamScan;
+        }
+    }
+
+    fn update_stat_line(&mut self) -> bool {
+        let line = (bit_set(&self.stat, STAT_LYC_INTERRUPT) && self.ly == self.lyc)
+            || (bit_set(&self.stat, STAT_HBLANK_INTERRUPT) && self.mode == PpuMode::HBlank)
+            || (bit_set(&self.stat, STAT_VBLANK_INTERRUPT) && self.mode == PpuMode::VBlank)
+            || (bit_set(&self.stat, STAT_OAM_INTERRUPT) && self.mode == PpuMode::OamScan);
+        let rising_edge = line && !self.stat_line;
+        self.stat_line = line;
+        rising_edge
+    }
+
+    fn render_scanline(&mut self) {
+        // the color numbers (before the palette) of the background are needed to know
+        // whether a sprite with the BG priority flag shows up. in CGB mode LCDC bit 0 does
+        // not hide the background, it only takes away its priority over the sprites
+        let mut bg_colors = [0; SCREEN_WIDTH];
+        let mut bg_attributes = [0; SCREEN_WIDTH];
+        if self.cgb || bit_set(&self.lcdc, LCDC_BG_ENABLE) {
+            self.render_background(&mut bg_colors, &mut bg_attributes);
+        }
+
+        let line = self.ly as usize * SCREEN_WIDTH;
+        for (x, (color, attributes)) in bg_colors.iter().zip(bg_attributes.iter()).enumerate() {
+            if self.cgb {
+                self.back_buffer[line + x] = *color;
+                self.rgb_back_buffer[line + x] = cgb_color(&self.bg_palettes, *attributes, *color);
+            } else {
+                let shade = apply_palette(self.bgp, *color);
+                self.back_buffer[line + x] = shade;
+                self.rgb_back_buffer[line + x] = DMG_COLORS[shade as usize];
+            }
+        }
+
+        if bit_set(&self.lcdc, LCDC_OBJ_ENABLE) {
+            self.render_sprites(&bg_colors, &bg_attributes);
+        }
+    }
+
+    // the background and the window share the tile data, each one has its own tile map
+    fn render_background(
+        &mut self,
+        bg_colors: &mut [Byte; SCREEN_WIDTH],
+        bg_attributes: &mut [Byte; SCREEN_WIDTH],
+    ) {
+        let bg_map = if bit_set(&self.lcdc, LCDC_BG_TILE_MAP) {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let window_map = if bit_set(&self.lcdc, LCDC_WINDOW_TILE_MAP) {
+            0x1C00
+        } else {
+            0x1800
+        };
+        // WX holds the window position plus 7
+        let window_x = self.wx as i32 - 7;
+        let window_visible = bit_set(&self.lcdc, LCDC_WINDOW_ENABLE)
+            && self.ly >= self.wy
+            && window_x < SCREEN_WIDTH as i32;
+
+        for x in 0..SCREEN_WIDTH {
+            let (map, px, py) = if window_visible && x as i32 >= window_x {
+                (window_map, (x as i32 - window_x) as Byte, self.window_line)
+            } else {
+                (
+                    bg_map,
+                    self.scx.wrapping_add(x as Byte),
+                    self.scy.wrapping_add(self.ly),
+                )
+            };
+
+            let map_addr = map + (py as usize / 8) * 32 + (px as usize / 8);
+            let tile_index = self.vram[map_addr];
+            let attributes = if self.cgb {
+                self.vram[VRAM_SIZE + map_addr]
+            } else {
+                0
+            };
+
+            let mut tile_addr = self.tile_data_addr(tile_index);
+            if bit_set(&attributes, BG_ATTR_BANK) {
+                tile_addr += VRAM_SIZE;
+            }
+            let tx = if bit_set(&attributes, BG_ATTR_X_FLIP) {
+                7 - px % 8
+            } else {
+                px % 8
+            };
+            let ty = if bit_set(&attributes, BG_ATTR_Y_FLIP) {
+                7 - py % 8
+            } else {
+                py % 8
+            };
+            bg_colors[x] = self.tile_pixel(tile_addr, tx, ty);
+            bg_attributes[x] = attributes;
+        }
+
+        if window_visible {
+            self.window_line += 1;
+        }
+    }
+
+    // LCDC bit 4 picks between 0x8000 with unsigned indices and 0x9000 with signed ones
+    fn tile_data_addr(&self, tile_index: Byte) -> usize {
+        if bit_set(&self.lcdc, LCDC_TILE_DATA) {
+            tile_index as usize * 16
+        } else {
+            (0x1000 + (tile_index as i8 as i32) * 16) as usize
+        }
+    }
+
+    // a tile row is two bytes, the first one holds the low bit of each pixel and the second
+    // one the high bit, the leftmost pixel is bit 7
+    fn tile_pixel(&self, tile_addr: usize, x: Byte, y: Byte) -> Byte {
+        let lo = self.vram[tile_addr + (y as usize) * 2];
+        let hi = self.vram[tile_addr + (y as usize) * 2 + 1];
+        let bit = 7 - x;
+        (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+    }
+
+    fn render_sprites(
+        &mut self,
+        bg_colors: &[Byte; SCREEN_WIDTH],
+        bg_attributes: &[Byte; SCREEN_WIDTH],
+    ) {
+        let height: i32 = if bit_set(&self.lcdc, LCDC_OBJ_SIZE) {
+            16
+        } else {
+            8
+        };
+        let ly = self.ly as i32;
+
+        // OAM scan: the first 10 sprites (in OAM order) that overlap this line
+        let mut sprites = [0usize; MAX_SPRITES_PER_LINE];
+        let mut count = 0;
+        for index in 0..SPRITE_COUNT {
+            let y = self.oam[index * 4] as i32 - 16;
+            if ly >= y && ly < y + height {
+                sprites[count] = index;
+                count += 1;
+                if count == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // on the DMG the sprite with the smallest X wins, ties go to the first one in OAM.
+        // the sort is stable so the OAM order is kept between equal X coordinates. the CGB
+        // only looks at the OAM order
+        let sprites = &mut sprites[..count];
+        if !self.cgb {
+            sprites.sort_by_key(|&index| self.oam[index * 4 + 1]);
+        }
+
+        let line = self.ly as usize * SCREEN_WIDTH;
+        for x in 0..SCREEN_WIDTH {
+            for &index in sprites.iter() {
+                let sprite_x = self.oam[index * 4 + 1] as i32 - 8;
+                let px = x as i32 - sprite_x;
+                if !(0..8).contains(&px) {
+                    continue;
+                }
+
+                let attributes = self.oam[index * 4 + 3];
+                let mut tile = self.oam[index * 4 + 2];
+                let mut py = ly - (self.oam[index * 4] as i32 - 16);
+                if bit_set(&attributes, SPRITE_Y_FLIP) {
+                    py = height - 1 - py;
+                }
+                // 8x16 sprites ignore bit 0 of the tile index
+                if height == 16 {
+                    tile &= 0xFE;
+                }
+                let px = if bit_set(&attributes, SPRITE_X_FLIP) {
+                    7 - px
+                } else {
+                    px
+                };
+
+                let mut tile_addr = tile as usize * 16;
+                if self.cgb && bit_set(&attributes, SPRITE_CGB_BANK) {
+                    tile_addr += VRAM_SIZE;
+                }
+                let color = self.tile_pixel(tile_addr, px as Byte, py as Byte);
+                // color 0 is transparent, the next sprite in line may show up instead
+                if color == 0 {
+                    continue;
+                }
+
+                let hidden = if self.cgb {
+                    // with LCDC bit 0 cleared the sprites are always on top
+                    bit_set(&self.lcdc, LCDC_BG_ENABLE)
+                        && bg_colors[x] != 0
+                        && (bit_set(&attributes, SPRITE_BG_PRIORITY)
+                            || bit_set(&bg_attributes[x], BG_ATTR_PRIORITY))
+                } else {
+                    bit_set(&attributes, SPRITE_BG_PRIORITY) && bg_colors[x] != 0
+                };
+                if !hidden {
+                    if self.cgb {
+                        self.back_buffer[line + x] = color;
+                        self.rgb_back_buffer[line + x] =
+                            cgb_color(&self.obj_palettes, attributes, color);
+                    } else {
+                        let palette = if bit_set(&attributes, SPRITE_PALETTE) {
+                            self.obp1
+                        } else {
+                            self.obp0
+                        };
+                        let shade = apply_palette(palette, color);
+                        self.back_buffer[line + x] = shade;
+                        self.rgb_back_buffer[line + x] = DMG_COLORS[shade as usize];
+                    }
+                }
+                break;
+            }
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
+// each palette packs four 2 bit shades, color number n uses bits 2n and 2n+1
+fn apply_palette(palette: Byte, color: Byte) -> Byte {
+    (palette >> (color * 2)) & 0x3
+}
+
+// the write is lost while the ppu reads the palettes but the index still moves on
+fn write_palette(
+    palettes: &mut [Byte; PALETTE_RAM_SIZE],
+    index: &mut Byte,
+    data: Byte,
+    drawing: bool,
+) {
+    if !drawing {
+        palettes[(*index & 0x3F) as usize] = data;
+    }
+    if bit_set(index, PALETTE_AUTO_INCREMENT) {
+        *index = (1 << PALETTE_AUTO_INCREMENT) | ((*index + 1) & 0x3F);
+    }
+}
+
+// every color is a little endian RGB555 value
+fn cgb_color(palettes: &[Byte; PALETTE_RAM_SIZE], palette: Byte, color: Byte) -> Word {
+    let index = (palette as usize & 0x07) * 8 + color as usize * 2;
+    (((palettes[index + 1] as Word) << 8) | palettes[index] as Word) & 0x7FFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tile 1 is filled with color 1, tile 2 with color 3, tile 0 is left blank
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        for row in 0..8 {
+            ppu.write_vram(0x8010 + row * 2, 0xFF);
+            ppu.write_vram(0x8020 + row * 2, 0xFF);
+            ppu.write_vram(0x8021 + row * 2, 0xFF);
+        }
+        ppu.write_byte(BG_PALETTE_ADDR, 0xE4);
+        ppu
+    }
+
+    // runs dot by dot until the next VBlank, returns the interrupts requested on the way
+    fn frame(ppu: &mut Ppu) -> Vec<Byte> {
+        let mut interrupts = Vec::new();
+        while !ppu.take_frame_ready() {
+            let requested = ppu.do_cycle(1);
+            if requested != 0 {
+                interrupts.push(requested);
+            }
+        }
+        interrupts
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> Byte {
+        ppu.framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn background_and_window() {
+        let mut ppu = ppu();
+        ppu.write_vram(0x9800, 0x01);
+        // the window uses the map at 0x9C00 and covers the bottom right quarter
+        for addr in 0x9C00..0xA000 {
+            ppu.write_vram(addr, 0x02);
+        }
+        ppu.write_byte(LCD_CONTROL_ADDR, 0xF1);
+        ppu.write_byte(WINDOW_X_ADDR, 80 + 7);
+        ppu.write_byte(WINDOW_Y_ADDR, 72);
+        frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 7, 7), 1);
+        assert_eq!(pixel(&ppu, 8, 0), 0);
+        assert_eq!(pixel(&ppu, 80, 72), 3);
+        assert_eq!(pixel(&ppu, 159, 143), 3);
+        assert_eq!(pixel(&ppu, 79, 72), 0);
+        assert_eq!(pixel(&ppu, 80, 71), 0);
+        assert_eq!(ppu.rgb_framebuffer()[0], DMG_COLORS[1]);
+
+        // scrolling moves the background but not the window
+        ppu.write_byte(SCROLL_X_ADDR, 4);
+        frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 3, 0), 1);
+        assert_eq!(pixel(&ppu, 4, 0), 0);
+        assert_eq!(pixel(&ppu, 80, 72), 3);
+    }
+
+    #[test]
+    fn framebuffer_only_changes_at_vblank() {
+        let mut ppu = ppu();
+        ppu.write_vram(0x9800, 0x01);
+        frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+
+        // the next frame is half drawn with a white palette
+        ppu.write_byte(BG_PALETTE_ADDR, 0x00);
+        while ppu.read_byte(CURRENT_SCANLINE_ADDR) != 72 {
+            ppu.do_cycle(1);
+        }
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), 0);
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let mut ppu = ppu();
+        ppu.write_vram(0x9800, 0x01);
+        ppu.write_byte(LCD_CONTROL_ADDR, 0x93);
+        ppu.write_byte(OBJ_PALETTE_0_ADDR, 0xE4);
+        ppu.write_byte(OBJ_PALETTE_1_ADDR, 0x54);
+        let sprites: [[Byte; 4]; 5] = [
+            // behind the background, which only shows where the background is not color 0
+            [16, 8, 0x02, 0x80],
+            [16, 8 + 16, 0x02, 0x80],
+            // in front of it
+            [16 + 8, 8, 0x02, 0x00],
+            // overlapping sprites, the smaller X wins over the OAM order
+            [16, 8 + 40, 0x02, 0x00],
+            [16, 8 + 36, 0x02, 0x10],
+        ];
+        for (index, sprite) in sprites.iter().enumerate() {
+            for (i, &byte) in sprite.iter().enumerate() {
+                ppu.write_oam(0xFE00 + (index * 4 + i) as Word, byte);
+            }
+        }
+        frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(pixel(&ppu, 16, 0), 3);
+        assert_eq!(pixel(&ppu, 0, 8), 3);
+        assert_eq!(pixel(&ppu, 36, 0), 1);
+        assert_eq!(pixel(&ppu, 40, 0), 1);
+        assert_eq!(pixel(&ppu, 44, 0), 3);
+        assert_eq!(pixel(&ppu, 48, 0), 0);
+
+        // with LCDC bit 1 cleared there are no sprites at all
+        ppu.write_byte(LCD_CONTROL_ADDR, 0x91);
+        frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 16, 0), 0);
+    }
+
+    #[test]
+    fn vblank_interrupt() {
+        let mut ppu = ppu();
+        let vblank = Interrupt::VBlank.mask();
+        let mut count = 0;
+        for _ in 0..CYCLES_PER_FRAME {
+            if ppu.do_cycle(1) & vblank != 0 {
+                count += 1;
+                assert_eq!(ppu.read_byte(CURRENT_SCANLINE_ADDR), VBLANK_LINE);
+                assert_eq!(ppu.mode(), PpuMode::VBlank);
+            }
+        }
+        assert_eq!(count, 1);
+    }
+
+    // counts the STAT interrupts requested during a whole frame
+    fn stat_interrupts(stat: Byte, lyc: Byte) -> Vec<Byte> {
+        let mut ppu = ppu();
+        ppu.write_byte(LCD_STATUS_ADDR, stat);
+        ppu.write_byte(LY_COMPARE_ADDR, lyc);
+        let mut lines = Vec::new();
+        for _ in 0..CYCLES_PER_FRAME {
+            if ppu.do_cycle(1) & Interrupt::LcdStat.mask() != 0 {
+                lines.push(ppu.read_byte(CURRENT_SCANLINE_ADDR));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn stat_interrupts_sources() {
+        assert_eq!(stat_interrupts(0x40, 10), [10]);
+        assert_eq!(stat_interrupts(0x08, 0).len(), 144);
+        assert_eq!(stat_interrupts(0x10, 0), [VBLANK_LINE]);
+        // the frame runs from the first dot of line 0 to the first dot of the next line 0
+        assert_eq!(stat_interrupts(0x20, 0).len(), 145);
+    }
+
+    #[test]
+    fn stat_line_blocks_back_to_back_sources() {
+        // HBlank of line 143 runs straight into VBlank: the line never goes low in between
+        assert_eq!(stat_interrupts(0x18, 0).len(), 144);
+        // LY becomes 10 while line 9 is still in HBlank, the LYC match and the HBlank of
+        // line 10 only keep the line up
+        let lines = stat_interrupts(0x48, 10);
+        assert_eq!(lines.len(), 143);
+        assert!(!lines.contains(&10));
+    }
+
+    // writes the bytes to the palette RAM starting at index, with auto increment
+    fn write_palettes(ppu: &mut Ppu, index_addr: Word, index: Byte, data: &[Byte]) {
+        ppu.write_byte(index_addr, 0x80 | index);
+        for &byte in data {
+            ppu.write_byte(index_addr + 1, byte);
+        }
+    }
+
+    #[test]
+    fn cgb_palette_ram() {
+        let mut ppu = Ppu::new_cgb();
+        // the index wraps around after the 64th byte
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 0x3E, &[0x11, 0x22, 0x33]);
+        assert_eq!(ppu.read_byte(BG_PALETTE_INDEX_ADDR), 0xC1);
+        ppu.write_byte(BG_PALETTE_INDEX_ADDR, 0x3E);
+        assert_eq!(ppu.read_byte(BG_PALETTE_INDEX_ADDR), 0x7E);
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0x11);
+        // without bit 7 the index stays where it is
+        ppu.write_byte(BG_PALETTE_DATA_ADDR, 0x44);
+        ppu.write_byte(BG_PALETTE_DATA_ADDR, 0x55);
+        assert_eq!(ppu.read_byte(BG_PALETTE_INDEX_ADDR), 0x7E);
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0x55);
+        ppu.write_byte(BG_PALETTE_INDEX_ADDR, 0x00);
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0x33);
+        // the sprite palettes are separate
+        ppu.write_byte(OBJ_PALETTE_INDEX_ADDR, 0x00);
+        assert_eq!(ppu.read_byte(OBJ_PALETTE_DATA_ADDR), 0xFF);
+
+        // during mode 3 reads return 0xFF and writes are lost, the index still moves on
+        while ppu.mode() != PpuMode::Drawing {
+            ppu.do_cycle(1);
+        }
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0xFF);
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 0x00, &[0x66]);
+        assert_eq!(ppu.read_byte(BG_PALETTE_INDEX_ADDR), 0xC1);
+        while ppu.mode() != PpuMode::HBlank {
+            ppu.do_cycle(1);
+        }
+        ppu.write_byte(BG_PALETTE_INDEX_ADDR, 0x00);
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0x33);
+
+        // none of it exists on the DMG
+        let mut ppu = Ppu::new();
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 0x00, &[0x12]);
+        assert_eq!(ppu.read_byte(BG_PALETTE_INDEX_ADDR), 0xFF);
+        assert_eq!(ppu.read_byte(BG_PALETTE_DATA_ADDR), 0xFF);
+    }
+
+    #[test]
+    fn cgb_background_attributes() {
+        let mut ppu = Ppu::new_cgb();
+        // tile 1 is color 1 in bank 1 and blank in bank 0, tile 2 is color 2 in bank 0
+        ppu.write_byte(VRAM_BANK_SELECT_ADDR, 0x01);
+        assert_eq!(ppu.read_byte(VRAM_BANK_SELECT_ADDR), 0xFF);
+        for row in 0..8 {
+            ppu.write_vram(0x8010 + row * 2, 0xFF);
+        }
+        // the attributes of the tiles at 0x9800 and 0x9802: palette 2 and bank 1, palette 3
+        // with a horizontal flip
+        ppu.write_vram(0x9800, 0x0A);
+        ppu.write_vram(0x9802, 0x23);
+        ppu.write_byte(VRAM_BANK_SELECT_ADDR, 0x00);
+        assert_eq!(ppu.read_byte(VRAM_BANK_SELECT_ADDR), 0xFE);
+        assert_eq!(ppu.read_vram(0x9800), 0x00);
+        ppu.write_vram(0x9800, 0x01);
+        ppu.write_vram(0x9801, 0x01);
+        // only the leftmost pixel of each row of tile 2
+        for row in 0..8 {
+            ppu.write_vram(0x8021 + row * 2, 0x80);
+        }
+        ppu.write_vram(0x9802, 0x02);
+
+        // RGB555 little endian, palette 2 color 1 and palette 3 color 2
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 2 * 8 + 2, &[0x1F, 0x7C]);
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 3 * 8 + 4, &[0xE0, 0x83]);
+        write_palettes(&mut ppu, BG_PALETTE_INDEX_ADDR, 0, &[0x00, 0x00]);
+        frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 0, 0), 1);
+        assert_eq!(ppu.rgb_framebuffer()[0], 0x7C1F);
+        // tile 1 from bank 0 with palette 0 is blank
+        assert_eq!(pixel(&ppu, 8, 0), 0);
+        assert_eq!(ppu.rgb_framebuffer()[8], 0x0000);
+        // the flip moves the pixel to the right end, bit 15 of the color is ignored
+        assert_eq!(pixel(&ppu, 16, 0), 0);
+        assert_eq!(pixel(&ppu, 23, 0), 2);
+        assert_eq!(ppu.rgb_framebuffer()[23], 0x03E0);
+    }
+}
@@ -1,46 +1,204 @@
 use std::fs;
+use std::path::Path;
 
-pub struct Rom{
-    data: Vec<u8>
+use crate::error::*;
+use crate::header::*;
+use crate::utils::*;
+
+pub struct Rom {
+    data: Vec<Byte>,
+    // parsed once, the header never changes after the cartridge is inserted
+    header: CartridgeHeader,
+    // the checksum mismatch the ChecksumPolicy let through with a warning
+    checksum_warning: Option<Error>,
 }
 
-impl Rom{
+impl Rom {
+    // a bad header checksum is an error, see ChecksumPolicy
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Rom> {
+        Rom::from_path_with_policy(path, ChecksumPolicy::default())
+    }
+
+    pub fn from_path_with_policy<P: AsRef<Path>>(path: P, policy: ChecksumPolicy) -> Result<Rom> {
+        let contents = fs::read(path)?;
+
+        Rom::from_bytes_with_policy(contents, policy)
+    }
+
+    pub fn from_bytes(data: Vec<Byte>) -> Result<Rom> {
+        Rom::from_bytes_with_policy(data, ChecksumPolicy::default())
+    }
 
-    pub fn new(file: &str) -> Rom{
-        let contents = fs::read(file).expect("Could not read ROM file");
+    pub fn from_bytes_with_policy(data: Vec<Byte>, policy: ChecksumPolicy) -> Result<Rom> {
+        if data.len() < ROM_HEADER_END {
+            return Err(Error::RomTooSmall(data.len()));
+        }
+
+        let mut header = CartridgeHeader::parse(&data);
+        // MMM01 multicarts boot into a menu stored in the last 32 KiB, its header is the one
+        // describing the cartridge, the one at the start belongs to the first game. any other
+        // ROM can have anything there, so it has to be a whole valid header for the full file
+        let menu = data.len().saturating_sub(2 * ROM_BANK_SIZE);
+        if menu > 0 {
+            let menu_header = CartridgeHeader::parse(&data[menu..]);
+            if matches!(
+                menu_header.cartridge_type,
+                CartridgeType::Mmm01 | CartridgeType::Mmm01Ram | CartridgeType::Mmm01RamBattery
+            ) && menu_header.header_checksum_valid()
+                && menu_header.rom_size == data.len()
+            {
+                header = menu_header;
+            }
+        }
 
-        Rom{
-            data: contents
+        if data.len() < header.rom_size {
+            return Err(Error::RomSizeMismatch {
+                expected: header.rom_size,
+                actual: data.len(),
+            });
         }
+        let checksum_warning = header.check(policy)?;
+
+        Ok(Rom {
+            data,
+            header,
+            checksum_warning,
+        })
     }
 
-    pub fn get_byte(&self mut, addr: usize) -> Byte{
-        self.data[addr]
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
     }
-    
-    pub fn get_cartridge_type(&self mut, addr: usize) -> Byte{
-        self.data[0x0147]
+
+    // left to the caller to show, the library does not print
+    pub fn checksum_warning(&self) -> Option<&Error> {
+        self.checksum_warning.as_ref()
     }
-    
-    pub fn length(&self) -> usize{
+
+    // reads past the end of the file behave like an open bus
+    pub fn get_byte(&self, addr: usize) -> Byte {
+        self.data.get(addr).copied().unwrap_or(0xFF)
+    }
+
+    pub fn get_cartridge_type(&self) -> CartridgeType {
+        self.header.cartridge_type
+    }
+
+    pub fn length(&self) -> usize {
         self.data.len()
     }
-    
-    pub fn get_number_banks(&self) -> u16{
-        match self.data[0x0148]{
-            0x00 => 1,
-            0x01 => 4,
-            0x02 => 8,
-            0x03 => 16,
-            0x04 => 32,
-            0x05 => 64,
-            0x06 => 128,
-            0x07 => 256,
-            0x08 => 512,
-            0x52 => 72,
-            0x53 => 80,
-            0x54 => 96,
-            _ => 2,
+
+    pub fn get_number_banks(&self) -> usize {
+        self.header.rom_banks()
+    }
+}
+
+// a cartridge for the unit tests, every byte of a bank holds the bank number and the header
+// checksum is right. rom_size and ram_size are the codes found at 0x148 and 0x149
+#[cfg(test)]
+pub(crate) fn test_rom(cartridge_type: Byte, rom_size: Byte, ram_size: Byte) -> Rom {
+    Rom::from_bytes(test_rom_data(cartridge_type, rom_size, ram_size)).unwrap()
+}
+
+#[cfg(test)]
+pub(crate) fn test_rom_data(cartridge_type: Byte, rom_size: Byte, ram_size: Byte) -> Vec<Byte> {
+    let banks = match rom_size {
+        0x52 => 72,
+        0x53 => 80,
+        0x54 => 96,
+        _ => 2 << rom_size,
+    };
+    let mut data: Vec<Byte> = (0..banks * ROM_BANK_SIZE)
+        .map(|addr| (addr / ROM_BANK_SIZE) as Byte)
+        .collect();
+    data[0x147] = cartridge_type;
+    data[0x148] = rom_size;
+    data[0x149] = ram_size;
+    set_header_checksum(&mut data);
+    data
+}
+
+// header is where the header's bank starts, MMM01 cartridges have theirs at the end
+#[cfg(test)]
+pub(crate) fn set_header_checksum(header: &mut [Byte]) {
+    header[0x14D] = header[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corrupted_rom() -> Vec<Byte> {
+        let mut data = test_rom_data(0x00, 0x00, 0x00);
+        data[0x14D] ^= 0xFF;
+        data
+    }
+
+    #[test]
+    fn bad_header_checksum() {
+        let data = corrupted_rom();
+        let computed = data[0x14D] ^ 0xFF;
+        match Rom::from_bytes(data.clone()) {
+            Err(Error::HeaderChecksumMismatch {
+                expected,
+                computed: actual,
+            }) => {
+                assert_eq!(expected, data[0x14D]);
+                assert_eq!(actual, computed);
+            }
+            _ => panic!("the corrupted header was accepted"),
         }
+        let rom = Rom::from_bytes_with_policy(data.clone(), ChecksumPolicy::Ignore).unwrap();
+        assert!(!rom.header().header_checksum_valid());
+        assert!(rom.checksum_warning().is_none());
+        let rom = Rom::from_bytes_with_policy(data, ChecksumPolicy::Warn).unwrap();
+        assert!(matches!(
+            rom.checksum_warning(),
+            Some(Error::HeaderChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn bad_global_checksum() {
+        // the global checksum covers the whole ROM, the boot ROM does not check it
+        let mut data = corrupted_rom();
+        data[0x14D] ^= 0xFF;
+        data[0x200] ^= 0xFF;
+        let rom = Rom::from_bytes(data).unwrap();
+        assert!(rom.header().header_checksum_valid());
+        assert!(!rom.header().global_checksum_valid());
+        assert!(matches!(
+            rom.checksum_warning(),
+            Some(Error::GlobalChecksumMismatch { .. })
+        ));
+    }
+
+    // an MBC1 game in 1 MiB, with an MMM01 menu header in the last 32 KiB
+    fn multicart(rom_size: Byte) -> Vec<Byte> {
+        let mut data = test_rom_data(0x01, 0x05, 0x00);
+        let menu = data.len() - 2 * ROM_BANK_SIZE;
+        data[menu + 0x147] = 0x0D;
+        data[menu + 0x148] = rom_size;
+        set_header_checksum(&mut data[menu..]);
+        data
+    }
+
+    #[test]
+    fn mmm01_menu_header() {
+        let rom = Rom::from_bytes(multicart(0x05)).unwrap();
+        assert_eq!(rom.get_cartridge_type(), CartridgeType::Mmm01RamBattery);
+
+        // a game that only happens to have an MMM01 type byte there
+        let mut data = multicart(0x05);
+        let menu = data.len() - 2 * ROM_BANK_SIZE;
+        data[menu + 0x14D] ^= 0xFF;
+        let rom = Rom::from_bytes(data).unwrap();
+        assert_eq!(rom.get_cartridge_type(), CartridgeType::Mbc1);
+
+        // or one describing a ROM of another size
+        let rom = Rom::from_bytes(multicart(0x04)).unwrap();
+        assert_eq!(rom.get_cartridge_type(), CartridgeType::Mbc1);
     }
 }
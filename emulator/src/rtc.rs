@@ -0,0 +1,391 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::*;
+
+/*
+ * MBC3 real time clock. It runs from its own 32768 Hz crystal, here it is advanced from the
+ * emulated clock cycles so it stays in sync with the game, plus the wall clock time that
+ * passed while the emulator was closed.
+ *
+ *    08    seconds     0 - 59
+ *    09    minutes     0 - 59
+ *    0A    hours       0 - 23
+ *    0B    days        lower 8 bits of the day counter
+ *    0C    days high   bit 0: bit 8 of the day counter, bit 6: halt, bit 7: day counter carry
+ *
+ * The game never reads the running registers: writing 0x00 then 0x01 to 0x6000 - 0x7FFF
+ * latches a copy of them and that copy is what reads return.
+ *
+ * Saves use the footer most emulators agree on, appended after the RAM in the .sav file:
+ * the five running registers and the five latched ones as 32 bit little endian values,
+ * followed by the 64 bit little endian UNIX timestamp of when it was written.
+ *
+ * see https://gbdev.io/pandocs/MBC3.html
+ */
+
+pub const RTC_FOOTER_SIZE: usize = 48;
+
+// clock cycles in one second of emulated time
+const CYCLES_PER_SECOND: u32 = 4194304;
+
+const DAY_HIGH_BIT: u8 = 0;
+const HALT_BIT: u8 = 6;
+const DAY_CARRY_BIT: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct RtcRegisters {
+    seconds: Byte,
+    minutes: Byte,
+    hours: Byte,
+    days_low: Byte,
+    days_high: Byte,
+}
+
+pub struct Rtc {
+    running: RtcRegisters,
+    latched: RtcRegisters,
+    // the last write to the latch register, latching happens on a 0x00 -> 0x01 sequence
+    latch_value: Byte,
+    cycles: u32,
+}
+
+impl RtcRegisters {
+    fn read(&self, register: usize) -> Byte {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days_low,
+            _ => self.days_high,
+        }
+    }
+
+    fn write(&mut self, register: usize, data: Byte) {
+        match register {
+            0x08 => self.seconds = data & 0x3F,
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days_low = data,
+            _ => self.days_high = data & 0xC1,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        bit_set(&self.days_high, HALT_BIT)
+    }
+
+    fn days(&self) -> Word {
+        (((self.days_high & 0x01) as Word) << 8) | self.days_low as Word
+    }
+
+    // the counters only roll over when they hit their limit exactly, a value written out of
+    // range keeps counting up to the top of its bits and wraps to 0 without a carry
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        let days = self.days() + 1;
+        self.days_low = days as Byte;
+        self.days_high &= !(1 << DAY_HIGH_BIT);
+        if days > 0x1FF {
+            // the day counter overflowed, the carry stays set until the game clears it
+            self.days_high |= 1 << DAY_CARRY_BIT;
+        } else {
+            self.days_high |= ((days >> 8) as Byte) << DAY_HIGH_BIT;
+        }
+    }
+
+    // the same as that many ticks, without going through them one by one
+    fn advance(&mut self, mut seconds: u64) {
+        // counters written out of range don't carry the usual way, tick them back in range
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+
+        let total =
+            seconds + self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600;
+        self.seconds = (total % 60) as Byte;
+        self.minutes = (total / 60 % 60) as Byte;
+        self.hours = (total / 3600 % 24) as Byte;
+
+        let days = self.days() as u64 + total / 86400;
+        self.days_low = days as Byte;
+        self.days_high &= !(1 << DAY_HIGH_BIT);
+        self.days_high |= (((days >> 8) & 1) as Byte) << DAY_HIGH_BIT;
+        if days > 0x1FF {
+            self.days_high |= 1 << DAY_CARRY_BIT;
+        }
+    }
+
+    fn to_footer(self, footer: &mut Vec<Byte>) {
+        for register in [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days_low,
+            self.days_high,
+        ] {
+            footer.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+    }
+
+    fn from_footer(footer: &[Byte]) -> RtcRegisters {
+        let register = |i: usize| footer[i * 4];
+        let mut registers = RtcRegisters::default();
+        for i in 0..5 {
+            registers.write(0x08 + i, register(i));
+        }
+        registers
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            running: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            latch_value: 0xFF,
+            cycles: 0,
+        }
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        if self.running.halted() {
+            return;
+        }
+        self.cycles += ticks;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.running.tick();
+        }
+    }
+
+    // register is the value written to 0x4000 - 0x5FFF, 0x08 - 0x0C
+    pub fn read(&self, register: usize) -> Byte {
+        self.latched.read(register)
+    }
+
+    pub fn write(&mut self, register: usize, data: Byte) {
+        // writing the seconds also resets the sub second divider
+        if register == 0x08 {
+            self.cycles = 0;
+        }
+        self.running.write(register, data);
+        self.latched.write(register, data);
+    }
+
+    pub fn write_latch(&mut self, data: Byte) {
+        if self.latch_value == 0x00 && data == 0x01 {
+            self.latched = self.running;
+        }
+        self.latch_value = data;
+    }
+
+    pub fn to_footer(&self) -> Vec<Byte> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        self.running.to_footer(&mut footer);
+        self.latched.to_footer(&mut footer);
+        footer.extend_from_slice(&unix_time().to_le_bytes());
+        footer
+    }
+
+    // restores the clock and catches up with the time that passed since it was saved
+    pub fn load_footer(&mut self, footer: &[Byte]) {
+        self.load_footer_at(footer, unix_time());
+    }
+
+    // now is the UNIX time the clock is caught up to
+    fn load_footer_at(&mut self, footer: &[Byte], now: u64) {
+        if footer.len() < RTC_FOOTER_SIZE {
+            return;
+        }
+        self.running = RtcRegisters::from_footer(&footer[0..20]);
+        self.latched = RtcRegisters::from_footer(&footer[20..40]);
+        self.cycles = 0;
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&footer[40..48]);
+        let saved_at = u64::from_le_bytes(timestamp);
+        // a zero timestamp was never written and one from the future can't be caught up with
+        if !self.running.halted() && saved_at != 0 {
+            self.running.advance(now.saturating_sub(saved_at));
+        }
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Rtc {
+        Rtc::new()
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECONDS: usize = 0x08;
+    const MINUTES: usize = 0x09;
+    const HOURS: usize = 0x0A;
+    const DAYS_LOW: usize = 0x0B;
+    const DAYS_HIGH: usize = 0x0C;
+
+    fn rtc(seconds: Byte, minutes: Byte, hours: Byte, days: Word) -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.write(SECONDS, seconds);
+        rtc.write(MINUTES, minutes);
+        rtc.write(HOURS, hours);
+        rtc.write(DAYS_LOW, days as Byte);
+        rtc.write(DAYS_HIGH, (days >> 8) as Byte);
+        rtc
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    // seconds, minutes, hours, days low and days high as the game reads them
+    fn registers(rtc: &Rtc) -> [Byte; 5] {
+        [SECONDS, MINUTES, HOURS, DAYS_LOW, DAYS_HIGH].map(|register| rtc.read(register))
+    }
+
+    #[test]
+    fn latching() {
+        let mut rtc = rtc(0, 0, 0, 0);
+        latch(&mut rtc);
+        rtc.do_cycle(CYCLES_PER_SECOND * 2);
+        assert_eq!(rtc.read(SECONDS), 0);
+        // only a 00 then 01 sequence latches
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(SECONDS), 0);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(SECONDS), 2);
+    }
+
+    #[test]
+    fn halt() {
+        let mut rtc = rtc(10, 0, 0, 0);
+        rtc.write(DAYS_HIGH, 1 << HALT_BIT);
+        rtc.do_cycle(CYCLES_PER_SECOND * 5);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(SECONDS), 10);
+        rtc.write(DAYS_HIGH, 0);
+        rtc.do_cycle(CYCLES_PER_SECOND * 5);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(SECONDS), 15);
+    }
+
+    #[test]
+    fn day_carry() {
+        let mut rtc = rtc(59, 59, 23, 0xFF);
+        rtc.do_cycle(CYCLES_PER_SECOND);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0x00, 0x01]);
+
+        let mut rtc = self::rtc(59, 59, 23, 0x1FF);
+        rtc.do_cycle(CYCLES_PER_SECOND);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0x00, 0x80]);
+        // the carry stays until the game clears it
+        for _ in 0..86400 {
+            rtc.do_cycle(CYCLES_PER_SECOND);
+        }
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = rtc(1, 2, 3, 0x104);
+        latch(&mut rtc);
+        rtc.write(SECONDS, 5);
+        let footer = rtc.to_footer();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+
+        let mut loaded = Rtc::new();
+        let saved_at = u64::from_le_bytes(footer[40..48].try_into().unwrap());
+        loaded.load_footer_at(&footer, saved_at);
+        // the write also went to the latched registers
+        assert_eq!(registers(&loaded), [5, 2, 3, 0x04, 0x01]);
+        assert_eq!(loaded.to_footer()[..40], footer[..40]);
+    }
+
+    #[test]
+    fn catches_up_with_the_time_away() {
+        let mut rtc = rtc(30, 59, 22, 0x1FE);
+        let mut footer = rtc.to_footer();
+        footer[40..48].copy_from_slice(&1_000_000u64.to_le_bytes());
+
+        // two days, one hour and 30 seconds later it is past midnight of day 0x201
+        rtc.load_footer_at(&footer, 1_000_000 + 2 * 86400 + 3600 + 30);
+        latch(&mut rtc);
+        assert_eq!(registers(&rtc), [0, 0, 0, 0x01, 0x80]);
+
+        // a halted clock does not move
+        let mut halted = self::rtc(30, 59, 22, 0x1FE);
+        halted.write(DAYS_HIGH, 0x41);
+        let mut footer = halted.to_footer();
+        footer[40..48].copy_from_slice(&1_000_000u64.to_le_bytes());
+        halted.load_footer_at(&footer, 2_000_000);
+        latch(&mut halted);
+        assert_eq!(registers(&halted), [30, 59, 22, 0xFE, 0x41]);
+    }
+
+    #[test]
+    fn ignores_nonsensical_timestamps() {
+        let mut rtc = rtc(1, 2, 3, 4);
+        let mut footer = rtc.to_footer();
+        // never written, the catch up would take the clock through 55 years
+        footer[40..48].copy_from_slice(&0u64.to_le_bytes());
+        rtc.load_footer_at(&footer, 1_700_000_000);
+        assert_eq!(registers(&rtc), [1, 2, 3, 4, 0]);
+
+        // from the future
+        footer[40..48].copy_from_slice(&2_000_000_000u64.to_le_bytes());
+        rtc.load_footer_at(&footer, 1_700_000_000);
+        assert_eq!(registers(&rtc), [1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn advance_matches_ticking() {
+        // including counters written out of range, which wrap without carrying
+        for start in [(59, 59, 23, 0x1FF), (61, 59, 23, 0x10), (0, 62, 25, 0x00)] {
+            for seconds in [0, 1, 3, 59, 61, 3599, 3601, 86399, 90061, 200000] {
+                let (s, m, h, d) = start;
+                let mut ticked = rtc(s, m, h, d).running;
+                let mut advanced = ticked;
+                for _ in 0..seconds {
+                    ticked.tick();
+                }
+                advanced.advance(seconds);
+                assert_eq!(advanced, ticked, "{:?} + {} seconds", start, seconds);
+            }
+        }
+    }
+}
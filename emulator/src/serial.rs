@@ -0,0 +1,210 @@
+use crate::utils::*;
+
+pub mod capture;
+pub mod loopback;
+pub mod tcp;
+
+pub use capture::CaptureCable;
+pub use loopback::LoopbackCable;
+pub use tcp::TcpCable;
+
+/*
+ * Serial port, the link cable connector:
+ *
+ *    FF01    SB, the byte shifted out, replaced bit by bit with the one shifted in
+ *    FF02    SC, bit 7 starts a transfer and stays set until it ends, bit 1 picks the fast
+ *            clock (CGB only), bit 0 picks the internal clock
+ *
+ * The console using its internal clock drives the transfer, one bit every 128 machine cycles
+ * (8192 Hz) or every 4 with the CGB fast clock. The other one waits with bit 0 cleared until
+ * the clock comes through the cable. Both ends request the serial interrupt once the eight
+ * bits are in.
+ *
+ * The transfer goes through a LinkCable a whole byte at a time: the driving end hands its
+ * byte over when the transfer starts and gets the byte of the other end back, which lands
+ * in SB when the eight bits have been clocked.
+ *
+ * see https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+ */
+
+// machine cycles per bit with the internal clock, normal and CGB fast
+const BIT_CYCLES: u32 = 128;
+const FAST_BIT_CYCLES: u32 = 4;
+
+const SC_TRANSFER: u8 = 7;
+const SC_FAST_CLOCK: u8 = 1;
+const SC_INTERNAL_CLOCK: u8 = 0;
+
+// what the console on the other end of the cable sees
+pub trait LinkCable {
+    // this end drives the clock: sends data and returns the byte the other end shifted out
+    fn exchange(&mut self, data: Byte) -> Byte;
+
+    // this end waits for the clock of the other one, data is what it shifts out in return.
+    // returns the byte that came in once the other end has clocked a transfer
+    fn receive(&mut self, data: Byte) -> Option<Byte>;
+
+    // this end is not waiting for a clock, a transfer from the other end finds nothing to
+    // shift and gets 0xFF back
+    fn idle(&mut self) {}
+}
+
+// nothing plugged in, the input line is pulled up so every bit reads 1 and no external clock
+// ever comes
+pub struct Disconnected;
+
+impl LinkCable for Disconnected {
+    fn exchange(&mut self, _data: Byte) -> Byte {
+        0xFF
+    }
+
+    fn receive(&mut self, _data: Byte) -> Option<Byte> {
+        None
+    }
+}
+
+pub struct Serial {
+    sb: Byte,
+    sc: Byte,
+    cgb: bool,
+    cable: Box<dyn LinkCable>,
+    // machine cycles left in a transfer on the internal clock
+    remaining: u32,
+    received: Byte,
+    // machine cycles until the cable is asked again for an external clock
+    poll_timer: u32,
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Serial {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            cgb,
+            cable: Box::new(Disconnected),
+            remaining: 0,
+            received: 0xFF,
+            poll_timer: 0,
+        }
+    }
+
+    // returns the cable that was plugged in before
+    pub fn set_cable(&mut self, cable: Box<dyn LinkCable>) -> Box<dyn LinkCable> {
+        std::mem::replace(&mut self.cable, cable)
+    }
+
+    pub fn is_transferring(&self) -> bool {
+        bit_set(&self.sc, SC_TRANSFER)
+    }
+
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        match addr {
+            SERIAL_DATA_ADDR => self.sb,
+            // the unused bits read back as 1, bit 1 only exists on the CGB
+            _ if self.cgb => 0x7C | self.sc,
+            _ => 0x7E | self.sc,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        match addr {
+            SERIAL_DATA_ADDR => self.sb = data,
+            _ => {
+                self.sc = data & if self.cgb { 0x83 } else { 0x81 };
+                if self.is_transferring() && bit_set(&self.sc, SC_INTERNAL_CLOCK) {
+                    self.received = self.cable.exchange(self.sb);
+                    self.remaining = 8 * self.bit_cycles();
+                }
+                self.poll_timer = 0;
+            }
+        }
+    }
+
+    // advances the port by one machine cycle, returns true when the serial interrupt has to
+    // be requested
+    pub fn do_cycle(&mut self) -> bool {
+        let internal = bit_set(&self.sc, SC_INTERNAL_CLOCK);
+        if self.is_transferring() && internal {
+            self.remaining = self.remaining.saturating_sub(1);
+            if self.remaining == 0 {
+                return self.complete(self.received);
+            }
+        }
+
+        // the cable is only checked once per bit time, a TCP peer costs a system call
+        if self.poll_timer > 0 {
+            self.poll_timer -= 1;
+            return false;
+        }
+        self.poll_timer = BIT_CYCLES;
+        if !self.is_transferring() || internal {
+            self.cable.idle();
+            return false;
+        }
+        match self.cable.receive(self.sb) {
+            Some(data) => self.complete(data),
+            None => false,
+        }
+    }
+
+    fn complete(&mut self, data: Byte) -> bool {
+        self.sb = data;
+        self.sc &= !(1 << SC_TRANSFER);
+        true
+    }
+
+    fn bit_cycles(&self) -> u32 {
+        if self.cgb && bit_set(&self.sc, SC_FAST_CLOCK) {
+            FAST_BIT_CYCLES
+        } else {
+            BIT_CYCLES
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // starts a transfer of 0x42 with SC and returns the machine cycles it took
+    fn transfer(serial: &mut Serial, sc: Byte, limit: u32) -> Option<u32> {
+        serial.write_byte(SERIAL_DATA_ADDR, 0x42);
+        serial.write_byte(SERIAL_CONTROL_ADDR, sc);
+        (1..=limit).find(|_| serial.do_cycle())
+    }
+
+    #[test]
+    fn internal_clock() {
+        let mut serial = Serial::new(false);
+        assert_eq!(transfer(&mut serial, 0x81, 10000), Some(8 * 128));
+        assert!(!serial.is_transferring());
+        // nothing plugged in shifts 1s in
+        assert_eq!(serial.read_byte(SERIAL_DATA_ADDR), 0xFF);
+        assert_eq!(serial.read_byte(SERIAL_CONTROL_ADDR), 0x7F);
+        // the DMG has no fast clock
+        assert_eq!(transfer(&mut serial, 0x83, 10000), Some(8 * 128));
+    }
+
+    #[test]
+    fn cgb_fast_clock() {
+        let mut serial = Serial::new(true);
+        assert_eq!(transfer(&mut serial, 0x83, 10000), Some(8 * 4));
+        assert_eq!(serial.read_byte(SERIAL_CONTROL_ADDR), 0x7F);
+        assert_eq!(transfer(&mut serial, 0x81, 10000), Some(8 * 128));
+    }
+
+    #[test]
+    fn external_clock_without_a_cable() {
+        let mut serial = Serial::new(false);
+        assert_eq!(transfer(&mut serial, 0x80, 100000), None);
+        assert!(serial.is_transferring());
+        assert_eq!(serial.read_byte(SERIAL_DATA_ADDR), 0x42);
+        assert_eq!(serial.read_byte(SERIAL_CONTROL_ADDR), 0xFE);
+    }
+}
@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::serial::LinkCable;
+use crate::utils::*;
+
+/*
+ * Both ends of a cable between two consoles of the same process, the frontend runs them in
+ * turns:
+ *
+ *    let (cable_a, cable_b) = LoopbackCable::pair();
+ *    gameboy_a.set_link_cable(Box::new(cable_a));
+ *    gameboy_b.set_link_cable(Box::new(cable_b));
+ *
+ * The end waiting for a clock leaves the byte it shifts out where the driving end picks it
+ * up, the driving end leaves its byte for the waiting one.
+ */
+
+#[derive(Default)]
+struct Wire {
+    // the byte each end left for the other one, indexed by the receiving end
+    incoming: [Option<Byte>; 2],
+    // the byte each end shifts out while it waits for a clock
+    outgoing: [Option<Byte>; 2],
+}
+
+pub struct LoopbackCable {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl LoopbackCable {
+    pub fn pair() -> (LoopbackCable, LoopbackCable) {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        (
+            LoopbackCable {
+                wire: Rc::clone(&wire),
+                side: 0,
+            },
+            LoopbackCable { wire, side: 1 },
+        )
+    }
+
+    fn other(&self) -> usize {
+        1 - self.side
+    }
+}
+
+impl LinkCable for LoopbackCable {
+    fn exchange(&mut self, data: Byte) -> Byte {
+        let mut wire = self.wire.borrow_mut();
+        wire.incoming[self.other()] = Some(data);
+        wire.outgoing[self.other()].take().unwrap_or(0xFF)
+    }
+
+    fn receive(&mut self, data: Byte) -> Option<Byte> {
+        let mut wire = self.wire.borrow_mut();
+        let incoming = wire.incoming[self.side].take();
+        wire.outgoing[self.side] = match incoming {
+            Some(_) => None,
+            None => Some(data),
+        };
+        incoming
+    }
+
+    // a transfer that came in meanwhile is lost, the other end already read 0xFF
+    fn idle(&mut self) {
+        let mut wire = self.wire.borrow_mut();
+        wire.incoming[self.side] = None;
+        wire.outgoing[self.side] = None;
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::serial::LinkCable;
+use crate::utils::*;
+
+/*
+ * A cable to a console running in another process, usually on localhost. One end listens
+ * and the other one connects, after that both are the same. Every message is two bytes, a
+ * kind and the data:
+ *
+ *    01 xx    transfer, the sender drove the clock and shifted out xx
+ *    02 xx    reply, the receiver of a transfer shifted out xx in return
+ *
+ * The driving end waits for the reply, up to LINK_TIMEOUT, so the game sees the byte of
+ * the other console at the end of its transfer. An end that is not waiting for a clock
+ * replies 0xFF right away, and when nothing comes back at all the game reads 0xFF too, as
+ * with nothing plugged in.
+ */
+
+const TRANSFER: Byte = 0x01;
+const REPLY: Byte = 0x02;
+
+const LINK_TIMEOUT: Duration = Duration::from_millis(500);
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+pub struct TcpCable {
+    stream: Option<TcpStream>,
+    // bytes read that do not make a whole message yet
+    buffer: Vec<Byte>,
+}
+
+impl TcpCable {
+    // waits for the other end to connect
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpCable> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        TcpCable::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpCable> {
+        TcpCable::from_stream(TcpStream::connect(addr)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpCable> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(TcpCable {
+            stream: Some(stream),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn send(&mut self, kind: Byte, data: Byte) {
+        let sent = match &mut self.stream {
+            Some(stream) => write_all(stream, &[kind, data]),
+            None => return,
+        };
+        if sent.is_err() {
+            self.stream = None;
+        }
+    }
+
+    // the next whole message, without waiting for one
+    fn next_message(&mut self) -> Option<(Byte, Byte)> {
+        if self.buffer.len() < 2 {
+            let stream = self.stream.as_mut()?;
+            let mut data = [0; 64];
+            match stream.read(&mut data) {
+                // the other end hung up
+                Ok(0) => self.stream = None,
+                Ok(len) => self.buffer.extend_from_slice(&data[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => self.stream = None,
+            }
+        }
+        if self.buffer.len() < 2 {
+            return None;
+        }
+        let message = (self.buffer[0], self.buffer[1]);
+        self.buffer.drain(..2);
+        Some(message)
+    }
+}
+
+impl LinkCable for TcpCable {
+    fn exchange(&mut self, data: Byte) -> Byte {
+        self.send(TRANSFER, data);
+        let deadline = Instant::now() + LINK_TIMEOUT;
+        while self.is_connected() && Instant::now() < deadline {
+            match self.next_message() {
+                Some((REPLY, reply)) => return reply,
+                // both ends drive the clock, neither of them gets anything
+                Some((TRANSFER, _)) => self.send(REPLY, 0xFF),
+                Some(_) => {}
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+        0xFF
+    }
+
+    fn receive(&mut self, data: Byte) -> Option<Byte> {
+        while let Some((kind, incoming)) = self.next_message() {
+            // a late reply to a transfer that already timed out is dropped
+            if kind == TRANSFER {
+                self.send(REPLY, data);
+                return Some(incoming);
+            }
+        }
+        None
+    }
+
+    fn idle(&mut self) {
+        while let Some((kind, _)) = self.next_message() {
+            if kind == TRANSFER {
+                self.send(REPLY, 0xFF);
+            }
+        }
+    }
+}
+
+// the socket is non blocking, a full send buffer is waited out
+fn write_all(stream: &mut TcpStream, mut data: &[Byte]) -> io::Result<()> {
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(len) => data = &data[len..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
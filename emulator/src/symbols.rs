@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::utils::*;
+
+/*
+ * Symbol files as written by rgblink -n and no$gmb, one label per line with its bank:
+ *
+ *    ; File generated by rgblink
+ *    00:0150 Start
+ *    01:4a3f PlayerUpdate
+ *    01:4a50 PlayerUpdate.loop
+ *    00:c000 wPlayerX
+ *
+ * Anything after a ; is a comment, lines that don't look like the above are skipped.
+ *
+ * An address with no label of its own shows as the closest label before it plus the
+ * offset, PlayerUpdate.loop+$03, as long as that label is in the same bank and the same
+ * memory region: a WRAM address is never shown relative to a label in ROM.
+ */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub addr: Word,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    // sorted by bank and address, labels sharing an address keep the file order
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<SymbolTable> {
+        Ok(SymbolTable::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut symbols: Vec<Symbol> = text.lines().filter_map(parse_line).collect();
+        symbols.sort_by_key(|symbol| (symbol.bank, symbol.addr));
+        SymbolTable { symbols }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // bank and address of a label, names are case sensitive
+    pub fn lookup(&self, name: &str) -> Option<(usize, Word)> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| (symbol.bank, symbol.addr))
+    }
+
+    // the first label at exactly bank:addr
+    pub fn label_at(&self, bank: usize, addr: Word) -> Option<&str> {
+        let start = self
+            .symbols
+            .partition_point(|symbol| (symbol.bank, symbol.addr) < (bank, addr));
+        self.symbols
+            .get(start)
+            .filter(|symbol| symbol.bank == bank && symbol.addr == addr)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    // the closest label at or before bank:addr and how far addr is from it
+    pub fn nearest(&self, bank: usize, addr: Word) -> Option<(&str, Word)> {
+        if let Some(name) = self.label_at(bank, addr) {
+            return Some((name, 0));
+        }
+        let end = self
+            .symbols
+            .partition_point(|symbol| (symbol.bank, symbol.addr) <= (bank, addr));
+        let symbol = self.symbols[..end].last()?;
+        if symbol.bank != bank || region(symbol.addr) != region(addr) {
+            return None;
+        }
+        // the last of the labels sharing that address is the one found, show the first
+        let name = self.label_at(bank, symbol.addr).unwrap_or(&symbol.name);
+        Some((name, addr - symbol.addr))
+    }
+
+    // Label or Label+$0F, None when no label comes close
+    pub fn describe(&self, bank: usize, addr: Word) -> Option<String> {
+        self.nearest(bank, addr).map(|(name, offset)| match offset {
+            0 => String::from(name),
+            offset => format!("{}+${:02X}", name, offset),
+        })
+    }
+}
+
+fn parse_line(line: &str) -> Option<Symbol> {
+    let line = line.split(';').next().unwrap_or("");
+    let (location, name) = line.trim().split_once(char::is_whitespace)?;
+    let (bank, addr) = location.split_once(':')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Symbol {
+        bank: usize::from_str_radix(bank, 16).ok()?,
+        addr: Word::from_str_radix(addr, 16).ok()?,
+        name: String::from(name),
+    })
+}
+
+// the parts of the memory map a label can't reach past
+fn region(addr: Word) -> usize {
+    match addr {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xDFFF => 4,
+        0xE000..=0xFDFF => 5,
+        0xFE00..=0xFEFF => 6,
+        _ => 7,
+    }
+}
@@ -0,0 +1,298 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::*;
+use crate::gameboy::GameBoy;
+use crate::serial::CaptureCable;
+use crate::utils::*;
+
+/*
+ * Headless runner for blargg's test ROMs, see https://github.com/retrio/gb-test-roms
+ *
+ * The ROMs print their results as text through the serial port, ending with "Passed" or
+ * "Failed". The newer ones (dmg_sound, mem_timing-2, halt_bug...) write them to the cartridge
+ * RAM instead, which the runner watches as well:
+ *
+ *    A000       status, 0x80 while the test runs, then 0x00 when it passed or an error code
+ *    A001       signature DE B0 61, only valid once it is there
+ *    A004 -     the text output, zero terminated
+ *
+ * Mooneye's test ROMs, see https://github.com/Gekkio/mooneye-test-suite, execute LD B,B
+ * once they are done. They pass with the Fibonacci numbers 3, 5, 8, 13, 21 and 34 in
+ * B, C, D, E, H and L, and fail with 0x42 in all of them.
+ *
+ * A ROM that gives no result within the cycle budget times out, one that can't be loaded at
+ * all is an error.
+ */
+
+// the suites the CI tracks, each one is a ROM of its own or a directory of them
+pub const BLARGG_SUITES: [&str; 5] = [
+    "cpu_instrs",
+    "instr_timing",
+    "mem_timing",
+    "halt_bug",
+    "dmg_sound",
+];
+
+// two minutes of emulated time, the whole cpu_instrs takes about one
+pub const DEFAULT_CYCLE_BUDGET: u64 = 120 * 4194304;
+
+// the mooneye directories the CI tracks
+pub const MOONEYE_SUITES: [&str; 2] = ["acceptance", "emulator-only"];
+
+const MOONEYE_PASS: [Byte; 6] = [3, 5, 8, 13, 21, 34];
+
+const RAM_STATUS_ADDR: Word = 0xA000;
+const RAM_SIGNATURE_ADDR: Word = 0xA001;
+const RAM_TEXT_ADDR: Word = 0xA004;
+const RAM_SIGNATURE: [Byte; 3] = [0xDE, 0xB0, 0x61];
+const RAM_RUNNING: Byte = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    Timeout,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub rom: PathBuf,
+    pub outcome: TestOutcome,
+    // everything the ROM printed
+    pub output: String,
+    pub cycles: u64,
+}
+
+impl TestReport {
+    // a ROM that could not be run, the output holds why
+    pub fn error<P: AsRef<Path>>(rom: P, err: &Error) -> TestReport {
+        TestReport {
+            rom: rom.as_ref().to_path_buf(),
+            outcome: TestOutcome::Error,
+            output: err.to_string(),
+            cycles: 0,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome == TestOutcome::Passed
+    }
+}
+
+impl TestOutcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            TestOutcome::Passed => "pass",
+            TestOutcome::Failed => "fail",
+            TestOutcome::Timeout => "timeout",
+            TestOutcome::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestOutcome::Passed => write!(f, "PASS"),
+            TestOutcome::Failed => write!(f, "FAIL"),
+            TestOutcome::Timeout => write!(f, "TIMEOUT"),
+            TestOutcome::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+// runs a blargg ROM until it reports a result or the cycle budget runs out
+pub fn run_blargg<P: AsRef<Path>>(path: P, cycle_budget: u64) -> Result<TestReport> {
+    let mut gameboy = GameBoy::from_rom_bytes(fs::read(&path)?)?;
+    let cable = CaptureCable::new();
+    let serial = cable.output();
+    gameboy.set_link_cable(Box::new(cable));
+
+    let mut cycles = 0;
+    let outcome = loop {
+        cycles += gameboy.run_frame() as u64;
+        if let Some(outcome) = serial_outcome(&serial) {
+            break outcome;
+        }
+        if let Some(outcome) = ram_outcome(&gameboy) {
+            break outcome;
+        }
+        if cycles >= cycle_budget {
+            break TestOutcome::Timeout;
+        }
+    };
+
+    let mut output = String::from_utf8_lossy(&serial.borrow()).into_owned();
+    if output.is_empty() {
+        output = ram_text(&gameboy);
+    }
+    Ok(TestReport {
+        rom: path.as_ref().to_path_buf(),
+        outcome,
+        output,
+        cycles,
+    })
+}
+
+// runs a mooneye ROM until it hits LD B,B or the cycle budget runs out
+pub fn run_mooneye<P: AsRef<Path>>(path: P, cycle_budget: u64) -> Result<TestReport> {
+    let mut gameboy = GameBoy::from_rom_bytes(fs::read(&path)?)?;
+    let cable = CaptureCable::new();
+    let serial = cable.output();
+    gameboy.set_link_cable(Box::new(cable));
+
+    let mut cycles = 0;
+    let outcome = loop {
+        cycles += gameboy.step_instruction() as u64;
+        if gameboy.cpu_mut().take_breakpoint() {
+            let reg = gameboy.registers();
+            break match [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l] == MOONEYE_PASS {
+                true => TestOutcome::Passed,
+                false => TestOutcome::Failed,
+            };
+        }
+        if cycles >= cycle_budget {
+            break TestOutcome::Timeout;
+        }
+    };
+
+    let reg = gameboy.registers();
+    let output = format!(
+        "B={:02X} C={:02X} D={:02X} E={:02X} H={:02X} L={:02X}",
+        reg.b, reg.c, reg.d, reg.e, reg.h, reg.l
+    );
+    let serial = String::from_utf8_lossy(&serial.borrow()).into_owned();
+    Ok(TestReport {
+        rom: path.as_ref().to_path_buf(),
+        outcome,
+        output: match serial.is_empty() {
+            true => output,
+            false => format!("{}\n{}", output, serial),
+        },
+        cycles,
+    })
+}
+
+/*
+ * One line per ROM, in the order given, with the paths relative to root so two runs on
+ * different machines diff cleanly:
+ *
+ *    {
+ *      "passed": 1,
+ *      "failed": 1,
+ *      "results": [
+ *        {"rom": "acceptance/add_sp_e_timing.gb", "outcome": "pass", "cycles": 1234},
+ *        {"rom": "acceptance/di_timing-GS.gb", "outcome": "timeout", "cycles": 5678},
+ *        {"rom": "acceptance/broken.gb", "outcome": "error", "cycles": 0,
+ *         "error": "bad header checksum"}
+ *      ]
+ *    }
+ */
+pub fn json_summary<P: AsRef<Path>>(reports: &[TestReport], root: P) -> String {
+    let passed = reports.iter().filter(|report| report.passed()).count();
+    let mut json = format!(
+        "{{\n  \"passed\": {},\n  \"failed\": {},\n  \"results\": [\n",
+        passed,
+        reports.len() - passed
+    );
+    for (i, report) in reports.iter().enumerate() {
+        let rom = report.rom.strip_prefix(&root).unwrap_or(&report.rom);
+        let error = match report.outcome {
+            TestOutcome::Error => format!(", \"error\": \"{}\"", json_escape(&report.output)),
+            _ => String::new(),
+        };
+        json.push_str(&format!(
+            "    {{\"rom\": \"{}\", \"outcome\": \"{}\", \"cycles\": {}{}}}{}\n",
+            json_escape(&rom.to_string_lossy().replace('\\', "/")),
+            report.outcome.name(),
+            report.cycles,
+            error,
+            if i + 1 < reports.len() { "," } else { "" }
+        ));
+    }
+    json.push_str("  ]\n}\n");
+    json
+}
+
+// every .gb file of a suite under root: either root/<suite>.gb or everything in root/<suite>/,
+// sorted so the reports come out in the same order every time
+pub fn suite_roms<P: AsRef<Path>>(root: P, suite: &str) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let single = root.as_ref().join(format!("{}.gb", suite));
+    if single.is_file() {
+        roms.push(single);
+    }
+    collect_roms(&root.as_ref().join(suite), &mut roms);
+    roms.sort();
+    roms
+}
+
+pub fn collect_roms(path: &Path, roms: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        if path
+            .extension()
+            .is_some_and(|ext| ext == "gb" || ext == "gbc")
+        {
+            roms.push(path.to_path_buf());
+        }
+        return;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        collect_roms(&entry.path(), roms);
+    }
+}
+
+fn serial_outcome(serial: &Rc<RefCell<Vec<Byte>>>) -> Option<TestOutcome> {
+    let serial = serial.borrow();
+    let text = String::from_utf8_lossy(&serial);
+    if text.contains("Passed") {
+        Some(TestOutcome::Passed)
+    } else if text.contains("Failed") {
+        Some(TestOutcome::Failed)
+    } else {
+        None
+    }
+}
+
+fn ram_outcome(gameboy: &GameBoy) -> Option<TestOutcome> {
+    let mmu = gameboy.mmu();
+    let signature = [0, 1, 2].map(|i| mmu.read_byte(RAM_SIGNATURE_ADDR + i));
+    if signature != RAM_SIGNATURE {
+        return None;
+    }
+    match mmu.read_byte(RAM_STATUS_ADDR) {
+        RAM_RUNNING => None,
+        0x00 => Some(TestOutcome::Passed),
+        _ => Some(TestOutcome::Failed),
+    }
+}
+
+fn ram_text(gameboy: &GameBoy) -> String {
+    let mmu = gameboy.mmu();
+    let text: Vec<Byte> = (RAM_TEXT_ADDR..0xC000)
+        .map(|addr| mmu.read_byte(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,260 @@
+use crate::utils::*;
+
+/*
+ * The timer is built on a 16 bit counter that goes up on every clock cycle, DIV (0xFF04) is just
+ * its upper 8 bits. TIMA (0xFF05) does not have a clock of its own: it is incremented whenever
+ * the counter bit selected by TAC (0xFF07) goes from 1 to 0 while the timer is enabled.
+ *
+ *    TAC & 0x3    frequency    counter bit
+ *    00           4096 Hz      9
+ *    01           262144 Hz    3
+ *    10           65536 Hz     5
+ *    11           16384 Hz     7
+ *
+ * Since it is an edge detector, anything that drops the selected bit also increments TIMA:
+ * writing to DIV (which clears the whole counter) or changing TAC.
+ *
+ * When TIMA overflows it reads as 0x00 for one machine cycle, only then it is reloaded from
+ * TMA (0xFF06) and the timer interrupt is requested. Writing TIMA during that cycle cancels the
+ * reload, writing TIMA on the reload cycle is ignored and writing TMA on it goes to TIMA too.
+ *
+ * see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+ */
+
+const TIMER_ENABLE_BIT: u8 = 2;
+
+// the counter bit watched for each of the four TAC frequencies
+const TAC_COUNTER_BITS: [Word; 4] = [9, 3, 5, 7];
+
+// what the DMG boot ROM leaves on the counter when it jumps to 0x0100
+const BOOT_COUNTER: Word = 0xABCC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimaState {
+    Running,
+    // TIMA just overflowed and reads 0x00, the reload happens on the next machine cycle
+    Overflowed,
+    // TIMA was reloaded from TMA during this machine cycle
+    Reloaded,
+}
+
+pub struct Timer {
+    counter: Word,
+    tima: Byte,
+    tma: Byte,
+    tac: Byte,
+    state: TimaState,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: BOOT_COUNTER,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            state: TimaState::Running,
+        }
+    }
+
+    // the internal 16 bit counter, DIV is the upper half
+    pub fn counter(&self) -> Word {
+        self.counter
+    }
+
+    pub fn div(&self) -> Byte {
+        (self.counter >> 8) as Byte
+    }
+
+    // advances the timer by one machine cycle (4 clock cycles), returns true when the timer
+    // interrupt has to be requested
+    pub fn do_cycle(&mut self) -> bool {
+        let mut interrupt = false;
+        match self.state {
+            TimaState::Overflowed => {
+                self.tima = self.tma;
+                self.state = TimaState::Reloaded;
+                interrupt = true;
+            }
+            TimaState::Reloaded => self.state = TimaState::Running,
+            TimaState::Running => {}
+        }
+
+        let old_signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_falling_edge(old_signal);
+
+        interrupt
+    }
+
+    pub fn read_byte(&self, addr: Word) -> Byte {
+        match addr {
+            DIVIDER_REGISTER_ADDR => self.div(),
+            TIMER_ADDR => self.tima,
+            TIMER_MODULATOR_ADDR => self.tma,
+            // the upper five bits of TAC are unused and read back as 1
+            _ => 0xF8 | self.tac,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: Word, data: Byte) {
+        match addr {
+            DIVIDER_REGISTER_ADDR => {
+                let old_signal = self.signal();
+                self.counter = 0;
+                self.detect_falling_edge(old_signal);
+            }
+            TIMER_ADDR => match self.state {
+                TimaState::Overflowed => {
+                    self.tima = data;
+                    self.state = TimaState::Running;
+                }
+                TimaState::Reloaded => {}
+                TimaState::Running => self.tima = data,
+            },
+            TIMER_MODULATOR_ADDR => {
+                self.tma = data;
+                if self.state == TimaState::Reloaded {
+                    self.tima = data;
+                }
+            }
+            _ => {
+                let old_signal = self.signal();
+                self.tac = data & 0x07;
+                self.detect_falling_edge(old_signal);
+            }
+        }
+    }
+
+    // the input of the edge detector: the selected counter bit ANDed with the enable bit
+    fn signal(&self) -> bool {
+        let bit = TAC_COUNTER_BITS[(self.tac & 0x3) as usize];
+        bit_set(&self.tac, TIMER_ENABLE_BIT) && (self.counter >> bit) & 1 == 1
+    }
+
+    fn detect_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.state = TimaState::Overflowed;
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TIMA clocked by counter bit 3, it goes up every 4 machine cycles from a cleared counter
+    fn timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write_byte(DIVIDER_REGISTER_ADDR, 0);
+        timer.write_byte(TIMER_CONTROL_ADDR, 0x05);
+        timer
+    }
+
+    fn run(timer: &mut Timer, cycles: usize) -> bool {
+        (0..cycles).fold(false, |interrupt, _| timer.do_cycle() || interrupt)
+    }
+
+    fn tima(timer: &Timer) -> Byte {
+        timer.read_byte(TIMER_ADDR)
+    }
+
+    #[test]
+    fn increments_at_the_selected_frequency() {
+        let mut timer = timer();
+        run(&mut timer, 3);
+        assert_eq!(tima(&timer), 0);
+        run(&mut timer, 1);
+        assert_eq!(tima(&timer), 1);
+        run(&mut timer, 8);
+        assert_eq!(tima(&timer), 3);
+        assert_eq!(timer.read_byte(TIMER_CONTROL_ADDR), 0xFD);
+    }
+
+    #[test]
+    fn div_write_increments_tima() {
+        let mut timer = timer();
+        // counter bit 3 is set
+        run(&mut timer, 2);
+        timer.write_byte(DIVIDER_REGISTER_ADDR, 0x12);
+        assert_eq!(timer.div(), 0);
+        assert_eq!(tima(&timer), 1);
+
+        // nothing happens while the bit is clear
+        timer.write_byte(DIVIDER_REGISTER_ADDR, 0x12);
+        assert_eq!(tima(&timer), 1);
+    }
+
+    #[test]
+    fn tac_change_glitch() {
+        let mut timer = timer();
+        run(&mut timer, 2);
+        // bit 9 of the counter is clear, going from bit 3 to bit 9 is a falling edge
+        timer.write_byte(TIMER_CONTROL_ADDR, 0x04);
+        assert_eq!(tima(&timer), 1);
+
+        // and so is disabling the timer while the selected bit is set
+        timer.write_byte(TIMER_CONTROL_ADDR, 0x05);
+        timer.write_byte(TIMER_CONTROL_ADDR, 0x01);
+        assert_eq!(tima(&timer), 2);
+
+        // enabling it does not count
+        timer.write_byte(TIMER_CONTROL_ADDR, 0x05);
+        assert_eq!(tima(&timer), 2);
+    }
+
+    #[test]
+    fn overflow_reads_zero_before_the_reload() {
+        let mut timer = timer();
+        timer.write_byte(TIMER_ADDR, 0xFF);
+        timer.write_byte(TIMER_MODULATOR_ADDR, 0x42);
+        assert!(!run(&mut timer, 4));
+        assert_eq!(tima(&timer), 0x00);
+        assert!(timer.do_cycle());
+        assert_eq!(tima(&timer), 0x42);
+        assert!(!timer.do_cycle());
+    }
+
+    #[test]
+    fn tima_write_cancels_the_reload() {
+        let mut timer = timer();
+        timer.write_byte(TIMER_ADDR, 0xFF);
+        timer.write_byte(TIMER_MODULATOR_ADDR, 0x42);
+        run(&mut timer, 4);
+        timer.write_byte(TIMER_ADDR, 0x10);
+        assert!(!run(&mut timer, 3));
+        assert_eq!(tima(&timer), 0x10);
+    }
+
+    #[test]
+    fn writes_on_the_reload_cycle() {
+        let mut timer = timer();
+        timer.write_byte(TIMER_ADDR, 0xFF);
+        timer.write_byte(TIMER_MODULATOR_ADDR, 0x42);
+        run(&mut timer, 5);
+        // TIMA writes are ignored, TMA writes go through to TIMA
+        timer.write_byte(TIMER_ADDR, 0x99);
+        assert_eq!(tima(&timer), 0x42);
+        timer.write_byte(TIMER_MODULATOR_ADDR, 0x77);
+        assert_eq!(tima(&timer), 0x77);
+
+        // the cycle after that TIMA is back to normal
+        timer.do_cycle();
+        timer.write_byte(TIMER_ADDR, 0x99);
+        assert_eq!(tima(&timer), 0x99);
+    }
+}
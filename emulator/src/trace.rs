@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::mmu::*;
+use crate::register::Registers;
+use crate::utils::*;
+
+// bytes from PC shown on each line
+const PCMEM_LEN: usize = 4;
+
+/*
+ * Instruction traces in the format gameboy-doctor compares against, one line per instruction
+ * with the state right before it runs:
+ *
+ *    A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+ *
+ * Only instructions that actually run are traced, not the cycles spent in HALT nor the
+ * interrupt dispatches. gameboy-doctor also expects LY to always read 90: the tracer does not
+ * change what the cpu reads, turn on the doctor mode of the mmu (Mmu::set_doctor_mode) for
+ * the traces of ROMs that wait on LY to match.
+ *
+ * The filters keep the instructions with PC in a range and, optionally, in a single bank,
+ * see Mmu::bank_at. With a ring size the tracer keeps the last lines in memory instead of
+ * writing them as they come, until dump is called: the cpu does it on LD B,B and when it
+ * locks up, the debugger when a run stops on a breakpoint or a watchpoint.
+ *
+ * Writing the trace does not stop the emulation when it fails, the error is kept for the
+ * owner of the tracer to check with last_error.
+ */
+
+#[derive(Clone, Copy)]
+struct TraceEntry {
+    reg: Registers,
+    pcmem: [Byte; PCMEM_LEN],
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    // both ends included
+    start: Word,
+    end: Word,
+    // any bank when None
+    bank: Option<usize>,
+    // the last entries when tracing to a ring, None writes every line right away
+    ring: Option<VecDeque<TraceEntry>>,
+    ring_size: usize,
+    // the last write that failed
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(out: W) -> Tracer {
+        Tracer {
+            out: Box::new(out),
+            start: 0x0000,
+            end: 0xFFFF,
+            bank: None,
+            ring: None,
+            ring_size: 0,
+            error: None,
+        }
+    }
+
+    // only traces the instructions with start <= PC <= end
+    pub fn set_pc_range(&mut self, start: Word, end: Word) {
+        self.start = start.min(end);
+        self.end = start.max(end);
+    }
+
+    pub fn set_bank(&mut self, bank: Option<usize>) {
+        self.bank = bank;
+    }
+
+    // keeps the last size lines until dump, None (or 0) goes back to writing them all
+    pub fn set_ring_size(&mut self, size: Option<usize>) {
+        match size {
+            Some(size) if size > 0 => {
+                self.ring_size = size;
+                self.ring = Some(VecDeque::with_capacity(size));
+            }
+            _ => {
+                self.dump();
+                self.ring = None;
+            }
+        }
+    }
+
+    pub fn ring_size(&self) -> Option<usize> {
+        self.ring.as_ref().map(|_| self.ring_size)
+    }
+
+    // called by the cpu right before it runs the instruction at PC
+    pub fn record(&mut self, reg: &Registers, mmu: &Mmu) {
+        let pc = reg.pc;
+        if pc < self.start || pc > self.end {
+            return;
+        }
+        if self.bank.is_some_and(|bank| bank != mmu.bank_at(pc)) {
+            return;
+        }
+        let mut pcmem = [0; PCMEM_LEN];
+        for (i, byte) in pcmem.iter_mut().enumerate() {
+            *byte = mmu.peek_byte(pc.wrapping_add(i as Word));
+        }
+        let entry = TraceEntry { reg: *reg, pcmem };
+        match &mut self.ring {
+            Some(ring) => {
+                if ring.len() == self.ring_size {
+                    ring.pop_front();
+                }
+                ring.push_back(entry);
+            }
+            None => {
+                let result = writeln!(self.out, "{}", format_entry(&entry));
+                self.check(result);
+            }
+        }
+    }
+
+    // writes out and empties the ring, nothing to do when tracing every line
+    pub fn dump(&mut self) {
+        let Some(ring) = &mut self.ring else {
+            return;
+        };
+        let lines: Vec<String> = ring.drain(..).map(|entry| format_entry(&entry)).collect();
+        for line in lines {
+            let result = writeln!(self.out, "{}", line);
+            self.check(result);
+        }
+        let result = self.out.flush();
+        self.check(result);
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    // the error of the last write that failed, the lines it was writing are lost
+    pub fn last_error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+fn format_entry(entry: &TraceEntry) -> String {
+    let reg = &entry.reg;
+    let [a, f] = reg.af().to_be_bytes();
+    let pcmem: Vec<String> = entry
+        .pcmem
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        a,
+        f,
+        reg.b,
+        reg.c,
+        reg.d,
+        reg.e,
+        reg.h,
+        reg.l,
+        reg.sp,
+        reg.pc,
+        pcmem.join(",")
+    )
+}
@@ -0,0 +1,74 @@
+// common types and constants shared by the cpu, the mmu and the mbcs
+
+// the bus is 8 bit wide for data and 16 bit wide for addresses
+pub type Byte = u8;
+pub type Word = u16;
+
+// the whole 16 bit address space, see the memory map in mmu.rs
+pub const MEMORY_SIZE: usize = 0x10000;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+pub const MAX_RAM_BANKS: usize = 4;
+
+// memory some mappers carry besides or instead of the RAM the header declares, the header
+// needs their sizes to know how large the save file is
+pub const MBC2_RAM_SIZE: usize = 0x200;
+pub const MBC6_RAM_SIZE: usize = 0x8000;
+pub const MBC6_FLASH_SIZE: usize = 0x100000;
+pub const MBC7_EEPROM_SIZE: usize = 0x100;
+pub const TAMA5_RAM_SIZE: usize = 0x20;
+
+// smallest file that still holds a complete cartridge header (0x0100 - 0x014F)
+pub const ROM_HEADER_END: usize = 0x0150;
+
+// I/O registers, see https://gbdev.io/pandocs/Memory_Map.html#io-ranges
+pub const JOYPAD_REGISTER_ADDR: Word = 0xFF00;
+pub const SERIAL_DATA_ADDR: Word = 0xFF01;
+pub const SERIAL_CONTROL_ADDR: Word = 0xFF02;
+pub const DIVIDER_REGISTER_ADDR: Word = 0xFF04;
+pub const TIMER_ADDR: Word = 0xFF05;
+pub const TIMER_MODULATOR_ADDR: Word = 0xFF06;
+pub const TIMER_CONTROL_ADDR: Word = 0xFF07;
+pub const INTERRUPT_FLAG_ADDR: Word = 0xFF0F;
+pub const LCD_CONTROL_ADDR: Word = 0xFF40;
+pub const LCD_STATUS_ADDR: Word = 0xFF41;
+pub const SCROLL_Y_ADDR: Word = 0xFF42;
+pub const SCROLL_X_ADDR: Word = 0xFF43;
+pub const CURRENT_SCANLINE_ADDR: Word = 0xFF44;
+pub const LY_COMPARE_ADDR: Word = 0xFF45;
+pub const DMA_TRANSFER_ADDR: Word = 0xFF46;
+pub const BG_PALETTE_ADDR: Word = 0xFF47;
+pub const OBJ_PALETTE_0_ADDR: Word = 0xFF48;
+pub const OBJ_PALETTE_1_ADDR: Word = 0xFF49;
+pub const WINDOW_Y_ADDR: Word = 0xFF4A;
+pub const WINDOW_X_ADDR: Word = 0xFF4B;
+pub const SPEED_SWITCH_ADDR: Word = 0xFF4D;
+pub const VRAM_BANK_SELECT_ADDR: Word = 0xFF4F;
+pub const VRAM_DMA_SOURCE_HIGH_ADDR: Word = 0xFF51;
+pub const VRAM_DMA_SOURCE_LOW_ADDR: Word = 0xFF52;
+pub const VRAM_DMA_DESTINATION_HIGH_ADDR: Word = 0xFF53;
+pub const VRAM_DMA_DESTINATION_LOW_ADDR: Word = 0xFF54;
+pub const VRAM_DMA_TRANSFER_ADDR: Word = 0xFF55;
+pub const BG_PALETTE_INDEX_ADDR: Word = 0xFF68;
+pub const BG_PALETTE_DATA_ADDR: Word = 0xFF69;
+pub const OBJ_PALETTE_INDEX_ADDR: Word = 0xFF6A;
+pub const OBJ_PALETTE_DATA_ADDR: Word = 0xFF6B;
+pub const WRAM_BANK_SELECT_ADDR: Word = 0xFF70;
+pub const INTERRUPT_ENABLE_ADDR: Word = 0xFFFF;
+
+// 4194304 Hz / 59.7 frames per second
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+pub fn bit_set(value: &Byte, bit: u8) -> bool {
+    (value >> bit) & 1 == 1
+}
+
+// a large array straight on the heap, building it on the stack first would overflow the
+// smaller stacks of threads when the console gets moved around in debug builds
+pub fn boxed_array<T: Copy, const N: usize>(value: T) -> Box<[T; N]> {
+    match vec![value; N].into_boxed_slice().try_into() {
+        Ok(array) => array,
+        Err(_) => unreachable!(),
+    }
+}
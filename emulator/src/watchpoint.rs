@@ -0,0 +1,143 @@
+use std::cell::Cell;
+
+use crate::utils::*;
+
+/*
+ * Watchpoints on the bus. Every read_byte and write_byte of the mmu goes through check, so
+ * they see everything the cpu does: the fetches of the instructions, the I/O registers, the
+ * writes to 0x0000 - 0x7FFF that only reach the mbc and the accesses the ppu blocks.
+ *
+ * A watchpoint covers a range of addresses, optionally in a single bank only: the ROM bank
+ * for 4000 - 7FFF, the cartridge RAM bank for A000 - BFFF, the VRAM bank for 8000 - 9FFF and
+ * the WRAM bank for D000 - DFFF, see Mmu::bank_at.
+ *
+ * The first access that triggers is kept until someone takes it, which is how the debugger
+ * finds out an instruction hit a watchpoint.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // reads and writes
+    Access,
+    // only writes of this value
+    Value(Byte),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    // both ends included
+    pub start: Word,
+    pub end: Word,
+    // any bank when None
+    pub bank: Option<usize>,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub addr: Word,
+    pub bank: usize,
+    pub access: Access,
+    // what was read or written
+    pub value: Byte,
+}
+
+impl Watchpoint {
+    fn triggers(&self, access: Access, value: Byte) -> bool {
+        match (self.kind, access) {
+            (WatchKind::Access, _) => true,
+            (WatchKind::Read, Access::Read) => true,
+            (WatchKind::Write, Access::Write) => true,
+            (WatchKind::Value(expected), Access::Write) => value == expected,
+            _ => false,
+        }
+    }
+}
+
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+    // reads only borrow the mmu, hence the cell
+    hit: Cell<Option<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            watchpoints: Vec::new(),
+            next_id: 0,
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // returns the id of the new watchpoint
+    pub fn add(&mut self, start: Word, end: Word, bank: Option<usize>, kind: WatchKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            start: start.min(end),
+            end: start.max(end),
+            bank,
+            kind,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // the first access that triggered a watchpoint since the last call
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    // bank_at is only asked for the bank when a watchpoint needs it
+    pub fn check<F: Fn(Word) -> usize>(&self, addr: Word, access: Access, value: Byte, bank_at: F) {
+        if self.watchpoints.is_empty() || self.hit.get().is_some() {
+            return;
+        }
+        let bank = bank_at(addr);
+        let watchpoint = self.watchpoints.iter().find(|watchpoint| {
+            (watchpoint.start..=watchpoint.end).contains(&addr)
+                && watchpoint.bank.is_none_or(|expected| expected == bank)
+                && watchpoint.triggers(access, value)
+        });
+        if let Some(watchpoint) = watchpoint {
+            self.hit.set(Some(WatchHit {
+                id: watchpoint.id,
+                addr,
+                bank,
+                access,
+                value,
+            }));
+        }
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Watchpoints {
+        Watchpoints::new()
+    }
+}
@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use yaregb::GameBoy;
+
+mod common;
+
+const MBC1_RAM_BATTERY: u8 = 0x03;
+const MBC3_TIMER_RAM_BATTERY: u8 = 0x10;
+
+// a cartridge with 8 KiB of battery backed RAM, running code from 0150
+fn cartridge(cartridge_type: u8, code: &[u8]) -> GameBoy {
+    let mut rom = common::test_rom_bytes(code);
+    rom[0x147] = cartridge_type;
+    rom[0x149] = 0x02;
+    common::fix_header_checksum(&mut rom);
+    GameBoy::from_rom_bytes(rom).unwrap()
+}
+
+//    LD A,0A / LD (0000),A    enables the RAM
+//    LD A,42 / LD (A000),A
+//    JR $
+fn battery_gameboy() -> GameBoy {
+    cartridge(
+        MBC1_RAM_BATTERY,
+        &[
+            0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x42, 0xEA, 0x00, 0xA0, 0x18, 0xFE,
+        ],
+    )
+}
+
+// a save file of its own in the target directory, without what a previous run left
+fn save_path(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.sav", name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn save_errors_go_to_the_callback() {
+    let mut gameboy = battery_gameboy();
+    // loading a missing file is fine, writing into a missing directory is not
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing/battery.sav");
+    gameboy.set_save_path(path).unwrap();
+    gameboy.set_save_interval(1);
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let sink = errors.clone();
+    gameboy.set_save_error_callback(move |err| sink.borrow_mut().push(err.to_string()));
+
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert!(errors.borrow().is_empty());
+    // the RAM is dirty from now on and every failed save is retried
+    gameboy.step_instruction();
+    gameboy.step_instruction();
+    assert_eq!(errors.borrow().len(), 2);
+
+    // save reports to its caller, drop to the callback
+    assert!(gameboy.save().is_err());
+    assert_eq!(errors.borrow().len(), 2);
+    drop(gameboy);
+    assert_eq!(errors.borrow().len(), 3);
+}
+
+#[test]
+fn round_trip() {
+    let path = save_path("round_trip");
+    let mut gameboy = battery_gameboy();
+    gameboy.set_save_path(&path).unwrap();
+    gameboy.set_save_interval(0);
+    for _ in 0..6 {
+        gameboy.step_instruction();
+    }
+    assert!(!path.exists());
+    drop(gameboy);
+
+    let save = fs::read(&path).unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!(save[0], 0x42);
+    assert!(save[1..].iter().all(|&byte| byte == 0));
+
+    // LD A,0A / LD (0000),A / LD A,(A000) / JR $
+    let mut gameboy = cartridge(
+        MBC1_RAM_BATTERY,
+        &[0x3E, 0x0A, 0xEA, 0x00, 0x00, 0xFA, 0x00, 0xA0, 0x18, 0xFE],
+    );
+    gameboy.set_save_path(&path).unwrap();
+    assert_eq!(gameboy.mbc().unwrap().get_ext_ram()[0], 0x42);
+    for _ in 0..5 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(gameboy.registers().a, 0x42);
+}
+
+#[test]
+fn only_stored_bytes_make_a_save() {
+    // LD A,42 / LD (A000),A with the RAM still disabled / JR $
+    let path = save_path("only_stored_bytes");
+    let mut gameboy = cartridge(
+        MBC1_RAM_BATTERY,
+        &[0x3E, 0x42, 0xEA, 0x00, 0xA0, 0x18, 0xFE],
+    );
+    gameboy.set_save_path(&path).unwrap();
+    gameboy.set_save_interval(1);
+    for _ in 0..6 {
+        gameboy.step_instruction();
+    }
+    assert!(!gameboy.mmu().is_ext_ram_dirty());
+    drop(gameboy);
+    assert!(!path.exists());
+}
+
+#[test]
+fn rtc_catches_up_with_the_saved_timestamp() {
+    let path = save_path("rtc_footer");
+    let mut save = vec![0; 0x2000];
+    save[0] = 0x42;
+    // running 5 days 03:20:10, latched all zero, saved 2:30:05 ago
+    for register in [10u32, 20, 3, 5, 0, 0, 0, 0, 0, 0] {
+        save.extend_from_slice(&register.to_le_bytes());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    save.extend_from_slice(&(now - 9005).to_le_bytes());
+    fs::write(&path, &save).unwrap();
+
+    let mut gameboy = cartridge(MBC3_TIMER_RAM_BATTERY, &[0x18, 0xFE]);
+    gameboy.set_save_path(&path).unwrap();
+    let mmu = gameboy.mmu_mut();
+    mmu.write_byte(0x0000, 0x0A);
+    assert_eq!(mmu.read_byte(0xA000), 0x42);
+    // the latched registers come back as they were saved
+    mmu.write_byte(0x4000, 0x08);
+    assert_eq!(mmu.read_byte(0xA000), 0x00);
+
+    mmu.write_byte(0x6000, 0x00);
+    mmu.write_byte(0x6000, 0x01);
+    let mut clock = [0; 5];
+    for (register, value) in clock.iter_mut().enumerate() {
+        mmu.write_byte(0x4000, 0x08 + register as u8);
+        *value = mmu.read_byte(0xA000);
+    }
+    // a second may pass between writing the file and loading it
+    assert!(clock[0] == 15 || clock[0] == 16, "seconds {}", clock[0]);
+    assert_eq!(clock[1..], [50, 5, 5, 0]);
+}
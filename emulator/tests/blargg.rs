@@ -0,0 +1,123 @@
+use std::env;
+use std::path::PathBuf;
+
+use yaregb::testrom::*;
+
+mod common;
+
+// blargg's test ROMs are not redistributed with the crate, they are looked up in
+// $YAREGB_TEST_ROMS/blargg or tests/roms/blargg. the suites only run with --ignored, a suite
+// whose ROMs are missing is skipped
+
+fn roms_root() -> PathBuf {
+    match env::var_os("YAREGB_TEST_ROMS") {
+        Some(root) => PathBuf::from(root).join("blargg"),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/blargg"),
+    }
+}
+
+fn run_suite(suite: &str) {
+    let roms = suite_roms(roms_root(), suite);
+    if roms.is_empty() {
+        eprintln!(
+            "skipping {}: no ROMs under {}",
+            suite,
+            roms_root().display()
+        );
+        return;
+    }
+
+    let mut failures = Vec::new();
+    for rom in roms {
+        let report = run_blargg(&rom, DEFAULT_CYCLE_BUDGET).unwrap();
+        println!("{:<8} {}", report.outcome.to_string(), rom.display());
+        if !report.passed() {
+            failures.push(format!(
+                "{}: {}\n{}",
+                rom.display(),
+                report.outcome,
+                report.output
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// prints the text at 0170 through the serial port, one byte at a time, then loops:
+//
+//    0150  LD HL,0170
+//    0153  LD A,(HL+) / AND A / JR Z,$
+//    0157  LDH (01),A / LD A,81 / LDH (02),A     SB, then start the transfer
+//    015D  LDH A,(02) / BIT 7,A / JR NZ,015D     until it is done
+//    0163  JR 0153
+fn serial_rom(name: &str, text: &str) -> PathBuf {
+    let mut code = vec![
+        0x21, 0x70, 0x01, 0x2A, 0xA7, 0x28, 0xFE, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0xF0, 0x02,
+        0xCB, 0x7F, 0x20, 0xFA, 0x18, 0xEE,
+    ];
+    code.resize(0x20, 0);
+    code.extend_from_slice(text.as_bytes());
+    code.push(0);
+    common::write_test_rom(name, &code)
+}
+
+#[test]
+fn serial_passed() {
+    let report = run_blargg(
+        serial_rom("blargg_passed", "cpu_instrs\n\nPassed"),
+        DEFAULT_CYCLE_BUDGET,
+    )
+    .unwrap();
+    assert_eq!(report.outcome, TestOutcome::Passed);
+    assert_eq!(report.output, "cpu_instrs\n\nPassed");
+}
+
+#[test]
+fn serial_failed() {
+    let report = run_blargg(
+        serial_rom("blargg_failed", "01:05\nFailed"),
+        DEFAULT_CYCLE_BUDGET,
+    )
+    .unwrap();
+    assert_eq!(report.outcome, TestOutcome::Failed);
+    assert_eq!(report.output, "01:05\nFailed");
+}
+
+#[test]
+fn timeout() {
+    let rom = serial_rom("blargg_timeout", "still running");
+    let report = run_blargg(rom, 4194304).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Timeout);
+    assert_eq!(report.output, "still running");
+    assert!(report.cycles >= 4194304);
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs"]
+fn cpu_instrs() {
+    run_suite("cpu_instrs");
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs"]
+fn instr_timing() {
+    run_suite("instr_timing");
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs"]
+fn mem_timing() {
+    run_suite("mem_timing");
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs"]
+fn halt_bug() {
+    run_suite("halt_bug");
+}
+
+#[test]
+#[ignore = "needs blargg's test ROMs"]
+fn dmg_sound() {
+    run_suite("dmg_sound");
+}
@@ -0,0 +1,51 @@
+// helpers shared by the integration tests, not every test file uses all of them
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+// the boot ROM refuses cartridges with a bad header checksum and so does Rom::from_bytes
+pub fn fix_header_checksum(rom: &mut [u8]) {
+    rom[0x14D] = rom[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+}
+
+// a 32 KiB ROM only cartridge jumping from the entry point to code at 0150
+pub fn test_rom_bytes(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x150 + code.len()].copy_from_slice(code);
+    fix_header_checksum(&mut rom);
+    rom
+}
+
+// the same, written to the target directory
+pub fn write_test_rom(name: &str, code: &[u8]) -> PathBuf {
+    write_rom(name, &test_rom_bytes(code))
+}
+
+pub fn write_rom(name: &str, rom: &[u8]) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.gb", name));
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+// the program the debugger and GDB tests run:
+//
+//    0100  JP 0150
+//    0150  LD A,12
+//    0152  CALL 0200
+//    0155  LD B,34
+//    0157  JR 0157
+//    0200  LD C,56
+//    0202  RET
+//    0300  LD (2000),A
+//    0303  LD A,(C000)
+//    0306  JR 0306
+pub fn test_rom(name: &str) -> PathBuf {
+    let mut rom = test_rom_bytes(&[0x3E, 0x12, 0xCD, 0x00, 0x02, 0x06, 0x34, 0x18, 0xFE]);
+    rom[0x200..0x203].copy_from_slice(&[0x0E, 0x56, 0xC9]);
+    rom[0x300..0x308].copy_from_slice(&[0xEA, 0x00, 0x20, 0xFA, 0x00, 0xC0, 0x18, 0xFE]);
+    write_rom(name, &rom)
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+mod common;
+
+// drives the yaregb-dbg binary with scripts on stdin, against common::test_rom
+
+// an MBC5 cartridge with 4 RAM banks, running this from 0150:
+//
+//    LD A,0A / LD (0000),A    enables the RAM
+//    LD A,02 / LD (4000),A    selects RAM bank 2
+//    LD A,AA / LD (A000),A
+//    LD A,01 / LD (4000),A
+//    LD A,BB / LD (A000),A
+//    JR $
+fn cart_ram_rom(name: &str) -> PathBuf {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x147] = 0x1B;
+    rom[0x149] = 0x03;
+    rom[0x150..0x16A].copy_from_slice(&[
+        0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x02, 0xEA, 0x00, 0x40, 0x3E, 0xAA, 0xEA, 0x00, 0xA0,
+        0x3E, 0x01, 0xEA, 0x00, 0x40, 0x3E, 0xBB, 0xEA, 0x00, 0xA0, 0x18,
+    ]);
+    rom[0x16A] = 0xFE;
+    common::fix_header_checksum(&mut rom);
+    common::write_rom(name, &rom)
+}
+
+fn run_script(name: &str, script: &str) -> String {
+    run_rom(common::test_rom(name), script)
+}
+
+fn run_rom(rom: PathBuf, script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_yaregb-dbg"))
+        .arg(rom)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn breakpoint_and_registers() {
+    let output = run_script(
+        "breakpoint_and_registers",
+        "break 0155\ncontinue\nregisters\nquit\n",
+    );
+    assert_eq!(
+        output,
+        "breakpoint 0 at 0155\n\
+         breakpoint 0 at 00:0155  ld b, $34\n\
+         AF=12B0 BC=0056 DE=00D8 HL=014D SP=FFFE PC=0155\n\
+         Z=1 N=0 H=1 C=1 IME=0 HALT=0 at 00:0155\n"
+    );
+}
+
+#[test]
+fn bank_qualified_breakpoint() {
+    let output = run_script(
+        "bank_qualified_breakpoint",
+        "b 01:0155\nc 100\nb $00:$0157\nbl\nc\n",
+    );
+    assert!(output.contains("stopped at 00:0157  jr $0157\n"));
+    assert!(output.contains("1    00:0157\n"));
+    assert!(output.ends_with("breakpoint 1 at 00:0157  jr $0157\n"));
+}
+
+#[test]
+fn step_next_and_finish() {
+    let output = run_script("step_next_and_finish", "step 2\nnext\nregisters\nstep\n");
+    assert_eq!(
+        output,
+        "stopped at 00:0152  call $0200\n\
+         stopped at 00:0155  ld b, $34\n\
+         AF=12B0 BC=0056 DE=00D8 HL=014D SP=FFFE PC=0155\n\
+         Z=1 N=0 H=1 C=1 IME=0 HALT=0 at 00:0155\n\
+         stopped at 00:0157  jr $0157\n"
+    );
+
+    // into the call this time, and back out of it
+    let output = run_script("step_into", "step 3\nr\nfinish\nr\n");
+    assert!(output.contains("PC=0200"));
+    assert!(output.contains("stopped at 00:0155  ld b, $34\n"));
+    assert!(output.contains("BC=0056"));
+}
+
+#[test]
+fn step_zero_stays() {
+    let output = run_script("step_zero_stays", "step 0\nr\n");
+    assert_eq!(
+        output,
+        "stopped at 00:0100  jp $0150\n\
+         AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100\n\
+         Z=1 N=0 H=1 C=1 IME=0 HALT=0 at 00:0100\n"
+    );
+}
+
+#[test]
+fn call_stack_does_not_trigger_watchpoints() {
+    // the debugger looks at the return address the CALL pushed, only the RET reads it
+    let output = run_script(
+        "call_stack_watchpoints",
+        "watch read fffc\nstep 3\nstep\nbt\nstep\n",
+    );
+    assert_eq!(
+        output,
+        "watchpoint 0: read of FFFC\n\
+         stopped at 00:0200  ld c, $56\n\
+         stopped at 00:0202  ret\n\
+         #0  00:0202\n\
+         #1  00:0155\n\
+         watchpoint 0: read of $55 from 00:FFFC by 00:0202\n\
+         stopped at 00:0155  ld b, $34\n"
+    );
+}
+
+#[test]
+fn write_and_examine() {
+    let output = run_script(
+        "write_and_examine",
+        "write c000 41 42 0 ff\nx c000 4\nw a 99\nw f 0\nw hl 0xC0DE\nr\nw a 100\nw zz 1\n",
+    );
+    assert!(output.contains("C000  41 42 00 FF"));
+    assert!(output.contains("  AB..\n"));
+    assert!(output.contains("AF=9900 BC=0013 DE=00D8 HL=C0DE"));
+    assert!(output.contains("Z=0 N=0 H=0 C=0"));
+    assert!(output.contains("error: 100 is larger than FF\n"));
+    assert!(output.contains("error: zz is neither a label nor an address\n"));
+}
+
+#[test]
+fn examine_during_oam_dma() {
+    // LD A,C0 / LDH (46),A starts a transfer, then NOP / NOP / JR $
+    let rom = common::write_test_rom(
+        "examine_during_oam_dma",
+        &[0x3E, 0xC0, 0xE0, 0x46, 0x00, 0x00, 0x18, 0xFE],
+    );
+    let output = run_rom(rom, "break 0155\ncontinue\nstep\nx 0150 4\n");
+    // the step runs into the transfer, the cpu fetches 0xFF from the ROM: RST 38
+    assert!(output.contains("stopped at 00:0038"));
+    assert!(output.contains("0150  3E C0 E0 46"));
+}
+
+#[test]
+fn disassemble() {
+    let listing = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("bank0.asm");
+    let output = run_script(
+        "disassemble",
+        &format!(
+            "dis\ndis 00:0200 2\ndis 01:0200\ndisassemble-bank 0 {}\ndisassemble-bank 2 x\n",
+            listing.display()
+        ),
+    );
+    assert_eq!(
+        output,
+        format!(
+            "00:0100  C3 50 01  jp $0150\n\
+             00:0103  00        nop\n\
+             00:0104  00        nop\n\
+             00:0105  00        nop\n\
+             00:0106  00        nop\n\
+             00:0107  00        nop\n\
+             00:0108  00        nop\n\
+             00:0109  00        nop\n\
+             00:0200  0E 56     ld c, $56\n\
+             00:0202  C9        ret\n\
+             error: 01:0200 is not in ROM, bank 0 is at 0000-3FFF and the others at 4000-7FFF\n\
+             bank 00 written to {}\n\
+             error: the ROM has no bank 02\n",
+            listing.display()
+        )
+    );
+
+    let listing = fs::read_to_string(listing).unwrap();
+    assert!(listing.starts_with("00:0000  00        nop\n"));
+    assert!(listing.contains("00:0152  CD 00 02  call $0200\n00:0155  06 34     ld b, $34\n"));
+    assert!(listing.ends_with("00:3FFF  00        nop\n"));
+}
+
+#[test]
+fn symbols() {
+    let sym = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("symbols.sym");
+    fs::write(
+        sym,
+        "; File generated by rgblink\n00:0150 Main\n00:0157 Main.loop\n00:0200 Helper\n00:c000 wBuffer\n",
+    )
+    .unwrap();
+    let output = run_script(
+        "symbols",
+        "break Helper\ncontinue\nbacktrace\ndis Main 4\nfinish\nbt\nwrite wBuffer 7\nx wBuffer 1\nb Nope\n",
+    );
+    assert_eq!(
+        output,
+        "breakpoint 0 at 00:0200 (Helper)\n\
+         breakpoint 0 at 00:0200 (Helper)  ld c, $56\n\
+         #0  00:0200 (Helper)\n\
+         #1  00:0155 (Main+$05)\n\
+         Main:\n\
+         00:0150  3E 12     ld a, $12\n\
+         00:0152  CD 00 02  call Helper\n\
+         00:0155  06 34     ld b, $34\n\
+         Main.loop:\n\
+         00:0157  18 FE     jr Main.loop\n\
+         stopped at 00:0155 (Main+$05)  ld b, $34\n\
+         #0  00:0155 (Main+$05)\n\
+         C000  07                                               .\n\
+         error: Nope is neither a label nor an address\n"
+    );
+}
+
+#[test]
+fn watchpoints() {
+    let output = run_script(
+        "watchpoints",
+        "wa write fffc-fffd\nc\nunwatch 0\n\
+         write c000 9\nw pc 0300\nw a 5\n\
+         watch write 2000-3FFF 7\nwatch write 2000-3FFF 5\nwatch read c000\nwl\nc\nc\n\
+         watch access 0100-00FF\nwatch read c000 1\n",
+    );
+    assert_eq!(
+        output,
+        "watchpoint 0: write to FFFC-FFFD\n\
+         watchpoint 0: write of $01 to 00:FFFD by 00:0152\n\
+         stopped at 00:0200  ld c, $56\n\
+         deleted watchpoint 0\n\
+         watchpoint 1: write of $07 to 2000-3FFF\n\
+         watchpoint 2: write of $05 to 2000-3FFF\n\
+         watchpoint 3: read of C000\n\
+         1    write of $07 to 2000-3FFF\n\
+         2    write of $05 to 2000-3FFF\n\
+         3    read of C000\n\
+         watchpoint 2: write of $05 to 00:2000 by 00:0300\n\
+         stopped at 00:0303  ld a, [$C000]\n\
+         watchpoint 3: read of $09 from 00:C000 by 00:0303\n\
+         stopped at 00:0306  jr $0306\n\
+         error: 0100-00FF ends before it starts\n\
+         error: only a write watchpoint takes a value\n"
+    );
+}
+
+#[test]
+fn cart_ram_watchpoint() {
+    let output = run_rom(
+        cart_ram_rom("cart_ram_watchpoint"),
+        "watch write 02:A000\nc\nr\nc 100\n",
+    );
+    assert_eq!(
+        output,
+        "watchpoint 0: write to 02:A000\n\
+         watchpoint 0: write of $AA to 02:A000 by 00:015C\n\
+         stopped at 00:015F  ld a, $01\n\
+         AF=AAB0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=015F\n\
+         Z=1 N=0 H=1 C=1 IME=0 HALT=0 at 00:015F\n\
+         stopped at 00:0169  jr $0169\n"
+    );
+}
+
+#[test]
+fn trace() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let all = dir.join("trace_all.log");
+    let ring = dir.join("trace_ring.log");
+    let filtered = dir.join("trace_filtered.log");
+    let output = run_script(
+        "trace",
+        &format!(
+            "trace {}\nstep 3\ntrace {} pc 0200-02FF\nstep 3\ntrace dump\n\
+             b 0157\nw pc 0150\ntrace {} ring 2\nc\ntrace off\ntrace {} ring\ntrace {} speed 2\n",
+            all.display(),
+            filtered.display(),
+            ring.display(),
+            ring.display(),
+            ring.display()
+        ),
+    );
+    assert_eq!(
+        output,
+        format!(
+            "tracing to {}\n\
+             stopped at 00:0200  ld c, $56\n\
+             tracing to {}\n\
+             stopped at 00:0157  jr $0157\n\
+             error: the trace has no ring, it is written as it goes\n\
+             breakpoint 0 at 0157\n\
+             tracing to {}\n\
+             breakpoint 0 at 00:0157  jr $0157\n\
+             tracing stopped\n\
+             error: ring needs a value\n\
+             error: unknown trace option speed\n",
+            all.display(),
+            filtered.display(),
+            ring.display()
+        )
+    );
+
+    assert_eq!(
+        fs::read_to_string(all).unwrap(),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C3,50,01,00\n\
+         A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,12,CD,00\n\
+         A:12 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:CD,00,02,06\n"
+    );
+    let filtered = fs::read_to_string(filtered).unwrap();
+    assert_eq!(filtered.lines().count(), 2);
+    assert!(filtered.contains("SP:FFFC PC:0200 PCMEM:0E,56,C9,00\n"));
+    assert!(filtered.ends_with("SP:FFFC PC:0202 PCMEM:C9,00,00,00\n"));
+    // the breakpoint wrote out the last two instructions before it
+    let ring = fs::read_to_string(ring).unwrap();
+    assert_eq!(ring.lines().count(), 2);
+    assert!(ring.contains("PC:0202 PCMEM:C9,00,00,00\n"));
+    assert!(ring.ends_with("PC:0155 PCMEM:06,34,18,FE\n"));
+}
+
+#[test]
+fn trace_doctor_mode() {
+    // LDH A,(44) then JR $
+    let rom = common::write_test_rom("trace_doctor_mode", &[0xF0, 0x44, 0x18, 0xFE]);
+    let log = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("trace_doctor_mode.log");
+    let output = run_rom(
+        rom,
+        &format!(
+            "trace {} doctor\nstep 3\nr\ntrace off\nw pc 0150\nstep\nr\n",
+            log.display()
+        ),
+    );
+    let registers: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("AF="))
+        .collect();
+    assert!(registers[0].starts_with("AF=90"));
+    // LY is back to what the PPU says once the trace stops
+    assert!(!registers[1].starts_with("AF=90"));
+    assert!(fs::read_to_string(&log)
+        .unwrap()
+        .ends_with("A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:18,FE,00,00\n"));
+}
@@ -0,0 +1,112 @@
+use yaregb::disasm::*;
+use yaregb::symbols::SymbolTable;
+use yaregb::watchpoint::WatchKind;
+use yaregb::GameBoy;
+
+mod common;
+
+// disassembles bytes placed at addr
+fn text(addr: u16, bytes: &[u8]) -> String {
+    decode(0, addr, bytes.len(), |at| {
+        bytes[at.wrapping_sub(addr) as usize]
+    })
+    .text
+}
+
+#[test]
+fn loads() {
+    assert_eq!(text(0, &[0x3E, 0x12]), "ld a, $12");
+    assert_eq!(text(0, &[0x36, 0xFF]), "ld [hl], $FF");
+    assert_eq!(text(0, &[0x78]), "ld a, b");
+    assert_eq!(text(0, &[0x22]), "ld [hl+], a");
+    assert_eq!(text(0, &[0x3A]), "ld a, [hl-]");
+    assert_eq!(text(0, &[0x1A]), "ld a, [de]");
+    assert_eq!(text(0, &[0x21, 0x34, 0x12]), "ld hl, $1234");
+    assert_eq!(text(0, &[0xEA, 0x00, 0xC0]), "ld [$C000], a");
+    assert_eq!(text(0, &[0x08, 0xFE, 0xFF]), "ld [$FFFE], sp");
+    assert_eq!(text(0, &[0xE0, 0x40]), "ldh [$FF40], a");
+    assert_eq!(text(0, &[0xF0, 0x44]), "ldh a, [$FF44]");
+    assert_eq!(text(0, &[0xE2]), "ldh [c], a");
+    assert_eq!(text(0, &[0xF8, 0x05]), "ld hl, sp+$05");
+    assert_eq!(text(0, &[0xF8, 0xFD]), "ld hl, sp-$03");
+    assert_eq!(text(0, &[0xF1]), "pop af");
+    assert_eq!(text(0, &[0xC5]), "push bc");
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(text(0, &[0x80]), "add a, b");
+    assert_eq!(text(0, &[0x9E]), "sbc a, [hl]");
+    assert_eq!(text(0, &[0xAF]), "xor a, a");
+    assert_eq!(text(0, &[0xFE, 0x90]), "cp a, $90");
+    assert_eq!(text(0, &[0x34]), "inc [hl]");
+    assert_eq!(text(0, &[0x0B]), "dec bc");
+    assert_eq!(text(0, &[0x39]), "add hl, sp");
+    assert_eq!(text(0, &[0xE8, 0xFE]), "add sp, -$02");
+    assert_eq!(text(0, &[0xE8, 0x10]), "add sp, $10");
+}
+
+#[test]
+fn jumps_resolve_their_target() {
+    let jr = decode(0, 0x0157, 2, |addr| [0x18, 0xFE][(addr - 0x0157) as usize]);
+    assert_eq!((jr.text.as_str(), jr.target), ("jr $0157", Some(0x0157)));
+    assert_eq!(text(0x4000, &[0x20, 0x10]), "jr nz, $4012");
+    assert_eq!(text(0, &[0xC3, 0x50, 0x01]), "jp $0150");
+    assert_eq!(text(0, &[0xDA, 0x00, 0x40]), "jp c, $4000");
+    assert_eq!(text(0, &[0xE9]), "jp hl");
+    assert_eq!(text(0, &[0xCC, 0x00, 0x02]), "call z, $0200");
+    assert_eq!(text(0, &[0xEF]), "rst $28");
+    assert_eq!(text(0, &[0xD0]), "ret nc");
+    assert_eq!(text(0, &[0xD9]), "reti");
+}
+
+#[test]
+fn cb_prefixed() {
+    assert_eq!(text(0, &[0xCB, 0x00]), "rlc b");
+    assert_eq!(text(0, &[0xCB, 0x37]), "swap a");
+    assert_eq!(text(0, &[0xCB, 0x7E]), "bit 7, [hl]");
+    assert_eq!(text(0, &[0xCB, 0x87]), "res 0, a");
+    assert_eq!(text(0, &[0xCB, 0xFF]), "set 7, a");
+}
+
+#[test]
+fn data_bytes() {
+    let illegal = decode(0, 0, 3, |_| 0xD3);
+    assert_eq!((illegal.text.as_str(), illegal.len()), ("db $D3", 1));
+    // a CALL cut short by the end of what can be read
+    let cut = decode(0, 0x7FFE, 2, |_| 0xCD);
+    assert_eq!((cut.text.as_str(), cut.len()), ("db $CD", 1));
+}
+
+#[test]
+fn every_opcode_decodes() {
+    for opcode in 0..=0xFF {
+        let instruction = decode(0, 0, MAX_INSTRUCTION_LEN, |addr| match addr {
+            0 => opcode,
+            _ => 0x00,
+        });
+        assert!(!instruction.text.is_empty());
+        assert!((1..=MAX_INSTRUCTION_LEN).contains(&instruction.len()));
+    }
+}
+
+#[test]
+fn reads_through_oam_dma() {
+    // LD A,C0 / LDH (46),A starts a transfer, then JR $
+    let rom = common::test_rom_bytes(&[0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE]);
+    let mut gameboy = GameBoy::from_rom_bytes(rom).unwrap();
+    gameboy
+        .mmu_mut()
+        .watchpoints_mut()
+        .add(0x0150, 0x0157, None, WatchKind::Read);
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert!(gameboy.mmu().dma().is_active());
+    gameboy.mmu().watchpoints().take_hit();
+
+    let mmu = gameboy.mmu();
+    let instruction = disassemble(mmu, &SymbolTable::new(), 0x0150);
+    assert_eq!(instruction.text, "ld a, $C0");
+    assert!(mmu.watchpoints().take_hit().is_none());
+}
@@ -0,0 +1,159 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+mod common;
+
+// talks the GDB remote protocol to yaregb-dbg --gdb, against common::test_rom
+
+struct Client {
+    child: Child,
+    stream: TcpStream,
+    no_ack: bool,
+}
+
+impl Client {
+    fn connect(name: &str) -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_yaregb-dbg"))
+            .args(["--gdb", "0"])
+            .arg(common::test_rom(name))
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line.trim().strip_prefix("listening on ").unwrap();
+        Client {
+            child,
+            stream: TcpStream::connect(addr).unwrap(),
+            no_ack: false,
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        if !self.no_ack {
+            assert_eq!(self.read_byte(), b'+');
+        }
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(format!("{:02x}", sum).as_bytes(), checksum);
+        if !self.no_ack {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.reply()
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut client = Client::connect("gdb_registers_and_memory");
+    assert!(client
+        .request("qSupported:swbreak+")
+        .contains("qXfer:features:read+"));
+    let xml = client.request("qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with("l<?xml"));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>"#));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("g"), "01b0001300d8014dfeff0001");
+    assert_eq!(client.request("p8"), "feff");
+
+    assert_eq!(client.request("P2=ab"), "OK");
+    assert_eq!(client.request("P9=0002"), "OK");
+    assert_eq!(client.request("p9"), "0002");
+    assert_eq!(client.request("G12b0345600d8014dfeff5001"), "OK");
+    assert_eq!(client.request("g"), "12b0345600d8014dfeff5001");
+    assert_eq!(client.request("P9=02"), "E01");
+
+    assert_eq!(client.request("m150,4"), "3e12cd00");
+    assert_eq!(client.request("Mc000,3:414200"), "OK");
+    assert_eq!(client.request("mc000,3"), "414200");
+    // }] stands for }
+    client.send("Xc002,2:}]*");
+    assert_eq!(client.reply(), "OK");
+    assert_eq!(client.request("mc000,4"), "41427d2a");
+    assert_eq!(client.request("mfffe,8"), "0000");
+    assert_eq!(client.request("Mc000,2:41"), "E01");
+
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.no_ack = true;
+    assert_eq!(client.request("qAttached"), "1");
+    assert_eq!(client.request("D"), "OK");
+    assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let mut client = Client::connect("gdb_breakpoints_and_watchpoints");
+    assert_eq!(client.request("Z0,200,1"), "OK");
+    assert_eq!(client.request("Z1,157,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p9"), "0002");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p9"), "0202");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p9"), "5701");
+    assert_eq!(client.request("z0,200,1"), "OK");
+    assert_eq!(client.request("z0,200,1"), "E01");
+    assert_eq!(client.request("z1,157,1"), "OK");
+
+    assert_eq!(client.request("Z2,2000,2000"), "OK");
+    assert_eq!(client.request("Z3,c000,1"), "OK");
+    assert_eq!(client.request("c300"), "T05watch:2000;");
+    assert_eq!(client.request("p9"), "0303");
+    assert_eq!(client.request("c"), "T05rwatch:c000;");
+    assert_eq!(client.request("?"), "T05rwatch:c000;");
+    assert_eq!(client.request("z2,2000,2000"), "OK");
+    assert_eq!(client.request("z3,c000,1"), "OK");
+
+    // the length is not cut to 16 bits, the whole address space is 0x10000 long
+    assert_eq!(client.request("Z4,0,10000"), "OK");
+    assert_eq!(client.request("z4,0,10000"), "OK");
+    assert_eq!(client.request("Z4,1,10000"), "E01");
+    assert_eq!(client.request("Z4,0,10001"), "E01");
+
+    // JR 0306 runs until the client interrupts it
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.request("p9"), "0603");
+    client.send("k");
+    assert!(client.child.wait().unwrap().success());
+}
+
+#[test]
+fn oversized_packet() {
+    let mut client = Client::connect("gdb_oversized_packet");
+    let packet = format!("Mc000,2001:{}", "00".repeat(0x2001));
+    assert_eq!(client.request(&packet), "E01");
+    // the stream is still in sync
+    assert_eq!(client.request("p9"), "0001");
+    client.send("k");
+    assert!(client.child.wait().unwrap().success());
+}
@@ -0,0 +1,76 @@
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use yaregb::serial::{LinkCable, LoopbackCable, TcpCable};
+use yaregb::GameBoy;
+
+mod common;
+
+// sends SB with SC, waits for the end of the transfer and keeps what came in in B:
+//
+//    LD A,sb / LDH (01),A / LD A,sc / LDH (02),A
+//    LDH A,(02) / BIT 7,A / JR NZ,-6
+//    LDH A,(01) / LD B,A / JR $
+fn link_gameboy(sb: u8, sc: u8) -> GameBoy {
+    let code = [
+        0x3E, sb, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA, 0xF0, 0x01,
+        0x47, 0x18, 0xFE,
+    ];
+    GameBoy::from_rom_bytes(common::test_rom_bytes(&code)).unwrap()
+}
+
+#[test]
+fn loopback_pair() {
+    let mut master = link_gameboy(0x42, 0x81);
+    let mut slave = link_gameboy(0x99, 0x80);
+    let (cable_a, cable_b) = LoopbackCable::pair();
+    master.set_link_cable(Box::new(cable_a));
+    slave.set_link_cable(Box::new(cable_b));
+
+    // the slave has to wait for the clock before the master starts
+    for _ in 0..20 {
+        slave.step_instruction();
+    }
+    for _ in 0..2000 {
+        master.step_instruction();
+        slave.step_instruction();
+    }
+    assert_eq!(master.registers().b, 0x99);
+    assert_eq!(slave.registers().b, 0x42);
+    // both ends request the serial interrupt
+    assert_eq!(master.mmu().read_byte(0xFF0F) & 0x08, 0x08);
+    assert_eq!(slave.mmu().read_byte(0xFF0F) & 0x08, 0x08);
+}
+
+fn tcp_pair() -> (TcpCable, TcpCable) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (
+        TcpCable::from_stream(server).unwrap(),
+        TcpCable::from_stream(client).unwrap(),
+    )
+}
+
+#[test]
+fn tcp_exchange() {
+    let (mut driver, mut receiver) = tcp_pair();
+    let waiting = thread::spawn(move || loop {
+        if let Some(data) = receiver.receive(0x99) {
+            return data;
+        }
+        thread::sleep(Duration::from_millis(1));
+    });
+    assert_eq!(driver.exchange(0x42), 0x99);
+    assert_eq!(waiting.join().unwrap(), 0x42);
+    assert!(driver.is_connected());
+}
+
+#[test]
+fn tcp_hang_up() {
+    let (mut driver, receiver) = tcp_pair();
+    drop(receiver);
+    assert_eq!(driver.exchange(0x42), 0xFF);
+    assert!(!driver.is_connected());
+}
@@ -0,0 +1,133 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use yaregb::testrom::*;
+
+mod common;
+
+// mooneye's test ROMs are not redistributed with the crate, they are looked up in
+// $YAREGB_TEST_ROMS/mooneye or tests/roms/mooneye. the suites only run with --ignored, a
+// directory whose ROMs are missing is skipped.
+//
+// many of these tests check hardware details the emulator does not have yet, so a failing
+// ROM does not fail the test: the results go to target/mooneye-<suite>.json, to be diffed
+// between commits. only a ROM that can't be loaded at all fails it
+
+fn roms_root() -> PathBuf {
+    match env::var_os("YAREGB_TEST_ROMS") {
+        Some(root) => PathBuf::from(root).join("mooneye"),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/mooneye"),
+    }
+}
+
+fn run_suite(suite: &str) {
+    let root = roms_root();
+    let roms = suite_roms(&root, suite);
+    if roms.is_empty() {
+        eprintln!("skipping {}: no ROMs under {}", suite, root.display());
+        return;
+    }
+
+    let reports: Vec<TestReport> = roms
+        .iter()
+        .map(|rom| run_mooneye(rom, DEFAULT_CYCLE_BUDGET).unwrap())
+        .collect();
+    for report in &reports {
+        println!("{:<8} {}", report.outcome.to_string(), report.rom.display());
+    }
+
+    let target = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let summary = target
+        .parent()
+        .unwrap_or(&target)
+        .join(format!("mooneye-{}.json", suite));
+    fs::write(&summary, json_summary(&reports, &root)).unwrap();
+    println!("summary written to {}", summary.display());
+}
+
+// loads the six registers, then LD B,B and JR $
+fn registers_rom(name: &str, registers: [u8; 6]) -> PathBuf {
+    let mut code = Vec::new();
+    for (opcode, value) in [0x06, 0x0E, 0x16, 0x1E, 0x26, 0x2E]
+        .into_iter()
+        .zip(registers)
+    {
+        code.extend_from_slice(&[opcode, value]);
+    }
+    code.extend_from_slice(&[0x40, 0x18, 0xFE]);
+    common::write_test_rom(name, &code)
+}
+
+#[test]
+fn fibonacci_passes() {
+    let rom = registers_rom("mooneye_pass", [3, 5, 8, 13, 21, 34]);
+    let report = run_mooneye(rom, DEFAULT_CYCLE_BUDGET).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Passed);
+    assert_eq!(report.output, "B=03 C=05 D=08 E=0D H=15 L=22");
+}
+
+#[test]
+fn forty_two_fails() {
+    let rom = registers_rom("mooneye_fail", [0x42; 6]);
+    let report = run_mooneye(rom, DEFAULT_CYCLE_BUDGET).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Failed);
+    assert_eq!(report.output, "B=42 C=42 D=42 E=42 H=42 L=42");
+}
+
+#[test]
+fn timeout() {
+    let rom = common::write_test_rom("mooneye_timeout", &[0x18, 0xFE]);
+    let report = run_mooneye(rom, 4194304).unwrap();
+    assert_eq!(report.outcome, TestOutcome::Timeout);
+    assert!(report.cycles >= 4194304);
+}
+
+#[test]
+fn unloadable_roms_are_errors() {
+    let passing = registers_rom("mooneye_json_pass", [3, 5, 8, 13, 21, 34]);
+    let mut broken = common::test_rom_bytes(&[0x18, 0xFE]);
+    broken[0x14D] ^= 0xFF;
+    let broken = common::write_rom("mooneye_json_broken", &broken);
+    let json = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mooneye_json.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_yaregb-test"))
+        .arg("--json")
+        .arg(&json)
+        .arg("--mooneye")
+        .arg(&passing)
+        .arg(&broken)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ERROR"), "{}", stdout);
+    assert!(stdout.contains("1 of 2 passed"), "{}", stdout);
+
+    let summary = fs::read_to_string(&json).unwrap();
+    assert!(summary.contains("\"passed\": 1,"), "{}", summary);
+    assert!(summary.contains("\"failed\": 1,"), "{}", summary);
+    let line = summary
+        .lines()
+        .find(|line| line.contains("mooneye_json_broken"))
+        .unwrap();
+    assert!(line.contains("\"outcome\": \"error\""), "{}", line);
+    assert!(
+        line.contains("\"error\": \"") && line.contains("checksum"),
+        "{}",
+        line
+    );
+}
+
+#[test]
+#[ignore = "needs mooneye's test ROMs"]
+fn acceptance() {
+    run_suite("acceptance");
+}
+
+#[test]
+#[ignore = "needs mooneye's test ROMs"]
+fn emulator_only() {
+    run_suite("emulator-only");
+}
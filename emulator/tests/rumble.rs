@@ -0,0 +1,45 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use yaregb::GameBoy;
+
+mod common;
+
+// writes each value to the MBC5 RAM bank register, then JR $
+fn rumble_gameboy(cartridge_type: u8, values: &[u8]) -> GameBoy {
+    let mut code = Vec::new();
+    for &value in values {
+        // LD A,value / LD (4000),A
+        code.extend_from_slice(&[0x3E, value, 0xEA, 0x00, 0x40]);
+    }
+    code.extend_from_slice(&[0x18, 0xFE]);
+    let mut rom = common::test_rom_bytes(&code);
+    rom[0x147] = cartridge_type;
+    common::fix_header_checksum(&mut rom);
+    GameBoy::from_rom_bytes(rom).unwrap()
+}
+
+fn run_with_callback(mut gameboy: GameBoy, instructions: usize) -> Vec<bool> {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let log = calls.clone();
+    gameboy.set_rumble_callback(move |on| log.borrow_mut().push(on));
+    for _ in 0..instructions {
+        gameboy.step_instruction();
+    }
+    let calls = calls.borrow().clone();
+    calls
+}
+
+#[test]
+fn callback_fires_on_changes_only() {
+    // MBC5+RUMBLE, switching the RAM bank with the motor on does not call again
+    let gameboy = rumble_gameboy(0x1C, &[0x08, 0x09, 0x0F, 0x01, 0x00, 0x08]);
+    assert_eq!(run_with_callback(gameboy, 20), [true, false, true]);
+}
+
+#[test]
+fn no_rumble_without_the_motor() {
+    // plain MBC5, bit 3 is part of the RAM bank
+    let gameboy = rumble_gameboy(0x19, &[0x08, 0x00, 0x08]);
+    assert!(run_with_callback(gameboy, 10).is_empty());
+}
@@ -0,0 +1,42 @@
+use yaregb::symbols::SymbolTable;
+
+const SYM: &str = "; File generated by rgblink
+00:0150 Start
+01:4a3f PlayerUpdate
+01:4a50 PlayerUpdate.loop
+01:4a50 PlayerUpdate.alias ; two labels on the same address
+02:4000 Bank2
+00:c000 wPlayerX
+[labels]
+not a symbol
+";
+
+#[test]
+fn parse_and_lookup() {
+    let symbols = SymbolTable::parse(SYM);
+    assert_eq!(symbols.len(), 6);
+    assert_eq!(symbols.lookup("PlayerUpdate"), Some((1, 0x4A3F)));
+    assert_eq!(symbols.lookup("wPlayerX"), Some((0, 0xC000)));
+    assert_eq!(symbols.lookup("playerupdate"), None);
+    assert_eq!(symbols.label_at(1, 0x4A50), Some("PlayerUpdate.loop"));
+    assert_eq!(symbols.label_at(2, 0x4A3F), None);
+}
+
+#[test]
+fn nearest_label_and_offset() {
+    let symbols = SymbolTable::parse(SYM);
+    assert_eq!(symbols.describe(1, 0x4A3F).as_deref(), Some("PlayerUpdate"));
+    assert_eq!(
+        symbols.describe(1, 0x4A42).as_deref(),
+        Some("PlayerUpdate+$03")
+    );
+    assert_eq!(
+        symbols.describe(1, 0x4A53).as_deref(),
+        Some("PlayerUpdate.loop+$03")
+    );
+    assert_eq!(symbols.describe(0, 0x0200).as_deref(), Some("Start+$B0"));
+    // nothing before it in the bank, or only labels of another region
+    assert_eq!(symbols.describe(1, 0x4000), None);
+    assert_eq!(symbols.describe(0, 0xFF80), None);
+    assert_eq!(symbols.describe(0, 0x4000), None);
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use yaregb::trace::Tracer;
+use yaregb::GameBoy;
+
+mod common;
+
+// keeps what the tracer writes where the test can read it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 0100  JP 0150 / 0150  LD A,12 / 0152  INC B / 0153  then the code given
+fn test_gameboy(code: &[u8]) -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+    rom[0x150..0x153].copy_from_slice(&[0x3E, 0x12, 0x04]);
+    rom[0x153..0x153 + code.len()].copy_from_slice(code);
+    common::fix_header_checksum(&mut rom);
+    GameBoy::from_rom_bytes(rom).unwrap()
+}
+
+fn traced(gameboy: &mut GameBoy, setup: impl FnOnce(&mut Tracer)) -> SharedBuffer {
+    let buffer = SharedBuffer::default();
+    let mut tracer = Tracer::new(buffer.clone());
+    setup(&mut tracer);
+    gameboy.set_tracer(Some(tracer));
+    buffer
+}
+
+#[test]
+fn doctor_format() {
+    let mut gameboy = test_gameboy(&[0x18, 0xFE]);
+    let buffer = traced(&mut gameboy, |_| ());
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(
+        buffer.text(),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C3,50,01,00\n\
+         A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,12,04,18\n\
+         A:12 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:04,18,FE,00\n\
+         A:12 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0153 PCMEM:18,FE,00,00\n"
+    );
+}
+
+#[test]
+fn filters() {
+    let mut gameboy = test_gameboy(&[0x18, 0xFE]);
+    let buffer = traced(&mut gameboy, |tracer| tracer.set_pc_range(0x0150, 0x0152));
+    for _ in 0..6 {
+        gameboy.step_instruction();
+    }
+    let text = buffer.text();
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.contains("PC:0150"));
+    assert!(text.contains("PC:0152"));
+
+    // 0000 - 3FFF is always bank 0
+    let mut gameboy = test_gameboy(&[0x18, 0xFE]);
+    let buffer = traced(&mut gameboy, |tracer| tracer.set_bank(Some(1)));
+    for _ in 0..6 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(buffer.text(), "");
+}
+
+#[test]
+fn ring_dumps_on_lock_up() {
+    // LD C,C, LD D,D, then an illegal opcode
+    let mut gameboy = test_gameboy(&[0x49, 0x52, 0xD3]);
+    let buffer = traced(&mut gameboy, |tracer| tracer.set_ring_size(Some(2)));
+    for _ in 0..5 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(buffer.text(), "");
+    gameboy.step_instruction();
+    assert!(gameboy.cpu().is_locked());
+    let text = buffer.text();
+    assert_eq!(text.lines().count(), 2);
+    assert!(text.contains("PC:0154 PCMEM:52,D3,00,00\n"));
+    assert!(text.ends_with("PC:0155 PCMEM:D3,00,00,00\n"));
+}
+
+#[test]
+fn ring_dumps_on_ld_b_b() {
+    let mut gameboy = test_gameboy(&[0x40, 0x18, 0xFE]);
+    let buffer = traced(&mut gameboy, |tracer| tracer.set_ring_size(Some(1)));
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert!(buffer.text().ends_with("PC:0153 PCMEM:40,18,FE,00\n"));
+    assert_eq!(buffer.text().lines().count(), 1);
+    // only once, even though nobody took the breakpoint
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(buffer.text().lines().count(), 1);
+    // the ring is empty after a dump
+    gameboy.tracer_mut().unwrap().dump();
+    assert_eq!(buffer.text().lines().count(), 2);
+    gameboy.tracer_mut().unwrap().dump();
+    assert_eq!(buffer.text().lines().count(), 2);
+}
+
+#[test]
+fn doctor_mode() {
+    // LDH A,(44) then JR $
+    let mut gameboy = test_gameboy(&[0xF0, 0x44, 0x18, 0xFE]);
+    let buffer = traced(&mut gameboy, |_| ());
+    for _ in 0..5 {
+        gameboy.step_instruction();
+    }
+    assert!(buffer
+        .text()
+        .ends_with("A:00 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0155 PCMEM:18,FE,00,00\n"));
+
+    let mut gameboy = test_gameboy(&[0xF0, 0x44, 0x18, 0xFE]);
+    gameboy.set_doctor_mode(true);
+    let buffer = traced(&mut gameboy, |_| ());
+    for _ in 0..5 {
+        gameboy.step_instruction();
+    }
+    assert!(buffer
+        .text()
+        .ends_with("A:90 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0155 PCMEM:18,FE,00,00\n"));
+}
+
+struct BrokenPipe;
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_errors_are_kept() {
+    let mut gameboy = test_gameboy(&[0x18, 0xFE]);
+    gameboy.set_tracer(Some(Tracer::new(BrokenPipe)));
+    assert!(gameboy.tracer_mut().unwrap().last_error().is_none());
+    // the emulation goes on
+    for _ in 0..4 {
+        gameboy.step_instruction();
+    }
+    assert_eq!(gameboy.registers().pc, 0x0153);
+    let err = gameboy.tracer_mut().unwrap().last_error().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}